@@ -0,0 +1,290 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{info, warn, debug};
+use exarobot_shared::api::{ConnectionSnapshot, UserConnections, UserTraffic};
+use crate::engine::{EngineService, NodeEngine};
+
+const CLASH_API: &str = "http://127.0.0.1:9090";
+/// Attribution entries kept before old ones are pruned
+const TRACKER_CAPACITY: usize = 50_000;
+const TRACKER_MAX_AGE: Duration = Duration::from_secs(6 * 3600);
+
+/// Local sing-box Clash API client. The secret is read from the applied config,
+/// so it follows whatever the panel generated last.
+pub struct ClashController {
+    client: reqwest::Client,
+    config_path: String,
+}
+
+impl ClashController {
+    pub fn new(config_path: &str) -> Arc<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(5))
+            .build()
+            .unwrap_or_default();
+        Arc::new(Self { client, config_path: config_path.to_string() })
+    }
+
+    fn secret(&self) -> Option<String> {
+        let content = std::fs::read_to_string(&self.config_path).ok()?;
+        let json: serde_json::Value = serde_json::from_str(&content).ok()?;
+        json["experimental"]["clash_api"]["secret"].as_str().map(|s| s.to_string())
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        let req = self.client.request(method, format!("{}{}", CLASH_API, path));
+        match self.secret() {
+            Some(secret) => req.bearer_auth(secret),
+            None => req,
+        }
+    }
+
+    /// Raw `/connections` response
+    pub async fn connections(&self) -> anyhow::Result<serde_json::Value> {
+        let resp = self.request(reqwest::Method::GET, "/connections").send().await?;
+        if !resp.status().is_success() {
+            anyhow::bail!("Clash API error: {}", resp.status());
+        }
+        Ok(resp.json().await?)
+    }
+
+    pub async fn close(&self, id: &str) -> anyhow::Result<()> {
+        let resp = self.request(reqwest::Method::DELETE, &format!("/connections/{}", id)).send().await?;
+        // 404 means already gone, which is fine
+        if !resp.status().is_success() && resp.status() != reqwest::StatusCode::NOT_FOUND {
+            anyhow::bail!("Clash API delete error: {}", resp.status());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct TrackerInner {
+    /// sing-box log context id -> client address, until the user line arrives
+    pending: HashMap<u64, (String, Instant)>,
+    /// client "ip:port" -> sing-box user
+    users: HashMap<String, (String, Instant)>,
+}
+
+/// The Clash API does not expose the inbound user, but sing-box logs both
+/// `inbound connection from <addr>` and `[<user>] inbound connection to <dest>`
+/// under the same context id. Correlating the two maps client addresses to users.
+#[derive(Default)]
+pub struct UserTracker {
+    inner: Mutex<TrackerInner>,
+}
+
+impl UserTracker {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Feed one raw (unredacted) sing-box log line
+    pub fn observe(&self, line: &str) {
+        let Some((ctx_id, rest)) = parse_context(line) else { return };
+        let Ok(mut inner) = self.inner.lock() else { return };
+        let now = Instant::now();
+
+        if let Some(addr) = rest.strip_prefix("inbound connection from ")
+            .or_else(|| rest.strip_prefix("inbound packet connection from "))
+        {
+            inner.pending.insert(ctx_id, (normalize_addr(addr.trim()), now));
+        } else if let Some(user) = rest.strip_prefix('[').and_then(|r| r.split_once("] inbound")).map(|(u, _)| u)
+            && let Some((addr, _)) = inner.pending.remove(&ctx_id)
+        {
+            inner.users.insert(addr, (user.to_string(), now));
+        }
+
+        if inner.pending.len() + inner.users.len() > TRACKER_CAPACITY {
+            inner.pending.retain(|_, (_, t)| now.duration_since(*t) < Duration::from_secs(60));
+            inner.users.retain(|_, (_, t)| now.duration_since(*t) < TRACKER_MAX_AGE);
+        }
+    }
+
+    fn user_of(&self, ip: &str, port: &str) -> Option<String> {
+        let inner = self.inner.lock().ok()?;
+        inner.users.get(&format!("{}:{}", ip, port)).map(|(u, _)| u.clone())
+    }
+}
+
+/// "INFO [3625478071 0ms] inbound/vless[vless-in]: inbound connection from ..." -> (3625478071, "inbound connection from ...")
+fn parse_context(line: &str) -> Option<(u64, &str)> {
+    let mut search = line;
+    while let Some(pos) = search.find('[') {
+        let after = &search[pos + 1..];
+        let id_str: String = after.chars().take_while(|c| c.is_ascii_digit()).collect();
+        if !id_str.is_empty() && after[id_str.len()..].starts_with(' ') {
+            let id = id_str.parse().ok()?;
+            let (_, rest) = after.split_once("]: ")?;
+            return Some((id, rest));
+        }
+        search = after;
+    }
+    None
+}
+
+/// "[2001:db8::1]:443" and "1.2.3.4:443" -> "ip:port" without brackets
+fn normalize_addr(addr: &str) -> String {
+    match addr.rsplit_once(':') {
+        Some((ip, port)) => format!("{}:{}", ip.trim_start_matches('[').trim_end_matches(']'), port),
+        None => addr.to_string(),
+    }
+}
+
+/// A core's running byte counter: one per sing-box connection, one per Xray user
+pub struct Counter {
+    pub key: String,
+    pub user: String,
+    pub upload: u64,
+    pub download: u64,
+}
+
+/// Turns the cores' running counters into the traffic each user moved since the last report
+/// the panel accepted. Bytes of sing-box connections that close between two reports after
+/// their last reading are not seen.
+#[derive(Default)]
+pub struct TrafficMeter {
+    last: HashMap<String, (u64, u64)>,
+    unsent: BTreeMap<String, (u64, u64)>,
+    /// The first reading only sets the baseline, so an agent restart doesn't book old bytes again
+    primed: bool,
+}
+
+impl TrafficMeter {
+    pub fn observe(&mut self, counters: Vec<Counter>) {
+        let mut last = HashMap::with_capacity(counters.len());
+        for c in counters {
+            if self.primed {
+                // A counter that went down belongs to a restarted core or a reused connection ID
+                let (up, down) = match self.last.get(&c.key) {
+                    Some(&(up, down)) if c.upload >= up && c.download >= down => (c.upload - up, c.download - down),
+                    _ => (c.upload, c.download),
+                };
+                if up > 0 || down > 0 {
+                    let entry = self.unsent.entry(c.user).or_default();
+                    entry.0 += up;
+                    entry.1 += down;
+                }
+            }
+            last.insert(c.key, (c.upload, c.download));
+        }
+        self.last = last;
+        self.primed = true;
+    }
+
+    pub fn unsent(&self) -> Vec<UserTraffic> {
+        self.unsent.iter()
+            .map(|(user, &(upload, download))| UserTraffic { user: user.clone(), upload, download })
+            .collect()
+    }
+
+    /// The panel booked everything reported so far
+    pub fn sent(&mut self) {
+        self.unsent.clear();
+    }
+}
+
+/// Group the controller's connections by user
+pub async fn snapshot(controller: &ClashController, tracker: &UserTracker) -> anyhow::Result<(ConnectionSnapshot, Vec<Counter>)> {
+    let conns = controller.connections().await?;
+    let mut per_user: BTreeMap<String, (BTreeSet<String>, u32, u64, u64)> = BTreeMap::new();
+    let mut counters = Vec::new();
+
+    for c in conns["connections"].as_array().into_iter().flatten() {
+        let ip = c["metadata"]["sourceIP"].as_str().unwrap_or("");
+        let port = c["metadata"]["sourcePort"].as_str().unwrap_or("");
+        let Some(user) = tracker.user_of(ip, port) else { continue };
+        let upload = c["upload"].as_u64().unwrap_or(0);
+        let download = c["download"].as_u64().unwrap_or(0);
+
+        if let Some(id) = c["id"].as_str() {
+            counters.push(Counter { key: id.to_string(), user: user.clone(), upload, download });
+        }
+        let entry = per_user.entry(user).or_default();
+        entry.0.insert(ip.to_string());
+        entry.1 += 1;
+        entry.2 += upload;
+        entry.3 += download;
+    }
+
+    let snapshot = ConnectionSnapshot {
+        users: per_user.into_iter().map(|(user, (ips, connections, upload, download))| UserConnections {
+            user,
+            ips: ips.into_iter().collect(),
+            connections,
+            upload,
+            download,
+        }).collect(),
+        traffic: Vec::new(),
+    };
+    Ok((snapshot, counters))
+}
+
+/// Close every open connection of a user. Returns how many were closed.
+pub async fn close_user(controller: &ClashController, tracker: &UserTracker, user: &str) -> anyhow::Result<usize> {
+    let conns = controller.connections().await?;
+    let mut closed = 0;
+    for c in conns["connections"].as_array().into_iter().flatten() {
+        let ip = c["metadata"]["sourceIP"].as_str().unwrap_or("");
+        let port = c["metadata"]["sourcePort"].as_str().unwrap_or("");
+        if tracker.user_of(ip, port).as_deref() != Some(user) {
+            continue;
+        }
+        if let Some(id) = c["id"].as_str() {
+            match controller.close(id).await {
+                Ok(_) => closed += 1,
+                Err(e) => warn!("Failed to close connection {}: {}", id, e),
+            }
+        }
+    }
+    info!("🔌 Closed {} connection(s) of user {}", closed, user);
+    Ok(closed)
+}
+
+/// Push the per-user connection snapshot to the panel every minute
+pub async fn run_reporter(
+    controller: Arc<ClashController>,
+    tracker: Arc<UserTracker>,
+    engine: Arc<crate::engine::EngineCell>,
+    client: reqwest::Client,
+    panel_url: String,
+    token: String,
+) {
+    info!("🔌 Connection reporter started");
+    let url = format!("{}/api/v2/node/connections", panel_url);
+    let mut meter = TrafficMeter::default();
+
+    loop {
+        tokio::time::sleep(Duration::from_secs(60)).await;
+
+        let snapshot = match engine.get() {
+            NodeEngine::Singbox => snapshot(&controller, &tracker).await,
+            NodeEngine::Xray => crate::xray_stats::snapshot().await,
+        };
+        let mut snapshot = match snapshot {
+            Ok((snapshot, counters)) => {
+                meter.observe(counters);
+                snapshot
+            }
+            Err(e) => {
+                debug!("{} stats unavailable for connection report: {}", engine.get().service(), e);
+                continue;
+            }
+        };
+        // Usage stays with the agent until the panel has booked it
+        snapshot.traffic = meter.unsent();
+
+        let res = client.post(&url)
+            .header("Authorization", format!("Bearer {}", token))
+            .json(&snapshot)
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await;
+        match res {
+            Ok(r) if !r.status().is_success() => warn!("Panel rejected connection report: {}", r.status()),
+            Err(e) => warn!("Failed to report connections: {}", e),
+            _ => meter.sent(),
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+use tracing::info;
+
+pub use exarobot_shared::engine::NodeEngine;
+
+/// Managing the systemd unit of the core the panel asked this node to run
+pub trait EngineService {
+    fn service(&self) -> &'static str;
+    fn restart(&self) -> anyhow::Result<()>;
+    fn stop(&self) -> anyhow::Result<()>;
+}
+
+impl EngineService for NodeEngine {
+    /// systemd unit of the core
+    fn service(&self) -> &'static str {
+        match self {
+            Self::Singbox => "sing-box",
+            Self::Xray => "xray",
+        }
+    }
+
+    fn restart(&self) -> anyhow::Result<()> {
+        info!("🔄 Restarting {} service...", self.service());
+        systemctl("restart", self.service())?;
+        info!("✅ Service restarted");
+        Ok(())
+    }
+
+    fn stop(&self) -> anyhow::Result<()> {
+        info!("🛑 Stopping {} service...", self.service());
+        systemctl("stop", self.service())
+    }
+}
+
+fn systemctl(action: &str, unit: &str) -> anyhow::Result<()> {
+    let output = std::process::Command::new("systemctl")
+        .args([action, unit])
+        .output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("systemctl {} {} failed: {}", action, unit, stderr);
+    }
+    Ok(())
+}
+
+/// Engine shared with background tasks (the connection reporter picks its data source from it)
+pub struct EngineCell(AtomicU8);
+
+impl EngineCell {
+    pub fn new(engine: NodeEngine) -> Self {
+        Self(AtomicU8::new(engine as u8))
+    }
+
+    pub fn get(&self) -> NodeEngine {
+        match self.0.load(Ordering::Relaxed) {
+            1 => NodeEngine::Xray,
+            _ => NodeEngine::Singbox,
+        }
+    }
+
+    pub fn set(&self, engine: NodeEngine) {
+        self.0.store(engine as u8, Ordering::Relaxed);
+    }
+}
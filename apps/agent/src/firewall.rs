@@ -0,0 +1,321 @@
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::net::IpAddr;
+use std::process::{Command, Stdio};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+use exarobot_shared::api::FirewallState;
+
+/// Everything the agent manages lives in this table; other tables (ufw, docker) are not touched.
+/// Its input chain drops by default, and a drop in any base chain is final, so services on the
+/// host that aren't SSH, the panel or an inbound must be listed in the extra ports.
+const TABLE: &str = "inet exarobot";
+
+/// Fetch the desired state from the panel
+pub async fn fetch_state(
+    client: &reqwest::Client,
+    panel_url: &str,
+    token: &str,
+) -> anyhow::Result<FirewallState> {
+    let url = format!("{}/api/v2/node/firewall", panel_url);
+    let resp = client.get(&url)
+        .header("Authorization", format!("Bearer {}", token))
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await?;
+
+    if !resp.status().is_success() {
+        anyhow::bail!("Panel returned {}", resp.status());
+    }
+    Ok(resp.json().await?)
+}
+
+/// Apply the desired state. Returns Ok(false) if nothing changed since `applied`.
+pub async fn reconcile(
+    desired: &FirewallState,
+    applied: Option<&FirewallState>,
+    panel_url: &str,
+    ssh_port: u16,
+    metrics_port: Option<u16>,
+) -> anyhow::Result<bool> {
+    if applied == Some(desired) {
+        return Ok(false);
+    }
+
+    if !desired.enabled {
+        // Also clean up after a previous run on first sync; hosts without nft have nothing to remove
+        if applied.is_none_or(|a| a.enabled) && remove_table().is_ok() {
+            info!("🧱 Firewall management disabled, nftables table removed");
+        }
+        return Ok(true);
+    }
+
+    let mut controller_allow: Vec<IpAddr> = desired.controller_allow.iter()
+        .filter_map(|ip| ip.parse().ok())
+        .collect();
+    if controller_allow.is_empty() {
+        controller_allow = resolve_panel(panel_url).await;
+        if controller_allow.is_empty() {
+            warn!("Could not resolve panel address, controller port stays closed");
+        }
+    }
+
+    let ruleset = render_ruleset(desired, &controller_allow, ssh_port, metrics_port, unix_now());
+    apply_ruleset(&ruleset)?;
+    info!(
+        "🧱 Firewall applied: tcp {:?}, udp {:?}, extra tcp {:?}, extra udp {:?}, {} hopping range(s), {} ban(s)",
+        desired.tcp_ports, desired.udp_ports, desired.extra_tcp_ports, desired.extra_udp_ports,
+        desired.port_hopping.len(), desired.bans.len()
+    );
+    Ok(true)
+}
+
+/// Full `nft -f` script. The table is recreated atomically in one transaction.
+fn render_ruleset(state: &FirewallState, controller_allow: &[IpAddr], ssh_port: u16, metrics_port: Option<u16>, now: i64) -> String {
+    let mut out = String::new();
+    // Declaring first makes the delete valid even when the table does not exist yet
+    let _ = writeln!(out, "table {} {{}}", TABLE);
+    let _ = writeln!(out, "delete table {}", TABLE);
+    let _ = writeln!(out, "table {} {{", TABLE);
+
+    // Sets
+    let (ban4, ban6): (Vec<_>, Vec<_>) = state.bans.iter()
+        .filter(|b| b.expires_at > now)
+        .filter_map(|b| b.ip.parse::<IpAddr>().ok().map(|ip| (ip, b.expires_at - now)))
+        .partition(|(ip, _)| ip.is_ipv4());
+    write_set(&mut out, "banned4", "ipv4_addr", true, ban4.iter().map(|(ip, ttl)| format!("{} timeout {}s", ip, ttl)));
+    write_set(&mut out, "banned6", "ipv6_addr", true, ban6.iter().map(|(ip, ttl)| format!("{} timeout {}s", ip, ttl)));
+    write_set(&mut out, "panel4", "ipv4_addr", false, controller_allow.iter().filter(|ip| ip.is_ipv4()).map(|ip| ip.to_string()));
+    write_set(&mut out, "panel6", "ipv6_addr", false, controller_allow.iter().filter(|ip| ip.is_ipv6()).map(|ip| ip.to_string()));
+
+    // Port hopping: redirect the whole range to the real inbound port
+    if !state.port_hopping.is_empty() {
+        let _ = writeln!(out, "  chain prerouting {{");
+        let _ = writeln!(out, "    type nat hook prerouting priority dstnat; policy accept;");
+        for hop in &state.port_hopping {
+            let _ = writeln!(out, "    udp dport {}-{} redirect to :{}", hop.start, hop.end, hop.target);
+        }
+        let _ = writeln!(out, "  }}");
+    }
+
+    let _ = writeln!(out, "  chain input {{");
+    let _ = writeln!(out, "    type filter hook input priority filter; policy drop;");
+    let _ = writeln!(out, "    iif \"lo\" accept");
+    let _ = writeln!(out, "    ip saddr @banned4 drop");
+    let _ = writeln!(out, "    ip6 saddr @banned6 drop");
+    let _ = writeln!(out, "    ct state established,related accept");
+    let _ = writeln!(out, "    ct state invalid drop");
+    let _ = writeln!(out, "    meta l4proto {{ icmp, ipv6-icmp }} accept");
+    let _ = writeln!(out, "    tcp dport {} accept", ssh_port);
+    let _ = writeln!(out, "    tcp dport {} ip saddr @panel4 accept", state.controller_port);
+    let _ = writeln!(out, "    tcp dport {} ip6 saddr @panel6 accept", state.controller_port);
+    // Prometheus scrapes from the panel host
+    if let Some(port) = metrics_port {
+        let _ = writeln!(out, "    tcp dport {} ip saddr @panel4 accept", port);
+        let _ = writeln!(out, "    tcp dport {} ip6 saddr @panel6 accept", port);
+    }
+    if !state.tcp_ports.is_empty() {
+        let _ = writeln!(out, "    tcp dport {{ {} }} accept", join(&state.tcp_ports));
+    }
+    if !state.udp_ports.is_empty() {
+        let _ = writeln!(out, "    udp dport {{ {} }} accept", join(&state.udp_ports));
+    }
+    // Other services the admin keeps reachable on this host
+    if !state.extra_tcp_ports.is_empty() {
+        let _ = writeln!(out, "    tcp dport {{ {} }} accept", join(&state.extra_tcp_ports));
+    }
+    if !state.extra_udp_ports.is_empty() {
+        let _ = writeln!(out, "    udp dport {{ {} }} accept", join(&state.extra_udp_ports));
+    }
+    let _ = writeln!(out, "  }}");
+    let _ = writeln!(out, "}}");
+    out
+}
+
+/// Port of a `METRICS_LISTEN` address that other hosts can reach; loopback listeners are covered by `lo`
+pub fn exposed_port(listen: &str) -> Option<u16> {
+    let addr: std::net::SocketAddr = listen.parse().ok()?;
+    (!addr.ip().is_loopback()).then_some(addr.port())
+}
+
+fn write_set(out: &mut String, name: &str, ty: &str, timeout: bool, elements: impl Iterator<Item = String>) {
+    let elements: Vec<String> = elements.collect();
+    let _ = writeln!(out, "  set {} {{", name);
+    let _ = writeln!(out, "    type {};", ty);
+    if timeout {
+        let _ = writeln!(out, "    flags timeout;");
+    }
+    if !elements.is_empty() {
+        let _ = writeln!(out, "    elements = {{ {} }}", elements.join(", "));
+    }
+    let _ = writeln!(out, "  }}");
+}
+
+fn join(ports: &[u16]) -> String {
+    ports.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+fn apply_ruleset(ruleset: &str) -> anyhow::Result<()> {
+    let mut child = Command::new("nft")
+        .args(["-f", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(ruleset.as_bytes())?;
+    }
+    let out = child.wait_with_output()?;
+    if !out.status.success() {
+        anyhow::bail!("nft rejected ruleset: {}", String::from_utf8_lossy(&out.stderr).trim());
+    }
+    Ok(())
+}
+
+fn remove_table() -> anyhow::Result<()> {
+    apply_ruleset(&format!("table {} {{}}\ndelete table {}\n", TABLE, TABLE))
+}
+
+/// Panel addresses from the agent's point of view (used when the panel did not pin them)
+async fn resolve_panel(panel_url: &str) -> Vec<IpAddr> {
+    let authority = panel_url
+        .split("://").nth(1).unwrap_or(panel_url)
+        .split('/').next().unwrap_or("");
+    let target = if authority.contains(':') && !authority.ends_with(']') {
+        authority.to_string()
+    } else {
+        format!("{}:443", authority)
+    };
+
+    match tokio::net::lookup_host(target).await {
+        Ok(addrs) => {
+            let mut ips: Vec<IpAddr> = addrs.map(|a| a.ip()).collect();
+            ips.sort();
+            ips.dedup();
+            ips
+        }
+        Err(e) => {
+            warn!("Failed to resolve panel host {}: {}", authority, e);
+            Vec::new()
+        }
+    }
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exarobot_shared::api::{IpBan, PortHopRange};
+
+    const NOW: i64 = 1_800_000_000;
+
+    fn state() -> FirewallState {
+        FirewallState {
+            enabled: true,
+            tcp_ports: vec![443, 8443],
+            udp_ports: vec![443],
+            controller_port: 9090,
+            ..Default::default()
+        }
+    }
+
+    fn panel() -> Vec<IpAddr> {
+        vec!["203.0.113.10".parse().unwrap(), "2001:db8::10".parse().unwrap()]
+    }
+
+    /// The body of a named set or chain
+    fn block<'a>(ruleset: &'a str, header: &str) -> &'a str {
+        let start = ruleset.find(header).unwrap_or_else(|| panic!("{} missing in:\n{}", header, ruleset));
+        let rest = &ruleset[start..];
+        &rest[..rest.find("\n  }").unwrap()]
+    }
+
+    #[test]
+    fn test_base_ruleset() {
+        let ruleset = render_ruleset(&state(), &panel(), 22, None, NOW);
+
+        assert!(ruleset.starts_with("table inet exarobot {}\ndelete table inet exarobot\n"));
+        let input = block(&ruleset, "chain input {");
+        assert!(input.contains("policy drop;"));
+        assert!(input.contains("tcp dport 22 accept"));
+        assert!(input.contains("tcp dport { 443, 8443 } accept"));
+        assert!(input.contains("udp dport { 443 } accept"));
+        assert!(input.contains("tcp dport 9090 ip saddr @panel4 accept"));
+        assert!(input.contains("tcp dport 9090 ip6 saddr @panel6 accept"));
+        assert!(!ruleset.contains("chain prerouting"));
+    }
+
+    #[test]
+    fn test_bans_skip_expired_and_split_by_family() {
+        let mut state = state();
+        state.bans = vec![
+            IpBan { ip: "198.51.100.7".into(), expires_at: NOW + 600 },
+            IpBan { ip: "2001:db8::bad".into(), expires_at: NOW + 60 },
+            IpBan { ip: "198.51.100.8".into(), expires_at: NOW },
+            IpBan { ip: "198.51.100.9".into(), expires_at: NOW - 1 },
+            IpBan { ip: "not-an-ip".into(), expires_at: NOW + 600 },
+        ];
+        let ruleset = render_ruleset(&state, &[], 22, None, NOW);
+
+        let ban4 = block(&ruleset, "set banned4 {");
+        assert!(ban4.contains("type ipv4_addr;"));
+        assert!(ban4.contains("flags timeout;"));
+        assert!(ban4.contains("elements = { 198.51.100.7 timeout 600s }"));
+        let ban6 = block(&ruleset, "set banned6 {");
+        assert!(ban6.contains("type ipv6_addr;"));
+        assert!(ban6.contains("elements = { 2001:db8::bad timeout 60s }"));
+        assert!(!ruleset.contains("198.51.100.8") && !ruleset.contains("198.51.100.9") && !ruleset.contains("not-an-ip"));
+    }
+
+    #[test]
+    fn test_panel_sets_split_by_family() {
+        let ruleset = render_ruleset(&state(), &panel(), 22, None, NOW);
+
+        assert!(block(&ruleset, "set panel4 {").contains("elements = { 203.0.113.10 }"));
+        assert!(block(&ruleset, "set panel6 {").contains("elements = { 2001:db8::10 }"));
+        // No resolvable panel leaves the sets empty, so the controller port stays closed
+        let ruleset = render_ruleset(&state(), &[], 22, None, NOW);
+        assert!(!block(&ruleset, "set panel4 {").contains("elements"));
+    }
+
+    #[test]
+    fn test_metrics_port_open_to_panel_only() {
+        let ruleset = render_ruleset(&state(), &panel(), 22, Some(9100), NOW);
+
+        let input = block(&ruleset, "chain input {");
+        assert!(input.contains("tcp dport 9100 ip saddr @panel4 accept"));
+        assert!(input.contains("tcp dport 9100 ip6 saddr @panel6 accept"));
+        assert!(!input.contains("tcp dport 9100 accept"));
+
+        assert_eq!(exposed_port("0.0.0.0:9100"), Some(9100));
+        assert_eq!(exposed_port("[::]:9100"), Some(9100));
+        assert_eq!(exposed_port("127.0.0.1:9100"), None);
+        assert_eq!(exposed_port("not an address"), None);
+    }
+
+    #[test]
+    fn test_port_hopping_redirects_range() {
+        let mut state = state();
+        state.port_hopping = vec![PortHopRange { start: 20000, end: 30000, target: 443 }];
+        let ruleset = render_ruleset(&state, &panel(), 22, None, NOW);
+
+        let prerouting = block(&ruleset, "chain prerouting {");
+        assert!(prerouting.contains("type nat hook prerouting priority dstnat; policy accept;"));
+        assert!(prerouting.contains("udp dport 20000-30000 redirect to :443"));
+    }
+
+    #[test]
+    fn test_extra_ports_open_to_everyone() {
+        let mut state = state();
+        state.extra_tcp_ports = vec![8080, 10050];
+        state.extra_udp_ports = vec![161];
+        let ruleset = render_ruleset(&state, &panel(), 22, None, NOW);
+
+        let input = block(&ruleset, "chain input {");
+        assert!(input.contains("tcp dport { 8080, 10050 } accept"));
+        assert!(input.contains("udp dport { 161 } accept"));
+    }
+}
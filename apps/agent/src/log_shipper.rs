@@ -0,0 +1,303 @@
+use std::collections::VecDeque;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tracing::{info, warn, error};
+use exarobot_shared::api::{NodeLogBatch, NodeLogLine};
+
+/// Lines kept locally, across all sources
+const BUFFER_CAPACITY: usize = 2000;
+/// Lines older than this are dropped even if the buffer is not full
+const MAX_AGE_SECS: i64 = 3600;
+/// How long a single viewer request keeps shipping enabled
+const STREAM_WINDOW: Duration = Duration::from_secs(120);
+/// History sent when a viewer opens
+const INITIAL_LINES: usize = 200;
+
+struct Inner {
+    lines: VecDeque<NodeLogLine>,
+    next_seq: u64,
+    redact_ips: bool,
+    // Shipping state
+    stream_until: Option<Instant>,
+    last_shipped: u64,
+}
+
+/// Bounded in-memory log buffer. Nothing is written to disk.
+pub struct LogBuffer {
+    inner: Mutex<Inner>,
+}
+
+impl LogBuffer {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            inner: Mutex::new(Inner {
+                lines: VecDeque::with_capacity(BUFFER_CAPACITY),
+                next_seq: 1,
+                redact_ips: true,
+                stream_until: None,
+                last_shipped: 0,
+            }),
+        })
+    }
+
+    pub fn set_redact_ips(&self, enabled: bool) {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.redact_ips = enabled;
+        }
+    }
+
+    pub fn push(&self, source: &str, raw: &str) {
+        let raw = raw.trim_end();
+        if raw.is_empty() {
+            return;
+        }
+        let Ok(mut inner) = self.inner.lock() else { return };
+
+        let message = if inner.redact_ips { redact_ips(raw) } else { raw.to_string() };
+        let now = unix_now();
+        let line = NodeLogLine {
+            seq: inner.next_seq,
+            ts: now,
+            source: source.to_string(),
+            level: detect_level(raw).to_string(),
+            message,
+        };
+        inner.next_seq += 1;
+
+        if inner.lines.len() >= BUFFER_CAPACITY {
+            inner.lines.pop_front();
+        }
+        while inner.lines.front().is_some_and(|l| now - l.ts > MAX_AGE_SECS) {
+            inner.lines.pop_front();
+        }
+        inner.lines.push_back(line);
+    }
+
+    /// Called when the panel asks for logs. A fresh session starts with recent history.
+    pub fn request_stream(&self) {
+        let Ok(mut inner) = self.inner.lock() else { return };
+        let active = inner.stream_until.is_some_and(|t| t > Instant::now());
+        if !active {
+            let skip = inner.lines.len().saturating_sub(INITIAL_LINES);
+            inner.last_shipped = inner.lines.get(skip).map(|l| l.seq - 1).unwrap_or(inner.next_seq - 1);
+        }
+        inner.stream_until = Some(Instant::now() + STREAM_WINDOW);
+    }
+
+    /// Lines pending shipment, or None if nobody is watching
+    fn pending(&self) -> Option<Vec<NodeLogLine>> {
+        let inner = self.inner.lock().ok()?;
+        if inner.stream_until.is_none_or(|t| t <= Instant::now()) {
+            return None;
+        }
+        Some(inner.lines.iter().filter(|l| l.seq > inner.last_shipped).cloned().collect())
+    }
+
+    fn mark_shipped(&self, seq: u64) {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.last_shipped = inner.last_shipped.max(seq);
+        }
+    }
+}
+
+/// `tracing` writer that copies the agent's own log output into the buffer
+#[derive(Clone)]
+pub struct BufferWriter(pub Arc<LogBuffer>);
+
+impl std::io::Write for BufferWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        for line in String::from_utf8_lossy(buf).lines() {
+            self.0.push("agent", line);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> tracing_subscriber::fmt::MakeWriter<'a> for BufferWriter {
+    type Writer = BufferWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+/// Follow `journalctl -u sing-box -u xray` into the buffer (and the user tracker), restarting the reader if it exits
+pub async fn tail_singbox(buffer: Arc<LogBuffer>, tracker: Arc<crate::controller::UserTracker>) {
+    loop {
+        let child = tokio::process::Command::new("journalctl")
+            .args(["-u", "sing-box", "-u", "xray", "-f", "-n", "100", "-o", "cat", "--no-pager"])
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::null())
+            .kill_on_drop(true)
+            .spawn();
+
+        match child {
+            Ok(mut child) => {
+                if let Some(stdout) = child.stdout.take() {
+                    let mut lines = BufReader::new(stdout).lines();
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        tracker.observe(&line);
+                        buffer.push("sing-box", &line);
+                    }
+                }
+                let _ = child.wait().await;
+                warn!("journalctl exited, restarting log tail in 10s");
+            }
+            Err(e) => error!("Failed to start journalctl: {}", e),
+        }
+        tokio::time::sleep(Duration::from_secs(10)).await;
+    }
+}
+
+/// Push buffered lines to the panel while a viewer is open
+pub async fn run_shipper(buffer: Arc<LogBuffer>, client: reqwest::Client, panel_url: String, token: String) {
+    info!("📜 Log shipper started");
+    let url = format!("{}/api/v2/node/logs", panel_url);
+
+    loop {
+        tokio::time::sleep(Duration::from_secs(2)).await;
+
+        let Some(lines) = buffer.pending() else { continue };
+        if lines.is_empty() {
+            continue;
+        }
+        let last_seq = lines.last().map(|l| l.seq).unwrap_or(0);
+
+        let res = client.post(&url)
+            .header("Authorization", format!("Bearer {}", token))
+            .json(&NodeLogBatch { lines })
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await;
+
+        match res {
+            Ok(r) if r.status().is_success() => buffer.mark_shipped(last_seq),
+            // Do not log through tracing here: it would feed back into the buffer on every failure
+            Ok(_) | Err(_) => {}
+        }
+    }
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+fn detect_level(line: &str) -> &'static str {
+    for word in line.split_whitespace().take(8) {
+        match word.trim_matches(|c: char| !c.is_ascii_alphabetic()) {
+            "FATAL" | "PANIC" | "ERROR" => return "error",
+            "WARN" | "WARNING" => return "warn",
+            "INFO" => return "info",
+            "DEBUG" => return "debug",
+            "TRACE" => return "trace",
+            _ => {}
+        }
+    }
+    "info"
+}
+
+/// Replace anything that parses as an IPv4/IPv6 address (client addresses in sing-box logs).
+/// Mapped addresses like `::ffff:1.2.3.4` go as a whole, and ports after an address are kept.
+fn redact_ips(line: &str) -> String {
+    let line = replace_tokens(line, |c| c.is_ascii_hexdigit() || c == ':' || c == '.', redact_address);
+    // Dotted quads glued to a word ("tcp:1.2.3.4:443") are not a token of their own above
+    replace_tokens(&line, |c| c.is_ascii_digit() || c == '.', |t| {
+        t.parse::<Ipv4Addr>().is_ok().then(|| REDACTED.to_string())
+    })
+}
+
+const REDACTED: &str = "[redacted]";
+
+/// A token that is an address, an address followed by `:`, or an address ending in a dotted
+/// quad with a port (unbracketed IPv6 with a port is ambiguous and taken as a whole)
+fn redact_address(token: &str) -> Option<String> {
+    if is_address(token) {
+        return Some(REDACTED.to_string());
+    }
+    if let Some(host) = token.strip_suffix(':') && !host.ends_with(':') && is_address(host) {
+        return Some(format!("{}:", REDACTED));
+    }
+    match token.rsplit_once(':') {
+        Some((host, port)) if host.contains('.') && is_address(host) && port.parse::<u16>().is_ok() => {
+            Some(format!("{}:{}", REDACTED, port))
+        }
+        _ => None,
+    }
+}
+
+fn is_address(token: &str) -> bool {
+    token.parse::<Ipv4Addr>().is_ok() || (token.matches(':').count() >= 2 && token.parse::<Ipv6Addr>().is_ok())
+}
+
+fn replace_tokens(line: &str, in_token: impl Fn(char) -> bool, redact: impl Fn(&str) -> Option<String>) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut token = String::new();
+    let flush = |token: &mut String, out: &mut String| {
+        if !token.is_empty() {
+            match redact(token) {
+                Some(replacement) => out.push_str(&replacement),
+                None => out.push_str(token),
+            }
+            token.clear();
+        }
+    };
+    for c in line.chars() {
+        if in_token(c) {
+            token.push(c);
+        } else {
+            flush(&mut token, &mut out);
+            out.push(c);
+        }
+    }
+    flush(&mut token, &mut out);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_singbox_connection_lines() {
+        assert_eq!(
+            redact_ips("+0000 2026-10-19 12:00:01 INFO [3127841 0ms] inbound/vless[vless-in]: inbound connection from 203.0.113.7:51234"),
+            "+0000 2026-10-19 12:00:01 INFO [3127841 0ms] inbound/vless[vless-in]: inbound connection from [redacted]:51234",
+        );
+        assert_eq!(
+            redact_ips("+0000 2026-10-19 12:00:01 INFO [3127841 0ms] inbound/hysteria2[hy2-in]: inbound connection from [2001:db8::1]:443"),
+            "+0000 2026-10-19 12:00:01 INFO [3127841 0ms] inbound/hysteria2[hy2-in]: inbound connection from [[redacted]]:443",
+        );
+        assert_eq!(
+            redact_ips("+0000 2026-10-19 12:00:02 ERROR [3127841 5ms] inbound/vless[vless-in]: process connection from [::ffff:198.51.100.23]:40112: EOF"),
+            "+0000 2026-10-19 12:00:02 ERROR [3127841 5ms] inbound/vless[vless-in]: process connection from [[redacted]]:40112: EOF",
+        );
+    }
+
+    #[test]
+    fn redacts_xray_access_lines() {
+        assert_eq!(
+            redact_ips("2026/10/19 12:00:03 from 203.0.113.7:51234 accepted tcp:www.example.com:443 [vless-in -> direct] email: 42"),
+            "2026/10/19 12:00:03 from [redacted]:51234 accepted tcp:www.example.com:443 [vless-in -> direct] email: 42",
+        );
+        assert_eq!(
+            redact_ips("2026/10/19 12:00:04 from tcp:198.51.100.23:40112 accepted tcp:[2606:4700::1111]:443 [vless-in -> direct]"),
+            "2026/10/19 12:00:04 from tcp:[redacted]:40112 accepted tcp:[[redacted]]:443 [vless-in -> direct]",
+        );
+        assert_eq!(
+            redact_ips("2026/10/19 12:00:05 from ::ffff:198.51.100.23:40112 accepted udp:1.1.1.1:53"),
+            "2026/10/19 12:00:05 from [redacted]:40112 accepted udp:[redacted]:53",
+        );
+    }
+
+    #[test]
+    fn leaves_timestamps_versions_and_words_alone() {
+        let line = "+0000 2026-10-19 12:00:01 INFO sing-box version 1.10.3 started, cafe:beef deadline";
+        assert_eq!(redact_ips(line), line);
+    }
+}
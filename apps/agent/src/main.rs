@@ -1,13 +1,22 @@
 use clap::Parser;
 use tracing::{info, warn, error};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 use std::time::Duration;
 use std::path::Path;
 use exarobot_shared::api::{HeartbeatRequest, HeartbeatResponse};
 use exarobot_shared::config::ConfigResponse;
+use engine::EngineService;
 
 mod sni_check;
 mod self_update;
 mod decoy_service; // NEW
+mod metrics;
+mod singbox_manager;
+mod log_shipper;
+mod firewall;
+mod controller;
+mod engine;
+mod xray_stats;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -27,6 +36,19 @@ struct Args {
     /// Config path (default: /etc/sing-box/config.json)
     #[arg(long, env = "CONFIG_PATH", default_value = "/etc/sing-box/config.json")]
     config_path: String,
+
+    /// Xray config path, used when the panel switches the node to Xray
+    #[arg(long, env = "XRAY_CONFIG_PATH", default_value = "/usr/local/etc/xray/config.json")]
+    xray_config_path: String,
+
+    /// Optional local Prometheus listener (e.g. 127.0.0.1:9101). Disabled if unset.
+    /// A non-loopback port is opened to the panel when the firewall is managed.
+    #[arg(long, env = "METRICS_LISTEN")]
+    metrics_listen: Option<String>,
+
+    /// SSH port kept open when the panel enables firewall management
+    #[arg(long, env = "SSH_PORT", default_value_t = 22)]
+    ssh_port: u16,
 }
 
 struct AgentState {
@@ -36,14 +58,36 @@ struct AgentState {
     kill_switch_enabled: bool,
     kill_switch_timeout: u64,
     vpn_stopped_by_kill_switch: bool,
+    telemetry: metrics::SharedTelemetry,
+    // sing-box Upgrade State
+    singbox_update_error: Option<String>,
+    singbox_failed_target: Option<String>,
+    singbox_upgrade: Option<singbox_manager::Upgrade>,
+    singbox_progress: singbox_manager::UpgradeProgress,
+    logs: std::sync::Arc<log_shipper::LogBuffer>,
+    // Last firewall state successfully applied
+    firewall_applied: Option<exarobot_shared::api::FirewallState>,
+    /// Metrics listener port the firewall opens to the panel; None when unset or loopback-only
+    metrics_port: Option<u16>,
+    controller: std::sync::Arc<controller::ClashController>,
+    user_tracker: std::sync::Arc<controller::UserTracker>,
+    /// Core currently running; follows the engine of the last applied config
+    engine: std::sync::Arc<engine::EngineCell>,
+    xray_config_path: String,
 }
 
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    // 1. Setup Logging
-    tracing_subscriber::fmt()
-        .with_env_filter("info")
+    // 1. Setup Logging (stdout + in-memory buffer for the panel log viewer)
+    let log_buffer = log_shipper::LogBuffer::new();
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::EnvFilter::new("info"))
+        .with(tracing_subscriber::fmt::layer())
+        .with(tracing_subscriber::fmt::layer()
+            .with_writer(log_shipper::BufferWriter(log_buffer.clone()))
+            .with_ansi(false)
+            .without_time())
         .init();
 
     info!("🚀 EXA ROBOT Node Agent v0.2.0 Starting...");
@@ -69,13 +113,35 @@ async fn main() -> anyhow::Result<()> {
     info!("🔑 Token: {}...", &token[0..4.min(token.len())]);
     info!("📁 Config Path: {}", args.config_path);
 
+    // Until the panel says otherwise, assume the core whose config is on disk
+    let initial_engine = if !Path::new(&args.config_path).exists() && Path::new(&args.xray_config_path).exists() {
+        engine::NodeEngine::Xray
+    } else {
+        engine::NodeEngine::Singbox
+    };
+
     // 3. Load current hash (if config exists)
     let mut state = AgentState {
-        current_hash: load_current_hash(&args.config_path).await,
+        current_hash: load_current_hash(match initial_engine {
+            engine::NodeEngine::Singbox => &args.config_path,
+            engine::NodeEngine::Xray => &args.xray_config_path,
+        }).await,
         last_successful_contact: std::time::Instant::now(),
         kill_switch_enabled: false,
         kill_switch_timeout: 300,
         vpn_stopped_by_kill_switch: false,
+        telemetry: Default::default(),
+        singbox_update_error: None,
+        singbox_failed_target: None,
+        singbox_upgrade: None,
+        singbox_progress: Default::default(),
+        logs: log_buffer.clone(),
+        firewall_applied: None,
+        metrics_port: args.metrics_listen.as_deref().and_then(firewall::exposed_port),
+        controller: controller::ClashController::new(&args.config_path),
+        user_tracker: controller::UserTracker::new(),
+        engine: std::sync::Arc::new(engine::EngineCell::new(initial_engine)),
+        xray_config_path: args.xray_config_path.clone(),
     };
 
     // Initialize HTTP Client
@@ -91,6 +157,7 @@ async fn main() -> anyhow::Result<()> {
             error!("⚠️ Failed to fetch initial config: {}. Will retry in mainloop.", e);
         }
     }
+    sync_firewall(&client, &panel_url, &token, args.ssh_port, &mut state).await;
     
     // 5. Start Decoy Service (Background)
     let decoy_svc = decoy_service::DecoyService::new(panel_url.clone(), token.clone());
@@ -98,27 +165,67 @@ async fn main() -> anyhow::Result<()> {
         decoy_svc.run_loop().await;
     });
 
-    // 6. Main Loop
+    // 6. Start Log Tail & Shipper (Background)
+    tokio::spawn(log_shipper::tail_singbox(log_buffer.clone(), state.user_tracker.clone()));
+    tokio::spawn(log_shipper::run_shipper(log_buffer.clone(), client.clone(), panel_url.clone(), token.clone()));
+
+    // 7. Start Connection Reporter (panel never reaches the controller directly)
+    tokio::spawn(controller::run_reporter(state.controller.clone(), state.user_tracker.clone(), state.engine.clone(), client.clone(), panel_url.clone(), token.clone()));
+
+    // 8. Start Metrics Listener (optional)
+    if let Some(listen) = args.metrics_listen.clone() {
+        let telemetry = state.telemetry.clone();
+        let controller = state.controller.clone();
+        tokio::spawn(async move {
+            metrics::serve(listen, telemetry, controller).await;
+        });
+    }
+
+    // 9. Main Loop
     let mut failures = 0;
     
     let start_time = std::time::Instant::now();
 
     loop {
         let uptime = start_time.elapsed().as_secs();
-        
+
+        // Collect a finished sing-box upgrade so this heartbeat reports its outcome
+        if state.singbox_upgrade.as_ref().is_some_and(|u| u.is_finished())
+            && let Some(upgrade) = state.singbox_upgrade.take() {
+            let version = upgrade.version.clone();
+            match upgrade.result().await {
+                Ok(()) => {
+                    state.singbox_update_error = None;
+                    state.singbox_failed_target = None;
+                }
+                Err(e) => {
+                    error!("❌ sing-box upgrade failed: {}", e);
+                    state.singbox_update_error = Some(e.to_string());
+                    state.singbox_failed_target = Some(version);
+                }
+            }
+            state.singbox_progress.clear();
+        }
+
         // Send Heartbeat
         match send_heartbeat(&client, &panel_url, &token, uptime, &state).await {
             Ok(resp) => {
                 failures = 0;
+                if let Ok(mut t) = state.telemetry.lock() {
+                    t.heartbeats_ok += 1;
+                }
                 state.last_successful_contact = std::time::Instant::now(); // Update contact time
                 
                 // If we were stopped by kill switch, revive!
                 if state.vpn_stopped_by_kill_switch {
                     info!("✅ Connection restored! Reviving VPN service...");
-                    if let Err(e) = restart_singbox() {
+                    if let Err(e) = state.engine.get().restart() {
                         error!("Failed to revive VPN: {}", e);
                     } else {
                         state.vpn_stopped_by_kill_switch = false;
+                        if let Ok(mut t) = state.telemetry.lock() {
+                            t.vpn_stopped_by_kill_switch = false;
+                        }
                     }
                 }
                 info!("💓 Heartbeat OK. Action: {:?}", resp.action);
@@ -133,6 +240,16 @@ async fn main() -> anyhow::Result<()> {
                     },
                     _ => {}
                 }
+                // Start a sing-box Upgrade in the background (skip a version that already failed, until the panel changes target)
+                if let Some(target) = resp.singbox_target
+                    && state.singbox_upgrade.is_none()
+                    && state.engine.get() == engine::NodeEngine::Singbox
+                    && state.singbox_failed_target.as_deref() != Some(target.version.as_str()) {
+                    state.singbox_upgrade = Some(singbox_manager::Upgrade::spawn(
+                        client.clone(), target, args.config_path.clone(), state.singbox_progress.clone(),
+                    ));
+                }
+
                 // Check for Agent Update
                 if let Some(target_ver) = resp.latest_version {
                     // Simple string comparison for now, or use semver crate if added
@@ -163,6 +280,9 @@ async fn main() -> anyhow::Result<()> {
             }
             Err(e) => {
                 failures += 1;
+                if let Ok(mut t) = state.telemetry.lock() {
+                    t.heartbeats_failed += 1;
+                }
                 error!("❌ Heartbeat failed ({}/10): {}", failures, e);
                 if failures >= 10 {
                     warn!("⚠️ Too many failures, backing off...");
@@ -182,8 +302,12 @@ async fn main() -> anyhow::Result<()> {
                 error!("Failed to fetch settings: {}", e);
             }
 
+            // Host Firewall (also re-adds anything removed by hand)
+            state.firewall_applied = None;
+            sync_firewall(&client, &panel_url, &token, args.ssh_port, &mut state).await;
+
             // SNI Health Check
-            if let Some(current_sni) = sni_check::get_current_sni(&args.config_path).await {
+            if let Some(current_sni) = sni_check::get_current_sni(state.active_config_path(&args.config_path)).await {
                 if !sni_check::check_reachability(&current_sni).await {
                     error!("⚠️ SNI {} is unreachable! Triggering rotation...", current_sni);
                     match rotate_sni(&client, &panel_url, &token, &current_sni).await {
@@ -206,10 +330,14 @@ async fn main() -> anyhow::Result<()> {
                 warn!("⚠️ EMERGENCY KILL SWITCH TRIGGERED! Lost connection for {}s (Timeout: {}s)", 
                     state.last_successful_contact.elapsed().as_secs(), state.kill_switch_timeout);
                 
-                if let Err(e) = stop_singbox() {
+                warn!("🛑 Stopping {} (Kill Switch Triggered)", state.engine.get().service());
+                if let Err(e) = state.engine.get().stop() {
                     error!("❌ FAILED TO STOP VPN SERVICE: {}", e);
                 } else {
                     state.vpn_stopped_by_kill_switch = true;
+                    if let Ok(mut t) = state.telemetry.lock() {
+                        t.vpn_stopped_by_kill_switch = true;
+                    }
                     warn!("💀 VPN Service has been terminated.");
                 }
             }
@@ -218,14 +346,39 @@ async fn main() -> anyhow::Result<()> {
         // Long poll (replaces sleep(10))
         // This effectively makes the heartbeat interval ~30s (timeout) unless update occurs
         match poll_events(&client, &panel_url, &token).await {
-            Ok(should_update) => {
-                if should_update {
+            Ok(Some(event)) => match event.as_str() {
+                "logs_request" => {
+                    state.logs.request_stream();
+                }
+                "settings_update" => {
+                    if let Err(e) = fetch_global_settings(&client, &panel_url, &token, &mut state).await {
+                        error!("Failed to fetch settings: {}", e);
+                    }
+                    sync_firewall(&client, &panel_url, &token, args.ssh_port, &mut state).await;
+                }
+                "firewall_update" => {
+                    sync_firewall(&client, &panel_url, &token, args.ssh_port, &mut state).await;
+                }
+                ev if ev.starts_with("kill_user:") => {
+                    let user = ev.trim_start_matches("kill_user:");
+                    let closed = match state.engine.get() {
+                        engine::NodeEngine::Xray => xray_stats::kick_user(&state.xray_config_path, user).await,
+                        engine::NodeEngine::Singbox => controller::close_user(&state.controller, &state.user_tracker, user).await,
+                    };
+                    if let Err(e) = closed {
+                        error!("Failed to close connections of user {}: {}", user, e);
+                    }
+                }
+                _ => {
                     info!("⚡ Instant Update Received!");
                     if let Err(e) = update_config(&client, &panel_url, &token, &args.config_path, &mut state).await {
                         error!("Failed to update config: {}", e);
                     }
+                    // Inbound ports may have changed
+                    sync_firewall(&client, &panel_url, &token, args.ssh_port, &mut state).await;
                 }
             },
+            Ok(None) => {},
             Err(e) => {
                 warn!("Long poll failed or timed out locally: {}. Backing off 5s.", e);
                 tokio::time::sleep(Duration::from_secs(5)).await;
@@ -234,11 +387,22 @@ async fn main() -> anyhow::Result<()> {
     }
 }
 
+impl AgentState {
+    /// Config file of the core that is running now
+    fn active_config_path<'a>(&'a self, singbox_path: &'a str) -> &'a str {
+        match self.engine.get() {
+            engine::NodeEngine::Singbox => singbox_path,
+            engine::NodeEngine::Xray => &self.xray_config_path,
+        }
+    }
+}
+
+/// Returns the event ("update", "settings_update", "logs_request", "firewall_update", "kill_user:<name>") if one arrived
 async fn poll_events(
     client: &reqwest::Client,
     panel_url: &str,
     token: &str,
-) -> anyhow::Result<bool> {
+) -> anyhow::Result<Option<String>> {
     let url = format!("{}/api/v2/node/updates/poll", panel_url);
     let resp = client.get(&url)
         .header("Authorization", format!("Bearer {}", token))
@@ -247,11 +411,36 @@ async fn poll_events(
         .await?;
 
     if !resp.status().is_success() {
-        return Ok(false);
+        return Ok(None);
     }
 
     let json: serde_json::Value = resp.json().await?;
-    Ok(json.get("update").and_then(|v| v.as_bool()).unwrap_or(false))
+    if !json.get("update").and_then(|v| v.as_bool()).unwrap_or(false) {
+        return Ok(None);
+    }
+    // Older panels only send {"update": true}
+    Ok(Some(json.get("event").and_then(|v| v.as_str()).unwrap_or("update").to_string()))
+}
+
+/// Fetch the desired firewall state from the panel and apply it if it changed
+async fn sync_firewall(
+    client: &reqwest::Client,
+    panel_url: &str,
+    token: &str,
+    ssh_port: u16,
+    state: &mut AgentState,
+) {
+    let desired = match firewall::fetch_state(client, panel_url, token).await {
+        Ok(d) => d,
+        Err(e) => {
+            error!("Failed to fetch firewall state: {}", e);
+            return;
+        }
+    };
+    match firewall::reconcile(&desired, state.firewall_applied.as_ref(), panel_url, ssh_port, state.metrics_port).await {
+        Ok(_) => state.firewall_applied = Some(desired),
+        Err(e) => error!("❌ Failed to apply firewall: {}", e),
+    }
 }
 
 async fn rotate_sni(
@@ -295,6 +484,12 @@ async fn send_heartbeat(
     
     // Collect Telemetry
     let (latency, cpu, ram) = collect_telemetry(client).await;
+    if let Ok(mut t) = state.telemetry.lock() {
+        t.uptime = uptime;
+        t.latency_ms = latency;
+        t.cpu_load = cpu;
+        t.memory_usage = ram;
+    }
 
     let payload = HeartbeatRequest {
         version: "0.2.0".to_string(),
@@ -307,6 +502,7 @@ async fn send_heartbeat(
         latency,
         cpu_usage: cpu,
         memory_usage: ram,
+        singbox: singbox_manager::detect(state.singbox_update_error.clone(), state.singbox_progress.get()),
     };
     
     let resp = client.post(&url)
@@ -348,12 +544,30 @@ async fn check_and_update_config(
             state.current_hash.as_deref().unwrap_or("none"), 
             &config_resp.hash);
         
+        let engine = engine::NodeEngine::parse(&config_resp.engine);
+        let path = match engine {
+            engine::NodeEngine::Singbox => config_path,
+            engine::NodeEngine::Xray => state.xray_config_path.as_str(),
+        };
+
         // Save new config
-        save_config(config_path, &config_resp.content).await?;
+        save_config(path, &config_resp.content).await?;
         state.current_hash = Some(config_resp.hash);
-        
-        // Restart sing-box
-        restart_singbox()?;
+
+        // Both cores bind the same ports; the old one must go before the new one starts
+        let previous = state.engine.get();
+        if previous != engine {
+            info!("🔀 Switching engine {} -> {}", previous.service(), engine.service());
+            if let Err(e) = previous.stop() {
+                warn!("Failed to stop {}: {}", previous.service(), e);
+            }
+            state.engine.set(engine);
+        }
+
+        engine.restart()?;
+        if let Ok(mut t) = state.telemetry.lock() {
+            t.config_updates += 1;
+        }
         
         info!("✅ Config updated and service restarted");
     } else {
@@ -401,22 +615,6 @@ async fn save_config(path: &str, content: &serde_json::Value) -> anyhow::Result<
     Ok(())
 }
 
-fn restart_singbox() -> anyhow::Result<()> {
-    info!("🔄 Restarting sing-box service...");
-    
-    let output = std::process::Command::new("systemctl")
-        .args(&["restart", "sing-box"])
-        .output()?;
-    
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        anyhow::bail!("systemctl restart failed: {}", stderr);
-    }
-    
-    info!("✅ Service restarted");
-    Ok(())
-}
-
 async fn check_certificates(config_path: &str) -> Vec<exarobot_shared::api::CertificateStatus> {
     let mut statuses = Vec::new();
     let cert_dir = Path::new(config_path).parent().unwrap_or(Path::new("/etc/sing-box")).join("certs");
@@ -487,18 +685,6 @@ async fn check_certificates(config_path: &str) -> Vec<exarobot_shared::api::Cert
     statuses
 }
 
-// Helper to stop sing-box
-fn stop_singbox() -> anyhow::Result<()> {
-    info!("🛑 Stopping sing-box service (Kill Switch Triggered)...");
-    let output = std::process::Command::new("systemctl")
-        .args(&["stop", "sing-box"])
-        .output()?;
-    if !output.status.success() {
-        anyhow::bail!("systemctl stop failed");
-    }
-    Ok(())
-}
-
 async fn fetch_global_settings(
     client: &reqwest::Client,
     panel_url: &str,
@@ -514,6 +700,9 @@ async fn fetch_global_settings(
             state.kill_switch_enabled = ks.get("enabled").and_then(|v| v.as_bool()).unwrap_or(false);
             state.kill_switch_timeout = ks.get("timeout").and_then(|v| v.as_u64()).unwrap_or(300);
         }
+        if let Some(logs) = json.get("logs") {
+            state.logs.set_redact_ips(logs.get("redact_ips").and_then(|v| v.as_bool()).unwrap_or(true));
+        }
     }
     Ok(())
 }
@@ -0,0 +1,150 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tracing::{info, error, debug};
+use crate::controller::ClashController;
+
+/// Values the main loop updates after every heartbeat.
+#[derive(Debug, Default, Clone)]
+pub struct AgentTelemetry {
+    pub uptime: u64,
+    pub latency_ms: Option<f64>,
+    pub cpu_load: Option<f64>,
+    pub memory_usage: Option<f64>,
+    pub heartbeats_ok: u64,
+    pub heartbeats_failed: u64,
+    pub config_updates: u64,
+    pub vpn_stopped_by_kill_switch: bool,
+}
+
+pub type SharedTelemetry = Arc<Mutex<AgentTelemetry>>;
+
+/// Minimal local Prometheus listener (GET /metrics only).
+/// Meant to be bound to localhost or a private interface for node_exporter-style scraping.
+pub async fn serve(listen: String, telemetry: SharedTelemetry, controller: Arc<ClashController>) {
+    let listener = match TcpListener::bind(&listen).await {
+        Ok(l) => l,
+        Err(e) => {
+            error!("❌ Failed to bind metrics listener on {}: {}", listen, e);
+            return;
+        }
+    };
+    info!("📈 Metrics listener started on {}", listen);
+
+    loop {
+        let (mut socket, _) = match listener.accept().await {
+            Ok(s) => s,
+            Err(e) => {
+                error!("Metrics accept failed: {}", e);
+                continue;
+            }
+        };
+
+        let telemetry = telemetry.clone();
+        let controller = controller.clone();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 1024];
+            let n = match socket.read(&mut buf).await {
+                Ok(n) => n,
+                Err(_) => return,
+            };
+            let request = String::from_utf8_lossy(&buf[..n]);
+
+            let response = if request.starts_with("GET /metrics") {
+                let snapshot = telemetry.lock().map(|t| t.clone()).unwrap_or_default();
+                let body = render(&controller, &snapshot).await;
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(), body
+                )
+            } else {
+                "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+            };
+
+            let _ = socket.write_all(response.as_bytes()).await;
+        });
+    }
+}
+
+async fn render(controller: &ClashController, t: &AgentTelemetry) -> String {
+    let mut out = String::new();
+
+    gauge(&mut out, "exarobot_agent_uptime_seconds", "Agent uptime", t.uptime as f64);
+    if let Some(v) = t.latency_ms {
+        gauge(&mut out, "exarobot_agent_latency_ms", "Outbound HTTP latency probe", v);
+    }
+    if let Some(v) = t.cpu_load {
+        gauge(&mut out, "exarobot_agent_load1", "1-minute load average", v);
+    }
+    if let Some(v) = t.memory_usage {
+        gauge(&mut out, "exarobot_agent_memory_usage_percent", "Used memory percentage", v);
+    }
+    counter(&mut out, "exarobot_agent_heartbeats_ok_total", "Successful heartbeats", t.heartbeats_ok);
+    counter(&mut out, "exarobot_agent_heartbeats_failed_total", "Failed heartbeats", t.heartbeats_failed);
+    counter(&mut out, "exarobot_agent_config_updates_total", "Applied config updates", t.config_updates);
+    gauge(&mut out, "exarobot_agent_kill_switch_active", "1 if the VPN was stopped by the kill switch", if t.vpn_stopped_by_kill_switch { 1.0 } else { 0.0 });
+
+    match controller.connections().await {
+        Ok(conns) => render_connections(&mut out, &conns),
+        Err(e) => {
+            debug!("Clash API unavailable for metrics: {}", e);
+            gauge(&mut out, "exarobot_agent_singbox_api_up", "1 if the sing-box Clash API answered", 0.0);
+        }
+    }
+
+    out
+}
+
+fn render_connections(out: &mut String, conns: &serde_json::Value) {
+    gauge(out, "exarobot_agent_singbox_api_up", "1 if the sing-box Clash API answered", 1.0);
+
+    let upload_total = conns["uploadTotal"].as_u64().unwrap_or(0);
+    let download_total = conns["downloadTotal"].as_u64().unwrap_or(0);
+    let _ = writeln!(out, "# HELP exarobot_agent_traffic_bytes_total Traffic through sing-box since its start");
+    let _ = writeln!(out, "# TYPE exarobot_agent_traffic_bytes_total counter");
+    let _ = writeln!(out, "exarobot_agent_traffic_bytes_total{{direction=\"up\"}} {}", upload_total);
+    let _ = writeln!(out, "exarobot_agent_traffic_bytes_total{{direction=\"down\"}} {}", download_total);
+
+    // sing-box reports metadata.type as "<inbound type>/<inbound tag>"
+    let mut per_inbound: BTreeMap<String, (u64, u64, u64)> = BTreeMap::new();
+    if let Some(list) = conns["connections"].as_array() {
+        for c in list {
+            let inbound = c["metadata"]["type"].as_str().unwrap_or("unknown").to_string();
+            let entry = per_inbound.entry(inbound).or_default();
+            entry.0 += 1;
+            entry.1 += c["upload"].as_u64().unwrap_or(0);
+            entry.2 += c["download"].as_u64().unwrap_or(0);
+        }
+    }
+
+    let _ = writeln!(out, "# HELP exarobot_agent_inbound_connections Active connections per inbound");
+    let _ = writeln!(out, "# TYPE exarobot_agent_inbound_connections gauge");
+    for (inbound, (count, _, _)) in &per_inbound {
+        let _ = writeln!(out, "exarobot_agent_inbound_connections{{inbound=\"{}\"}} {}", escape_label(inbound), count);
+    }
+    let _ = writeln!(out, "# HELP exarobot_agent_inbound_bytes Traffic of currently active connections per inbound");
+    let _ = writeln!(out, "# TYPE exarobot_agent_inbound_bytes gauge");
+    for (inbound, (_, up, down)) in &per_inbound {
+        let label = escape_label(inbound);
+        let _ = writeln!(out, "exarobot_agent_inbound_bytes{{inbound=\"{}\",direction=\"up\"}} {}", label, up);
+        let _ = writeln!(out, "exarobot_agent_inbound_bytes{{inbound=\"{}\",direction=\"down\"}} {}", label, down);
+    }
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: f64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} gauge", name);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+fn escape_label(v: &str) -> String {
+    v.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -0,0 +1,230 @@
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{info, error, warn};
+use sha2::{Sha256, Digest};
+use exarobot_shared::api::{SingboxInfo, SingboxTarget};
+use crate::engine::EngineService;
+
+/// Inspect the installed sing-box binary (`sing-box version`).
+pub fn detect(update_error: Option<String>, upgrade_status: Option<String>) -> Option<SingboxInfo> {
+    let output = Command::new("sing-box").arg("version").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let (version, capabilities) = parse_version_output(&String::from_utf8_lossy(&output.stdout))?;
+
+    Some(SingboxInfo {
+        version,
+        capabilities,
+        arch: release_arch().to_string(),
+        update_error,
+        upgrade_status,
+    })
+}
+
+/// Output looks like:
+/// ```text
+/// sing-box version 1.10.7
+///
+/// Environment: go1.23.4 linux/amd64
+/// Tags: with_gvisor,with_quic,with_dhcp,with_wireguard,with_utls,with_reality_server,with_clash_api
+/// ```
+fn parse_version_output(stdout: &str) -> Option<(String, Vec<String>)> {
+    let version = stdout.lines()
+        .find_map(|l| l.strip_prefix("sing-box version "))?
+        .trim()
+        .to_string();
+    let capabilities = stdout.lines()
+        .find_map(|l| l.strip_prefix("Tags: "))
+        .map(|t| t.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect())
+        .unwrap_or_default();
+    Some((version, capabilities))
+}
+
+/// Architecture in sing-box release asset naming
+fn release_arch() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        "x86" => "386",
+        "arm" => "armv7",
+        other => other,
+    }
+}
+
+/// Stage of the upgrade running in the background, reported with every heartbeat
+#[derive(Clone, Default)]
+pub struct UpgradeProgress(Arc<Mutex<Option<String>>>);
+
+impl UpgradeProgress {
+    fn set(&self, version: &str, stage: &str) {
+        if let Ok(mut status) = self.0.lock() {
+            *status = Some(format!("{}: {}", version, stage));
+        }
+    }
+
+    pub fn get(&self) -> Option<String> {
+        self.0.lock().ok().and_then(|status| status.clone())
+    }
+
+    pub fn clear(&self) {
+        if let Ok(mut status) = self.0.lock() {
+            *status = None;
+        }
+    }
+}
+
+/// An upgrade running in the background, so heartbeats keep going while it downloads
+pub struct Upgrade {
+    pub version: String,
+    handle: tokio::task::JoinHandle<anyhow::Result<()>>,
+}
+
+impl Upgrade {
+    pub fn spawn(client: reqwest::Client, target: SingboxTarget, config_path: String, progress: UpgradeProgress) -> Self {
+        let version = target.version.clone();
+        let handle = tokio::spawn(async move {
+            perform_upgrade(&client, &target, &config_path, &progress).await
+        });
+        Self { version, handle }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+
+    pub async fn result(self) -> anyhow::Result<()> {
+        self.handle.await.unwrap_or_else(|e| Err(anyhow::anyhow!("Upgrade task failed: {}", e)))
+    }
+}
+
+/// Download, verify and swap the sing-box binary. Rolls back if the service fails to come up.
+/// Refuses targets without a pinned checksum: the binary runs as root.
+async fn perform_upgrade(
+    client: &reqwest::Client,
+    target: &SingboxTarget,
+    config_path: &str,
+    progress: &UpgradeProgress,
+) -> anyhow::Result<()> {
+    let Some(expected) = target.sha256.as_deref().map(str::trim).filter(|h| !h.is_empty()) else {
+        anyhow::bail!("No SHA-256 pinned for sing-box {}, refusing to install an unverified binary", target.version);
+    };
+    info!("📦 Upgrading sing-box to {} from {}", target.version, target.url);
+
+    // 1. Download Release Archive
+    progress.set(&target.version, "downloading");
+    let response = client.get(&target.url)
+        .timeout(Duration::from_secs(300))
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        anyhow::bail!("Failed to download sing-box: {}", response.status());
+    }
+    let bytes = response.bytes().await?;
+
+    // 2. Verify Checksum
+    progress.set(&target.version, "verifying");
+    let calculated = format!("{:x}", Sha256::digest(&bytes));
+    if !calculated.eq_ignore_ascii_case(expected) {
+        anyhow::bail!("Checksum mismatch! Expected {}, got {}", expected, calculated);
+    }
+    info!("✅ Checksum verified: {}", expected);
+
+    // 3. Extract
+    let work_dir = std::env::temp_dir().join(format!("sing-box-{}", target.version));
+    let _ = std::fs::remove_dir_all(&work_dir);
+    std::fs::create_dir_all(&work_dir)?;
+    let archive = work_dir.join("sing-box.tar.gz");
+    std::fs::write(&archive, &bytes)?;
+
+    let out = Command::new("tar")
+        .arg("-xzf").arg(&archive)
+        .arg("-C").arg(&work_dir)
+        .output()?;
+    if !out.status.success() {
+        anyhow::bail!("Failed to extract archive: {}", String::from_utf8_lossy(&out.stderr));
+    }
+    let new_bin = find_binary(&work_dir)
+        .ok_or_else(|| anyhow::anyhow!("sing-box binary not found in archive"))?;
+    std::fs::set_permissions(&new_bin, std::fs::Permissions::from_mode(0o755))?;
+
+    // 4. Verify New Binary (version + current config still valid)
+    let out = Command::new(&new_bin).arg("version").output()?;
+    let reported = parse_version_output(&String::from_utf8_lossy(&out.stdout)).map(|(v, _)| v);
+    if reported.as_deref() != Some(target.version.as_str()) {
+        anyhow::bail!("Downloaded binary reports version {:?}, expected {}", reported, target.version);
+    }
+    if Path::new(config_path).exists() {
+        let out = Command::new(&new_bin).args(["check", "-c", config_path]).output()?;
+        if !out.status.success() {
+            anyhow::bail!("Current config rejected by sing-box {}: {}", target.version, String::from_utf8_lossy(&out.stderr));
+        }
+    }
+
+    // 5. Swap Binaries
+    progress.set(&target.version, "installing");
+    let current_bin = installed_path()?;
+    let backup_bin = current_bin.with_extension("bak");
+    let staged_bin = current_bin.with_extension("new");
+
+    std::fs::copy(&current_bin, &backup_bin)?;
+    std::fs::copy(&new_bin, &staged_bin)?;
+    std::fs::rename(&staged_bin, &current_bin)?;
+    let _ = std::fs::remove_dir_all(&work_dir);
+
+    // 6. Restart & Health Check
+    progress.set(&target.version, "restarting");
+    let healthy = crate::engine::NodeEngine::Singbox.restart().is_ok() && {
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        is_service_active()
+    };
+
+    if !healthy {
+        error!("❌ sing-box {} failed to start. Rolling back...", target.version);
+        std::fs::rename(&backup_bin, &current_bin)?;
+        if let Err(e) = crate::engine::NodeEngine::Singbox.restart() {
+            error!("❌ Rollback restart failed: {}", e);
+        }
+        anyhow::bail!("sing-box {} failed health check, rolled back", target.version);
+    }
+
+    info!("✅ sing-box upgraded to {}", target.version);
+    Ok(())
+}
+
+fn find_binary(dir: &Path) -> Option<PathBuf> {
+    // Release archives contain sing-box-<ver>-linux-<arch>/sing-box
+    for entry in std::fs::read_dir(dir).ok()?.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            if let Some(found) = find_binary(&path) {
+                return Some(found);
+            }
+        } else if path.file_name().is_some_and(|n| n == "sing-box") {
+            return Some(path);
+        }
+    }
+    None
+}
+
+fn installed_path() -> anyhow::Result<PathBuf> {
+    let out = Command::new("which").arg("sing-box").output()?;
+    let path = String::from_utf8_lossy(&out.stdout).trim().to_string();
+    if !out.status.success() || path.is_empty() {
+        warn!("sing-box not found in PATH, assuming /usr/bin/sing-box");
+        return Ok(PathBuf::from("/usr/bin/sing-box"));
+    }
+    // Resolve symlinks so we replace the real file
+    Ok(std::fs::canonicalize(&path)?)
+}
+
+fn is_service_active() -> bool {
+    Command::new("systemctl")
+        .args(["is-active", "--quiet", "sing-box"])
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false)
+}
@@ -17,6 +17,10 @@ pub async fn get_current_sni(config_path: &str) -> Option<String> {
                     return Some(server_name.to_string());
                 }
             }
+            // Xray keeps it under streamSettings.realitySettings.serverNames
+            if let Some(server_name) = inbound.pointer("/streamSettings/realitySettings/serverNames/0").and_then(|v| v.as_str()) {
+                return Some(server_name.to_string());
+            }
         }
     }
     
@@ -0,0 +1,118 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tracing::info;
+use exarobot_shared::api::{ConnectionSnapshot, UserConnections};
+use crate::controller::Counter;
+
+/// Xray API endpoint the panel puts into generated configs
+const XRAY_API: &str = "127.0.0.1:10085";
+
+async fn xray_cli(args: &[&str]) -> anyhow::Result<String> {
+    let server = format!("--server={}", XRAY_API);
+    let output = tokio::time::timeout(
+        Duration::from_secs(10),
+        tokio::process::Command::new("xray").arg("api").args(args).arg(&server).output(),
+    ).await??;
+    if !output.status.success() {
+        anyhow::bail!("xray api {} failed: {}", args.first().unwrap_or(&""), String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+async fn xray_api(args: &[&str]) -> anyhow::Result<serde_json::Value> {
+    let stdout = xray_cli(args).await?;
+    if stdout.trim().is_empty() {
+        return Ok(serde_json::Value::Null);
+    }
+    Ok(serde_json::from_str(&stdout)?)
+}
+
+/// Drops a user's sessions. Xray can't close single connections, so the user is removed from
+/// every inbound through HandlerService and added back from the running config.
+/// Returns the number of inbounds the user was cycled on.
+pub async fn kick_user(config_path: &str, user: &str) -> anyhow::Result<usize> {
+    let config: serde_json::Value = serde_json::from_str(&tokio::fs::read_to_string(config_path).await?)?;
+
+    let mut cycled = 0;
+    for inbound in user_inbounds(&config, user) {
+        let tag = inbound["tag"].as_str().unwrap_or_default();
+        xray_cli(&["rmu", &format!("-tag={}", tag), user]).await?;
+
+        // `adu` reads the users to add from an inbound config with the same tag
+        let path = std::env::temp_dir().join(format!("exarobot-adu-{}.json", std::process::id()));
+        tokio::fs::write(&path, serde_json::to_vec(&serde_json::json!({ "inbounds": [inbound] }))?).await?;
+        let added = xray_cli(&["adu", &path.to_string_lossy()]).await;
+        let _ = tokio::fs::remove_file(&path).await;
+        added?;
+        cycled += 1;
+    }
+    info!("🔌 Re-added user {} on {} Xray inbound(s)", user, cycled);
+    Ok(cycled)
+}
+
+/// The config's inbounds that have `user` as a client, trimmed to that one client
+fn user_inbounds(config: &serde_json::Value, user: &str) -> Vec<serde_json::Value> {
+    config["inbounds"].as_array().into_iter().flatten().filter_map(|inbound| {
+        let client = inbound["settings"]["clients"].as_array()?
+            .iter()
+            .find(|c| c["email"].as_str() == Some(user))?
+            .clone();
+        let mut inbound = inbound.clone();
+        inbound["settings"]["clients"] = serde_json::json!([client]);
+        Some(inbound)
+    }).collect()
+}
+
+/// Per-user traffic since Xray started and the users' online IPs, in the same shape
+/// the sing-box reporter sends. Xray has no per-connection view, so `connections`
+/// counts distinct online IPs.
+pub async fn snapshot() -> anyhow::Result<(ConnectionSnapshot, Vec<Counter>)> {
+    let mut per_user: BTreeMap<String, UserConnections> = BTreeMap::new();
+
+    // Stat names look like "user>>>123456>>>traffic>>>uplink"
+    let stats = xray_api(&["statsquery", "-pattern", "user>>>"]).await?;
+    for stat in stats["stat"].as_array().into_iter().flatten() {
+        let name = stat["name"].as_str().unwrap_or("");
+        let parts: Vec<&str> = name.split(">>>").collect();
+        let [_, user, "traffic", direction] = parts.as_slice() else { continue };
+        // Values are int64 and may come back as strings
+        let value = stat["value"].as_u64()
+            .or_else(|| stat["value"].as_str().and_then(|v| v.parse().ok()))
+            .unwrap_or(0);
+
+        let entry = per_user.entry(user.to_string()).or_insert_with(|| empty(user));
+        match *direction {
+            "uplink" => entry.upload += value,
+            "downlink" => entry.download += value,
+            _ => {}
+        }
+    }
+
+    let online = xray_api(&["statsgetallonlineusers"]).await?;
+    for name in online["users"].as_array().into_iter().flatten().filter_map(|v| v.as_str()) {
+        let Some(user) = name.split(">>>").nth(1) else { continue };
+        let ips = match xray_api(&["statsonlineiplist", "-email", user]).await {
+            Ok(v) => v["ips"].as_object().map(|m| m.keys().cloned().collect::<Vec<_>>()).unwrap_or_default(),
+            Err(_) => continue,
+        };
+        let entry = per_user.entry(user.to_string()).or_insert_with(|| empty(user));
+        entry.connections = ips.len() as u32;
+        entry.ips = ips;
+    }
+
+    // Idle users still count for traffic
+    let counters = per_user.values()
+        .map(|u| Counter { key: u.user.clone(), user: u.user.clone(), upload: u.upload, download: u.download })
+        .collect();
+
+    // Users without live IPs are idle; traffic counters alone don't make a connection
+    let snapshot = ConnectionSnapshot {
+        users: per_user.into_values().filter(|u| !u.ips.is_empty()).collect(),
+        traffic: Vec::new(),
+    };
+    Ok((snapshot, counters))
+}
+
+fn empty(user: &str) -> UserConnections {
+    UserConnections { user: user.to_string(), ips: Vec::new(), connections: 0, upload: 0, download: 0 }
+}
@@ -1,26 +1,44 @@
 use axum::{
-    extract::{Path, Query, State},
-    http::{StatusCode, header},
-    response::{IntoResponse, Response},
+    extract::{ConnectInfo, Path, Query, State},
+    http::{StatusCode, header, HeaderMap, HeaderValue},
+    response::{Html, IntoResponse, Response},
 };
+use exarobot_shared::client_detect::{self, SubFormat};
 use serde::Deserialize;
+use std::net::SocketAddr;
 use tracing::{info, error};
 use crate::AppState;
 
 #[derive(Deserialize)]
 pub struct SubParams {
-    pub client: Option<String>, // "clash" | "v2ray" | "singbox"
+    pub client: Option<String>, // "clash" | "v2ray" | "singbox" | "html", overrides User-Agent detection
 }
 
 pub async fn subscription_handler(
     Path(uuid): Path<String>,
     Query(params): Query<SubParams>,
     State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request_headers: HeaderMap,
 ) -> Response {
-    info!("Subscription request: UUID={}, client={:?}", uuid, params.client);
+    let user_agent = request_headers.get(header::USER_AGENT).and_then(|v| v.to_str().ok());
+    let profile = client_detect::negotiate(params.client.as_deref(), user_agent);
+    info!("Subscription request: UUID={}, client={:?} -> {}", uuid, params.client, profile.name);
+
+    // Browsers get the panel's landing page, with links pointing at this frontend
+    if profile.format == SubFormat::Landing {
+        let host = request_headers.get(header::HOST).and_then(|v| v.to_str().ok());
+        return match state.panel_client.get_subscription_page(&uuid, host).await {
+            Ok(page) => Html(page).into_response(),
+            Err(e) => {
+                error!("Failed to fetch subscription page: {}", e);
+                (StatusCode::NOT_FOUND, "Subscription not found").into_response()
+            }
+        };
+    }
     
     // 1. Get subscription from main panel
-    let sub = match state.panel_client.get_subscription(&uuid).await {
+    let sub = match state.panel_client.get_subscription(&uuid, params.client.as_deref(), peer.ip(), user_agent).await {
         Ok(s) => s,
         Err(e) => {
             error!("Failed to fetch subscription: {}", e);
@@ -28,7 +46,18 @@ pub async fn subscription_handler(
         }
     };
     
-    // 2. Check if active
+    // 2. Check if active. Limited subscriptions still get the landing page above; their
+    // configs are withheld until a traffic pack is added.
+    if sub.status == "limited" {
+        info!("Subscription {} is out of traffic, withholding configs", uuid);
+        let mut headers = HeaderMap::new();
+        for (name, value) in sub.headers.as_ref().map(|h| h.header_pairs()).unwrap_or_default() {
+            if let Ok(v) = HeaderValue::from_str(&value) {
+                headers.insert(name, v);
+            }
+        }
+        return (StatusCode::FORBIDDEN, headers, "Traffic quota exhausted, add a traffic pack to continue").into_response();
+    }
     if sub.status != "active" {
         info!("Subscription {} is inactive (status: {})", uuid, sub.status);
         return (StatusCode::FORBIDDEN, "Subscription inactive or expired").into_response();
@@ -52,49 +81,37 @@ pub async fn subscription_handler(
         }
     };
     
-    // 5. Generate config based on client type
-    let client_type = params.client.as_deref().unwrap_or("singbox");
-    let (content, content_type, filename) = match client_type {
-        "clash" => {
-            match generate_clash_config(&nodes, &user_keys) {
-                Ok(yaml) => (yaml, "application/yaml", "config.yaml"),
-                Err(e) => {
-                    error!("Failed to generate Clash config: {}", e);
-                    return (StatusCode::INTERNAL_SERVER_ERROR, "Config generation failed").into_response();
-                }
-            }
-        }
-        "v2ray" => {
-            match generate_v2ray_config(&nodes, &user_keys) {
-                Ok(b64) => (b64, "text/plain", "config.txt"),
-                Err(e) => {
-                    error!("Failed to generate V2Ray config: {}", e);
-                    return (StatusCode::INTERNAL_SERVER_ERROR, "Config generation failed").into_response();
-                }
-            }
-        }
-        _ => {
-            match generate_singbox_config(&nodes, &user_keys) {
-                Ok(json) => (json, "application/json", "config.json"),
-                Err(e) => {
-                    error!("Failed to generate Sing-box config: {}", e);
-                    return (StatusCode::INTERNAL_SERVER_ERROR, "Config generation failed").into_response();
-                }
-            }
+    // 5. Generate config in the format the client understands (only VLESS is mirrored here)
+    let format = profile.format;
+    let generated = match format {
+        SubFormat::Clash => generate_clash_config(&nodes, &user_keys),
+        SubFormat::Links => generate_v2ray_config(&nodes, &user_keys),
+        _ => generate_singbox_config(&nodes, &user_keys),
+    };
+    let content = match generated {
+        Ok(content) => content,
+        Err(e) => {
+            error!("Failed to generate {:?} config: {}", format, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Config generation failed").into_response();
         }
     };
     
-    info!("Generated {} config for subscription {} ({} bytes)", client_type, uuid, content.len());
-    
-    // 6. Return with proper headers
-    (
-        StatusCode::OK,
-        [
-            (header::CONTENT_TYPE, content_type),
-            (header::CONTENT_DISPOSITION, format!("inline; filename={}", filename).as_str()),
-        ],
-        content
-    ).into_response()
+    info!("Generated {:?} config for {} on subscription {} ({} bytes)", format, profile.name, uuid, content.len());
+    
+    // 6. Return with proper headers (same usage/profile headers as the panel endpoint)
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(format.content_type()));
+    headers.insert(header::VARY, HeaderValue::from_static("User-Agent"));
+    if let Ok(v) = HeaderValue::from_str(&format!("inline; filename={}", format.filename())) {
+        headers.insert(header::CONTENT_DISPOSITION, v);
+    }
+    for (name, value) in sub.headers.as_ref().map(|h| h.header_pairs()).unwrap_or_default() {
+        if let Ok(v) = HeaderValue::from_str(&value) {
+            headers.insert(name, v);
+        }
+    }
+
+    (StatusCode::OK, headers, content).into_response()
 }
 
 // Config generators (simplified versions from main panel)
@@ -56,7 +56,7 @@ async fn main() -> anyhow::Result<()> {
     tracing::info!("Frontend listening on {}", addr);
     
     let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
 
     Ok(())
 }
@@ -72,6 +72,7 @@ impl AppState {
         let panel_client = panel_client::PanelClient::new(
             config.panel_url.clone(),
             config.auth_token.clone(),
+            config.domain.clone(),
         );
         
         Self {
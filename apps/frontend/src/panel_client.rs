@@ -1,31 +1,46 @@
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use anyhow::Result;
+use std::net::IpAddr;
 
 #[derive(Clone)]
 pub struct PanelClient {
     client: Client,
     base_url: String,
     auth_token: String,
+    /// Sent as `X-Frontend-Domain` so the panel can check the token against this frontend
+    domain: String,
 }
 
 impl PanelClient {
-    pub fn new(base_url: String, auth_token: String) -> Self {
+    pub fn new(base_url: String, auth_token: String, domain: String) -> Self {
         Self {
             client: Client::new(),
             base_url,
             auth_token,
+            domain,
         }
     }
     
-    pub async fn get_subscription(&self, uuid: &str) -> Result<Subscription> {
+    /// The panel logs the fetch for sharing detection, so pass along who is asking
+    pub async fn get_subscription(&self, uuid: &str, client: Option<&str>, client_ip: IpAddr, user_agent: Option<&str>) -> Result<Subscription> {
         let url = format!("{}/api/internal/subscriptions/{}", self.base_url, uuid);
         
-        let response = self.client
+        let mut request = self.client
             .get(&url)
             .bearer_auth(&self.auth_token)
+            .header("X-Frontend-Domain", &self.domain)
+            .header("X-Client-IP", client_ip.to_string());
+        if let Some(client) = client {
+            request = request.query(&[("client", client)]);
+        }
+        if let Some(user_agent) = user_agent {
+            request = request.header(reqwest::header::USER_AGENT, user_agent);
+        }
+        let response = request
             .send()
-            .await?;
+            .await?
+            .error_for_status()?;
         
         Ok(response.json().await?)
     }
@@ -54,6 +69,20 @@ impl PanelClient {
         Ok(response.json().await?)
     }
     
+    /// Browser landing page rendered by the panel. `public_host` is this frontend's
+    /// host so the links on the page point here rather than at the panel.
+    pub async fn get_subscription_page(&self, uuid: &str, public_host: Option<&str>) -> Result<String> {
+        let url = format!("{}/sub/{}?client=html", self.base_url, uuid);
+
+        let mut request = self.client.get(&url);
+        if let Some(host) = public_host {
+            request = request.header("X-Forwarded-Host", host);
+        }
+        let response = request.send().await?.error_for_status()?;
+
+        Ok(response.text().await?)
+    }
+
     pub async fn send_heartbeat(&self, stats: FrontendStats) -> Result<()> {
         let url = format!("{}/api/internal/frontend/heartbeat", self.base_url);
         
@@ -76,6 +105,9 @@ pub struct Subscription {
     pub status: String,
     pub used_traffic: i64,
     pub subscription_uuid: String,
+    /// Usage/profile headers computed by the panel
+    #[serde(default)]
+    pub headers: Option<exarobot_shared::subscription::SubscriptionHeaders>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
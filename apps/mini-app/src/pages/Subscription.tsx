@@ -1,17 +1,101 @@
-import React, { useState } from 'react';
+import React, { useEffect, useState } from 'react';
 import { useNavigate } from 'react-router-dom';
 import { QRCodeSVG } from 'qrcode.react'; // Using SVG for better quality
 import { useAuth } from '../context/AuthContext';
 import './Subscription.css';
 
+interface PlanOption {
+    plan_name: string;
+    duration_id: number;
+    days: number;
+    price: number;
+    credit: number;
+    due: number;
+}
+
+const formatCents = (cents: number) => `$${(cents / 100).toFixed(2)}`;
+
 export default function Subscription() {
-    const { subscription, isLoading } = useAuth();
+    const { subscription, isLoading, token } = useAuth();
     const navigate = useNavigate();
     const [copied, setCopied] = useState(false);
+    const [planOptions, setPlanOptions] = useState<PlanOption[]>([]);
+    const [changing, setChanging] = useState(false);
+    const [planMessage, setPlanMessage] = useState<string | null>(null);
+    const [resetUrl, setResetUrl] = useState<string | null>(null);
+    const [resetting, setResetting] = useState(false);
+    const [resetMessage, setResetMessage] = useState<string | null>(null);
+
+    const subscriptionUrl = resetUrl ?? subscription?.subscription_url ?? '';
+
+    useEffect(() => {
+        if (!token || !subscription) return;
+        fetch('/api/client/user/subscription/plans', {
+            headers: { 'Authorization': `Bearer ${token}` }
+        })
+            .then(res => res.ok ? res.json() : null)
+            .then(data => setPlanOptions(data?.options ?? []))
+            .catch(err => console.error("Failed to fetch plan options", err));
+    }, [token, subscription]);
+
+    const handleChangePlan = async (option: PlanOption) => {
+        const cost = option.due >= 0
+            ? `${formatCents(option.due)} will be taken from your balance`
+            : `${formatCents(-option.due)} will be added to your balance`;
+        if (!window.confirm(`Switch to ${option.plan_name} for ${option.days} days? ${cost}.`)) return;
+
+        setChanging(true);
+        setPlanMessage(null);
+        try {
+            const res = await fetch('/api/client/user/subscription/change-plan', {
+                method: 'POST',
+                headers: { 'Authorization': `Bearer ${token}`, 'Content-Type': 'application/json' },
+                body: JSON.stringify({ duration_id: option.duration_id })
+            });
+            if (res.ok) {
+                const data = await res.json();
+                setPlanMessage(`Switched to ${data.plan_name}. Your link stays the same.`);
+                setPlanOptions([]);
+            } else {
+                setPlanMessage(await res.text());
+            }
+        } catch (err) {
+            console.error("Failed to change plan", err);
+            setPlanMessage("Failed to change plan");
+        } finally {
+            setChanging(false);
+        }
+    };
+
+    const handleReset = async () => {
+        if (!window.confirm('Reset your link? The old link and keys stop working and every device has to import the new one.')) return;
+
+        setResetting(true);
+        setResetMessage(null);
+        try {
+            const res = await fetch('/api/client/user/subscription/reset', {
+                method: 'POST',
+                headers: { 'Authorization': `Bearer ${token}`, 'Content-Type': 'application/json' },
+                body: JSON.stringify({})
+            });
+            if (res.ok) {
+                const data = await res.json();
+                setResetUrl(data.subscription_url);
+                setResetMessage('Your link was reset. Import the new one on your devices.');
+            } else {
+                setResetMessage(await res.text());
+            }
+        } catch (err) {
+            console.error("Failed to reset link", err);
+            setResetMessage("Failed to reset link");
+        } finally {
+            setResetting(false);
+        }
+    };
 
     const handleCopy = () => {
-        if (subscription?.subscription_url) {
-            navigator.clipboard.writeText(subscription.subscription_url);
+        if (subscriptionUrl) {
+            navigator.clipboard.writeText(subscriptionUrl);
             setCopied(true);
             setTimeout(() => setCopied(false), 2000);
         }
@@ -44,7 +128,7 @@ export default function Subscription() {
                 <h3>Your Access Key</h3>
                 <div className="qr-wrapper">
                     <QRCodeSVG
-                        value={subscription.subscription_url}
+                        value={subscriptionUrl}
                         size={200}
                         bgColor={"#ffffff"}
                         fgColor={"#000000"}
@@ -61,7 +145,7 @@ export default function Subscription() {
                     <input
                         type="text"
                         readOnly
-                        value={subscription.subscription_url}
+                        value={subscriptionUrl}
                         onClick={(e) => e.currentTarget.select()}
                     />
                 </div>
@@ -73,6 +157,34 @@ export default function Subscription() {
                 </button>
             </div>
 
+            <div className="card reset-card">
+                <h3>Reset Link</h3>
+                <p className="instruction">If your link leaked, reset it to cut off everyone using the old one.</p>
+                <button className="reset-button" disabled={resetting} onClick={handleReset}>
+                    {resetting ? 'Resetting...' : 'Reset Link'}
+                </button>
+                {resetMessage && <p className="instruction">{resetMessage}</p>}
+            </div>
+
+            {(planOptions.length > 0 || planMessage) && (
+                <div className="card plan-card">
+                    <h3>Change Plan</h3>
+                    <p className="instruction">Unused time of your current plan is credited towards the new one.</p>
+                    {planOptions.map(option => (
+                        <button
+                            key={option.duration_id}
+                            className="plan-option"
+                            disabled={changing}
+                            onClick={() => handleChangePlan(option)}
+                        >
+                            <span>{option.plan_name} · {option.days}d</span>
+                            <span>{option.due >= 0 ? formatCents(option.due) : `+${formatCents(-option.due)}`}</span>
+                        </button>
+                    ))}
+                    {planMessage && <p className="instruction">{planMessage}</p>}
+                </div>
+            )}
+
             <style>{`
                 .subscription-page {
                     padding: 20px;
@@ -138,6 +250,33 @@ export default function Subscription() {
                     background: #22c55e;
                     color: white;
                 }
+                .plan-option {
+                    width: 100%;
+                    display: flex;
+                    justify-content: space-between;
+                    padding: 12px;
+                    margin-top: 10px;
+                    border-radius: 10px;
+                    border: 1px solid rgba(255,255,255,0.1);
+                    background: rgba(0,0,0,0.2);
+                    color: #fff;
+                    cursor: pointer;
+                }
+                .reset-button {
+                    width: 100%;
+                    padding: 12px;
+                    margin-top: 10px;
+                    border-radius: 10px;
+                    border: none;
+                    background: #ef4444;
+                    color: white;
+                    font-weight: bold;
+                    cursor: pointer;
+                }
+                .reset-button:disabled,
+                .plan-option:disabled {
+                    opacity: 0.5;
+                }
                 .empty-state-action button {
                     background: #3b82f6;
                     color: white;
@@ -0,0 +1,14 @@
+-- sing-box binary lifecycle: node groups with a target version,
+-- and what each agent reports about its installed binary.
+CREATE TABLE IF NOT EXISTS node_groups (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL UNIQUE,
+    singbox_version TEXT, -- Target version (e.g. 1.10.7), NULL = use global default
+    singbox_sha256 TEXT, -- Optional checksum of the release archive
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+ALTER TABLE nodes ADD COLUMN group_id INTEGER REFERENCES node_groups(id) ON DELETE SET NULL;
+ALTER TABLE nodes ADD COLUMN singbox_version TEXT; -- Reported by agent
+ALTER TABLE nodes ADD COLUMN singbox_capabilities TEXT; -- Build tags, comma separated
+ALTER TABLE nodes ADD COLUMN singbox_update_error TEXT; -- Last failed upgrade attempt
@@ -0,0 +1,12 @@
+-- Temporary IP bans enforced by the agent-managed host firewall.
+-- node_id NULL applies the ban on every node.
+CREATE TABLE IF NOT EXISTS ip_bans (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    node_id INTEGER REFERENCES nodes(id) ON DELETE CASCADE,
+    ip TEXT NOT NULL,
+    reason TEXT,
+    expires_at DATETIME NOT NULL,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_ip_bans_expires ON ip_bans (expires_at);
@@ -0,0 +1,4 @@
+-- Per-node Clash API secret. The controller is only reached by the local agent.
+ALTER TABLE nodes ADD COLUMN clash_secret TEXT;
+
+UPDATE nodes SET clash_secret = lower(hex(randomblob(16))) WHERE clash_secret IS NULL;
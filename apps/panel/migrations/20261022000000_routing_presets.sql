@@ -0,0 +1,26 @@
+-- Named client routing presets (direct rule sets, DNS, ad blocking, bypass lists)
+CREATE TABLE IF NOT EXISTS routing_presets (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    code TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    -- Comma-separated geosite / geoip names routed around the tunnel
+    direct_geosites TEXT NOT NULL DEFAULT '',
+    direct_geoips TEXT NOT NULL DEFAULT '',
+    -- Comma-separated domain suffixes routed around the tunnel
+    bypass_domains TEXT NOT NULL DEFAULT '',
+    remote_dns TEXT NOT NULL DEFAULT 'tls://8.8.8.8',
+    local_dns TEXT,
+    block_ads BOOLEAN NOT NULL DEFAULT 0,
+    is_default BOOLEAN NOT NULL DEFAULT 0,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT OR IGNORE INTO routing_presets (code, name, direct_geosites, direct_geoips, bypass_domains, remote_dns, local_dns, block_ads, is_default) VALUES
+    ('global', 'Global (everything via VPN)', '', '', '', 'tls://8.8.8.8', NULL, 0, 1),
+    ('ru', 'Russia', 'category-ru', 'ru', 'ru,su,xn--p1ai', 'tls://8.8.8.8', '77.88.8.8', 1, 0),
+    ('ir', 'Iran', 'category-ir', 'ir', 'ir', 'tls://8.8.8.8', '178.22.122.100', 1, 0),
+    ('cn', 'China', 'cn', 'cn', 'cn', 'tls://8.8.8.8', '223.5.5.5', 1, 0);
+
+-- Explicit choice by the user (bot) or the plan; NULL falls through to region detection and the default
+ALTER TABLE users ADD COLUMN routing_preset_id INTEGER REFERENCES routing_presets(id) ON DELETE SET NULL;
+ALTER TABLE plans ADD COLUMN routing_preset_id INTEGER REFERENCES routing_presets(id) ON DELETE SET NULL;
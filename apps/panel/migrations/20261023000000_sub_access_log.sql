@@ -0,0 +1,35 @@
+-- Every subscription fetch, kept for a limited retention window
+CREATE TABLE IF NOT EXISTS subscription_access_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    subscription_id INTEGER NOT NULL REFERENCES subscriptions(id) ON DELETE CASCADE,
+    -- Keyed hash of the client IP; raw addresses are never stored
+    ip_hash TEXT NOT NULL,
+    -- "AS12389" when the ASN is known, otherwise a hash of the /24 (IPv4) or /48 (IPv6) prefix
+    network TEXT NOT NULL,
+    asn_org TEXT,
+    country TEXT,
+    user_agent TEXT,
+    -- Client app family derived from the User-Agent ("browser" for the landing page)
+    client TEXT NOT NULL,
+    accessed_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_sub_access_log_sub ON subscription_access_log(subscription_id, accessed_at);
+CREATE INDEX IF NOT EXISTS idx_sub_access_log_time ON subscription_access_log(accessed_at);
+
+-- Subscriptions fetched from suspiciously many networks or client apps
+CREATE TABLE IF NOT EXISTS subscription_sharing_flags (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    subscription_id INTEGER NOT NULL REFERENCES subscriptions(id) ON DELETE CASCADE,
+    window_hours INTEGER NOT NULL,
+    fetches INTEGER NOT NULL,
+    distinct_networks INTEGER NOT NULL,
+    distinct_countries INTEGER NOT NULL,
+    distinct_clients INTEGER NOT NULL,
+    -- What the detector did: 'flag' or 'warn'
+    action TEXT NOT NULL DEFAULT 'flag',
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    dismissed_at DATETIME
+);
+
+CREATE INDEX IF NOT EXISTS idx_sharing_flags_sub ON subscription_sharing_flags(subscription_id);
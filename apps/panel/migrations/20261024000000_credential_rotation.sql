@@ -0,0 +1,17 @@
+-- Older subscriptions (and trials) were created without link or protocol credentials
+UPDATE subscriptions
+SET subscription_uuid = lower(
+    hex(randomblob(4)) || '-' || hex(randomblob(2)) || '-' || hex(randomblob(2)) || '-' ||
+    hex(randomblob(2)) || '-' || hex(randomblob(6))
+)
+WHERE subscription_uuid IS NULL;
+
+UPDATE subscriptions
+SET vless_uuid = lower(
+    hex(randomblob(4)) || '-' || hex(randomblob(2)) || '-' || hex(randomblob(2)) || '-' ||
+    hex(randomblob(2)) || '-' || hex(randomblob(6))
+)
+WHERE vless_uuid IS NULL;
+
+-- Last self-service link/credential reset, for the cooldown
+ALTER TABLE subscriptions ADD COLUMN credentials_rotated_at DATETIME;
@@ -0,0 +1,16 @@
+-- AmneziaWG peers: one tunnel address and keypair per subscription and inbound
+CREATE TABLE IF NOT EXISTS awg_peers (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    subscription_id INTEGER NOT NULL REFERENCES subscriptions(id) ON DELETE CASCADE,
+    inbound_id INTEGER NOT NULL REFERENCES inbounds(id) ON DELETE CASCADE,
+    -- Host address inside the inbound's tunnel subnet, e.g. 10.10.0.7
+    client_ip TEXT NOT NULL,
+    private_key TEXT NOT NULL,
+    public_key TEXT NOT NULL,
+    preshared_key TEXT,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (subscription_id, inbound_id),
+    UNIQUE (inbound_id, client_ip)
+);
+
+CREATE INDEX IF NOT EXISTS idx_awg_peers_sub ON awg_peers(subscription_id);
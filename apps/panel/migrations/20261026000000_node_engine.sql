@@ -0,0 +1,2 @@
+-- Proxy core running on the node: 'singbox' or 'xray'
+ALTER TABLE nodes ADD COLUMN engine TEXT NOT NULL DEFAULT 'singbox';
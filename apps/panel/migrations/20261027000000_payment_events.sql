@@ -0,0 +1,22 @@
+-- Journal of confirmed payments as reported by the providers, one row per provider payment.
+-- The unique key makes webhook retries idempotent: a payment is credited at most once.
+CREATE TABLE IF NOT EXISTS payment_events (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    provider TEXT NOT NULL,
+    external_id TEXT NOT NULL,
+    amount_usd REAL NOT NULL,
+    -- "<user_id>:<type>:<target>" payload carried through the provider
+    payload TEXT NOT NULL,
+    -- Request body exactly as received, for audit
+    raw_body TEXT NOT NULL,
+    -- 'processing', 'processed' or 'failed'
+    status TEXT NOT NULL DEFAULT 'processing',
+    attempts INTEGER NOT NULL DEFAULT 1,
+    error TEXT,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    processed_at DATETIME,
+    UNIQUE(provider, external_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_payment_events_status ON payment_events(status, created_at);
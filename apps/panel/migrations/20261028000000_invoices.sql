@@ -0,0 +1,21 @@
+-- Every invoice created at a provider, tracked from creation until it is paid or expires
+CREATE TABLE IF NOT EXISTS invoices (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    provider TEXT NOT NULL,
+    -- Provider-side id; unknown until the webhook for providers that don't return one up front
+    external_id TEXT,
+    amount_usd REAL NOT NULL,
+    -- Payload sent to the provider, ending with this invoice's id
+    payload TEXT NOT NULL,
+    url TEXT NOT NULL DEFAULT '',
+    -- 'pending', 'paid', 'expired' or 'failed'
+    status TEXT NOT NULL DEFAULT 'pending',
+    expires_at DATETIME NOT NULL,
+    last_checked_at DATETIME,
+    paid_at DATETIME,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_invoices_status ON invoices(status, expires_at);
+CREATE INDEX IF NOT EXISTS idx_invoices_user ON invoices(user_id, status);
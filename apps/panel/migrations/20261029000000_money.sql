@@ -0,0 +1,39 @@
+-- Units of each currency per 1 USD. `per_usd` is the last fetched (or seeded) value;
+-- `manual_per_usd`, when set by an admin, takes precedence.
+CREATE TABLE IF NOT EXISTS exchange_rates (
+    currency TEXT PRIMARY KEY,
+    per_usd REAL NOT NULL,
+    manual_per_usd REAL,
+    -- 'seed' or 'api'
+    source TEXT NOT NULL DEFAULT 'seed',
+    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT OR IGNORE INTO exchange_rates (currency, per_usd) VALUES
+    ('USD', 1.0),
+    ('USDT', 1.0),
+    ('EUR', 0.92),
+    ('RUB', 90.0),
+    ('XTR', 50.0);
+
+-- Exact integer amounts instead of floating-point USD
+ALTER TABLE payment_events ADD COLUMN amount INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE payment_events ADD COLUMN currency TEXT NOT NULL DEFAULT 'USD';
+UPDATE payment_events SET amount = CAST(ROUND(amount_usd * 100) AS INTEGER);
+ALTER TABLE payment_events DROP COLUMN amount_usd;
+
+-- What the user owes in USD cents, and what the provider bills in its own currency,
+-- fixed at the rate of the moment the invoice was created
+ALTER TABLE invoices ADD COLUMN amount INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE invoices ADD COLUMN billed_amount INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE invoices ADD COLUMN billed_currency TEXT NOT NULL DEFAULT 'USD';
+UPDATE invoices SET amount = CAST(ROUND(amount_usd * 100) AS INTEGER), billed_amount = CAST(ROUND(amount_usd * 100) AS INTEGER);
+ALTER TABLE invoices DROP COLUMN amount_usd;
+
+-- Payments are credited in USD cents (`amount`); record what the provider actually settled
+ALTER TABLE payments RENAME COLUMN transaction_id TO external_id;
+ALTER TABLE payments ADD COLUMN settled_amount INTEGER;
+ALTER TABLE payments ADD COLUMN settled_currency TEXT;
+
+-- Orders carry integer cents in `total_amount`; the floating-point copy is gone
+ALTER TABLE orders DROP COLUMN amount;
@@ -0,0 +1,36 @@
+-- Every balance movement, one immutable row each. `amount` is signed from the user's side
+-- (USD cents, positive credits the user); `contra_account` is the other side of the entry.
+CREATE TABLE IF NOT EXISTS balance_ledger (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL REFERENCES users(id),
+    amount INTEGER NOT NULL,
+    balance_after INTEGER NOT NULL,
+    contra_account TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    ref_type TEXT,
+    ref_id INTEGER,
+    actor TEXT NOT NULL DEFAULT 'system',
+    note TEXT,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_balance_ledger_user ON balance_ledger(user_id, id);
+CREATE INDEX IF NOT EXISTS idx_balance_ledger_ref ON balance_ledger(ref_type, ref_id);
+
+CREATE TRIGGER IF NOT EXISTS balance_ledger_no_update
+BEFORE UPDATE ON balance_ledger
+BEGIN
+    SELECT RAISE(ABORT, 'balance_ledger is append-only');
+END;
+
+CREATE TRIGGER IF NOT EXISTS balance_ledger_no_delete
+BEFORE DELETE ON balance_ledger
+BEGIN
+    SELECT RAISE(ABORT, 'balance_ledger is append-only');
+END;
+
+-- Carry existing balances over so every balance equals the sum of its entries
+INSERT INTO balance_ledger (user_id, amount, balance_after, contra_account, reason, note)
+SELECT id, balance, balance, 'opening', 'opening', 'Balance before the ledger was introduced'
+FROM users
+WHERE balance != 0;
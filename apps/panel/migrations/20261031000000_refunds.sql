@@ -0,0 +1,27 @@
+-- Money going back out of a payment: refunds we send through the provider or the provider
+-- reports, and disputes (chargebacks) opened by the payer
+CREATE TABLE IF NOT EXISTS refunds (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    payment_id INTEGER NOT NULL REFERENCES payments(id),
+    kind TEXT NOT NULL DEFAULT 'refund',       -- refund | dispute
+    reference TEXT,                            -- provider's refund or dispute id
+    amount INTEGER NOT NULL,                   -- USD cents taken back from the user
+    provider_amount INTEGER NOT NULL,          -- minor units of provider_currency
+    provider_currency TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending',    -- refunds: pending | succeeded | failed; disputes: open | won | lost
+    actor TEXT NOT NULL DEFAULT 'provider',
+    error TEXT,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_refunds_payment ON refunds(payment_id);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_refunds_reference ON refunds(payment_id, kind, reference) WHERE reference IS NOT NULL;
+
+-- Subscription bought with the payment, suspended if the payment is reversed
+ALTER TABLE payments ADD COLUMN subscription_id INTEGER;
+
+-- One bonus row per referred payment (not per referred user), so a bonus can be reversed with its payment
+DROP INDEX IF EXISTS idx_referral_unique;
+ALTER TABLE referral_bonuses ADD COLUMN payment_id INTEGER;
+CREATE INDEX IF NOT EXISTS idx_referral_bonuses_payment ON referral_bonuses(payment_id);
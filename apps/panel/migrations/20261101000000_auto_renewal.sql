@@ -0,0 +1,16 @@
+-- The plan duration a subscription renews with: the one bought, or the one picked in the bot
+ALTER TABLE subscriptions ADD COLUMN duration_id INTEGER;
+
+-- Failed renewals are retried on a schedule until the grace period runs out
+ALTER TABLE subscriptions ADD COLUMN renewal_attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE subscriptions ADD COLUMN next_renewal_at DATETIME;
+
+-- Best guess for existing subscriptions: the duration of their plan that matches their length
+UPDATE subscriptions
+SET duration_id = (
+    SELECT pd.id FROM plan_durations pd
+    WHERE pd.plan_id = subscriptions.plan_id
+      AND pd.duration_days = CAST(ROUND(julianday(subscriptions.expires_at) - julianday(COALESCE(subscriptions.activated_at, subscriptions.created_at))) AS INTEGER)
+    LIMIT 1
+)
+WHERE duration_id IS NULL;
@@ -0,0 +1,37 @@
+-- Promo codes: a discount on the next plan purchase, bonus balance on redemption, or both
+CREATE TABLE IF NOT EXISTS promo_codes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    code TEXT NOT NULL UNIQUE COLLATE NOCASE,
+    description TEXT,
+    discount_percent INTEGER NOT NULL DEFAULT 0,   -- 0-100, off the plan price
+    discount_amount INTEGER NOT NULL DEFAULT 0,    -- USD cents off the plan price
+    bonus_amount INTEGER NOT NULL DEFAULT 0,       -- USD cents credited to the balance on redemption
+    max_uses INTEGER NOT NULL DEFAULT 0,           -- redemptions in total, 0 = unlimited
+    max_uses_per_user INTEGER NOT NULL DEFAULT 1,  -- 0 = unlimited
+    current_uses INTEGER NOT NULL DEFAULT 0,
+    plan_ids TEXT NOT NULL DEFAULT '',             -- comma separated, empty = every plan
+    duration_days TEXT NOT NULL DEFAULT '',        -- comma separated, empty = every duration
+    first_purchase_only INTEGER NOT NULL DEFAULT 0,
+    is_active INTEGER NOT NULL DEFAULT 1,
+    expires_at DATETIME,
+    created_by TEXT,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+-- One row per user entering a code. A discount stays `reserved` until the next qualifying
+-- plan purchase turns it `applied`; entering another code `released` it.
+CREATE TABLE IF NOT EXISTS promo_redemptions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    promo_code_id INTEGER NOT NULL REFERENCES promo_codes(id),
+    user_id INTEGER NOT NULL REFERENCES users(id),
+    status TEXT NOT NULL DEFAULT 'reserved',       -- reserved | applied | released
+    bonus_amount INTEGER NOT NULL DEFAULT 0,
+    discount_amount INTEGER NOT NULL DEFAULT 0,
+    purchase_amount INTEGER NOT NULL DEFAULT 0,    -- USD cents the discounted purchase charged
+    subscription_id INTEGER,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    applied_at DATETIME
+);
+
+CREATE INDEX IF NOT EXISTS idx_promo_redemptions_code ON promo_redemptions(promo_code_id);
+CREATE INDEX IF NOT EXISTS idx_promo_redemptions_user ON promo_redemptions(user_id, status);
@@ -0,0 +1,21 @@
+-- Traffic quotas run in cycles: monthly, with each paid period, or over the whole subscription
+ALTER TABLE plans ADD COLUMN traffic_reset_period TEXT NOT NULL DEFAULT 'never'; -- monthly | billing_cycle | never
+
+ALTER TABLE subscriptions ADD COLUMN traffic_extra_bytes INTEGER NOT NULL DEFAULT 0; -- add-on packs bought this cycle
+ALTER TABLE subscriptions ADD COLUMN traffic_reset_at DATETIME;                       -- start of the current cycle, NULL = since creation
+
+-- Extra traffic sold on top of a plan's quota
+CREATE TABLE IF NOT EXISTS traffic_packs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    traffic_gb INTEGER NOT NULL,
+    price INTEGER NOT NULL,                  -- USD cents
+    is_active INTEGER NOT NULL DEFAULT 1,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Subscriptions cut off by their quota were marked expired; they are `limited` now
+UPDATE subscriptions SET status = 'limited'
+WHERE status = 'expired'
+  AND datetime(expires_at) > datetime('now')
+  AND plan_id IN (SELECT id FROM plans WHERE traffic_limit_gb > 0)
+  AND used_traffic >= (SELECT CAST(traffic_limit_gb AS BIGINT) * 1024 * 1024 * 1024 FROM plans WHERE plans.id = subscriptions.plan_id);
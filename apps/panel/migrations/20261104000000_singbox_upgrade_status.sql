@@ -0,0 +1,2 @@
+-- Stage of the sing-box upgrade an agent is running ("1.11.0: downloading"), NULL when idle
+ALTER TABLE nodes ADD COLUMN singbox_upgrade_status TEXT;
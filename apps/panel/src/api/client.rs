@@ -2,7 +2,7 @@ use axum::{
     routing::{post, get},
     Router,
     response::{IntoResponse, Json},
-    extract::{State, Request},
+    extract::{State, Request, Query},
     http::{StatusCode, header},
     middleware::{self, Next},
 };
@@ -38,6 +38,9 @@ pub fn routes(state: AppState) -> Router<AppState> {
         .route("/auth/telegram", post(auth_telegram))
         .route("/user/stats", get(get_user_stats).layer(middleware::from_fn_with_state(state.clone(), auth_middleware)))
         .route("/user/subscription", get(get_user_subscription).layer(middleware::from_fn_with_state(state.clone(), auth_middleware)))
+        .route("/user/subscription/reset", post(reset_user_subscription).layer(middleware::from_fn_with_state(state.clone(), auth_middleware)))
+        .route("/user/subscription/plans", get(get_plan_change_options).layer(middleware::from_fn_with_state(state.clone(), auth_middleware)))
+        .route("/user/subscription/change-plan", post(change_user_plan).layer(middleware::from_fn_with_state(state.clone(), auth_middleware)))
         .route("/user/payments", get(get_user_payments).layer(middleware::from_fn_with_state(state.clone(), auth_middleware)))
         .route("/referrals", get(get_user_referrals).layer(middleware::from_fn_with_state(state.clone(), auth_middleware)))
         .route("/leaderboard", get(get_leaderboard).layer(middleware::from_fn_with_state(state.clone(), auth_middleware)))
@@ -238,7 +241,7 @@ async fn get_user_subscription(
     let tg_id: i64 = claims.sub.parse().unwrap_or(0);
 
     let sub = sqlx::query(r#"
-        SELECT s.uuid 
+        SELECT s.id, s.subscription_uuid
         FROM subscriptions s
         JOIN users u ON s.user_id = u.id
         WHERE u.tg_id = ? AND s.status = 'active'
@@ -250,27 +253,127 @@ async fn get_user_subscription(
     .unwrap_or(None);
 
     if let Some(row) = sub {
-        let uuid: String = row.get("uuid");
-        // Construct full URL (this should be in config, but we'll use a placeholder or derived)
-        // The frontend knows the base URL usually.
-        // Return UUID and let frontend construct it, OR return full URL.
-        // Let's return UUID + formatted URL.
-        
-        let domain = env::var("PANEL_URL").unwrap_or_else(|_| "panel.example.com".to_string());
-        // Clean functionality: remove http/https prefix if present for cleaner display, or ensure it for real link
-        // Actually, PANEL_URL usually includes protocol. If not, assume https.
-        let base_url = if domain.starts_with("http") { domain } else { format!("https://{}", domain) };
-        let sub_url = format!("{}/sub/{}", base_url, uuid);
-        
+        let id: i64 = row.get("id");
+        let uuid: String = row.get("subscription_uuid");
+
         Json(serde_json::json!({
+            "id": id,
             "uuid": uuid,
-            "subscription_url": sub_url
+            "subscription_url": crate::subscription::public_url(&uuid)
         })).into_response()
     } else {
         (StatusCode::NOT_FOUND, "No subscription").into_response()
     }
 }
 
+#[derive(Deserialize)]
+struct ResetSubscriptionRequest {
+    /// Defaults to the user's active subscription
+    subscription_id: Option<i64>,
+}
+
+// Regenerate the subscription URL and credentials after a leak
+async fn reset_user_subscription(
+    State(state): State<AppState>,
+    axum::Extension(claims): axum::Extension<Claims>,
+    Json(payload): Json<ResetSubscriptionRequest>,
+) -> impl IntoResponse {
+    let tg_id: i64 = claims.sub.parse().unwrap_or(0);
+    let user = match state.store_service.get_user_by_tg_id(tg_id).await {
+        Ok(Some(u)) => u,
+        _ => return (StatusCode::NOT_FOUND, "User not found").into_response(),
+    };
+
+    let Some(sub_id) = resolve_subscription_id(&state, user.id, payload.subscription_id).await else {
+        return (StatusCode::NOT_FOUND, "No subscription").into_response();
+    };
+
+    match crate::subscription::reset_link(&state, user.id, sub_id).await {
+        Ok(sub) => Json(serde_json::json!({
+            "id": sub.id,
+            "uuid": sub.subscription_uuid,
+            "subscription_url": crate::subscription::public_url(&sub.subscription_uuid)
+        })).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+/// The requested subscription, or the user's active one
+async fn resolve_subscription_id(state: &AppState, user_id: i64, requested: Option<i64>) -> Option<i64> {
+    match requested {
+        Some(id) => Some(id),
+        None => sqlx::query_scalar::<_, i64>("SELECT id FROM subscriptions WHERE user_id = ? AND status = 'active' LIMIT 1")
+            .bind(user_id)
+            .fetch_optional(&state.pool)
+            .await
+            .ok()
+            .flatten(),
+    }
+}
+
+#[derive(Deserialize)]
+struct PlanOptionsQuery {
+    subscription_id: Option<i64>,
+}
+
+// Plans the subscription can switch to, with the prorated price of each
+async fn get_plan_change_options(
+    State(state): State<AppState>,
+    axum::Extension(claims): axum::Extension<Claims>,
+    Query(query): Query<PlanOptionsQuery>,
+) -> impl IntoResponse {
+    let tg_id: i64 = claims.sub.parse().unwrap_or(0);
+    let user = match state.store_service.get_user_by_tg_id(tg_id).await {
+        Ok(Some(u)) => u,
+        _ => return (StatusCode::NOT_FOUND, "User not found").into_response(),
+    };
+    let Some(sub_id) = resolve_subscription_id(&state, user.id, query.subscription_id).await else {
+        return (StatusCode::NOT_FOUND, "No subscription").into_response();
+    };
+
+    match state.store_service.plan_change_options(user.id, sub_id).await {
+        Ok(options) => Json(serde_json::json!({
+            "balance": user.balance,
+            "options": options
+        })).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct ChangePlanRequest {
+    /// Defaults to the user's active subscription
+    subscription_id: Option<i64>,
+    duration_id: i64,
+}
+
+// Upgrade or downgrade, paid from the balance after crediting the unused time
+async fn change_user_plan(
+    State(state): State<AppState>,
+    axum::Extension(claims): axum::Extension<Claims>,
+    Json(payload): Json<ChangePlanRequest>,
+) -> impl IntoResponse {
+    let tg_id: i64 = claims.sub.parse().unwrap_or(0);
+    let user = match state.store_service.get_user_by_tg_id(tg_id).await {
+        Ok(Some(u)) => u,
+        _ => return (StatusCode::NOT_FOUND, "User not found").into_response(),
+    };
+    let Some(sub_id) = resolve_subscription_id(&state, user.id, payload.subscription_id).await else {
+        return (StatusCode::NOT_FOUND, "No subscription").into_response();
+    };
+
+    match crate::subscription::change_plan(&state, user.id, sub_id, payload.duration_id).await {
+        Ok((sub, quote)) => Json(serde_json::json!({
+            "id": sub.id,
+            "plan_id": sub.plan_id,
+            "plan_name": quote.plan_name,
+            "expires_at": sub.expires_at,
+            "charged": quote.due
+        })).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
 // Helper for haversine distance
 fn haversine_distance(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
     let r = 6371.0; // Earth radius in km
@@ -5,7 +5,7 @@ use axum::{
 };
 use tracing::{info, warn, error};
 use crate::AppState;
-use exarobot_shared::api::{HeartbeatRequest, HeartbeatResponse, AgentAction};
+use exarobot_shared::api::{HeartbeatRequest, HeartbeatResponse, AgentAction, ConnectionSnapshot, NodeLogBatch, SingboxInfo, SingboxTarget};
 use exarobot_shared::config::ConfigResponse;
 use serde::Deserialize;
 
@@ -69,6 +69,18 @@ pub async fn heartbeat(
             .await;
     }
 
+    // sing-box Binary Info
+    if let Some(sb) = &req.singbox {
+        let _ = sqlx::query("UPDATE nodes SET singbox_version = ?, singbox_capabilities = ?, singbox_update_error = ?, singbox_upgrade_status = ? WHERE id = ?")
+            .bind(&sb.version)
+            .bind(sb.capabilities.join(","))
+            .bind(&sb.update_error)
+            .bind(&sb.upgrade_status)
+            .bind(node_id)
+            .execute(&state.pool)
+            .await;
+    }
+
     // GeoIP Check (Async)
     if node_country.is_none() {
         let pool = state.pool.clone();
@@ -97,14 +109,53 @@ pub async fn heartbeat(
     // 4. Check if config update is needed (hash mismatch)
     // 5. Check for Agent Update
     let latest_version: String = state.settings.get_or_default("agent_latest_version", "0.0.0").await;
+
+    // 6. Check for sing-box Upgrade
+    let singbox_target = match &req.singbox {
+        Some(sb) => resolve_singbox_target(&state, node_id, sb).await,
+        None => None,
+    };
     
     (StatusCode::OK, Json(HeartbeatResponse {
         success: true,
         action: AgentAction::None,
         latest_version: Some(latest_version),
+        singbox_target,
     })).into_response()
 }
 
+/// Default release URL; override with the `singbox_download_url` setting (e.g. a mirror).
+const SINGBOX_DOWNLOAD_URL: &str = "https://github.com/SagerNet/sing-box/releases/download/v{version}/sing-box-{version}-linux-{arch}.tar.gz";
+
+/// Target binary for the node's group, or None if the group has no target or the node already runs it
+async fn resolve_singbox_target(state: &AppState, node_id: i64, installed: &SingboxInfo) -> Option<SingboxTarget> {
+    let row: Option<(Option<String>, Option<String>)> = sqlx::query_as(
+        "SELECT g.singbox_version, g.singbox_sha256 FROM nodes n JOIN node_groups g ON g.id = n.group_id WHERE n.id = ?"
+    )
+        .bind(node_id)
+        .fetch_optional(&state.pool)
+        .await
+        .unwrap_or_else(|e| {
+            error!("Failed to resolve sing-box target for node {}: {}", node_id, e);
+            None
+        });
+
+    let (version, sha256) = row?;
+    let version = version.filter(|v| !v.trim().is_empty())?.trim().trim_start_matches('v').to_string();
+    if installed.version == version {
+        return None;
+    }
+
+    let template = state.settings.get_or_default("singbox_download_url", SINGBOX_DOWNLOAD_URL).await;
+    let url = template.replace("{version}", &version).replace("{arch}", &installed.arch);
+
+    Some(SingboxTarget {
+        version,
+        url,
+        sha256: sha256.filter(|h| !h.trim().is_empty()),
+    })
+}
+
 // ... (existing code) ...
 
 /// Get Agent Update Info
@@ -186,13 +237,16 @@ pub async fn get_config(
 
     // 3. Generate Config
     match state.orchestration_service.generate_node_config_json(node_id_scalar).await {
-        Ok((_, config_value)) => {
-            let config_str = config_value.to_string();
+        Ok((node, config_value)) => {
+            let engine = node.engine().as_str().to_string();
+            // Engine is part of the hash so switching it alone makes the agent re-apply
+            let config_str = format!("{}{}", engine, config_value);
             let hash = format!("{:x}", md5::compute(config_str.as_bytes()));
             
             (StatusCode::OK, Json(ConfigResponse {
                 hash,
                 content: config_value,
+                engine,
             })).into_response()
         },
         Err(e) => {
@@ -328,9 +382,9 @@ pub async fn poll_updates(
 
     // 4. Select with timeout (30s)
     match tokio::time::timeout(std::time::Duration::from_secs(30), rx).await {
-        Ok(Ok(_payload)) => {
-            // Message received
-            (StatusCode::OK, Json(serde_json::json!({"update": true}))).into_response()
+        Ok(Ok(payload)) => {
+            // Message received ("update", "settings_update", "logs_request", "firewall_update", "kill_user:<name>")
+            (StatusCode::OK, Json(serde_json::json!({"update": true, "event": payload}))).into_response()
         },
         Ok(Err(_)) => {
             // Sender dropped
@@ -378,6 +432,9 @@ pub async fn get_settings(
     let kill_switch_enabled: bool = state.settings.get_or_default("kill_switch_enabled", "false").await.parse().unwrap_or(false);
     let kill_switch_timeout: u64 = state.settings.get_or_default("kill_switch_timeout", "300").await.parse().unwrap_or(300);
 
+    // 5. Fetch Log Shipping Settings
+    let redact_ips: bool = state.settings.get_or_default("node_logs_redact_ips", "true").await.parse().unwrap_or(true);
+
     Json(serde_json::json!({
         "decoy": {
             "enabled": decoy_enabled,
@@ -388,6 +445,124 @@ pub async fn get_settings(
         "kill_switch": {
             "enabled": kill_switch_enabled,
             "timeout": kill_switch_timeout
+        },
+        "logs": {
+            "redact_ips": redact_ips
         }
     })).into_response()
 }
+
+/// Receive Log Lines (only sent while an admin has the log viewer open)
+/// POST /api/v2/node/logs
+pub async fn push_logs(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+    Json(batch): Json<NodeLogBatch>,
+) -> impl IntoResponse {
+    // 1. Extract Token
+    let token = match headers.get("Authorization") {
+        Some(hv) => hv.to_str().unwrap_or("").replace("Bearer ", ""),
+        None => return (StatusCode::UNAUTHORIZED, "Missing Token").into_response(),
+    };
+
+    // 2. Validate Node
+    let node_id: i64 = match sqlx::query_scalar("SELECT id FROM nodes WHERE join_token = ?")
+        .bind(&token)
+        .fetch_optional(&state.pool)
+        .await
+    {
+        Ok(Some(id)) => id,
+        Ok(None) => return (StatusCode::UNAUTHORIZED, "Invalid Token").into_response(),
+        Err(e) => {
+            error!("DB Error in push_logs: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "DB Error").into_response();
+        }
+    };
+
+    // 3. Store in capped Redis list (expires after retention window)
+    let retention_minutes: usize = state.settings.get_or_default("node_logs_retention_minutes", "60").await.parse().unwrap_or(60);
+    if let Err(e) = state.redis.append_node_logs(node_id, &batch.lines, retention_minutes * 60).await {
+        error!("Failed to store logs for node {}: {}", node_id, e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Storage Error").into_response();
+    }
+
+    StatusCode::OK.into_response()
+}
+
+/// Desired Host Firewall State
+/// GET /api/v2/node/firewall
+pub async fn get_firewall(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+) -> impl IntoResponse {
+    // 1. Extract Token
+    let token = match headers.get("Authorization") {
+        Some(hv) => hv.to_str().unwrap_or("").replace("Bearer ", ""),
+        None => return (StatusCode::UNAUTHORIZED, "Missing Token").into_response(),
+    };
+
+    // 2. Validate Node
+    let node_id: i64 = match sqlx::query_scalar("SELECT id FROM nodes WHERE join_token = ?")
+        .bind(&token)
+        .fetch_optional(&state.pool)
+        .await
+    {
+        Ok(Some(id)) => id,
+        Ok(None) => return (StatusCode::UNAUTHORIZED, "Invalid Token").into_response(),
+        Err(e) => {
+            error!("DB Error in get_firewall: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "DB Error").into_response();
+        }
+    };
+
+    // 3. Build Desired State
+    match state.firewall.desired_state(node_id).await {
+        Ok(fw) => Json(fw).into_response(),
+        Err(e) => {
+            error!("Failed to build firewall state for node {}: {}", node_id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Firewall Error").into_response()
+        }
+    }
+}
+
+/// Receive Live Connections (read by the agent from the local sing-box controller)
+/// POST /api/v2/node/connections
+pub async fn push_connections(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+    Json(snapshot): Json<ConnectionSnapshot>,
+) -> impl IntoResponse {
+    // 1. Extract Token
+    let token = match headers.get("Authorization") {
+        Some(hv) => hv.to_str().unwrap_or("").replace("Bearer ", ""),
+        None => return (StatusCode::UNAUTHORIZED, "Missing Token").into_response(),
+    };
+
+    // 2. Validate Node
+    let node_id: i64 = match sqlx::query_scalar("SELECT id FROM nodes WHERE join_token = ?")
+        .bind(&token)
+        .fetch_optional(&state.pool)
+        .await
+    {
+        Ok(Some(id)) => id,
+        Ok(None) => return (StatusCode::UNAUTHORIZED, "Invalid Token").into_response(),
+        Err(e) => {
+            error!("DB Error in push_connections: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "DB Error").into_response();
+        }
+    };
+
+    // 3. Keep latest snapshot for device limit enforcement
+    if let Err(e) = state.redis.set_node_connections(node_id, &snapshot, crate::services::connection_service::SNAPSHOT_TTL_SECS).await {
+        error!("Failed to store connections for node {}: {}", node_id, e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Storage Error").into_response();
+    }
+
+    // 4. Book usage last: the agent keeps unbooked bytes and sends them again after an error
+    if let Err(e) = state.store_service.book_node_traffic(node_id, &snapshot.traffic).await {
+        error!("Failed to book traffic for node {}: {}", node_id, e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, "DB Error").into_response();
+    }
+
+    StatusCode::OK.into_response()
+}
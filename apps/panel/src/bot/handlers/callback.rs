@@ -1,10 +1,11 @@
 use teloxide::prelude::*;
-use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, ForceReply, ParseMode, CallbackQuery, ChatId, LabeledPrice};
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, ForceReply, ParseMode, CallbackQuery, ChatId};
 use tracing::{info, error};
 use crate::AppState;
 use crate::bot::utils::escape_md;
 use crate::bot::keyboards::{main_menu, terms_keyboard};
 use crate::services::pay_service::PaymentType;
+use crate::models::money::{Currency, Money};
 
 pub async fn callback_handler(
     bot: Bot,
@@ -118,7 +119,7 @@ pub async fn callback_handler(
             "enter_promo" => {
                 let _ = bot.answer_callback_query(&callback_id).await;
                 if let Some(msg) = q.message {
-                    let _ = bot.send_message(msg.chat().id, "🎟 Enter your Gift Code below:")
+                    let _ = bot.send_message(msg.chat().id, "🎟 Enter your Promo Code or Gift Code below:")
                         .reply_markup(ForceReply::new().selective())
                         .await;
                 }
@@ -126,14 +127,16 @@ pub async fn callback_handler(
 
             "topup_menu" => {
                 let response = "💳 *Choose Top-up Method:*";
-                let buttons = vec![
-                    vec![InlineKeyboardButton::callback("🪙 Crypto (USDT/TON)", "pay_cryptobot")],
-                    vec![InlineKeyboardButton::callback("⚡ Crypto (Altcoins)", "pay_nowpayments")],
-                    vec![InlineKeyboardButton::callback("🇷🇺 Cards (RUB/SBP)", "pay_crystal")],
-                    vec![InlineKeyboardButton::callback("🌍 Global Cards (USD)", "pay_stripe")],
-                    vec![InlineKeyboardButton::callback("⭐️ Telegram Stars", "pay_stars")],
-                ];
+                let mut buttons: Vec<Vec<InlineKeyboardButton>> = state.pay_service.providers().await
+                    .iter()
+                    .map(|p| vec![InlineKeyboardButton::callback(p.spec().button, format!("pay_{}", p.spec().id))])
+                    .collect();
                 if let Some(msg) = q.message {
+                    if buttons.is_empty() {
+                        let _ = bot.answer_callback_query(&callback_id).text("Top-ups are not available right now").show_alert(true).await;
+                        return Ok(());
+                    }
+                    buttons.push(vec![InlineKeyboardButton::callback("🧾 My Invoices", "my_invoices")]);
                     let _ = bot.edit_message_text(msg.chat().id, msg.id(), response)
                         .parse_mode(ParseMode::MarkdownV2)
                         .reply_markup(InlineKeyboardMarkup::new(buttons))
@@ -141,108 +144,181 @@ pub async fn callback_handler(
                 }
             }
 
-            // Amount Selection Menus
-            "pay_cryptobot" => {
-                let buttons = make_amount_keyboard("cb");
-                if let Some(msg) = q.message {
-                    let _ = bot.edit_message_text(msg.chat().id, msg.id(), "🔹 *Select amount for CryptoBot:*")
-                        .parse_mode(ParseMode::MarkdownV2).reply_markup(buttons).await;
+            "routing_menu" => {
+                let _ = bot.answer_callback_query(&callback_id).await;
+
+                if let Some(u) = state.store_service.get_user_by_tg_id(tg_id).await.ok().flatten() {
+                    let current = state.routing.user_preset_id(u.id).await.ok().flatten();
+                    let presets = state.routing.list().await.unwrap_or_default();
+
+                    let mark = |selected: bool| if selected { "✅ " } else { "" };
+                    let mut buttons = vec![vec![InlineKeyboardButton::callback(
+                        format!("{}🤖 Auto", mark(current.is_none())),
+                        "set_routing_auto",
+                    )]];
+                    for preset in presets {
+                        buttons.push(vec![InlineKeyboardButton::callback(
+                            format!("{}{}", mark(current == Some(preset.id)), preset.name),
+                            format!("set_routing_{}", preset.id),
+                        )]);
+                    }
+
+                    let response = "🌍 *Routing*\n\nChoose which sites open directly, without the VPN\\. \
+                        *Auto* picks the rules for your region\\. Update the subscription in your app after changing it\\.";
+                    if let Some(msg) = q.message {
+                        let _ = bot.send_message(msg.chat().id, response)
+                            .parse_mode(ParseMode::MarkdownV2)
+                            .reply_markup(InlineKeyboardMarkup::new(buttons))
+                            .await;
+                    }
                 }
             }
-            "pay_nowpayments" => {
-                let buttons = make_amount_keyboard("np");
-                if let Some(msg) = q.message {
-                    let _ = bot.edit_message_text(msg.chat().id, msg.id(), "🔹 *Select amount for NOWPayments:*")
-                        .parse_mode(ParseMode::MarkdownV2).reply_markup(buttons).await;
+
+            set_routing if set_routing.starts_with("set_routing_") => {
+                let choice = set_routing.strip_prefix("set_routing_").unwrap_or("auto");
+                let preset_id: Option<i64> = choice.parse().ok();
+
+                if let Some(u) = state.store_service.get_user_by_tg_id(tg_id).await.ok().flatten() {
+                    match state.routing.set_user_preset(u.id, preset_id).await {
+                        Ok(_) => {
+                            let _ = bot.answer_callback_query(&callback_id).text("✅ Routing updated").await;
+                            if let Some(msg) = q.message {
+                                let _ = bot.delete_message(msg.chat().id, msg.id()).await;
+                            }
+                        }
+                        Err(e) => {
+                            error!("Failed to set routing preset: {}", e);
+                            let _ = bot.answer_callback_query(&callback_id).text("❌ Failed to update routing").await;
+                        }
+                    }
                 }
             }
-            "pay_crystal" => {
-                let buttons = make_amount_keyboard("cp");
-                if let Some(msg) = q.message {
-                    let _ = bot.edit_message_text(msg.chat().id, msg.id(), "🔹 *Select amount for CrystalPay (Cards/SBP):*")
-                        .parse_mode(ParseMode::MarkdownV2).reply_markup(buttons).await;
+
+            "my_invoices" => {
+                let _ = bot.answer_callback_query(&callback_id).await;
+                let Some(u) = state.store_service.get_user_by_tg_id(tg_id).await.ok().flatten() else {
+                    return Ok(());
+                };
+                let invoices = state.pay_service.open_invoices(u.id).await.unwrap_or_default();
+
+                let mut response = String::from("🧾 *Your Invoices*\n\n");
+                let mut buttons = Vec::new();
+                if invoices.is_empty() {
+                    response.push_str("You have no open invoices\\.");
                 }
-            }
-            "pay_stripe" => {
-                let buttons = make_amount_keyboard("str");
-                if let Some(msg) = q.message {
-                    let _ = bot.edit_message_text(msg.chat().id, msg.id(), "🔹 *Select amount for Stripe:*")
-                        .parse_mode(ParseMode::MarkdownV2).reply_markup(buttons).await;
+                let now = chrono::Utc::now();
+                for inv in &invoices {
+                    let provider = crate::services::payments::spec(&inv.provider).map(|p| p.name).unwrap_or(inv.provider.as_str());
+                    let open = inv.status == "pending" && inv.expires_at > now;
+                    let status = if open {
+                        format!("⏳ awaiting payment, {} min left", (inv.expires_at - now).num_minutes().max(1))
+                    } else {
+                        "⌛ expired".to_string()
+                    };
+                    let price = match inv.billed() {
+                        Some(billed) if billed.currency != inv.amount().currency => format!("{} ({})", inv.amount(), billed),
+                        _ => inv.amount().to_string(),
+                    };
+                    response.push_str(&format!(
+                        "*\\#{}* · {} via {}\n{}\n\n",
+                        inv.id,
+                        escape_md(&price),
+                        escape_md(provider),
+                        escape_md(&status)
+                    ));
+
+                    if open && let Ok(url) = inv.url.parse() {
+                        buttons.push(vec![InlineKeyboardButton::url(format!("💳 Pay #{} ({})", inv.id, price), url)]);
+                    } else if inv.payload.split(':').nth(1) == Some("bal") {
+                        // Top-ups can simply be re-issued; order and plan invoices start from the store again
+                        buttons.push(vec![InlineKeyboardButton::callback(
+                            format!("🔁 Pay #{} again ({})", inv.id, inv.amount()),
+                            format!("inv_{}_{}", inv.provider, inv.amount().to_decimal_string()),
+                        )]);
+                    }
                 }
-            }
-            "pay_stars" => {
-                let buttons = make_amount_keyboard("star");
+                buttons.push(vec![InlineKeyboardButton::callback("« Back", "topup_menu")]);
+
                 if let Some(msg) = q.message {
-                    let _ = bot.edit_message_text(msg.chat().id, msg.id(), "🔹 *Select amount via Stars:*")
-                        .parse_mode(ParseMode::MarkdownV2).reply_markup(buttons).await;
+                    let _ = bot.send_message(msg.chat().id, response)
+                        .parse_mode(ParseMode::MarkdownV2)
+                        .reply_markup(InlineKeyboardMarkup::new(buttons))
+                        .await;
                 }
             }
 
-            // Handlers
-            cb if cb.starts_with("cb_") => {
-                let amount = cb.strip_prefix("cb_").unwrap_or("0").parse::<f64>().unwrap_or(0.0);
-                let user_db = state.store_service.get_user_by_tg_id(tg_id).await.ok().flatten();
-                if let Some(u) = user_db {
-                    match state.pay_service.create_cryptobot_invoice(u.id, amount, PaymentType::BalanceTopup).await {
-                        Ok(url) => {
-                             let buttons = vec![vec![InlineKeyboardButton::url("🔗 Pay with CryptoBot", url.parse().unwrap())]];
-                             let _ = bot.answer_callback_query(&callback_id).await;
-                             if let Some(msg) = q.message {
-                                 let _ = bot.send_message(msg.chat().id, format!("💳 Invoice for *${:.2}* created\\!", amount)).parse_mode(ParseMode::MarkdownV2).reply_markup(InlineKeyboardMarkup::new(buttons)).await;
-                             }
-                        }
-                        Err(e) => {
-                             let _ = bot.answer_callback_query(&callback_id).text(format!("Error: {}", e)).show_alert(true).await;
-                        }
-                    }
+            "balance_statement" => {
+                let _ = bot.answer_callback_query(&callback_id).await;
+                let Some(u) = state.store_service.get_user_by_tg_id(tg_id).await.ok().flatten() else {
+                    return Ok(());
+                };
+                let entries = crate::services::ledger_service::LedgerService::statement(&state.pool, u.id, 15).await.unwrap_or_default();
+
+                let mut response = format!("📜 *Balance Statement*\n\nCurrent balance: *{}*\n\n", escape_md(&Money::usd_cents(u.balance).to_string()));
+                if entries.is_empty() {
+                    response.push_str("No balance movements yet\\.");
                 }
-            }
-            np if np.starts_with("np_") => {
-                let amount = np.strip_prefix("np_").unwrap_or("0").parse::<f64>().unwrap_or(0.0);
-                let user_db = state.store_service.get_user_by_tg_id(tg_id).await.ok().flatten();
-                if let Some(u) = user_db {
-                    match state.pay_service.create_nowpayments_invoice(u.id, amount, PaymentType::BalanceTopup).await {
-                        Ok(url) => {
-                             let buttons = vec![vec![InlineKeyboardButton::url("🔗 Pay with NOWPayments", url.parse().unwrap())]];
-                             let _ = bot.answer_callback_query(&callback_id).await;
-                             if let Some(msg) = q.message {
-                                 let _ = bot.send_message(msg.chat().id, format!("💳 Invoice for *${:.2}* created\\!", amount)).parse_mode(ParseMode::MarkdownV2).reply_markup(InlineKeyboardMarkup::new(buttons)).await;
-                             }
-                        }
-                        Err(e) => {
-                             let _ = bot.answer_callback_query(&callback_id).text(format!("Error: {}", e)).show_alert(true).await;
-                        }
-                    }
+                for entry in &entries {
+                    let label = match entry.reason.as_str() {
+                        "topup" => "💳 Top-up",
+                        "purchase" => "🛒 Purchase",
+                        "renewal" => "🔄 Auto-renewal",
+                        "refund" => "↩️ Refund",
+                        "referral" => "🎁 Referral bonus",
+                        "reversal" => "💸 Payment reversed",
+                        "promo" => "🎟 Promo bonus",
+                        "adjustment" => "🛠 Adjustment",
+                        _ => "📌 Opening balance",
+                    };
+                    let sign = if entry.amount > 0 { "+" } else { "" };
+                    response.push_str(&format!(
+                        "{} *{}* → {}\n_{}_\n\n",
+                        label,
+                        escape_md(&format!("{}{}", sign, entry.money())),
+                        escape_md(&entry.balance().to_string()),
+                        escape_md(&entry.created_at.format("%Y-%m-%d %H:%M").to_string())
+                    ));
+                }
+
+                if let Some(msg) = q.message {
+                    let _ = bot.send_message(msg.chat().id, response)
+                        .parse_mode(ParseMode::MarkdownV2)
+                        .reply_markup(InlineKeyboardMarkup::new(vec![vec![InlineKeyboardButton::callback("💳 Top-up Balance", "topup_menu")]]))
+                        .await;
                 }
             }
-            cp if cp.starts_with("cp_") => {
-                let amount = cp.strip_prefix("cp_").unwrap_or("0").parse::<f64>().unwrap_or(0.0);
-                let user_db = state.store_service.get_user_by_tg_id(tg_id).await.ok().flatten();
-                if let Some(u) = user_db {
-                    match state.pay_service.create_crystalpay_invoice(u.id, amount, PaymentType::BalanceTopup).await {
-                        Ok(url) => {
-                             let buttons = vec![vec![InlineKeyboardButton::url("🔗 Pay with Card (CrystalPay)", url.parse().unwrap())]];
-                             let _ = bot.answer_callback_query(&callback_id).await;
-                             if let Some(msg) = q.message {
-                                 let _ = bot.send_message(msg.chat().id, format!("💳 Invoice for *${:.2}* created\\!", amount)).parse_mode(ParseMode::MarkdownV2).reply_markup(InlineKeyboardMarkup::new(buttons)).await;
-                             }
-                        }
-                        Err(e) => {
-                             let _ = bot.answer_callback_query(&callback_id).text(format!("Error: {}", e)).show_alert(true).await;
-                        }
-                    }
+
+            // Amount Selection Menu
+            pay if pay.starts_with("pay_") => {
+                let provider_id = pay.strip_prefix("pay_").unwrap_or_default();
+                let provider = state.pay_service.providers().await.into_iter().find(|p| p.spec().id == provider_id);
+                if let Some(provider) = provider && let Some(msg) = q.message {
+                    let text = format!("🔹 *Select amount for {}:*", escape_md(provider.spec().name));
+                    let _ = bot.edit_message_text(msg.chat().id, msg.id(), text)
+                        .parse_mode(ParseMode::MarkdownV2).reply_markup(make_amount_keyboard(&format!("inv_{}", provider_id))).await;
                 }
             }
-            str_pay if str_pay.starts_with("str_") => {
-                let amount = str_pay.strip_prefix("str_").unwrap_or("0").parse::<f64>().unwrap_or(0.0);
+
+            // Invoice creation: inv_<provider>_<amount>
+            inv if inv.starts_with("inv_") => {
+                let Some((provider_id, amount)) = inv.strip_prefix("inv_").and_then(|s| s.rsplit_once('_')) else {
+                    return Ok(());
+                };
+                let Some(amount) = Money::parse(amount, Currency::Usd) else {
+                    return Ok(());
+                };
                 let user_db = state.store_service.get_user_by_tg_id(tg_id).await.ok().flatten();
                 if let Some(u) = user_db {
-                    match state.pay_service.create_stripe_session(u.id, amount, PaymentType::BalanceTopup).await {
-                        Ok(url) => {
-                             let buttons = vec![vec![InlineKeyboardButton::url("🔗 Pay with Stripe", url.parse().unwrap())]];
+                    match state.pay_service.create_invoice(provider_id, u.id, amount, PaymentType::BalanceTopup).await {
+                        Ok(invoice) => {
+                             let Ok(url) = invoice.url.parse() else {
+                                 let _ = bot.answer_callback_query(&callback_id).text("Error: payment provider returned an invalid link").show_alert(true).await;
+                                 return Ok(());
+                             };
+                             let buttons = vec![vec![InlineKeyboardButton::url("🔗 Pay", url)]];
                              let _ = bot.answer_callback_query(&callback_id).await;
                              if let Some(msg) = q.message {
-                                 let _ = bot.send_message(msg.chat().id, format!("💳 Invoice for *${:.2}* created\\!", amount)).parse_mode(ParseMode::MarkdownV2).reply_markup(InlineKeyboardMarkup::new(buttons)).await;
+                                 let _ = bot.send_message(msg.chat().id, format!("💳 Invoice for *{}* created\\!", escape_md(&amount.to_string()))).parse_mode(ParseMode::MarkdownV2).reply_markup(InlineKeyboardMarkup::new(buttons)).await;
                              }
                         }
                         Err(e) => {
@@ -251,35 +327,6 @@ pub async fn callback_handler(
                     }
                 }
             }
-            
-            star if star.starts_with("star_") => {
-                let amount_usd = star.strip_prefix("star_").unwrap_or("0").parse::<f64>().unwrap_or(0.0);
-                // 1 USD approx 50 XTR (Telegram Stars). Rate varies.
-                // Official: 1 XTR ~ $0.02 USD (purchase cost for user usually higher).
-                // Let's charge 50 XTR per $1 USD balance.
-                let xtr_amount = (amount_usd * 50.0) as u32; 
-                
-                let user_db = state.store_service.get_user_by_tg_id(tg_id).await.ok().flatten();
-                if let Some(u) = user_db {
-                    let payload = PaymentType::BalanceTopup.to_payload_string(u.id);
-                    let prices = vec![LabeledPrice { label: "Top-up".to_string(), amount: xtr_amount as u32 }];
-                    
-                    if let Some(msg) = q.message {
-                        // Delete menu message
-                        let _ = bot.delete_message(msg.chat().id, msg.id()).await;
-                        
-                        let _ = bot.send_invoice(
-                            msg.chat().id,
-                            "Balance Top-up",
-                            format!("Top-up balance by ${}", amount_usd),
-                            payload,
-                            "", // Provider token empty for XTR
-                            "XTR",
-                            prices
-                        ).await;
-                    }
-                }
-            }
 
             get_links if get_links.starts_with("get_links_") => {
                     let sub_id = get_links.strip_prefix("get_links_").unwrap_or("0").parse::<i64>().unwrap_or(0);
@@ -321,7 +368,8 @@ pub async fn callback_handler(
                 
                 let user_db = state.store_service.get_user_by_tg_id(tg_id).await.ok().flatten();
                 if let Some(u) = user_db {
-                    match state.store_service.generate_subscription_file(u.id).await {
+                    let routing = state.routing.policy_for(u.id, None).await;
+                    match state.store_service.generate_subscription_file(u.id, &routing).await {
                         Ok(json_content) => {
                             let data = json_content.into_bytes();
                             let input_file = teloxide::types::InputFile::memory(data).file_name("exarobot_v2_profile.json");
@@ -361,6 +409,96 @@ pub async fn callback_handler(
                 }
             }
 
+            awg if awg.starts_with("awg_conf_") => {
+                let sub_id = awg.strip_prefix("awg_conf_").unwrap_or("0").parse::<i64>().unwrap_or(0);
+                let _ = bot.answer_callback_query(&callback_id).text("Generating AmneziaWG config...").await;
+
+                let user_db = state.store_service.get_user_by_tg_id(tg_id).await.ok().flatten();
+                if let Some(u) = user_db {
+                    let sub = sqlx::query_as::<_, crate::models::store::Subscription>(
+                        "SELECT * FROM subscriptions WHERE id = ? AND user_id = ? AND status = 'active'"
+                    )
+                    .bind(sub_id)
+                    .bind(u.id)
+                    .fetch_optional(&state.pool)
+                    .await
+                    .ok()
+                    .flatten();
+
+                    let configs = match &sub {
+                        Some(sub) => state.store_service.awg().client_configs(sub).await,
+                        None => Ok(Vec::new()),
+                    };
+                    match configs {
+                        Ok(configs) if !configs.is_empty() => {
+                            for config in configs {
+                                let file_name = crate::subscription::awg_file_name(&config.node_name);
+                                let _ = bot.send_document(ChatId(tg_id), teloxide::types::InputFile::memory(config.conf.clone().into_bytes()).file_name(file_name))
+                                    .caption(format!("🛡 <b>AmneziaWG · {}</b>\n\nImport this file in the Amnezia or AmneziaWG app, or scan the QR code below.", config.node_name))
+                                    .parse_mode(ParseMode::Html)
+                                    .await;
+                                if let Some(png) = crate::subscription_page::qr_png(&config.conf) {
+                                    let _ = bot.send_photo(ChatId(tg_id), teloxide::types::InputFile::memory(png).file_name("amneziawg.png")).await;
+                                }
+                            }
+                        }
+                        Ok(_) => {
+                            let _ = bot.send_message(ChatId(tg_id), "ℹ️ Your plan has no AmneziaWG servers.").await;
+                        }
+                        Err(e) => {
+                            error!("Failed to build AmneziaWG configs for sub {}: {}", sub_id, e);
+                            let _ = bot.send_message(ChatId(tg_id), "❌ Failed to generate AmneziaWG config.").await;
+                        }
+                    }
+                }
+            }
+
+            reset if reset.starts_with("reset_link_confirm_") => {
+                let sub_id = reset.strip_prefix("reset_link_confirm_").unwrap_or("0").parse::<i64>().unwrap_or(0);
+                let user_db = state.store_service.get_user_by_tg_id(tg_id).await.ok().flatten();
+
+                if let Some(u) = user_db {
+                    match crate::subscription::reset_link(&state, u.id, sub_id).await {
+                        Ok(sub) => {
+                            let _ = bot.answer_callback_query(&callback_id).text("✅ Link reset").await;
+                            if let Some(msg) = q.message {
+                                let response = format!(
+                                    "🔄 *Subscription Link Reset*\n\nYour new link:\n`{}`\n\nThe old link and configs no longer work\\. \
+                                    Remove the old profile from your apps and import the new link\\.",
+                                    crate::subscription::public_url(&sub.subscription_uuid)
+                                );
+                                let _ = bot.edit_message_text(msg.chat().id, msg.id(), response)
+                                    .parse_mode(ParseMode::MarkdownV2)
+                                    .await;
+                            }
+                        }
+                        Err(e) => {
+                            error!("Link reset failed for sub {}: {}", sub_id, e);
+                            let _ = bot.answer_callback_query(&callback_id).text(format!("❌ {}", e)).show_alert(true).await;
+                        }
+                    }
+                }
+            }
+
+            reset if reset.starts_with("reset_link_") => {
+                let sub_id = reset.strip_prefix("reset_link_").unwrap_or("0");
+                let _ = bot.answer_callback_query(&callback_id).await;
+                if let Some(msg) = q.message {
+                    let buttons = vec![vec![
+                        InlineKeyboardButton::callback("✅ Reset", format!("reset_link_confirm_{}", sub_id)),
+                        InlineKeyboardButton::callback("« Cancel", "myservices_page_0"),
+                    ]];
+                    let _ = bot.send_message(
+                        msg.chat().id,
+                        "🔄 *Reset subscription link?*\n\nUse this if your link leaked\\. You get a new link and new keys; \
+                        every device using the old one is disconnected and has to import the new link\\.",
+                    )
+                    .parse_mode(ParseMode::MarkdownV2)
+                    .reply_markup(InlineKeyboardMarkup::new(buttons))
+                    .await;
+                }
+            }
+
             "my_gifts" => {
                 let user_db = state.store_service.get_user_by_tg_id(tg_id).await.ok().flatten();
                 if let Some(u) = user_db {
@@ -473,6 +611,10 @@ pub async fn callback_handler(
                     if let Some(desc) = &plan.description {
                         text.push_str(&format!("_{}_\n", escape_md(desc)));
                     }
+                    if let Ok(Some(u)) = state.store_service.get_user_by_tg_id(tg_id).await
+                        && let Ok(Some(promo)) = state.promos.reserved(u.id).await {
+                        text.push_str(&format!("\n🏷 Promo *{}*: {} off at checkout\n", escape_md(&promo.code), escape_md(&promo.discount_label())));
+                    }
 
                     let mut buttons = Vec::new();
                     
@@ -537,7 +679,7 @@ pub async fn callback_handler(
                             let sub = &sorted_subs[page];
 
                             let mut response = "🔐 *MY SERVICES*\n\n".to_string();
-                            let status_icon = if sub.sub.status == "active" { "✅" } else { "⏳" };
+                            let status_icon = match sub.sub.status.as_str() { "active" => "✅", "limited" => "🚫", _ => "⏳" };
                             response.push_str(&format!("🔹 *Subscription \\#{}/{:}*\n", page + 1, total_pages));
                             response.push_str(&format!("   💎 *Plan:* {}\n", escape_md(&sub.plan_name)));
                             if let Some(desc) = &sub.plan_description {
@@ -547,17 +689,18 @@ pub async fn callback_handler(
 
                             // Traffic
                             let used_gb = sub.sub.used_traffic as f64 / 1024.0 / 1024.0 / 1024.0;
-                            if let Some(limit) = sub.traffic_limit_gb {
-                                    if limit == 0 {
-                                        response.push_str(&format!("   📊 *Traffic:* `{:.2} GB / ∞`\n", used_gb));
-                                    } else {
-                                        response.push_str(&format!("   📊 *Traffic:* `{:.2} GB / {} GB`\n", used_gb, limit));
-                                    }
+                            if let Some(quota) = sub.traffic_quota_bytes() {
+                                response.push_str(&format!("   📊 *Traffic:* `{:.2} GB / {} GB`\n", used_gb, quota / 1024 / 1024 / 1024));
+                                if let Some(reset) = sub.next_traffic_reset() {
+                                    response.push_str(&format!("   🔄 *Traffic resets:* `{}`\n", reset.format("%Y-%m-%d")));
+                                }
+                            } else if sub.traffic_limit_gb.is_some() {
+                                response.push_str(&format!("   📊 *Traffic:* `{:.2} GB / ∞`\n", used_gb));
                             } else {
-                                    response.push_str(&format!("   📊 *Traffic Used:* `{:.2} GB`\n", used_gb));
+                                response.push_str(&format!("   📊 *Traffic Used:* `{:.2} GB`\n", used_gb));
                             }
                             
-                            if sub.sub.status == "active" {
+                            if sub.sub.status == "active" || sub.sub.status == "limited" {
                                 response.push_str(&format!("   ⌛ *Expires:* `{}`\n", sub.sub.expires_at.format("%Y-%m-%d")));
                             } else {
                                 let duration = sub.sub.expires_at - sub.sub.created_at;
@@ -574,6 +717,13 @@ pub async fn callback_handler(
                                 // Edit Note Button
                             buttons.push(vec![InlineKeyboardButton::callback("📝 Edit Note", format!("edit_note_{}", sub.sub.id))]);
 
+                            if sub.sub.status == "active" {
+                                buttons.push(vec![
+                                    InlineKeyboardButton::callback("🛡 AmneziaWG", format!("awg_conf_{}", sub.sub.id)),
+                                    InlineKeyboardButton::callback("🔄 Reset Link", format!("reset_link_{}", sub.sub.id)),
+                                ]);
+                            }
+
                             // Action Buttons
                             if sub.sub.status == "active" {
                                 buttons.push(vec![
@@ -581,6 +731,19 @@ pub async fn callback_handler(
                                     InlineKeyboardButton::callback("📄 JSON Profile", format!("get_config_{}", sub.sub.id)),
                                     InlineKeyboardButton::callback("⏳ Extend", format!("extend_sub_{}", sub.sub.id))
                                 ]);
+                                let renew_label = if sub.sub.auto_renew.unwrap_or(false) { "🔁 Auto-Renew: On" } else { "🔁 Auto-Renew: Off" };
+                                buttons.push(vec![
+                                    InlineKeyboardButton::callback(renew_label, format!("renew_menu_{}", sub.sub.id)),
+                                    InlineKeyboardButton::callback("🔀 Change Plan", format!("chg_plan_{}", sub.sub.id)),
+                                ]);
+                                if sub.traffic_quota_bytes().is_some() {
+                                    buttons.push(vec![InlineKeyboardButton::callback("➕ Add Traffic", format!("traffic_packs_{}", sub.sub.id))]);
+                                }
+                            } else if sub.sub.status == "limited" {
+                                buttons.push(vec![
+                                    InlineKeyboardButton::callback("➕ Add Traffic", format!("traffic_packs_{}", sub.sub.id)),
+                                    InlineKeyboardButton::callback("🔀 Change Plan", format!("chg_plan_{}", sub.sub.id)),
+                                ]);
                             } else if sub.sub.status == "pending" {
                                 buttons.push(vec![
                                     InlineKeyboardButton::callback("▶️ Activate", format!("activate_{}", sub.sub.id)),
@@ -1006,12 +1169,139 @@ pub async fn callback_handler(
                 }
             }
 
-            // === Quick Wins: Auto-Renewal Toggle ===
+            // === Traffic Packs ===
+            packs if packs.starts_with("traffic_packs_") => {
+                let sub_id: i64 = packs.strip_prefix("traffic_packs_").and_then(|s| s.parse().ok()).unwrap_or(0);
+
+                let packs = state.store_service.get_traffic_packs().await.unwrap_or_default();
+                if packs.is_empty() {
+                    let _ = bot.answer_callback_query(&callback_id).text("No traffic packs are available right now").show_alert(true).await;
+                    return Ok(());
+                }
+                let _ = bot.answer_callback_query(&callback_id).await;
+
+                let text = "➕ *Add Traffic*\n\n\
+                    Extra traffic is added to your current cycle and paid from your balance\\. \
+                    It does not carry over to the next reset\\.";
+                let buttons: Vec<Vec<InlineKeyboardButton>> = packs.iter().map(|pack| {
+                    vec![InlineKeyboardButton::callback(
+                        format!("+{} GB · {}", pack.traffic_gb, Money::usd_cents(pack.price)),
+                        format!("buy_pack_{}_{}", sub_id, pack.id)
+                    )]
+                }).collect();
+
+                if let Some(msg) = q.message {
+                    let _ = bot.send_message(msg.chat().id, text)
+                        .parse_mode(ParseMode::MarkdownV2)
+                        .reply_markup(InlineKeyboardMarkup::new(buttons))
+                        .await;
+                }
+            }
+
+            buy if buy.starts_with("buy_pack_") => {
+                let mut ids = buy.strip_prefix("buy_pack_").unwrap_or("").split('_').filter_map(|s| s.parse::<i64>().ok());
+                let (Some(sub_id), Some(pack_id)) = (ids.next(), ids.next()) else { return Ok(()); };
+
+                let Some(u) = state.store_service.get_user_by_tg_id(tg_id).await.ok().flatten() else { return Ok(()); };
+                match crate::subscription::add_traffic_pack(&state, u.id, sub_id, pack_id).await {
+                    Ok((sub, pack)) => {
+                        let _ = bot.answer_callback_query(&callback_id).text("✅ Traffic added").await;
+                        let mut text = format!("✅ *\\+{} GB added*\n\n{} was charged from your balance\\.",
+                            pack.traffic_gb, escape_md(&Money::usd_cents(pack.price).to_string()));
+                        if sub.status == "active" {
+                            text.push_str("\nYour subscription is active\\.");
+                        }
+                        if let Some(msg) = q.message {
+                            let _ = bot.send_message(msg.chat().id, text)
+                                .parse_mode(ParseMode::MarkdownV2)
+                                .reply_markup(InlineKeyboardMarkup::new(vec![vec![
+                                    InlineKeyboardButton::callback("« Back to Services", "myservices_page_0")
+                                ]]))
+                                .await;
+                        }
+                    }
+                    Err(e) => {
+                        let _ = bot.answer_callback_query(&callback_id).text(format!("❌ {}", e)).show_alert(true).await;
+                    }
+                }
+            }
+
+            // === Auto-Renewal: duration picker ===
+            menu if menu.starts_with("renew_menu_") => {
+                let sub_id: i64 = menu.strip_prefix("renew_menu_").and_then(|s| s.parse().ok()).unwrap_or(0);
+                let _ = bot.answer_callback_query(&callback_id).await;
+
+                let Some(u) = state.store_service.get_user_by_tg_id(tg_id).await.ok().flatten() else { return Ok(()); };
+                match state.store_service.get_renewal_options(u.id, sub_id).await {
+                    Ok((sub, durations)) => {
+                        let enabled = sub.auto_renew.unwrap_or(false);
+                        let current = state.store_service.renewal_duration(sub.plan_id, sub.duration_id).await.ok().flatten();
+
+                        let mut text = "🔁 *Auto\\-Renewal*\n\n".to_string();
+                        if enabled {
+                            text.push_str("Status: ✅ On\n");
+                            if let Some(dur) = &current {
+                                text.push_str(&format!("Renews for *{} days* at *{}*, charged from your balance 24h before expiry\\.\n",
+                                    dur.duration_days, escape_md(&Money::usd_cents(dur.price).to_string())));
+                            }
+                        } else {
+                            text.push_str("Status: 🔴 Off\n");
+                        }
+                        text.push_str("\nPick the period to renew with:");
+
+                        let mut buttons: Vec<Vec<InlineKeyboardButton>> = durations.iter().map(|dur| {
+                            let mark = if enabled && current.as_ref().map(|c| c.id) == Some(dur.id) { "✅ " } else { "" };
+                            vec![InlineKeyboardButton::callback(
+                                format!("{}{}d - {}", mark, dur.duration_days, Money::usd_cents(dur.price)),
+                                format!("renew_dur_{}_{}", sub.id, dur.id)
+                            )]
+                        }).collect();
+                        if enabled {
+                            buttons.push(vec![InlineKeyboardButton::callback("🔴 Turn Off", format!("toggle_renew_{}", sub.id))]);
+                        }
+
+                        if let Some(msg) = q.message {
+                            let _ = bot.send_message(msg.chat().id, text)
+                                .parse_mode(ParseMode::MarkdownV2)
+                                .reply_markup(InlineKeyboardMarkup::new(buttons))
+                                .await;
+                        }
+                    }
+                    Err(e) => error!("Failed to load renewal options for sub {}: {}", sub_id, e),
+                }
+            }
+
+            pick if pick.starts_with("renew_dur_") => {
+                let mut ids = pick.strip_prefix("renew_dur_").unwrap_or("").split('_').filter_map(|s| s.parse::<i64>().ok());
+                let (Some(sub_id), Some(duration_id)) = (ids.next(), ids.next()) else { return Ok(()); };
+
+                let Some(u) = state.store_service.get_user_by_tg_id(tg_id).await.ok().flatten() else { return Ok(()); };
+                match state.store_service.set_renewal_duration(u.id, sub_id, duration_id).await {
+                    Ok(dur) => {
+                        let _ = bot.answer_callback_query(&callback_id).await;
+                        if let Some(msg) = q.message {
+                            let text = format!(
+                                "✅ *Auto\\-Renewal Enabled*\n\nYour subscription will renew for *{} days* at *{}* 24h before expiration if you have sufficient balance\\.",
+                                dur.duration_days, escape_md(&Money::usd_cents(dur.price).to_string())
+                            );
+                            let _ = bot.edit_message_text(msg.chat().id, msg.id(), text)
+                                .parse_mode(ParseMode::MarkdownV2)
+                                .await;
+                        }
+                    }
+                    Err(e) => {
+                        error!("Failed to set renewal duration: {}", e);
+                        let _ = bot.answer_callback_query(&callback_id).text("❌ This period is no longer available").show_alert(true).await;
+                    }
+                }
+            }
+
             toggle if toggle.starts_with("toggle_renew_") => {
                 let sub_id: i64 = toggle.strip_prefix("toggle_renew_").and_then(|s| s.parse().ok()).unwrap_or(0);
                 let _ = bot.answer_callback_query(&callback_id).await;
                 
-                match state.store_service.toggle_auto_renewal(sub_id).await {
+                let Some(u) = state.store_service.get_user_by_tg_id(tg_id).await.ok().flatten() else { return Ok(()); };
+                match state.store_service.toggle_auto_renewal(u.id, sub_id).await {
                     Ok(new_state) => {
                         let status_text = if new_state {
                             "✅ *Auto\\-Renewal Enabled*\n\nYour subscription will automatically renew 24h before expiration if you have sufficient balance\\."
@@ -1036,6 +1326,113 @@ pub async fn callback_handler(
                 }
             }
 
+            // === Plan change with proration ===
+            change if change.starts_with("chg_plan_") => {
+                let sub_id: i64 = change.strip_prefix("chg_plan_").and_then(|s| s.parse().ok()).unwrap_or(0);
+                let Some(u) = state.store_service.get_user_by_tg_id(tg_id).await.ok().flatten() else { return Ok(()); };
+
+                match state.store_service.plan_change_options(u.id, sub_id).await {
+                    Ok(options) if !options.is_empty() => {
+                        let _ = bot.answer_callback_query(&callback_id).await;
+                        let text = "🔀 *Change Plan*\n\nThe unused part of your current subscription is credited towards the new plan\\. \
+                            Your link and apps keep working\\.\n\nPick a plan and period:";
+                        let buttons: Vec<Vec<InlineKeyboardButton>> = options.iter().map(|o| {
+                            let cost = if o.due >= 0 {
+                                format!("pay {}", Money::usd_cents(o.due))
+                            } else {
+                                format!("{} back", Money::usd_cents(-o.due))
+                            };
+                            vec![InlineKeyboardButton::callback(
+                                format!("{} · {}d · {}", o.plan_name, o.days, cost),
+                                format!("chg_dur_{}_{}", sub_id, o.duration_id)
+                            )]
+                        }).collect();
+
+                        if let Some(msg) = q.message {
+                            let _ = bot.send_message(msg.chat().id, text)
+                                .parse_mode(ParseMode::MarkdownV2)
+                                .reply_markup(InlineKeyboardMarkup::new(buttons))
+                                .await;
+                        }
+                    }
+                    Ok(_) => {
+                        let _ = bot.answer_callback_query(&callback_id).text("No other plans available").show_alert(true).await;
+                    }
+                    Err(e) => {
+                        let _ = bot.answer_callback_query(&callback_id).text(format!("❌ {}", e)).show_alert(true).await;
+                    }
+                }
+            }
+
+            change if change.starts_with("chg_dur_") => {
+                let mut ids = change.strip_prefix("chg_dur_").unwrap_or("").split('_').filter_map(|s| s.parse::<i64>().ok());
+                let (Some(sub_id), Some(duration_id)) = (ids.next(), ids.next()) else { return Ok(()); };
+                let Some(u) = state.store_service.get_user_by_tg_id(tg_id).await.ok().flatten() else { return Ok(()); };
+
+                match state.store_service.quote_plan_change(u.id, sub_id, duration_id).await {
+                    Ok(quote) => {
+                        let _ = bot.answer_callback_query(&callback_id).await;
+                        let settle = if quote.due >= 0 {
+                            format!("💳 *To pay:* {}", escape_md(&Money::usd_cents(quote.due).to_string()))
+                        } else {
+                            format!("💰 *Back to balance:* {}", escape_md(&Money::usd_cents(-quote.due).to_string()))
+                        };
+                        let text = format!(
+                            "🔀 *Switch to {}*\n\n\
+                             📅 Period: {} days, until {}\n\
+                             🏷 Price: {}\n\
+                             ↩️ Unused time credited: {}\n\
+                             {}\n\n\
+                             Paid from your balance: *{}*",
+                            escape_md(&quote.plan_name),
+                            quote.days,
+                            escape_md(&quote.expires_at.format("%Y-%m-%d").to_string()),
+                            escape_md(&Money::usd_cents(quote.price).to_string()),
+                            escape_md(&Money::usd_cents(quote.credit).to_string()),
+                            settle,
+                            escape_md(&Money::usd_cents(u.balance).to_string())
+                        );
+                        let buttons = vec![vec![InlineKeyboardButton::callback("✅ Confirm", format!("chg_ok_{}_{}", sub_id, duration_id))]];
+
+                        if let Some(msg) = q.message {
+                            let _ = bot.edit_message_text(msg.chat().id, msg.id(), text)
+                                .parse_mode(ParseMode::MarkdownV2)
+                                .reply_markup(InlineKeyboardMarkup::new(buttons))
+                                .await;
+                        }
+                    }
+                    Err(e) => {
+                        let _ = bot.answer_callback_query(&callback_id).text(format!("❌ {}", e)).show_alert(true).await;
+                    }
+                }
+            }
+
+            change if change.starts_with("chg_ok_") => {
+                let mut ids = change.strip_prefix("chg_ok_").unwrap_or("").split('_').filter_map(|s| s.parse::<i64>().ok());
+                let (Some(sub_id), Some(duration_id)) = (ids.next(), ids.next()) else { return Ok(()); };
+                let Some(u) = state.store_service.get_user_by_tg_id(tg_id).await.ok().flatten() else { return Ok(()); };
+
+                match crate::subscription::change_plan(&state, u.id, sub_id, duration_id).await {
+                    Ok((sub, quote)) => {
+                        let _ = bot.answer_callback_query(&callback_id).await;
+                        let text = format!(
+                            "✅ *Plan Changed*\n\n💎 Plan: {}\n⌛ Expires: {}\n\nYour subscription link stays the same\\.",
+                            escape_md(&quote.plan_name),
+                            escape_md(&sub.expires_at.format("%Y-%m-%d").to_string())
+                        );
+                        if let Some(msg) = q.message {
+                            let _ = bot.edit_message_text(msg.chat().id, msg.id(), text)
+                                .parse_mode(ParseMode::MarkdownV2)
+                                .await;
+                        }
+                    }
+                    Err(e) => {
+                        error!("Plan change of sub {} failed: {}", sub_id, e);
+                        let _ = bot.answer_callback_query(&callback_id).text(format!("❌ {}", e)).show_alert(true).await;
+                    }
+                }
+            }
+
             // === Quick Wins: Free Trial Activation ===
             "start_trial" => {
                 let _ = bot.answer_callback_query(&callback_id).await;
@@ -15,19 +15,19 @@ pub async fn message_handler(
     let tg_id = msg.chat.id.0 as i64;
 
     if let Some(payment) = msg.successful_payment() {
-         let amount_xtr = payment.total_amount as f64;
-         // 1 USD approx 50 XTR
-         let amount_usd = amount_xtr / 50.0;
-         info!("Processing Stars Payment: {} XTR (${:.2})", amount_xtr, amount_usd);
-         
-         match state.pay_service.process_any_payment(amount_usd, "stars", Some(payment.provider_payment_charge_id.clone()), &payment.invoice_payload).await {
+         let amount = crate::models::money::Money::new(payment.total_amount as i64, crate::models::money::Currency::Xtr);
+         info!("Processing Stars Payment: {}", amount);
+
+         // Stars have no webhook: Telegram's own charge id identifies the payment
+         let raw = serde_json::to_string(payment).unwrap_or_default();
+         match state.pay_service.record_payment(crate::services::payments::stars::SPEC.id, &payment.telegram_payment_charge_id, amount, &payment.invoice_payload, &raw).await {
              Ok(_) => { 
                  // Log successful payment
                  let _ = LoggingService::log_user(
                      &state.pool,
                      Some(tg_id),
                      "payment_stars",
-                     &format!("Stars payment successful: {} XTR (${:.2})", amount_xtr, amount_usd),
+                     &format!("Stars payment successful: {}", amount),
                      None
                  ).await;
                  
@@ -272,7 +272,19 @@ pub async fn message_handler(
                                     Err(e) => { let _ = bot.send_message(msg.chat.id, format!("❌ Redemption Failed: {}", escape_md(&e.to_string()))).parse_mode(ParseMode::MarkdownV2).await; }
                                 }
                             } else {
-                                let _ = bot.send_message(msg.chat.id, "❌ Invalid code format\\.").parse_mode(ParseMode::MarkdownV2).await;
+                                match state.promos.redeem(u.id, code).await {
+                                    Ok(redeemed) => {
+                                        let mut response = format!("✅ *Promo Code {} Applied\\!*\n", escape_md(&redeemed.code));
+                                        if redeemed.bonus > 0 {
+                                            response.push_str(&format!("\n💰 {} added to your balance\\.", escape_md(&crate::models::money::Money::usd_cents(redeemed.bonus).to_string())));
+                                        }
+                                        if let Some(discount) = &redeemed.discount {
+                                            response.push_str(&format!("\n🏷 {} off your next plan purchase\\.", escape_md(discount)));
+                                        }
+                                        let _ = bot.send_message(msg.chat.id, response).parse_mode(ParseMode::MarkdownV2).await;
+                                    }
+                                    Err(e) => { let _ = bot.send_message(msg.chat.id, format!("❌ {}", escape_md(&e.to_string()))).parse_mode(ParseMode::MarkdownV2).await; }
+                                }
                             }
                         }
                         return Ok(());
@@ -460,7 +472,7 @@ pub async fn message_handler(
                  }
             }
             "/enter_promo" | "🎁 Redeem Code" => {
-                let _ = bot.send_message(msg.chat.id, "🎟 *Redeem Gift Code*\n\nPlease reply to this message with your code (e.g., `EXA-GIFT-XYZ`).")
+                let _ = bot.send_message(msg.chat.id, "🎟 *Enter your Promo Code*\n\nReply to this message with a promo code or a gift code \\(e\\.g\\. `EXA-GIFT-XYZ`\\)\\.")
                     .parse_mode(ParseMode::MarkdownV2)
                     .reply_markup(ForceReply::new().selective())
                     .await;
@@ -483,6 +495,10 @@ pub async fn message_handler(
                     if let Some(desc) = &plan.description {
                         text.push_str(&format!("_{}_\n", escape_md(desc)));
                     }
+                    if let Some(u) = &user_db
+                        && let Ok(Some(promo)) = state.promos.reserved(u.id).await {
+                        text.push_str(&format!("\n🏷 Promo *{}*: {} off at checkout\n", escape_md(&promo.code), escape_md(&promo.discount_label())));
+                    }
 
                     let mut buttons = Vec::new();
                     
@@ -549,8 +565,11 @@ pub async fn message_handler(
                         user.tg_id, price_major, price_minor
                     );
 
-                    let mut buttons = Vec::new();
-                    buttons.push(vec![InlineKeyboardButton::callback("💳 Top-up Balance", "topup_menu")]);
+                    let buttons = vec![
+                        vec![InlineKeyboardButton::callback("💳 Top-up Balance", "topup_menu")],
+                        vec![InlineKeyboardButton::callback("📜 Balance Statement", "balance_statement")],
+                        vec![InlineKeyboardButton::callback("🌍 Routing", "routing_menu")],
+                    ];
 
                     let _ = bot.send_message(msg.chat.id, response)
                         .parse_mode(ParseMode::MarkdownV2)
@@ -609,7 +628,7 @@ pub async fn message_handler(
                         let total_pages = sorted_subs.len();
                         let sub = &sorted_subs[page];
 
-                        let status_icon = if sub.sub.status == "active" { "✅" } else { "⏳" };
+                        let status_icon = match sub.sub.status.as_str() { "active" => "✅", "limited" => "🚫", _ => "⏳" };
                         response.push_str(&format!("🔹 *Subscription \\#{}/{:}*\n", page + 1, total_pages));
                         response.push_str(&format!("   💎 *Plan:* {}\n", escape_md(&sub.plan_name)));
                         if let Some(desc) = &sub.plan_description {
@@ -619,17 +638,18 @@ pub async fn message_handler(
                         
                         // Traffic
                         let used_gb = sub.sub.used_traffic as f64 / 1024.0 / 1024.0 / 1024.0;
-                        if let Some(limit) = sub.traffic_limit_gb {
-                                if limit == 0 {
-                                    response.push_str(&format!("   📊 *Traffic:* `{:.2} GB / ∞`\n", used_gb));
-                                } else {
-                                    response.push_str(&format!("   📊 *Traffic:* `{:.2} GB / {} GB`\n", used_gb, limit));
-                                }
+                        if let Some(quota) = sub.traffic_quota_bytes() {
+                            response.push_str(&format!("   📊 *Traffic:* `{:.2} GB / {} GB`\n", used_gb, quota / 1024 / 1024 / 1024));
+                            if let Some(reset) = sub.next_traffic_reset() {
+                                response.push_str(&format!("   🔄 *Traffic resets:* `{}`\n", reset.format("%Y-%m-%d")));
+                            }
+                        } else if sub.traffic_limit_gb.is_some() {
+                            response.push_str(&format!("   📊 *Traffic:* `{:.2} GB / ∞`\n", used_gb));
                         } else {
-                                response.push_str(&format!("   📊 *Traffic Used:* `{:.2} GB`\n", used_gb));
+                            response.push_str(&format!("   📊 *Traffic Used:* `{:.2} GB`\n", used_gb));
                         }
 
-                        if sub.sub.status == "active" {
+                        if sub.sub.status == "active" || sub.sub.status == "limited" {
                             let duration = sub.sub.expires_at - sub.sub.created_at;
                             if duration.num_days() == 0 {
                                 response.push_str("   ⌛ *Expires:* `No expiration` \\(Traffic Plan\\)\n");
@@ -657,15 +677,32 @@ pub async fn message_handler(
 
                         // Connected Devices Button (for active subscriptions)
                         if sub.sub.status == "active" {
-                            buttons.push(vec![InlineKeyboardButton::callback("📱 Connected Devices", format!("devices_{}", sub.sub.id))]);
+                            buttons.push(vec![
+                                InlineKeyboardButton::callback("📱 Connected Devices", format!("devices_{}", sub.sub.id)),
+                                InlineKeyboardButton::callback("🔄 Reset Link", format!("reset_link_{}", sub.sub.id)),
+                            ]);
                         }
 
                         // Action Buttons
                         if sub.sub.status == "active" {
                             buttons.push(vec![
                                 InlineKeyboardButton::callback("🔗 Get Config", format!("get_links_{}", sub.sub.id)),
+                                InlineKeyboardButton::callback("🛡 AmneziaWG", format!("awg_conf_{}", sub.sub.id)),
                                 InlineKeyboardButton::callback("⏳ Extend", format!("extend_sub_{}", sub.sub.id))
                             ]);
+                            let renew_label = if sub.sub.auto_renew.unwrap_or(false) { "🔁 Auto-Renew: On" } else { "🔁 Auto-Renew: Off" };
+                            buttons.push(vec![
+                                InlineKeyboardButton::callback(renew_label, format!("renew_menu_{}", sub.sub.id)),
+                                InlineKeyboardButton::callback("🔀 Change Plan", format!("chg_plan_{}", sub.sub.id)),
+                            ]);
+                            if sub.traffic_quota_bytes().is_some() {
+                                buttons.push(vec![InlineKeyboardButton::callback("➕ Add Traffic", format!("traffic_packs_{}", sub.sub.id))]);
+                            }
+                        } else if sub.sub.status == "limited" {
+                            buttons.push(vec![
+                                InlineKeyboardButton::callback("➕ Add Traffic", format!("traffic_packs_{}", sub.sub.id)),
+                                InlineKeyboardButton::callback("🔀 Change Plan", format!("chg_plan_{}", sub.sub.id)),
+                            ]);
                         } else if sub.sub.status == "pending" {
                             buttons.push(vec![
                                 InlineKeyboardButton::callback("▶️ Activate", format!("activate_{}", sub.sub.id)),
@@ -1,6 +1,6 @@
 use teloxide::{
     prelude::*,
-    types::Update,
+    types::{Update, UpdateKind},
     dptree,
 };
 use tracing::{info, error};
@@ -37,6 +37,9 @@ pub async fn run_bot(bot: Bot, mut shutdown_signal: tokio::sync::broadcast::Rece
     let pre_checkout_handler = Update::filter_pre_checkout_query().endpoint(handlers::payment::pre_checkout_handler);
 
     let mut dispatcher = Dispatcher::builder(bot, dptree::entry()
+        .inspect(|upd: Update, state: crate::AppState| {
+            state.metrics.record_bot_update(update_kind(&upd));
+        })
         .branch(handler)
         .branch(callback_handler)
         .branch(pre_checkout_handler))
@@ -56,3 +59,13 @@ pub async fn run_bot(bot: Bot, mut shutdown_signal: tokio::sync::broadcast::Rece
         }
     }
 }
+
+/// Label for the bot_updates_total metric
+fn update_kind(upd: &Update) -> &'static str {
+    match upd.kind {
+        UpdateKind::Message(_) => "message",
+        UpdateKind::CallbackQuery(_) => "callback_query",
+        UpdateKind::PreCheckoutQuery(_) => "pre_checkout_query",
+        _ => "other",
+    }
+}
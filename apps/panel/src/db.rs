@@ -244,3 +244,15 @@ pub async fn init_db() -> Result<SqlitePool> {
 
     Ok(pool)
 }
+
+/// In-memory database with the full schema for tests; one connection, as each holds its own `:memory:` database
+#[cfg(test)]
+pub async fn test_pool() -> SqlitePool {
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    sqlx::migrate!("./migrations").run(&pool).await.unwrap();
+    pool
+}
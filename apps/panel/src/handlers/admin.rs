@@ -5,7 +5,7 @@ use axum::{
 use askama::Template;
 use serde::Deserialize;
 use crate::AppState;
-use crate::models::node::Node;
+use crate::models::node::{Node, NodeGroup};
 use crate::models::store::{Plan, User, Order};
 use crate::services::logging_service::LoggingService;
 use std::collections::HashMap;
@@ -47,22 +47,29 @@ pub struct TrialStats {
     pub channel_count: i64,
     pub active_count: i64,
 }
+/// One payment provider's credential inputs on the settings page
+pub struct GatewaySettingsView {
+    pub name: &'static str,
+    pub fields: Vec<GatewayFieldView>,
+}
+
+pub struct GatewayFieldView {
+    pub key: &'static str,
+    pub label: &'static str,
+    pub masked_value: String,
+}
+
 #[derive(Template)]
 #[template(path = "settings.html")]
 pub struct SettingsTemplate {
     pub username: String, // NEW
-    pub masked_payment_api_key: String,
-    pub masked_nowpayments_api_key: String,
-    pub masked_cryptomus_merchant_id: String,
-    pub masked_cryptomus_payment_api_key: String,
-    pub masked_aaio_merchant_id: String,
-    pub masked_aaio_secret_1: String,
-    pub masked_aaio_secret_2: String,
-    pub masked_lava_project_id: String,
-    pub masked_lava_secret_key: String,
+    pub gateways: Vec<GatewaySettingsView>,
     pub telegram_stars_enabled: bool,
     pub payment_ipn_url: String,
-    pub currency_rate: String,
+    pub exchange_rates: Vec<crate::services::rate_service::ExchangeRate>,
+    pub renewal_grace_hours: String,
+    pub renewal_retry_hours: String,
+    pub renewal_max_attempts: String,
     pub support_url: String,
     pub bot_username: String,
     pub brand_name: String,
@@ -73,6 +80,25 @@ pub struct SettingsTemplate {
     pub decoy_max_interval: String,
     pub kill_switch_enabled: bool,
     pub kill_switch_timeout: String,
+    pub node_logs_redact_ips: bool,
+    pub node_logs_retention_minutes: String,
+    pub firewall_enabled: bool,
+    pub firewall_panel_ips: String,
+    pub firewall_extra_ports: String,
+    pub firewall_abuse_ban_minutes: String,
+    pub sub_profile_title: String,
+    pub sub_profile_web_page_url: String,
+    pub sub_support_url: String,
+    pub sub_announce: String,
+    pub sub_update_interval_hours: String,
+    pub credential_rotation_cooldown_hours: String,
+    pub sub_access_retention_days: String,
+    pub sharing_window_hours: String,
+    pub sharing_max_networks: String,
+    pub sharing_max_clients: String,
+    pub sharing_action: String,
+    pub geoip_asn_db: String,
+    pub geoip_country_db: String,
     pub free_trial_days: i64,
     pub channel_trial_days: i64,
     pub required_channel_id: String,
@@ -98,18 +124,11 @@ pub struct BotTemplate {
 #[derive(Deserialize)]
 pub struct SaveSettingsForm {
     pub bot_token: Option<String>,
-    pub payment_api_key: Option<String>,
-    pub nowpayments_api_key: Option<String>,
-    pub lava_project_id: Option<String>,
-    pub lava_secret_key: Option<String>,
     pub telegram_stars_enabled: Option<String>,
-    pub cryptomus_merchant_id: Option<String>,
-    pub cryptomus_payment_api_key: Option<String>,
-    pub aaio_merchant_id: Option<String>,
-    pub aaio_secret_1: Option<String>,
-    pub aaio_secret_2: Option<String>,
     pub payment_ipn_url: Option<String>,
-    pub currency_rate: Option<String>,
+    pub renewal_grace_hours: Option<String>,
+    pub renewal_retry_hours: Option<String>,
+    pub renewal_max_attempts: Option<String>,
     pub support_url: Option<String>,
     pub bot_username: Option<String>,
     pub brand_name: Option<String>,
@@ -120,6 +139,28 @@ pub struct SaveSettingsForm {
     pub decoy_max_interval: Option<String>,
     pub kill_switch_enabled: Option<String>,
     pub kill_switch_timeout: Option<String>,
+    pub node_logs_redact_ips: Option<String>,
+    pub node_logs_retention_minutes: Option<String>,
+    pub firewall_enabled: Option<String>,
+    pub firewall_panel_ips: Option<String>,
+    pub firewall_extra_ports: Option<String>,
+    pub firewall_abuse_ban_minutes: Option<String>,
+    pub sub_profile_title: Option<String>,
+    pub sub_profile_web_page_url: Option<String>,
+    pub sub_support_url: Option<String>,
+    pub sub_announce: Option<String>,
+    pub sub_update_interval_hours: Option<String>,
+    pub credential_rotation_cooldown_hours: Option<String>,
+    pub sub_access_retention_days: Option<String>,
+    pub sharing_window_hours: Option<String>,
+    pub sharing_max_networks: Option<String>,
+    pub sharing_max_clients: Option<String>,
+    pub sharing_action: Option<String>,
+    pub geoip_asn_db: Option<String>,
+    pub geoip_country_db: Option<String>,
+    /// Payment gateway credentials, keyed by the provider's setting keys
+    #[serde(flatten)]
+    pub gateway_fields: HashMap<String, String>,
 }
 
 fn mask_key(key: &str) -> String {
@@ -157,12 +198,25 @@ pub struct UserDetailsTemplate {
     pub referrals: Vec<crate::services::store_service::DetailedReferral>,
     pub total_referral_earnings: String,
     pub available_plans: Vec<Plan>,
+    pub sharing_flags: Vec<crate::services::sub_access_service::SharingFlag>,
+    pub sub_access_log: Vec<crate::services::sub_access_service::AccessLogEntry>,
+    pub payments: Vec<UserPaymentView>,
+    pub ledger: Vec<crate::models::store::LedgerEntry>,
+    /// Sum of all ledger entries; differs from `user.balance` only if something bypassed the ledger
+    pub ledger_balance: i64,
     pub is_auth: bool,
     pub username: String, // NEW
     pub admin_path: String,
     pub active_page: String,
 }
 
+pub struct UserPaymentView {
+    pub payment: crate::models::store::Payment,
+    pub refunds: Vec<crate::models::store::Refund>,
+    /// Paid through a provider that can send money back
+    pub refundable: bool,
+}
+
 #[derive(sqlx::FromRow, Debug, Clone)]
 pub struct SubscriptionWithPlan {
     pub id: i64,
@@ -184,6 +238,20 @@ pub struct BotLogsTemplate {
     pub active_page: String,
 }
 
+#[derive(Template)]
+#[template(path = "partials/node_logs.html")]
+pub struct NodeLogsPartial {
+    pub node_id: i64,
+    pub node_name: String,
+    pub admin_path: String,
+}
+
+#[derive(Deserialize)]
+pub struct NodeLogsQuery {
+    pub after: Option<u64>,
+    pub level: Option<String>,
+}
+
 #[derive(Template)]
 #[template(path = "partials/bot_status.html")]
 pub struct BotStatusPartial {
@@ -195,6 +263,7 @@ pub struct BotStatusPartial {
 #[template(path = "nodes.html")]
 pub struct NodesTemplate {
     pub nodes: Vec<Node>,
+    pub groups: Vec<NodeGroup>,
     pub is_auth: bool,
     pub username: String, // NEW
     pub admin_path: String,
@@ -229,12 +298,36 @@ pub struct InstallNodeForm {
 pub struct UpdateNodeForm {
     pub name: String,
     pub ip: String,
+    pub group_id: Option<String>,
+    pub engine: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct NodeGroupForm {
+    pub name: String,
+    pub singbox_version: Option<String>,
+    pub singbox_sha256: Option<String>,
+}
+
+impl NodeGroupForm {
+    /// Agents only install archives they can verify, so a target version needs its checksum
+    fn validate(&self) -> Result<(), &'static str> {
+        let set = |v: &Option<String>| v.as_deref().is_some_and(|v| !v.trim().is_empty());
+        if !set(&self.singbox_version) {
+            return Ok(());
+        }
+        match self.singbox_sha256.as_deref().map(str::trim) {
+            Some(h) if h.len() == 64 && h.chars().all(|c| c.is_ascii_hexdigit()) => Ok(()),
+            _ => Err("A sing-box version needs the SHA-256 of its release archive (64 hex characters)"),
+        }
+    }
 }
 
 #[derive(askama::Template)]
 #[template(path = "node_edit_modal.html")]
 pub struct NodeEditModalTemplate {
     pub node: Node,
+    pub groups: Vec<NodeGroup>,
     pub admin_path: String,
 }
 
@@ -545,14 +638,10 @@ pub async fn get_settings(
         return axum::response::Redirect::to(&admin_path).into_response();
     }
 
-    let payment_api_key = state.settings.get_or_default("payment_api_key", "").await;
-    let nowpayments_api_key = state.settings.get_or_default("nowpayments_api_key", "").await;
-    let lava_project_id = state.settings.get_or_default("lava_project_id", "").await;
-    let lava_secret_key = state.settings.get_or_default("lava_secret_key", "").await;
     let telegram_stars_enabled = state.settings.get_or_default("telegram_stars_enabled", "false").await == "true";
 
     let payment_ipn_url = state.settings.get_or_default("payment_ipn_url", "").await;
-    let currency_rate = state.settings.get_or_default("currency_rate", "1.0").await;
+    let exchange_rates = state.rates.list().await.unwrap_or_default();
     let support_url = state.settings.get_or_default("support_url", "").await;
     let bot_username = state.settings.get_or_default("bot_username", "exarobot_bot").await;
     let brand_name = state.settings.get_or_default("brand_name", "CARAMBA").await;
@@ -563,9 +652,36 @@ pub async fn get_settings(
     let decoy_min_interval = state.settings.get_or_default("decoy_min_interval", "60").await;
     let decoy_max_interval = state.settings.get_or_default("decoy_max_interval", "600").await;
 
+    let renewal_grace_hours = state.settings.get_or_default("renewal_grace_hours", "24").await;
+    let renewal_retry_hours = state.settings.get_or_default("renewal_retry_hours", "6").await;
+    let renewal_max_attempts = state.settings.get_or_default("renewal_max_attempts", "4").await;
+
     let kill_switch_enabled = state.settings.get_or_default("kill_switch_enabled", "false").await == "true";
     let kill_switch_timeout = state.settings.get_or_default("kill_switch_timeout", "300").await;
 
+    let node_logs_redact_ips = state.settings.get_or_default("node_logs_redact_ips", "true").await == "true";
+    let node_logs_retention_minutes = state.settings.get_or_default("node_logs_retention_minutes", "60").await;
+
+    let firewall_enabled = state.settings.get_or_default("firewall_enabled", "false").await == "true";
+    let firewall_panel_ips = state.settings.get_or_default("firewall_panel_ips", "").await;
+    let firewall_extra_ports = state.settings.get_or_default("firewall_extra_ports", "").await;
+    let firewall_abuse_ban_minutes = state.settings.get_or_default("firewall_abuse_ban_minutes", "0").await;
+
+    let sub_profile_title = state.settings.get_or_default("sub_profile_title", "").await;
+    let sub_profile_web_page_url = state.settings.get_or_default("sub_profile_web_page_url", "").await;
+    let sub_support_url = state.settings.get_or_default("sub_support_url", "").await;
+    let sub_announce = state.settings.get_or_default("sub_announce", "").await;
+    let sub_update_interval_hours = state.settings.get_or_default("sub_update_interval_hours", "24").await;
+    let credential_rotation_cooldown_hours = state.settings.get_or_default("credential_rotation_cooldown_hours", "24").await;
+
+    let sub_access_retention_days = state.settings.get_or_default("sub_access_retention_days", "30").await;
+    let sharing_window_hours = state.settings.get_or_default("sharing_window_hours", "24").await;
+    let sharing_max_networks = state.settings.get_or_default("sharing_max_networks", "5").await;
+    let sharing_max_clients = state.settings.get_or_default("sharing_max_clients", "3").await;
+    let sharing_action = state.settings.get_or_default("sharing_action", "flag").await;
+    let geoip_asn_db = state.settings.get_or_default("geoip_asn_db", "geoip/GeoLite2-ASN.mmdb").await;
+    let geoip_country_db = state.settings.get_or_default("geoip_country_db", "geoip/GeoLite2-Country.mmdb").await;
+
     let admin_path = std::env::var("ADMIN_PATH").unwrap_or_else(|_| "/admin".to_string());
     let admin_path = if admin_path.starts_with('/') { admin_path } else { format!("/{}", admin_path) };
 
@@ -574,37 +690,28 @@ pub async fn get_settings(
     let required_channel_id = state.settings.get_or_default("required_channel_id", "").await;
     let last_export = state.settings.get_or_default("last_export", "Never").await;
 
-    let masked_payment_api_key = if !payment_api_key.is_empty() { mask_key(&payment_api_key) } else { "".to_string() };
-    let masked_nowpayments_api_key = if !nowpayments_api_key.is_empty() { mask_key(&nowpayments_api_key) } else { "".to_string() };
-
-    let cryptomus_merchant_id = state.settings.get_or_default("cryptomus_merchant_id", "").await;
-    let cryptomus_payment_api_key = state.settings.get_or_default("cryptomus_payment_api_key", "").await;
-    let aaio_merchant_id = state.settings.get_or_default("aaio_merchant_id", "").await;
-    let aaio_secret_1 = state.settings.get_or_default("aaio_secret_1", "").await;
-    let aaio_secret_2 = state.settings.get_or_default("aaio_secret_2", "").await;
-    
-    let masked_lava_project_id = if !lava_project_id.is_empty() { mask_key(&lava_project_id) } else { "".to_string() };
-    let masked_lava_secret_key = if !lava_secret_key.is_empty() { mask_key(&lava_secret_key) } else { "".to_string() };
-
-    let masked_cryptomus_merchant_id = if !cryptomus_merchant_id.is_empty() { mask_key(&cryptomus_merchant_id) } else { "".to_string() };
-    let masked_cryptomus_payment_api_key = if !cryptomus_payment_api_key.is_empty() { mask_key(&cryptomus_payment_api_key) } else { "".to_string() };
-    let masked_aaio_merchant_id = if !aaio_merchant_id.is_empty() { mask_key(&aaio_merchant_id) } else { "".to_string() };
-    let masked_aaio_secret_1 = if !aaio_secret_1.is_empty() { mask_key(&aaio_secret_1) } else { "".to_string() };
-    let masked_aaio_secret_2 = if !aaio_secret_2.is_empty() { mask_key(&aaio_secret_2) } else { "".to_string() };
+    let mut gateways = Vec::new();
+    for spec in crate::services::payments::PROVIDERS {
+        if spec.fields.is_empty() {
+            continue;
+        }
+        let mut fields = Vec::new();
+        for field in spec.fields {
+            let value = state.settings.get_or_default(field.key, "").await;
+            let masked_value = if !value.is_empty() { mask_key(&value) } else { "".to_string() };
+            fields.push(GatewayFieldView { key: field.key, label: field.label, masked_value });
+        }
+        gateways.push(GatewaySettingsView { name: spec.name, fields });
+    }
 
     let template = SettingsTemplate {
-        masked_payment_api_key,
-        masked_nowpayments_api_key,
-        masked_cryptomus_merchant_id,
-        masked_cryptomus_payment_api_key,
-        masked_aaio_merchant_id,
-        masked_aaio_secret_1,
-        masked_aaio_secret_2,
-        masked_lava_project_id,
-        masked_lava_secret_key,
+        gateways,
         telegram_stars_enabled,
         payment_ipn_url,
-        currency_rate,
+        exchange_rates,
+        renewal_grace_hours,
+        renewal_retry_hours,
+        renewal_max_attempts,
         support_url,
         bot_username,
         brand_name,
@@ -615,6 +722,25 @@ pub async fn get_settings(
         decoy_max_interval,
         kill_switch_enabled,
         kill_switch_timeout,
+        node_logs_redact_ips,
+        node_logs_retention_minutes,
+        firewall_enabled,
+        firewall_panel_ips,
+        firewall_extra_ports,
+        firewall_abuse_ban_minutes,
+        sub_profile_title,
+        sub_profile_web_page_url,
+        sub_support_url,
+        sub_announce,
+        sub_update_interval_hours,
+        credential_rotation_cooldown_hours,
+        sub_access_retention_days,
+        sharing_window_hours,
+        sharing_max_networks,
+        sharing_max_clients,
+        sharing_action,
+        geoip_asn_db,
+        geoip_country_db,
         free_trial_days,
         channel_trial_days,
         required_channel_id,
@@ -733,38 +859,16 @@ pub async fn save_settings(
         }
     }
 
-    let current_payment_key = state.settings.get_or_default("payment_api_key", "").await;
-    let masked_payment_key = if !current_payment_key.is_empty() { mask_key(&current_payment_key) } else { "".to_string() };
-
-    if let Some(v) = form.payment_api_key {
-        if !v.is_empty() && v != masked_payment_key {
-            settings.insert("payment_api_key".to_string(), v);
-        }
-    }
-
-    // NOWPayments Key
-    let current_nowpayments_key = state.settings.get_or_default("nowpayments_api_key", "").await;
-    let masked_nowpayments_key = if !current_nowpayments_key.is_empty() { mask_key(&current_nowpayments_key) } else { "".to_string() };
-    if let Some(v) = form.nowpayments_api_key {
-        if !v.is_empty() && v != masked_nowpayments_key {
-            settings.insert("nowpayments_api_key".to_string(), v);
-        }
-    }
-
-    // Lava.top
-    let current_lava_project = state.settings.get_or_default("lava_project_id", "").await;
-    let masked_lava_project = if !current_lava_project.is_empty() { mask_key(&current_lava_project) } else { "".to_string() };
-    if let Some(v) = form.lava_project_id {
-        if !v.is_empty() && v != masked_lava_project {
-            settings.insert("lava_project_id".to_string(), v);
-        }
-    }
-
-    let current_lava_secret = state.settings.get_or_default("lava_secret_key", "").await;
-    let masked_lava_secret = if !current_lava_secret.is_empty() { mask_key(&current_lava_secret) } else { "".to_string() };
-    if let Some(v) = form.lava_secret_key {
-        if !v.is_empty() && v != masked_lava_secret {
-            settings.insert("lava_secret_key".to_string(), v);
+    // Gateway credentials: same masked-value rule as the bot token
+    for spec in crate::services::payments::PROVIDERS {
+        for field in spec.fields {
+            let Some(v) = form.gateway_fields.get(field.key) else { continue };
+            let v = v.trim();
+            let current = state.settings.get_or_default(field.key, "").await;
+            let masked = if !current.is_empty() { mask_key(&current) } else { "".to_string() };
+            if !v.is_empty() && v != masked {
+                settings.insert(field.key.to_string(), v.to_string());
+            }
         }
     }
 
@@ -773,50 +877,8 @@ pub async fn save_settings(
         settings.insert("telegram_stars_enabled".to_string(), v);
     }
 
-
-    let current_cryptomus_id = state.settings.get_or_default("cryptomus_merchant_id", "").await;
-    let masked_cryptomus_id = if !current_cryptomus_id.is_empty() { mask_key(&current_cryptomus_id) } else { "".to_string() };
-    if let Some(v) = form.cryptomus_merchant_id {
-        if !v.is_empty() && v != masked_cryptomus_id {
-            settings.insert("cryptomus_merchant_id".to_string(), v);
-        }
-    }
-
-    let current_cryptomus_key = state.settings.get_or_default("cryptomus_payment_api_key", "").await;
-    let masked_cryptomus_key = if !current_cryptomus_key.is_empty() { mask_key(&current_cryptomus_key) } else { "".to_string() };
-    if let Some(v) = form.cryptomus_payment_api_key {
-        if !v.is_empty() && v != masked_cryptomus_key {
-            settings.insert("cryptomus_payment_api_key".to_string(), v);
-        }
-    }
-
-    let current_aaio_id = state.settings.get_or_default("aaio_merchant_id", "").await;
-    let masked_aaio_id = if !current_aaio_id.is_empty() { mask_key(&current_aaio_id) } else { "".to_string() };
-    if let Some(v) = form.aaio_merchant_id {
-        if !v.is_empty() && v != masked_aaio_id {
-            settings.insert("aaio_merchant_id".to_string(), v);
-        }
-    }
-
-    let current_aaio_s1 = state.settings.get_or_default("aaio_secret_1", "").await;
-    let masked_aaio_s1 = if !current_aaio_s1.is_empty() { mask_key(&current_aaio_s1) } else { "".to_string() };
-    if let Some(v) = form.aaio_secret_1 {
-        if !v.is_empty() && v != masked_aaio_s1 {
-            settings.insert("aaio_secret_1".to_string(), v);
-        }
-    }
-
-    let current_aaio_s2 = state.settings.get_or_default("aaio_secret_2", "").await;
-    let masked_aaio_s2 = if !current_aaio_s2.is_empty() { mask_key(&current_aaio_s2) } else { "".to_string() };
-    if let Some(v) = form.aaio_secret_2 {
-        if !v.is_empty() && v != masked_aaio_s2 {
-            settings.insert("aaio_secret_2".to_string(), v);
-        }
-    }
-
     // For other fields, update if provided (allow empty to clear)
     if let Some(v) = form.payment_ipn_url { settings.insert("payment_ipn_url".to_string(), v); }
-    if let Some(v) = form.currency_rate { settings.insert("currency_rate".to_string(), v); }
     if let Some(v) = form.support_url { settings.insert("support_url".to_string(), v); }
     if let Some(v) = form.bot_username { settings.insert("bot_username".to_string(), v); }
     if let Some(v) = form.brand_name { settings.insert("brand_name".to_string(), v); }
@@ -836,14 +898,52 @@ pub async fn save_settings(
     if let Some(v) = form.decoy_min_interval { settings.insert("decoy_min_interval".to_string(), v); }
     if let Some(v) = form.decoy_max_interval { settings.insert("decoy_max_interval".to_string(), v); }
 
+    // Auto-Renewal
+    if let Some(v) = form.renewal_grace_hours { settings.insert("renewal_grace_hours".to_string(), v); }
+    if let Some(v) = form.renewal_retry_hours { settings.insert("renewal_retry_hours".to_string(), v); }
+    if let Some(v) = form.renewal_max_attempts { settings.insert("renewal_max_attempts".to_string(), v); }
+
     // Kill Switch Settings
     let kill_switch_enabled = form.kill_switch_enabled.is_some();
     settings.insert("kill_switch_enabled".to_string(), kill_switch_enabled.to_string());
     
     if let Some(v) = form.kill_switch_timeout { settings.insert("kill_switch_timeout".to_string(), v); }
 
+    // Node Log Shipping
+    let node_logs_redact_ips = form.node_logs_redact_ips.is_some();
+    settings.insert("node_logs_redact_ips".to_string(), node_logs_redact_ips.to_string());
+
+    if let Some(v) = form.node_logs_retention_minutes { settings.insert("node_logs_retention_minutes".to_string(), v); }
+
+    // Node Firewall
+    let firewall_enabled = form.firewall_enabled.is_some();
+    settings.insert("firewall_enabled".to_string(), firewall_enabled.to_string());
+
+    if let Some(v) = form.firewall_panel_ips { settings.insert("firewall_panel_ips".to_string(), v.trim().to_string()); }
+    if let Some(v) = form.firewall_extra_ports { settings.insert("firewall_extra_ports".to_string(), v.trim().to_string()); }
+    if let Some(v) = form.firewall_abuse_ban_minutes { settings.insert("firewall_abuse_ban_minutes".to_string(), v); }
+
+    // Subscription Profile Headers
+    if let Some(v) = form.sub_profile_title { settings.insert("sub_profile_title".to_string(), v.trim().to_string()); }
+    if let Some(v) = form.sub_profile_web_page_url { settings.insert("sub_profile_web_page_url".to_string(), v.trim().to_string()); }
+    if let Some(v) = form.sub_support_url { settings.insert("sub_support_url".to_string(), v.trim().to_string()); }
+    if let Some(v) = form.sub_announce { settings.insert("sub_announce".to_string(), v.trim().to_string()); }
+    if let Some(v) = form.sub_update_interval_hours { settings.insert("sub_update_interval_hours".to_string(), v); }
+    if let Some(v) = form.credential_rotation_cooldown_hours { settings.insert("credential_rotation_cooldown_hours".to_string(), v); }
+
+    // Subscription Access Log & Sharing Detection
+    if let Some(v) = form.sub_access_retention_days { settings.insert("sub_access_retention_days".to_string(), v); }
+    if let Some(v) = form.sharing_window_hours { settings.insert("sharing_window_hours".to_string(), v); }
+    if let Some(v) = form.sharing_max_networks { settings.insert("sharing_max_networks".to_string(), v); }
+    if let Some(v) = form.sharing_max_clients { settings.insert("sharing_max_clients".to_string(), v); }
+    if let Some(v) = form.sharing_action { settings.insert("sharing_action".to_string(), v); }
+    if let Some(v) = form.geoip_asn_db { settings.insert("geoip_asn_db".to_string(), v.trim().to_string()); }
+    if let Some(v) = form.geoip_country_db { settings.insert("geoip_country_db".to_string(), v.trim().to_string()); }
+
     match state.settings.set_multiple(settings).await {
         Ok(_) => {
+             state.pay_service.reload_providers().await;
+
              // Notify ALL nodes about settings change
              // We can use a wildcard channel or iterate used nodes.
              // Ideally we publish to "global_settings_update" channel if we had one.
@@ -925,8 +1025,11 @@ pub async fn get_nodes(
         return Html(template.render().unwrap()).into_response();
     }
     
+    let groups = state.orchestration_service.get_node_groups().await.unwrap_or_default();
+
     let template = NodesTemplate { 
         nodes, 
+        groups,
         is_auth: true, 
         username: get_auth_user(&state, &jar).await.unwrap_or("Admin".to_string()),
         admin_path,
@@ -1035,7 +1138,9 @@ pub async fn get_node_edit(
     // Ensure leading slash
     let admin_path = if admin_path.starts_with('/') { admin_path } else { format!("/{}", admin_path) };
 
-    let template = NodeEditModalTemplate { node, admin_path };
+    let groups = state.orchestration_service.get_node_groups().await.unwrap_or_default();
+
+    let template = NodeEditModalTemplate { node, groups, admin_path };
      match template.render() {
         Ok(html) => Html(html).into_response(),
         Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, format!("Template error: {}", e)).into_response(),
@@ -1054,13 +1159,23 @@ pub async fn update_node(
     // Let's assume for simplicity we update everything. If password field is empty, it might clear it.
     // Better logic: if password is NOT empty, update it.
     
-    let query = sqlx::query("UPDATE nodes SET name = ?, ip = ? WHERE id = ?")
+    let group_id = form.group_id.as_deref().and_then(|g| g.parse::<i64>().ok());
+    let engine = form.engine.as_deref().map(crate::models::node::NodeEngine::parse);
+
+    let query = sqlx::query("UPDATE nodes SET name = ?, ip = ?, group_id = ?, engine = COALESCE(?, engine) WHERE id = ?")
         .bind(&form.name)
         .bind(&form.ip)
+        .bind(group_id)
+        .bind(engine.map(|e| e.as_str()))
         .bind(id);
 
     match query.execute(&state.pool).await {
         Ok(_) => {
+             // The agent re-fetches the config; an engine switch makes it swap services
+             if let Err(e) = state.pubsub.publish(&format!("node_events:{}", id), "update").await {
+                 error!("Failed to notify node {}: {}", id, e);
+             }
+
              let admin_path = std::env::var("ADMIN_PATH").unwrap_or_else(|_| "/admin".to_string());
              let admin_path = if admin_path.starts_with('/') { admin_path } else { format!("/{}", admin_path) };
              
@@ -1075,6 +1190,74 @@ pub async fn update_node(
     }
 }
 
+pub async fn create_node_group(
+    State(state): State<AppState>,
+    Form(form): Form<NodeGroupForm>,
+) -> impl IntoResponse {
+    if let Err(e) = form.validate() {
+        return (axum::http::StatusCode::BAD_REQUEST, e).into_response();
+    }
+    let res = sqlx::query("INSERT INTO node_groups (name, singbox_version, singbox_sha256) VALUES (?, ?, ?)")
+        .bind(form.name.trim())
+        .bind(non_empty(form.singbox_version))
+        .bind(non_empty(form.singbox_sha256))
+        .execute(&state.pool)
+        .await;
+
+    match res {
+        Ok(_) => ([("HX-Refresh", "true")], "Created").into_response(),
+        Err(e) => {
+            error!("Failed to create node group: {}", e);
+            (axum::http::StatusCode::BAD_REQUEST, format!("Failed to create group: {}", e)).into_response()
+        }
+    }
+}
+
+pub async fn update_node_group(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+    Form(form): Form<NodeGroupForm>,
+) -> impl IntoResponse {
+    if let Err(e) = form.validate() {
+        return (axum::http::StatusCode::BAD_REQUEST, e).into_response();
+    }
+    let res = sqlx::query("UPDATE node_groups SET name = ?, singbox_version = ?, singbox_sha256 = ? WHERE id = ?")
+        .bind(form.name.trim())
+        .bind(non_empty(form.singbox_version))
+        .bind(non_empty(form.singbox_sha256))
+        .bind(id)
+        .execute(&state.pool)
+        .await;
+
+    match res {
+        Ok(_) => {
+            info!("Node group {} updated", id);
+            ([("HX-Refresh", "true")], "Updated").into_response()
+        },
+        Err(e) => {
+            error!("Failed to update node group: {}", e);
+            (axum::http::StatusCode::BAD_REQUEST, format!("Failed to update group: {}", e)).into_response()
+        }
+    }
+}
+
+pub async fn delete_node_group(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    match sqlx::query("DELETE FROM node_groups WHERE id = ?").bind(id).execute(&state.pool).await {
+        Ok(_) => ([("HX-Refresh", "true")], "Deleted").into_response(),
+        Err(e) => {
+            error!("Failed to delete node group: {}", e);
+            (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete group").into_response()
+        }
+    }
+}
+
+fn non_empty(v: Option<String>) -> Option<String> {
+    v.map(|s| s.trim().to_string()).filter(|s| !s.is_empty())
+}
+
 pub async fn sync_node(
     Path(id): Path<i64>,
     State(state): State<AppState>,
@@ -1144,7 +1327,7 @@ pub async fn get_plans(
     State(state): State<AppState>,
     jar: CookieJar,
 ) -> impl IntoResponse {
-    let mut plans = match sqlx::query_as::<_, Plan>("SELECT id, name, description, is_active, created_at, device_limit, traffic_limit_gb FROM plans")
+    let mut plans = match sqlx::query_as::<_, Plan>("SELECT * FROM plans")
         .fetch_all(&state.pool)
         .await {
             Ok(p) => {
@@ -1175,15 +1358,19 @@ pub async fn get_plans(
     pub struct PlansTemplate {
         pub plans: Vec<Plan>,
         pub nodes: Vec<Node>,
+        pub packs: Vec<crate::models::store::TrafficPack>,
         pub is_auth: bool,
         pub username: String, // NEW
         pub admin_path: String,
         pub active_page: String,
     }
 
+    let packs = state.store_service.get_traffic_packs().await.unwrap_or_default();
+
     let template = PlansTemplate { 
         plans, 
         nodes,
+        packs,
         is_auth: true, 
         username: get_auth_user(&state, &jar).await.unwrap_or("Admin".to_string()),
         admin_path: {
@@ -1257,6 +1444,15 @@ pub struct CreatePlanForm {
     pub traffic_gb: Vec<i32>,
 }
 
+/// Traffic reset periods a plan can use; anything else means the quota never resets
+fn traffic_reset_period_value(value: &str) -> &'static str {
+    match value {
+        "monthly" => "monthly",
+        "billing_cycle" => "billing_cycle",
+        _ => "never",
+    }
+}
+
 pub async fn add_plan(
     State(state): State<AppState>,
     Form(raw_form): Form<Vec<(String, String)>>,
@@ -1267,6 +1463,7 @@ pub async fn add_plan(
     let mut duration_days: Vec<i32> = Vec::new();
     let mut price: Vec<i64> = Vec::new();
     let mut traffic_limit_gb: i32 = 0;
+    let mut traffic_reset_period = "never";
 
     let mut node_ids: Vec<i64> = Vec::new();
 
@@ -1295,6 +1492,7 @@ pub async fn add_plan(
                     traffic_limit_gb = v;
                 }
             },
+            "traffic_reset_period" => traffic_reset_period = traffic_reset_period_value(&value),
             "node_ids" => {
                 if let Ok(v) = value.parse() {
                     node_ids.push(v);
@@ -1316,10 +1514,11 @@ pub async fn add_plan(
 
     // 1. Insert Plan
     // Using traffic_limit_gb for the plan
-    let plan_id: i64 = match sqlx::query("INSERT INTO plans (name, description, is_active, price, traffic_limit_gb, device_limit) VALUES (?, ?, 1, 0, ?, ?) RETURNING id")
+    let plan_id: i64 = match sqlx::query("INSERT INTO plans (name, description, is_active, price, traffic_limit_gb, traffic_reset_period, device_limit) VALUES (?, ?, 1, 0, ?, ?, ?) RETURNING id")
         .bind(&name)
         .bind(&description)
         .bind(traffic_limit_gb)
+        .bind(traffic_reset_period)
         .bind(device_limit)
         .fetch_one(&mut *tx)
         .await {
@@ -1402,7 +1601,7 @@ pub async fn get_plan_edit(
     State(state): State<AppState>,
     Path(id): Path<i64>,
 ) -> impl IntoResponse {
-    let plan = match sqlx::query_as::<_, Plan>("SELECT id, name, description, is_active, created_at, device_limit, traffic_limit_gb FROM plans WHERE id = ?").bind(id).fetch_optional(&state.pool).await {
+    let plan = match sqlx::query_as::<_, Plan>("SELECT * FROM plans WHERE id = ?").bind(id).fetch_optional(&state.pool).await {
         Ok(Some(mut p)) => {
             let durations = sqlx::query_as::<_, crate::models::store::PlanDuration>(
                 "SELECT * FROM plan_durations WHERE plan_id = ? ORDER BY duration_days ASC"
@@ -1433,6 +1632,8 @@ pub async fn get_plan_edit(
     struct PlanEditModalTemplate {
         plan: Plan,
         nodes: Vec<(crate::models::node::Node, bool)>,
+        presets: Vec<crate::services::routing_service::RoutingPreset>,
+        routing_preset_id: Option<i64>,
         admin_path: String,
     }
 
@@ -1444,7 +1645,16 @@ pub async fn get_plan_edit(
         (n, is_linked)
     }).collect();
 
-    Html(PlanEditModalTemplate { plan, nodes: nodes_with_status, admin_path }.render().unwrap_or_default()).into_response()
+    let presets = state.routing.list().await.unwrap_or_default();
+    let routing_preset_id: Option<i64> = sqlx::query_scalar("SELECT routing_preset_id FROM plans WHERE id = ?")
+        .bind(id)
+        .fetch_optional(&state.pool)
+        .await
+        .ok()
+        .flatten()
+        .flatten();
+
+    Html(PlanEditModalTemplate { plan, nodes: nodes_with_status, presets, routing_preset_id, admin_path }.render().unwrap_or_default()).into_response()
 }
 
 pub async fn update_plan(
@@ -1460,8 +1670,10 @@ pub async fn update_plan(
     let mut duration_days: Vec<i32> = Vec::new();
     let mut price: Vec<i64> = Vec::new();
     let mut traffic_limit_gb: i32 = 0;
+    let mut traffic_reset_period = "never";
 
     let mut node_ids: Vec<i64> = Vec::new();
+    let mut routing_preset_id: Option<i64> = None;
 
     for (key, value) in raw_form {
         match key.as_str() {
@@ -1487,11 +1699,13 @@ pub async fn update_plan(
                     traffic_limit_gb = v;
                 }
             },
+            "traffic_reset_period" => traffic_reset_period = traffic_reset_period_value(&value),
             "node_ids" => {
                 if let Ok(v) = value.parse() {
                     node_ids.push(v);
                 }
             },
+            "routing_preset_id" => routing_preset_id = value.parse().ok(),
             _ => {}
         }
     }
@@ -1506,11 +1720,13 @@ pub async fn update_plan(
     };
 
     // 1. Update Plan
-    if let Err(e) = sqlx::query("UPDATE plans SET name = ?, description = ?, device_limit = ?, traffic_limit_gb = ? WHERE id = ?")
+    if let Err(e) = sqlx::query("UPDATE plans SET name = ?, description = ?, device_limit = ?, traffic_limit_gb = ?, traffic_reset_period = ?, routing_preset_id = ? WHERE id = ?")
         .bind(&name)
         .bind(&description)
         .bind(device_limit)
         .bind(traffic_limit_gb)
+        .bind(traffic_reset_period)
+        .bind(routing_preset_id)
         .bind(id)
         .execute(&mut *tx)
         .await {
@@ -1730,6 +1946,31 @@ pub async fn get_user_details(
     // 5. Fetch Available Plans for Gifting
     let available_plans = state.store_service.get_active_plans().await.unwrap_or_default();
 
+    // 6. Link sharing flags and recent subscription fetches
+    let sharing_flags = state.sub_access.flags_for_user(id).await.unwrap_or_default();
+    let sub_access_log = state.sub_access.recent_for_user(id, 20).await.unwrap_or_default();
+
+    // 7. Provider payments with their refunds and disputes
+    let db_payments = sqlx::query_as::<_, crate::models::store::Payment>(
+        "SELECT * FROM payments WHERE user_id = ? ORDER BY id DESC LIMIT 20"
+    )
+    .bind(id)
+    .fetch_all(&state.pool)
+    .await
+    .unwrap_or_default();
+    let mut payments = Vec::new();
+    for payment in db_payments {
+        let refunds = state.pay_service.payment_refunds(payment.id).await.unwrap_or_default();
+        let refundable = payment.external_id.is_some()
+            && matches!(payment.status.as_str(), "paid" | "partially_refunded")
+            && matches!(payment.method.as_str(), "stripe" | "stars" | "cryptobot");
+        payments.push(UserPaymentView { payment, refunds, refundable });
+    }
+
+    // 8. Balance statement
+    let ledger = crate::services::ledger_service::LedgerService::statement(&state.pool, id, 50).await.unwrap_or_default();
+    let ledger_balance = crate::services::ledger_service::LedgerService::ledger_balance(&state.pool, id).await.unwrap_or(user.balance);
+
     let template = UserDetailsTemplate {
         user,
         subscriptions,
@@ -1737,6 +1978,11 @@ pub async fn get_user_details(
         referrals,
         total_referral_earnings: format!("{:.2}", earnings_cents as f64 / 100.0),
         available_plans,
+        sharing_flags,
+        sub_access_log,
+        payments,
+        ledger,
+        ledger_balance,
         is_auth: true,
         username: get_auth_user(&state, &jar).await.unwrap_or("Admin".to_string()),
         admin_path: {
@@ -1767,9 +2013,19 @@ pub struct UpdateUserForm {
     pub referral_code: Option<String>,
 }
 
+/// Admin balance edits are booked as ledger adjustments for the difference
+async fn set_user_balance(state: &AppState, jar: &CookieJar, user_id: i64, balance: i64, note: Option<String>) -> anyhow::Result<()> {
+    let admin = get_auth_user(state, jar).await.unwrap_or_else(|| "admin".to_string());
+    let mut tx = state.pool.begin().await?;
+    crate::services::ledger_service::LedgerService::adjust_to(&mut tx, user_id, balance, &format!("admin:{}", admin), note).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
 pub async fn update_user(
     Path(id): Path<i64>,
     State(state): State<AppState>,
+    jar: CookieJar,
     Form(form): Form<UpdateUserForm>,
 ) -> impl IntoResponse {
     // Fetch previous state
@@ -1779,13 +2035,17 @@ pub async fn update_user(
         .await
         .unwrap_or(None);
 
-    let res = sqlx::query("UPDATE users SET balance = ?, is_banned = ?, referral_code = ? WHERE id = ?")
-        .bind(form.balance)
+    let res = sqlx::query("UPDATE users SET is_banned = ?, referral_code = ? WHERE id = ?")
         .bind(form.is_banned)
         .bind(form.referral_code.as_deref().map(|s| s.trim()))
         .bind(id)
         .execute(&state.pool)
-        .await;
+        .await
+        .map_err(anyhow::Error::from);
+    let res = match res {
+        Ok(_) => set_user_balance(&state, &jar, id, form.balance, None).await,
+        Err(e) => Err(e),
+    };
 
     match res {
         Ok(_) => {
@@ -1828,16 +2088,14 @@ pub async fn update_user(
 pub async fn update_user_balance(
     Path(id): Path<i64>,
     State(state): State<AppState>,
-    Form(form): Form<HashMap<String, String>>, // Accept generic form for the modal which sends 'balance'
+    jar: CookieJar,
+    Form(form): Form<HashMap<String, String>>, // Accept generic form for the modal which sends 'balance' and an optional 'note'
 ) -> impl IntoResponse {
     let balance_str = form.get("balance").unwrap_or(&"0".to_string()).clone();
     let balance: i64 = balance_str.parse().unwrap_or(0);
+    let note = form.get("note").map(|n| n.trim().to_string()).filter(|n| !n.is_empty());
 
-    let res = sqlx::query("UPDATE users SET balance = ? WHERE id = ?")
-        .bind(balance)
-        .bind(id)
-        .execute(&state.pool)
-        .await;
+    let res = set_user_balance(&state, &jar, id, balance, note).await;
 
     match res {
         Ok(_) => {
@@ -1892,6 +2150,41 @@ pub async fn refund_user_subscription(
     }
 }
 
+#[derive(Deserialize)]
+pub struct PaymentRefundForm {
+    /// Dollars; empty refunds whatever is left of the payment
+    pub amount: Option<String>,
+}
+
+/// Refund a payment through its provider, back to the card, Stars or CryptoBot wallet it came from
+pub async fn refund_payment(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Form(form): Form<PaymentRefundForm>,
+) -> impl IntoResponse {
+    if !is_authenticated(&jar) {
+        return (axum::http::StatusCode::UNAUTHORIZED, "Unauthorized").into_response();
+    }
+
+    let amount = match form.amount.as_deref().map(str::trim) {
+        None | Some("") => None,
+        Some(value) => match crate::models::money::Money::parse(value, crate::models::money::Currency::Usd) {
+            Some(amount) => Some(amount),
+            None => return (axum::http::StatusCode::BAD_REQUEST, "Invalid amount").into_response(),
+        },
+    };
+    let admin = get_auth_user(&state, &jar).await.unwrap_or_else(|| "admin".to_string());
+
+    match state.pay_service.refund_payment(id, amount, &format!("admin:{}", admin)).await {
+        Ok(()) => ([("HX-Refresh", "true")], "Refunded").into_response(),
+        Err(e) => {
+            error!("Failed to refund payment {}: {}", id, e);
+            (axum::http::StatusCode::BAD_REQUEST, format!("Refund failed: {}", e)).into_response()
+        }
+    }
+}
+
 #[derive(Deserialize)]
 pub struct ExtendForm {
     pub days: i32,
@@ -1922,17 +2215,13 @@ pub async fn handle_payment(
     body: String,
 ) -> impl IntoResponse {
     info!("Received payment webhook from source: {}", source);
-    
-    // Extract potential signatures
-    let crypto_sig = headers.get("crypto-pay-api-signature").and_then(|h| h.to_str().ok());
-    let nowpayments_sig = headers.get("x-nowpayments-sig").and_then(|h| h.to_str().ok());
-    let stripe_sig = headers.get("stripe-signature").and_then(|h| h.to_str().ok());
-    let cryptomus_sig = headers.get("sign").and_then(|h| h.to_str().ok());
 
-    if let Err(e) = state.pay_service.handle_webhook(&source, &body, crypto_sig, nowpayments_sig, stripe_sig, cryptomus_sig).await {
+    if let Err(e) = state.pay_service.handle_webhook(&source, &headers, &body).await {
         error!("Failed to process payment webhook: {}", e);
+        state.metrics.record_payment_webhook(&source, false);
         return axum::http::StatusCode::INTERNAL_SERVER_ERROR;
     }
+    state.metrics.record_payment_webhook(&source, true);
     axum::http::StatusCode::OK
 }
 
@@ -2089,6 +2378,79 @@ pub async fn bot_logs_tail(jar: CookieJar) -> impl IntoResponse {
     }
 }
 
+/// Live log viewer for a node (rendered into the logs modal on the nodes page)
+pub async fn get_node_logs(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let node_name: String = match sqlx::query_scalar("SELECT name FROM nodes WHERE id = ?")
+        .bind(id)
+        .fetch_optional(&state.pool)
+        .await
+    {
+        Ok(Some(name)) => name,
+        _ => return (axum::http::StatusCode::NOT_FOUND, "Node not found").into_response(),
+    };
+
+    // Ask the agent to start shipping
+    let _ = state.pubsub.publish(&format!("node_events:{}", id), "logs_request").await;
+
+    let admin_path = std::env::var("ADMIN_PATH").unwrap_or_else(|_| "/admin".to_string());
+    let admin_path = if admin_path.starts_with('/') { admin_path } else { format!("/{}", admin_path) };
+
+    match (NodeLogsPartial { node_id: id, node_name, admin_path }).render() {
+        Ok(html) => Html(html).into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, format!("Template error: {}", e)).into_response(),
+    }
+}
+
+/// Keep the agent shipping while the viewer stays open (called periodically by the viewer)
+pub async fn request_node_logs(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    match state.pubsub.publish(&format!("node_events:{}", id), "logs_request").await {
+        Ok(_) => axum::http::StatusCode::OK,
+        Err(e) => {
+            error!("Failed to request logs from node {}: {}", id, e);
+            axum::http::StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// New log lines after `after`, at or above `level` severity
+pub async fn node_logs_tail(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+    Query(query): Query<NodeLogsQuery>,
+) -> impl IntoResponse {
+    fn severity(level: &str) -> u8 {
+        match level {
+            "error" => 0,
+            "warn" => 1,
+            "info" => 2,
+            "debug" => 3,
+            _ => 4,
+        }
+    }
+
+    let after = query.after.unwrap_or(0);
+    let max_severity = severity(query.level.as_deref().unwrap_or("trace"));
+
+    match state.redis.get_node_logs(id).await {
+        Ok(lines) => {
+            let lines: Vec<_> = lines.into_iter()
+                .filter(|l| l.seq > after && severity(&l.level) <= max_severity)
+                .collect();
+            axum::Json(lines).into_response()
+        }
+        Err(e) => {
+            error!("Failed to read logs for node {}: {}", id, e);
+            (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "Failed to read logs").into_response()
+        }
+    }
+}
+
 pub async fn delete_node(
     State(state): State<AppState>,
     Path(id): Path<i64>,
@@ -2225,6 +2587,86 @@ pub async fn get_transactions(
     }
 }
 
+// --- Payment Events ---
+
+#[derive(Deserialize)]
+pub struct PaymentEventsFilter {
+    pub status: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "payment_events.html")]
+pub struct PaymentEventsTemplate {
+    pub events: Vec<crate::models::store::PaymentEvent>,
+    pub status_filter: String,
+    pub failed_count: i64,
+    pub is_auth: bool,
+    pub username: String,
+    pub admin_path: String,
+    pub active_page: String,
+}
+
+pub async fn get_payment_events(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Query(filter): Query<PaymentEventsFilter>,
+) -> impl IntoResponse {
+    if !is_authenticated(&jar) {
+        return axum::response::Redirect::to("/admin/login").into_response();
+    }
+
+    let status_filter = filter.status.unwrap_or_default();
+    let events = sqlx::query_as::<_, crate::models::store::PaymentEvent>(
+        "SELECT * FROM payment_events WHERE (? = '' OR status = ?) ORDER BY created_at DESC LIMIT 200"
+    )
+    .bind(&status_filter)
+    .bind(&status_filter)
+    .fetch_all(&state.pool)
+    .await
+    .unwrap_or_default();
+
+    let failed_count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM payment_events WHERE status = 'failed'")
+        .fetch_one(&state.pool)
+        .await
+        .unwrap_or(0);
+
+    let template = PaymentEventsTemplate {
+        events,
+        status_filter,
+        failed_count,
+        is_auth: true,
+        username: get_auth_user(&state, &jar).await.unwrap_or("Admin".to_string()),
+        admin_path: {
+            let p = std::env::var("ADMIN_PATH").unwrap_or_else(|_| "/admin".to_string());
+            if p.starts_with('/') { p } else { format!("/{}", p) }
+        },
+        active_page: "payment_events".to_string(),
+    };
+
+    match template.render() {
+        Ok(html) => Html(html).into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, format!("Template error: {}", e)).into_response(),
+    }
+}
+
+pub async fn reprocess_payment_event(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    jar: CookieJar,
+) -> impl IntoResponse {
+    if !is_authenticated(&jar) {
+        return (axum::http::StatusCode::UNAUTHORIZED, "Unauthorized").into_response();
+    }
+
+    let admin = get_auth_user(&state, &jar).await.unwrap_or("Admin".to_string());
+    info!("Admin {} re-processing payment event {}", admin, id);
+
+    match state.pay_service.reprocess_event(id).await {
+        Ok(_) => ([("HX-Refresh", "true")], "Re-processed").into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, format!("Re-processing failed: {}", e)).into_response(),
+    }
+}
+
 
 pub async fn get_subscription_devices(
     State(state): State<AppState>,
@@ -2256,8 +2698,7 @@ pub async fn get_subscription_devices(
                 <p class="text-sm font-bold text-orange-400 mb-0.5">Manage Active Sessions</p>
                 <p class="text-[11px] text-slate-500">Disconnect all current devices immediately</p>
             </div>
-            <button hx-post="{}/subs/{}/devices/kill" hx-target="#[derive(askama::Template)]
-#[template(path = "node_edit_modal.html")]evices_content" hx-confirm="This will disconnect ALL currently connected users for this subscription. Continue?"
+            <button hx-post="{}/subs/{}/devices/kill" hx-target="#devices_content" hx-confirm="This will disconnect ALL currently connected users for this subscription. Continue?"
                 class="px-4 py-2 rounded-xl bg-orange-600 hover:bg-orange-500 text-white text-xs font-bold transition-all shadow-lg shadow-orange-500/20 active:scale-95">
                 Reset All
             </button>
@@ -2265,6 +2706,22 @@ pub async fn get_subscription_devices(
         "##, admin_path, sub_id
     ));
 
+    // Live usage from the latest agent snapshots (sing-box users are keyed by Telegram ID)
+    let tg_id: Option<i64> = sqlx::query_scalar("SELECT u.tg_id FROM subscriptions s JOIN users u ON s.user_id = u.id WHERE s.id = ?")
+        .bind(sub_id)
+        .fetch_optional(&state.pool)
+        .await
+        .unwrap_or(None);
+    if let Some(tg_id) = tg_id
+        && let Ok((connections, up, down)) = state.connection_service.live_usage(&tg_id.to_string()).await
+        && connections > 0
+    {
+        html.push_str(&format!(
+            "<p class='text-[11px] text-slate-500 mb-4 px-1'>Live: {} connection(s) · ↑ {} · ↓ {}</p>",
+            connections, format_bytes_str(up), format_bytes_str(down)
+        ));
+    }
+
     if ips.is_empty() {
         html.push_str("<div class='py-12 text-center text-slate-500 border border-white/5 rounded-2xl bg-slate-950/20'><p class='text-sm'>No active devices detected in the last 15 minutes.</p></div>");
         return Html(html).into_response();
@@ -2272,13 +2729,13 @@ pub async fn get_subscription_devices(
 
     html.push_str("<div class='overflow-hidden rounded-2xl border border-white/5 bg-slate-950/30 shadow-inner'>");
     html.push_str("<table class='w-full text-left border-collapse'>");
-    html.push_str("<thead><tr class='text-[10px] font-bold text-slate-500 uppercase tracking-widest bg-white/5'><th class='px-6 py-3'>Client IP Address</th><th class='px-6 py-3'>Activity</th></tr></thead>");
+    html.push_str("<thead><tr class='text-[10px] font-bold text-slate-500 uppercase tracking-widest bg-white/5'><th class='px-6 py-3'>Client IP Address</th><th class='px-6 py-3'>Activity</th><th class='px-6 py-3'></th></tr></thead>");
     html.push_str("<tbody class='divide-y divide-white/5'>");
     for ip_record in ips {
         let time_ago = format_duration(chrono::Utc::now() - ip_record.last_seen_at);
         html.push_str(&format!(
-            "<tr class='hover:bg-white/5 transition-colors'><td class='px-6 py-4'><div class='flex items-center gap-2'><i data-lucide='globe' class='w-3 h-3 text-indigo-500 opacity-50'></i><code class='text-sm text-indigo-400 font-mono'>{}</code></div></td><td class='px-6 py-4 text-xs text-slate-400 font-medium'>{} ago</td></tr>",
-            ip_record.client_ip, time_ago
+            "<tr class='hover:bg-white/5 transition-colors'><td class='px-6 py-4'><div class='flex items-center gap-2'><i data-lucide='globe' class='w-3 h-3 text-indigo-500 opacity-50'></i><code class='text-sm text-indigo-400 font-mono'>{}</code></div></td><td class='px-6 py-4 text-xs text-slate-400 font-medium'>{} ago</td><td class='px-6 py-4 text-right'><button hx-post='{}/subs/{}/devices/ban' hx-vals='{{\"ip\": \"{}\"}}' hx-target='#devices_content' hx-confirm='Block {} on all nodes for 1 hour?' class='text-[11px] font-bold text-red-400 hover:text-red-300'>Ban 1h</button></td></tr>",
+            ip_record.client_ip, time_ago, admin_path, sub_id, ip_record.client_ip, ip_record.client_ip
         ));
     }
     html.push_str("</tbody></table></div>");
@@ -2327,8 +2784,7 @@ pub async fn admin_kill_subscription_sessions(
             </div>
             <h4 class="text-xl font-bold text-white mb-2 tracking-tight">Sessions Reset Successfully</h4>
             <p class="text-sm text-slate-500 mb-8 px-12 leading-relaxed">All active connections for subscription #{} have been terminated. It may take up to 60 seconds for all caches to clear.</p>
-            <button hx-get="{}/subs/{}/devices" hx-target="#[derive(askama::Template)]
-#[template(path = "node_edit_modal.html")]evices_content"
+            <button hx-get="{}/subs/{}/devices" hx-target="#devices_content"
                 class="px-6 py-2.5 rounded-xl bg-slate-800 hover:bg-slate-700 text-white text-sm font-bold shadow-lg transition-all active:scale-95 border border-white/5">
                 Refresh Device List
             </button>
@@ -2339,6 +2795,101 @@ pub async fn admin_kill_subscription_sessions(
     Html(success_html).into_response()
 }
 
+#[derive(Deserialize)]
+pub struct BanDeviceForm {
+    pub ip: String,
+}
+
+pub async fn admin_ban_subscription_device(
+    State(state): State<AppState>,
+    Path(sub_id): Path<i64>,
+    jar: CookieJar,
+    Form(form): Form<BanDeviceForm>,
+) -> impl IntoResponse {
+    if !is_authenticated(&jar) {
+        return (axum::http::StatusCode::UNAUTHORIZED, "Unauthorized").into_response();
+    }
+
+    let reason = format!("Banned by admin from subscription {}", sub_id);
+    if let Err(e) = state.firewall.ban_ip(None, &form.ip, &reason, 60).await {
+        error!("Failed to ban {}: {}", form.ip, e);
+        return (axum::http::StatusCode::BAD_REQUEST, format!("Failed to ban: {}", e)).into_response();
+    }
+
+    // Existing connections are dropped by the firewall; also reset the Clash sessions right away
+    let uuid: Option<String> = sqlx::query_scalar::<_, Option<String>>("SELECT vless_uuid FROM subscriptions WHERE id = ?")
+        .bind(sub_id)
+        .fetch_optional(&state.pool)
+        .await
+        .ok()
+        .flatten()
+        .flatten();
+    if let Some(uuid) = uuid {
+        let _ = state.connection_service.kill_subscription_connections(&uuid).await;
+    }
+
+    get_subscription_devices(State(state), Path(sub_id), jar).await.into_response()
+}
+
+/// Active firewall bans (settings page partial)
+pub async fn get_firewall_bans(
+    State(state): State<AppState>,
+    jar: CookieJar,
+) -> impl IntoResponse {
+    if !is_authenticated(&jar) {
+        return (axum::http::StatusCode::UNAUTHORIZED, "Unauthorized").into_response();
+    }
+
+    let bans = match state.firewall.list_active_bans().await {
+        Ok(b) => b,
+        Err(e) => {
+            error!("Failed to fetch IP bans: {}", e);
+            return (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch bans").into_response();
+        }
+    };
+
+    if bans.is_empty() {
+        return Html("<p class='text-xs text-slate-500'>No active bans.</p>".to_string()).into_response();
+    }
+
+    let admin_path = std::env::var("ADMIN_PATH").unwrap_or_else(|_| "/admin".to_string());
+    let admin_path = if admin_path.starts_with('/') { admin_path } else { format!("/{}", admin_path) };
+
+    let mut html = String::from("<div class='space-y-2'>");
+    for ban in bans {
+        let scope = ban.node_id.map(|id| format!("node #{}", id)).unwrap_or_else(|| "all nodes".to_string());
+        html.push_str(&format!(
+            "<div class='flex items-center justify-between gap-3 p-3 rounded-xl bg-slate-950/40 border border-white/5'><div><code class='text-sm text-red-400 font-mono'>{}</code><p class='text-[10px] text-slate-500'>{} · {} · {} left</p></div><button hx-delete='{}/firewall/bans/{}' hx-target='#firewall-bans' class='text-[11px] font-bold text-slate-400 hover:text-white'>Unban</button></div>",
+            ban.ip,
+            scope,
+            ban.reason.unwrap_or_default(),
+            format_duration(ban.expires_at - chrono::Utc::now()),
+            admin_path,
+            ban.id
+        ));
+    }
+    html.push_str("</div>");
+
+    Html(html).into_response()
+}
+
+pub async fn delete_firewall_ban(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    jar: CookieJar,
+) -> impl IntoResponse {
+    if !is_authenticated(&jar) {
+        return (axum::http::StatusCode::UNAUTHORIZED, "Unauthorized").into_response();
+    }
+
+    if let Err(e) = state.firewall.unban(id).await {
+        error!("Failed to remove IP ban {}: {}", id, e);
+        return (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "Failed to remove ban").into_response();
+    }
+
+    get_firewall_bans(State(state), jar).await.into_response()
+}
+
 fn format_duration(dur: chrono::Duration) -> String {
     let secs = dur.num_seconds();
     if secs < 60 {
@@ -2475,6 +3026,36 @@ pub struct TrialConfigForm {
     pub required_channel_id: String,
 }
 
+/// Manual exchange-rate overrides from the payments tab: `manual_<CODE>`, empty to use the fetched rate
+pub async fn save_exchange_rates(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Form(form): Form<HashMap<String, String>>,
+) -> impl IntoResponse {
+    if !is_authenticated(&jar) {
+        return (axum::http::StatusCode::UNAUTHORIZED, "Unauthorized").into_response();
+    }
+
+    for currency in crate::models::money::Currency::ALL {
+        let Some(value) = form.get(&format!("manual_{}", currency.code())) else {
+            continue;
+        };
+        let manual = match value.trim() {
+            "" => None,
+            v => match v.parse::<f64>() {
+                Ok(rate) => Some(rate),
+                Err(_) => return (axum::http::StatusCode::BAD_REQUEST, format!("Invalid {} rate", currency)).into_response(),
+            },
+        };
+        if let Err(e) = state.rates.set_manual(currency, manual).await {
+            return (axum::http::StatusCode::BAD_REQUEST, format!("{}: {}", currency, e)).into_response();
+        }
+    }
+
+    info!("Exchange rate overrides updated");
+    ([("HX-Refresh", "true")], "Saved").into_response()
+}
+
 pub async fn update_trial_config(
     State(state): State<AppState>,
     Form(form): Form<TrialConfigForm>,
@@ -2738,3 +3319,209 @@ pub async fn check_update(State(_state): State<AppState>) -> impl IntoResponse {
 }
 
 
+
+#[derive(Template)]
+#[template(path = "routing_presets.html")]
+pub struct RoutingPresetsTemplate {
+    pub presets: Vec<crate::services::routing_service::RoutingPreset>,
+    pub is_auth: bool,
+    pub username: String,
+    pub admin_path: String,
+    pub active_page: String,
+}
+
+pub async fn get_routing_presets_page(
+    State(state): State<AppState>,
+    jar: CookieJar,
+) -> impl IntoResponse {
+    let presets = state.routing.list().await.unwrap_or_default();
+
+    let admin_path = std::env::var("ADMIN_PATH").unwrap_or_else(|_| "/admin".to_string());
+    let admin_path = if admin_path.starts_with('/') { admin_path } else { format!("/{}", admin_path) };
+
+    let template = RoutingPresetsTemplate {
+        presets,
+        is_auth: true,
+        username: get_auth_user(&state, &jar).await.unwrap_or("Admin".to_string()),
+        admin_path,
+        active_page: "routing".to_string(),
+    };
+
+    match template.render() {
+        Ok(html) => Html(html).into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, format!("Template error: {}", e)).into_response(),
+    }
+}
+
+pub async fn create_routing_preset(
+    State(state): State<AppState>,
+    Form(form): Form<crate::services::routing_service::RoutingPresetForm>,
+) -> impl IntoResponse {
+    save_routing_preset(&state, None, form).await
+}
+
+pub async fn update_routing_preset(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Form(form): Form<crate::services::routing_service::RoutingPresetForm>,
+) -> impl IntoResponse {
+    save_routing_preset(&state, Some(id), form).await
+}
+
+async fn save_routing_preset(
+    state: &AppState,
+    id: Option<i64>,
+    form: crate::services::routing_service::RoutingPresetForm,
+) -> axum::response::Response {
+    let admin_path = std::env::var("ADMIN_PATH").unwrap_or_else(|_| "/admin".to_string());
+
+    match state.routing.save(id, &form).await {
+        Ok(_) => [("HX-Redirect", format!("{}/routing", admin_path))].into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, format!("Failed to save preset: {}", e)).into_response(),
+    }
+}
+
+pub async fn delete_routing_preset(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    match state.routing.delete(id).await {
+        Ok(_) => (axum::http::StatusCode::OK, "").into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, format!("Database error: {}", e)).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct TrafficPackForm {
+    pub traffic_gb: i32,
+    pub price: i64,
+}
+
+pub async fn create_traffic_pack(
+    State(state): State<AppState>,
+    Form(form): Form<TrafficPackForm>,
+) -> impl IntoResponse {
+    match state.store_service.add_traffic_pack(form.traffic_gb, form.price).await {
+        Ok(_) => ([("HX-Refresh", "true")], "Created").into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, format!("Failed to add traffic pack: {}", e)).into_response(),
+    }
+}
+
+pub async fn delete_traffic_pack(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    match state.store_service.remove_traffic_pack(id).await {
+        Ok(_) => (axum::http::StatusCode::OK, "").into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, format!("Database error: {}", e)).into_response(),
+    }
+}
+
+#[derive(Template)]
+#[template(path = "promo_codes.html")]
+pub struct PromoCodesTemplate {
+    pub promos: Vec<crate::services::promo_service::PromoCodeStats>,
+    pub is_auth: bool,
+    pub username: String,
+    pub admin_path: String,
+    pub active_page: String,
+}
+
+pub async fn get_promo_codes_page(
+    State(state): State<AppState>,
+    jar: CookieJar,
+) -> impl IntoResponse {
+    let promos = match state.promos.list_with_stats().await {
+        Ok(promos) => promos,
+        Err(e) => {
+            error!("Failed to load promo codes: {}", e);
+            Vec::new()
+        }
+    };
+
+    let admin_path = std::env::var("ADMIN_PATH").unwrap_or_else(|_| "/admin".to_string());
+    let admin_path = if admin_path.starts_with('/') { admin_path } else { format!("/{}", admin_path) };
+
+    let template = PromoCodesTemplate {
+        promos,
+        is_auth: true,
+        username: get_auth_user(&state, &jar).await.unwrap_or("Admin".to_string()),
+        admin_path,
+        active_page: "promos".to_string(),
+    };
+
+    match template.render() {
+        Ok(html) => Html(html).into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, format!("Template error: {}", e)).into_response(),
+    }
+}
+
+pub async fn create_promo_code(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Form(form): Form<crate::services::promo_service::PromoCodeForm>,
+) -> impl IntoResponse {
+    save_promo_code(&state, &jar, None, form).await
+}
+
+pub async fn update_promo_code(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Path(id): Path<i64>,
+    Form(form): Form<crate::services::promo_service::PromoCodeForm>,
+) -> impl IntoResponse {
+    save_promo_code(&state, &jar, Some(id), form).await
+}
+
+async fn save_promo_code(
+    state: &AppState,
+    jar: &CookieJar,
+    id: Option<i64>,
+    form: crate::services::promo_service::PromoCodeForm,
+) -> axum::response::Response {
+    let admin_path = std::env::var("ADMIN_PATH").unwrap_or_else(|_| "/admin".to_string());
+    let actor = get_auth_user(state, jar).await.unwrap_or("Admin".to_string());
+
+    match state.promos.save(id, &form, &actor).await {
+        Ok(_) => [("HX-Redirect", format!("{}/promos", admin_path))].into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, format!("Failed to save promo code: {}", e)).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PromoToggleForm {
+    pub active: bool,
+}
+
+pub async fn toggle_promo_code(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+    Form(form): Form<PromoToggleForm>,
+) -> impl IntoResponse {
+    match state.promos.set_active(id, form.active).await {
+        Ok(_) => ([("HX-Refresh", "true")], "").into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, format!("Database error: {}", e)).into_response(),
+    }
+}
+
+pub async fn delete_promo_code(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    match state.promos.delete(id).await {
+        Ok(true) => (axum::http::StatusCode::OK, "").into_response(),
+        // Redeemed codes stay for their stats; the page reloads to show them deactivated
+        Ok(false) => ([("HX-Refresh", "true")], "").into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, format!("Database error: {}", e)).into_response(),
+    }
+}
+
+pub async fn dismiss_sharing_flag(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    match state.sub_access.dismiss_flag(id).await {
+        Ok(_) => (axum::http::StatusCode::OK, "").into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, format!("Database error: {}", e)).into_response(),
+    }
+}
@@ -105,6 +105,19 @@ pub async fn add_inbound(
                 return (axum::http::StatusCode::BAD_REQUEST, format!("Invalid Trojan Settings: {}", e)).into_response();
             }
         },
+        "shadowsocks" => {
+            match serde_json::from_str::<crate::models::network::ShadowsocksSettings>(&form.settings) {
+                Ok(ss) => if let Err(e) = ss.validate() {
+                    return (axum::http::StatusCode::BAD_REQUEST, format!("Invalid Shadowsocks Settings: {}", e)).into_response();
+                },
+                Err(e) => return (axum::http::StatusCode::BAD_REQUEST, format!("Invalid Shadowsocks Settings: {}", e)).into_response(),
+            }
+        },
+        "tuic" => {
+            if let Err(e) = serde_json::from_str::<crate::models::network::TuicSettings>(&form.settings) {
+                return (axum::http::StatusCode::BAD_REQUEST, format!("Invalid TUIC Settings: {}", e)).into_response();
+            }
+        },
         _ => {
             // Unknown protocol, just check valid JSON
             if let Err(e) = serde_json::from_str::<serde_json::Value>(&form.settings) {
@@ -375,7 +388,10 @@ pub async fn preview_node_config(
     }
 
     // 4. Generate Config
-    let config = crate::singbox::ConfigGenerator::generate_config(&node, inbounds);
+    let config = match crate::services::orchestration_service::OrchestrationService::render_config(&node, inbounds) {
+        Ok(c) => c,
+        Err(e) => return (axum::http::StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to render config: {}", e)).into_response(),
+    };
     let json = serde_json::to_string_pretty(&config).unwrap_or_default();
 
     (axum::http::StatusCode::OK, json).into_response()
@@ -438,6 +454,19 @@ pub async fn update_inbound(
                 return (axum::http::StatusCode::BAD_REQUEST, format!("Invalid Trojan Settings: {}", e)).into_response();
             }
         },
+        "shadowsocks" => {
+            match serde_json::from_str::<crate::models::network::ShadowsocksSettings>(&form.settings) {
+                Ok(ss) => if let Err(e) = ss.validate() {
+                    return (axum::http::StatusCode::BAD_REQUEST, format!("Invalid Shadowsocks Settings: {}", e)).into_response();
+                },
+                Err(e) => return (axum::http::StatusCode::BAD_REQUEST, format!("Invalid Shadowsocks Settings: {}", e)).into_response(),
+            }
+        },
+        "tuic" => {
+            if let Err(e) = serde_json::from_str::<crate::models::network::TuicSettings>(&form.settings) {
+                return (axum::http::StatusCode::BAD_REQUEST, format!("Invalid TUIC Settings: {}", e)).into_response();
+            }
+        },
         _ => {
             // Unknown protocol, just check valid JSON
             if let Err(e) = serde_json::from_str::<serde_json::Value>(&form.settings) {
@@ -1,5 +1,5 @@
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     http::StatusCode,
     Json,
 };
@@ -116,6 +116,48 @@ pub async fn frontend_heartbeat(
     headers: axum::http::HeaderMap,  // Get headers for auth
     Json(data): Json<FrontendHeartbeat>,
 ) -> Result<StatusCode, StatusCode> {
+    let frontend = authenticate_frontend(&state, &domain, &headers).await?;
+    
+    // Token is valid - update heartbeat
+    sqlx::query(
+        "UPDATE frontend_servers 
+         SET last_heartbeat = CURRENT_TIMESTAMP,
+             traffic_monthly = traffic_monthly + ?
+         WHERE domain = ?"
+    )
+    .bind(data.bandwidth_used as i64)
+    .bind(&domain)
+    .execute(&state.pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to update heartbeat: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    
+    // Record stats
+    sqlx::query(
+        "INSERT INTO frontend_server_stats (frontend_id, requests_count, bandwidth_used)
+         VALUES (?, ?, ?)"
+    )
+    .bind(frontend.id)
+    .bind(data.requests_count as i64)
+    .bind(data.bandwidth_used as i64)
+    .execute(&state.pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to record stats: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    
+    Ok(StatusCode::OK)
+}
+
+/// Check the bearer token a frontend server sends with its requests
+async fn authenticate_frontend(
+    state: &AppState,
+    domain: &str,
+    headers: &axum::http::HeaderMap,
+) -> Result<FrontendServer, StatusCode> {
     // Extract and validate bearer token
     let token = headers
         .get("Authorization")
@@ -130,7 +172,7 @@ pub async fn frontend_heartbeat(
     let frontend = sqlx::query_as::<_, FrontendServer>(
         "SELECT * FROM frontend_servers WHERE domain = ? AND is_active = 1"
     )
-    .bind(&domain)
+    .bind(domain)
     .fetch_optional(&state.pool)
     .await
     .map_err(|e| {
@@ -143,13 +185,13 @@ pub async fn frontend_heartbeat(
     })?;
     
     // Validate token hash
-    let token_hash = frontend.auth_token_hash
+    let token_hash = frontend.auth_token_hash.as_deref()
         .ok_or_else(|| {
             tracing::warn!("Frontend {} has no token hash (needs rotation)", domain);
             StatusCode::UNAUTHORIZED
         })?;
     
-    let valid = bcrypt::verify(token, &token_hash)
+    let valid = bcrypt::verify(token, token_hash)
         .map_err(|e| {
             tracing::error!("Token verification error: {}", e);
             StatusCode::INTERNAL_SERVER_ERROR
@@ -168,38 +210,60 @@ pub async fn frontend_heartbeat(
         }
     }
     
-    // Token is valid - update heartbeat
-    sqlx::query(
-        "UPDATE frontend_servers 
-         SET last_heartbeat = CURRENT_TIMESTAMP,
-             traffic_monthly = traffic_monthly + ?
-         WHERE domain = ?"
-    )
-    .bind(data.bandwidth_used as i64)
-    .bind(&domain)
-    .execute(&state.pool)
-    .await
-    .map_err(|e| {
-        tracing::error!("Failed to update heartbeat: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-    
-    // Record stats
-    sqlx::query(
-        "INSERT INTO frontend_server_stats (frontend_id, requests_count, bandwidth_used)
-         VALUES (?, ?, ?)"
+    Ok(frontend)
+}
+
+#[derive(serde::Deserialize)]
+pub struct InternalSubscriptionQuery {
+    /// The `client` override the subscriber passed to the frontend
+    pub client: Option<String>,
+}
+
+/// Subscription lookup for frontend servers, with the usage/profile headers the panel
+/// would send itself. The frontend names itself in `X-Frontend-Domain` and forwards the
+/// subscriber's address (`X-Client-IP`) and User-Agent so the fetch is logged like a direct one.
+pub async fn get_internal_subscription(
+    Path(uuid): Path<String>,
+    Query(query): Query<InternalSubscriptionQuery>,
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let domain = headers
+        .get("X-Frontend-Domain")
+        .and_then(|v| v.to_str().ok())
+        .ok_or(StatusCode::UNAUTHORIZED)?
+        .to_string();
+    authenticate_frontend(&state, &domain, &headers).await?;
+
+    let sub = sqlx::query_as::<_, crate::models::store::Subscription>(
+        "SELECT * FROM subscriptions WHERE subscription_uuid = ?"
     )
-    .bind(frontend.id)
-    .bind(data.requests_count as i64)
-    .bind(data.bandwidth_used as i64)
-    .execute(&state.pool)
+    .bind(&uuid)
+    .fetch_optional(&state.pool)
     .await
     .map_err(|e| {
-        tracing::error!("Failed to record stats: {}", e);
+        tracing::error!("Failed to fetch subscription: {}", e);
         StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-    
-    Ok(StatusCode::OK)
+    })?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+    if sub.status == "active" || sub.status == "limited" {
+        let user_agent = headers.get(axum::http::header::USER_AGENT).and_then(|v| v.to_str().ok());
+        let client_ip = headers.get("X-Client-IP").and_then(|v| v.to_str().ok()).and_then(|ip| ip.parse().ok());
+        let profile = exarobot_shared::client_detect::negotiate(query.client.as_deref(), user_agent);
+        crate::subscription::log_access(&state, sub.id, client_ip, user_agent, profile);
+    }
+
+    let sub_headers = crate::subscription::subscription_headers(&state, &sub).await;
+
+    Ok(Json(serde_json::json!({
+        "id": sub.id,
+        "user_id": sub.user_id,
+        "status": sub.status,
+        "used_traffic": sub.used_traffic,
+        "subscription_uuid": sub.subscription_uuid,
+        "headers": sub_headers,
+    })))
 }
 
 /// Rotate frontend server auth token
@@ -0,0 +1,61 @@
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::IntoResponse,
+};
+use tracing::error;
+use crate::AppState;
+
+/// Who may scrape `/metrics`
+pub enum MetricsAccess {
+    /// Scrapers must send `Authorization: Bearer <token>`
+    Token(String),
+    /// `METRICS_PUBLIC=true` without a token: anyone can scrape
+    Public,
+    /// Neither configured: the endpoint answers 404
+    Disabled,
+}
+
+impl MetricsAccess {
+    pub fn from_env() -> Self {
+        match std::env::var("METRICS_TOKEN") {
+            Ok(token) if !token.is_empty() => Self::Token(token),
+            _ if std::env::var("METRICS_PUBLIC").is_ok_and(|v| v == "true" || v == "1") => Self::Public,
+            _ => Self::Disabled,
+        }
+    }
+}
+
+/// Prometheus scrape endpoint
+/// GET /metrics
+///
+/// Fails closed: without `METRICS_TOKEN` the endpoint is hidden unless `METRICS_PUBLIC` opts in.
+pub async fn get_metrics(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    match MetricsAccess::from_env() {
+        MetricsAccess::Token(expected) => {
+            let token = headers.get(header::AUTHORIZATION)
+                .and_then(|h| h.to_str().ok())
+                .and_then(|h| h.strip_prefix("Bearer "))
+                .unwrap_or("");
+            if token != expected {
+                return (StatusCode::UNAUTHORIZED, "Invalid Token").into_response();
+            }
+        }
+        MetricsAccess::Public => {}
+        MetricsAccess::Disabled => return StatusCode::NOT_FOUND.into_response(),
+    }
+
+    match state.metrics.render(&state.pool).await {
+        Ok(body) => (
+            [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+            body,
+        ).into_response(),
+        Err(e) => {
+            error!("Failed to render metrics: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Metrics Error").into_response()
+        }
+    }
+}
@@ -6,11 +6,13 @@ mod bot_manager;
 
 mod scripts;
 mod singbox;
+mod xray;
 mod cli;
 mod models;
 mod services;
 mod api;
 mod subscription;
+mod subscription_page;
 mod utils;
 
 use std::net::SocketAddr;
@@ -47,6 +49,12 @@ pub struct AppState {
     pub connection_service: Arc<services::connection_service::ConnectionService>, // NEW
     pub redis: Arc<services::redis_service::RedisService>, // NEW
     pub pubsub: Arc<services::pubsub_service::PubSubService>, // NEW
+    pub metrics: Arc<services::metrics_service::MetricsService>,
+    pub firewall: Arc<services::firewall_service::FirewallService>,
+    pub routing: Arc<services::routing_service::RoutingService>,
+    pub promos: Arc<services::promo_service::PromoService>,
+    pub sub_access: Arc<services::sub_access_service::SubAccessService>,
+    pub rates: Arc<services::rate_service::RateService>,
     pub ssh_public_key: String,
     // Format: IP -> (Lat, Lon, Timestamp)
     pub geo_cache: Arc<Mutex<HashMap<String, (f64, f64, Instant)>>>,
@@ -141,6 +149,25 @@ async fn auth_middleware(
     axum::response::Redirect::to(&login_path).into_response()
 }
 
+/// Records request count and latency per matched route (not raw path, to keep label cardinality bounded).
+async fn metrics_middleware(
+    State(state): State<AppState>,
+    req: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> impl IntoResponse {
+    let method = req.method().to_string();
+    let route = req.extensions()
+        .get::<axum::extract::MatchedPath>()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+
+    let start = Instant::now();
+    let response = next.run(req).await;
+    state.metrics.observe_http(&method, &route, response.status().as_u16(), start.elapsed().as_secs_f64());
+
+    response
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     println!("ExaRobot binary started. Version: {}", env!("CARGO_PKG_VERSION"));
@@ -225,56 +252,49 @@ async fn run_server(pool: sqlx::SqlitePool, ssh_public_key: String) -> Result<()
         store_service.clone(),
     ));
 
-    // Initialize connection service
-    let connection_service = Arc::new(services::connection_service::ConnectionService::new(
-        pool.clone(),
-        orchestration_service.clone(),
-        store_service.clone(),
-    ));
-
-    let bot_token = settings.get_or_default("bot_token", "").await;
-    let pay_token = settings.get_or_default("payment_api_key", "").await;
-    let nowpayments_key = settings.get_or_default("nowpayments_key", "").await;
-    let crystalpay_login = settings.get_or_default("crystalpay_login", "").await;
-    let crystalpay_secret = settings.get_or_default("crystalpay_secret", "").await;
-
-    let stripe_secret_key = settings.get_or_default("stripe_secret_key", "").await;
-    let cryptomus_merchant_id = settings.get_or_default("cryptomus_merchant_id", "").await;
-    let cryptomus_payment_api_key = settings.get_or_default("cryptomus_payment_api_key", "").await;
-    let aaio_merchant_id = settings.get_or_default("aaio_merchant_id", "").await;
-    let aaio_secret_1 = settings.get_or_default("aaio_secret_1", "").await;
-    let aaio_secret_2 = settings.get_or_default("aaio_secret_2", "").await;
-    
-    let lava_project_id = settings.get_or_default("lava_project_id", "").await;
-    let lava_secret_key = settings.get_or_default("lava_secret_key", "").await;
+    let rates = Arc::new(services::rate_service::RateService::new(pool.clone()).await?);
 
-    let is_testnet: String = settings.get_or_default("payment_testnet", "true").await;
-    
     let pay_service = Arc::new(services::pay_service::PayService::new(
         pool.clone(),
         store_service.clone(),
         bot_manager.clone(),
-        bot_token,
-        pay_token,
-        nowpayments_key,
-        crystalpay_login,
-        crystalpay_secret,
-        stripe_secret_key,
-        cryptomus_merchant_id,
-        cryptomus_payment_api_key,
-        aaio_merchant_id,
-        aaio_secret_1,
-        aaio_secret_2,
-        lava_project_id,
-        lava_secret_key,
-        is_testnet == "true",
-    ));
+        settings.clone(),
+        rates.clone(),
+    ).await);
 
     let export_service = Arc::new(services::export_service::ExportService::new(pool.clone()));
     let channel_trial_service = Arc::new(services::channel_trial_service::ChannelTrialService::new(pool.clone()));
     let notification_service = Arc::new(services::notification_service::NotificationService::new(pool.clone()));
 
     let pubsub = services::pubsub_service::PubSubService::new(redis_url).await.expect("Failed to init PubSub");
+    let metrics = Arc::new(services::metrics_service::MetricsService::new()?);
+    match handlers::metrics::MetricsAccess::from_env() {
+        handlers::metrics::MetricsAccess::Public => tracing::warn!("METRICS_PUBLIC is set without METRICS_TOKEN: /metrics is open to anyone"),
+        handlers::metrics::MetricsAccess::Disabled => tracing::info!("METRICS_TOKEN is not set, /metrics is disabled"),
+        handlers::metrics::MetricsAccess::Token(_) => {}
+    }
+    let firewall = Arc::new(services::firewall_service::FirewallService::new(pool.clone(), settings.clone(), pubsub.clone()));
+    let routing = Arc::new(services::routing_service::RoutingService::new(pool.clone()));
+    let promos = Arc::new(services::promo_service::PromoService::new(pool.clone()));
+    let session_secret = std::env::var("SESSION_SECRET").unwrap_or_else(|_| "secret".to_string());
+    let geoip = Arc::new(services::geoip_service::GeoIpService::new(settings.clone()));
+    let sub_access = Arc::new(services::sub_access_service::SubAccessService::new(
+        pool.clone(),
+        settings.clone(),
+        geoip,
+        store_service.clone(),
+        session_secret.clone(),
+    ));
+
+    // Initialize connection service
+    let connection_service = Arc::new(services::connection_service::ConnectionService::new(
+        pool.clone(),
+        orchestration_service.clone(),
+        store_service.clone(),
+        firewall.clone(),
+        redis_service.clone(),
+        pubsub.clone(),
+    ));
 
     // App state
     let state = AppState {
@@ -290,9 +310,15 @@ async fn run_server(pool: sqlx::SqlitePool, ssh_public_key: String) -> Result<()
         notification_service,
         redis: redis_service.clone(),
         pubsub,
+        metrics,
+        firewall,
+        routing,
+        promos,
+        sub_access,
+        rates,
         ssh_public_key,
         geo_cache: Arc::new(Mutex::new(HashMap::new())),
-        session_secret: std::env::var("SESSION_SECRET").unwrap_or_else(|_| "secret".to_string()),
+        session_secret,
     };
     
     // ... rest of function ...
@@ -323,6 +349,29 @@ async fn run_server(pool: sqlx::SqlitePool, ssh_public_key: String) -> Result<()
         traffic_svc.start().await;
     });
 
+    // Start Subscription Sharing Detector
+    let sub_access = state.sub_access.clone();
+    tokio::spawn(async move {
+        sub_access.start().await;
+    });
+
+    // Start Exchange Rate Updater
+    let rates = state.rates.clone();
+    tokio::spawn(async move {
+        rates.start().await;
+    });
+
+    // Check balances against the ledger
+    if let Err(e) = services::ledger_service::LedgerService::verify(&state.pool).await {
+        tracing::error!("Failed to verify balance ledger: {}", e);
+    }
+
+    // Start Invoice Reconciler
+    let pay_service = state.pay_service.clone();
+    tokio::spawn(async move {
+        pay_service.start_reconciler().await;
+    });
+
     // Start Connection Service (Device Limit Enforcement)
     let connection_state = state.clone();
     let connection_store = state.store_service.clone();
@@ -332,6 +381,9 @@ async fn run_server(pool: sqlx::SqlitePool, ssh_public_key: String) -> Result<()
             connection_state.pool.clone(),
             connection_orch,
             connection_store,
+            connection_state.firewall.clone(),
+            connection_state.redis.clone(),
+            connection_state.pubsub.clone(),
         );
         connection_svc.start_monitoring().await;
     });
@@ -345,6 +397,7 @@ use tower_http::services::ServeDir;
         .route("/dashboard", axum::routing::get(handlers::admin::get_dashboard))
         .route("/settings", axum::routing::get(handlers::admin::get_settings))
         .route("/settings/save", axum::routing::post(handlers::admin::save_settings))
+        .route("/settings/rates", axum::routing::post(handlers::admin::save_exchange_rates))
         .route("/settings/bot/toggle", axum::routing::post(handlers::admin::toggle_bot))
         .route("/settings/update/check", axum::routing::post(handlers::admin::check_update)) // NEW
         // New Bot Page
@@ -358,6 +411,8 @@ use tower_http::services::ServeDir;
         .route("/nodes", axum::routing::get(handlers::admin::get_nodes))
 
         .route("/nodes/install", axum::routing::post(handlers::admin::install_node))
+        .route("/nodes/groups", axum::routing::post(handlers::admin::create_node_group))
+        .route("/nodes/groups/:id", axum::routing::post(handlers::admin::update_node_group).delete(handlers::admin::delete_node_group))
         .route("/nodes/:id/edit", axum::routing::get(handlers::admin::get_node_edit))
         .route("/nodes/:id/update", axum::routing::post(handlers::admin::update_node))
         .route("/nodes/:id/activate", axum::routing::post(handlers::admin::activate_node))
@@ -365,6 +420,9 @@ use tower_http::services::ServeDir;
         .route("/nodes/:id/raw-install", axum::routing::get(handlers::admin::get_node_raw_install_script))
         .route("/nodes/:id/config/preview", axum::routing::get(handlers::admin_network::preview_node_config))
         .route("/nodes/:id/sync", axum::routing::post(handlers::admin::sync_node))
+        .route("/nodes/:id/logs", axum::routing::get(handlers::admin::get_node_logs))
+        .route("/nodes/:id/logs/tail", axum::routing::get(handlers::admin::node_logs_tail))
+        .route("/nodes/:id/logs/stream", axum::routing::post(handlers::admin::request_node_logs))
         // SSH-based Node Control removed - use Agent API endpoints instead
         .route("/nodes/:id/delete", axum::routing::delete(handlers::admin::delete_node))
         .route("/nodes/:id/toggle", axum::routing::post(handlers::admin::toggle_node_enable))
@@ -373,6 +431,13 @@ use tower_http::services::ServeDir;
         .route("/plans", axum::routing::get(handlers::admin::get_plans))
         .route("/plans/add", axum::routing::post(handlers::admin::add_plan))
         .route("/plans/:id", axum::routing::get(handlers::admin::get_plan_edit).post(handlers::admin::update_plan).delete(handlers::admin::delete_plan))
+        .route("/routing", axum::routing::get(handlers::admin::get_routing_presets_page).post(handlers::admin::create_routing_preset))
+        .route("/routing/:id", axum::routing::post(handlers::admin::update_routing_preset).delete(handlers::admin::delete_routing_preset))
+        .route("/promos", axum::routing::get(handlers::admin::get_promo_codes_page).post(handlers::admin::create_promo_code))
+        .route("/promos/:id", axum::routing::post(handlers::admin::update_promo_code).delete(handlers::admin::delete_promo_code))
+        .route("/promos/:id/toggle", axum::routing::post(handlers::admin::toggle_promo_code))
+        .route("/traffic-packs", axum::routing::post(handlers::admin::create_traffic_pack))
+        .route("/traffic-packs/:id", axum::routing::delete(handlers::admin::delete_traffic_pack))
         .route("/plans/:id/bindings", axum::routing::get(handlers::admin_network::get_plan_bindings).post(handlers::admin_network::save_plan_bindings))
         .route("/users", get(handlers::admin::get_users))
         .route("/users/:id", get(handlers::admin::get_user_details))
@@ -382,8 +447,12 @@ use tower_http::services::ServeDir;
         .route("/users/subs/:id", axum::routing::delete(handlers::admin::delete_user_subscription))
         .route("/users/subs/:id/refund", axum::routing::post(handlers::admin::refund_user_subscription))
         .route("/users/subs/:id/extend", axum::routing::post(handlers::admin::extend_user_subscription))
+        .route("/users/sharing-flags/:id/dismiss", axum::routing::post(handlers::admin::dismiss_sharing_flag))
         .route("/subs/:id/devices", axum::routing::get(handlers::admin::get_subscription_devices))
         .route("/subs/:id/devices/kill", axum::routing::post(handlers::admin::admin_kill_subscription_sessions))
+        .route("/subs/:id/devices/ban", axum::routing::post(handlers::admin::admin_ban_subscription_device))
+        .route("/firewall/bans", axum::routing::get(handlers::admin::get_firewall_bans))
+        .route("/firewall/bans/:id", axum::routing::delete(handlers::admin::delete_firewall_ban))
         .route("/analytics", axum::routing::get(handlers::admin::get_traffic_analytics))
         
         // Frontend Servers
@@ -403,6 +472,9 @@ use tower_http::services::ServeDir;
         .route("/api/client/user/referrals", axum::routing::get(handlers::client::get_user_referrals))
         
         .route("/transactions", axum::routing::get(handlers::admin::get_transactions))
+        .route("/payments/events", axum::routing::get(handlers::admin::get_payment_events))
+        .route("/payments/events/:id/reprocess", axum::routing::post(handlers::admin::reprocess_payment_event))
+        .route("/payments/:id/refund", axum::routing::post(handlers::admin::refund_payment))
         .route("/bot-logs", axum::routing::get(handlers::admin::bot_logs_page))
         .route("/bot-logs/history", axum::routing::get(handlers::admin::bot_logs_history))
         .route("/bot-logs/tail", axum::routing::get(handlers::admin::bot_logs_tail))
@@ -440,12 +512,21 @@ use tower_http::services::ServeDir;
         .route("/api/v2/node/update-info", axum::routing::get(api::v2::node::get_update_info))
         .route("/api/v2/node/updates/poll", axum::routing::get(api::v2::node::poll_updates)) // NEW
         .route("/api/v2/node/settings", axum::routing::get(api::v2::node::get_settings)) // NEW
+        .route("/api/v2/node/logs", axum::routing::post(api::v2::node::push_logs))
+        .route("/api/v2/node/firewall", axum::routing::get(api::v2::node::get_firewall))
+        .route("/api/v2/node/connections", axum::routing::post(api::v2::node::push_connections))
         .route("/api/v2/client/recommended", axum::routing::get(api::v2::client::get_recommended_nodes)) // AI Routing
+        // Frontend server API
+        .route("/api/internal/subscriptions/:uuid", axum::routing::get(handlers::frontend::get_internal_subscription))
         // Client API
         .nest("/api/client", api::client::routes(state.clone()))
         // Public Subscription URL endpoint
         .route("/sub/:uuid", axum::routing::get(subscription::subscription_handler))
+        .route("/sub/:uuid/awg/:inbound_id", axum::routing::get(subscription::awg_config_handler))
+        // Prometheus scrape endpoint (404 unless METRICS_TOKEN or METRICS_PUBLIC is set)
+        .route("/metrics", axum::routing::get(handlers::metrics::get_metrics))
         .nest(&admin_path, admin_routes)
+        .route_layer(axum::middleware::from_fn_with_state(state.clone(), metrics_middleware))
         .with_state(state)
         .layer(tower_http::compression::CompressionLayer::new())
         .layer(tower_http::limit::RequestBodyLimitLayer::new(10 * 1024 * 1024)) // 10MB limit
@@ -3,3 +3,4 @@ pub mod network;
 pub mod node;
 pub mod activity;
 pub mod frontend;
+pub mod money;
@@ -0,0 +1,154 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Currencies the panel bills or gets paid in. Balances are always kept in USD cents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Currency {
+    Usd,
+    Eur,
+    Rub,
+    Usdt,
+    /// Telegram Stars
+    Xtr,
+}
+
+impl Currency {
+    pub const ALL: [Currency; 5] = [Currency::Usd, Currency::Eur, Currency::Rub, Currency::Usdt, Currency::Xtr];
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Currency::Usd => "USD",
+            Currency::Eur => "EUR",
+            Currency::Rub => "RUB",
+            Currency::Usdt => "USDT",
+            Currency::Xtr => "XTR",
+        }
+    }
+
+    pub fn parse(code: &str) -> Option<Self> {
+        let code = code.trim();
+        Self::ALL.into_iter().find(|c| c.code().eq_ignore_ascii_case(code))
+    }
+
+    /// Digits after the decimal point; amounts are stored as integers of 10^-exponent
+    pub fn exponent(&self) -> u32 {
+        match self {
+            Currency::Xtr => 0,
+            _ => 2,
+        }
+    }
+
+    fn scale(&self) -> i64 {
+        10i64.pow(self.exponent())
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+/// An amount in minor units (cents, kopecks, whole Stars) of an explicit currency
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Money {
+    pub minor: i64,
+    pub currency: Currency,
+}
+
+impl Money {
+    pub fn new(minor: i64, currency: Currency) -> Self {
+        Self { minor, currency }
+    }
+
+    pub fn usd_cents(cents: i64) -> Self {
+        Self::new(cents, Currency::Usd)
+    }
+
+    /// Exact parse of a decimal string such as "5", "5.5" or "1234.56". Extra precision is rounded half up.
+    pub fn parse(amount: &str, currency: Currency) -> Option<Self> {
+        let amount = amount.trim();
+        let (negative, amount) = match amount.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, amount),
+        };
+        let (whole, frac) = amount.split_once('.').unwrap_or((amount, ""));
+        if whole.is_empty() && frac.is_empty() {
+            return None;
+        }
+        if !whole.chars().chain(frac.chars()).all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+
+        let exp = currency.exponent() as usize;
+        let whole: i64 = if whole.is_empty() { 0 } else { whole.parse().ok()? };
+        let mut digits: String = frac.chars().take(exp).collect();
+        while digits.len() < exp {
+            digits.push('0');
+        }
+        let mut minor = whole.checked_mul(currency.scale())? + if digits.is_empty() { 0 } else { digits.parse::<i64>().ok()? };
+        if frac.chars().nth(exp).is_some_and(|d| d >= '5') {
+            minor += 1;
+        }
+        Some(Self::new(if negative { -minor } else { minor }, currency))
+    }
+
+    /// From a JSON number or decimal string, as gateways send them
+    pub fn from_json(value: &serde_json::Value, currency: Currency) -> Option<Self> {
+        match value {
+            serde_json::Value::String(s) => Self::parse(s, currency),
+            serde_json::Value::Number(n) => Self::parse(&n.to_string(), currency),
+            _ => None,
+        }
+    }
+
+    /// Plain decimal amount without the currency, as most gateway APIs expect ("5.00", "250")
+    pub fn to_decimal_string(self) -> String {
+        let exp = self.currency.exponent() as usize;
+        if exp == 0 {
+            return self.minor.to_string();
+        }
+        let scale = self.currency.scale();
+        let sign = if self.minor < 0 { "-" } else { "" };
+        let abs = self.minor.abs();
+        format!("{}{}.{:0width$}", sign, abs / scale, abs % scale, width = exp)
+    }
+
+    pub fn is_positive(self) -> bool {
+        self.minor > 0
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.currency {
+            Currency::Usd if self.minor < 0 => write!(f, "-${}", Money::new(-self.minor, Currency::Usd).to_decimal_string()),
+            Currency::Usd => write!(f, "${}", self.to_decimal_string()),
+            Currency::Xtr => write!(f, "{} ⭐", self.minor),
+            c => write!(f, "{} {}", self.to_decimal_string(), c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_format() {
+        assert_eq!(Money::parse("5", Currency::Usd), Some(Money::usd_cents(500)));
+        assert_eq!(Money::parse("5.5", Currency::Usd), Some(Money::usd_cents(550)));
+        assert_eq!(Money::parse("0.125", Currency::Usd), Some(Money::usd_cents(13)));
+        assert_eq!(Money::parse("250", Currency::Xtr), Some(Money::new(250, Currency::Xtr)));
+        assert_eq!(Money::parse("abc", Currency::Usd), None);
+        assert_eq!(Money::parse(".", Currency::Usd), None);
+        assert_eq!(Money::from_json(&serde_json::json!(19.99), Currency::Rub), Some(Money::new(1999, Currency::Rub)));
+
+        assert_eq!(Money::usd_cents(1205).to_decimal_string(), "12.05");
+        assert_eq!(Money::usd_cents(-5).to_decimal_string(), "-0.05");
+        assert_eq!(Money::usd_cents(500).to_string(), "$5.00");
+        assert_eq!(Money::usd_cents(-250).to_string(), "-$2.50");
+        assert_eq!(Currency::parse("usdt"), Some(Currency::Usdt));
+    }
+}
@@ -40,6 +40,8 @@ pub enum InboundType {
     Trojan(TrojanSettings),
     #[serde(rename = "amneziawg")]
     AmneziaWg(AmneziaWgSettings),
+    Shadowsocks(ShadowsocksSettings),
+    Tuic(TuicSettings),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -47,6 +49,9 @@ pub struct AmneziaWgSettings {
     pub users: Vec<AmneziaWgUser>,
     pub private_key: String,
     pub listen_port: u16,
+    /// IPv4 tunnel subnet; the server takes the first host, peers are allocated from the rest
+    #[serde(default = "default_awg_subnet")]
+    pub subnet: String,
     // Obfuscation parameters
     pub jc: u16,
     pub jmin: u16,
@@ -59,6 +64,10 @@ pub struct AmneziaWgSettings {
     pub h4: u32,
 }
 
+fn default_awg_subnet() -> String {
+    "10.10.0.0/24".to_string()
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AmneziaWgUser {
     pub name: String,
@@ -68,6 +77,19 @@ pub struct AmneziaWgUser {
     pub client_ip: String, // e.g. 10.10.0.2/32
 }
 
+/// Allocated AmneziaWG peer of a subscription on one inbound
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct AwgPeer {
+    pub id: i64,
+    pub subscription_id: i64,
+    pub inbound_id: i64,
+    pub client_ip: String,
+    pub private_key: String,
+    pub public_key: String,
+    pub preshared_key: Option<String>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VlessSettings {
     pub clients: Vec<VlessClient>,
@@ -92,6 +114,9 @@ pub struct Hysteria2Settings {
     pub obfs: Option<Hysteria2Obfs>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub masquerade: Option<String>,
+    /// UDP range redirected to listen_port by the node firewall (e.g. "20000-40000")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port_hopping: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -119,6 +144,71 @@ pub struct TrojanClient {
     pub email: String,
 }
 
+/// Shadowsocks 2022 multi-user: clients authenticate with "server_psk:user_psk"
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShadowsocksSettings {
+    /// 2022-blake3-aes-128-gcm | 2022-blake3-aes-256-gcm | 2022-blake3-chacha20-poly1305
+    pub method: String,
+    /// Server PSK, base64 of exactly `key_len()` bytes
+    pub password: String,
+    #[serde(default)]
+    pub users: Vec<ShadowsocksUser>,
+}
+
+impl ShadowsocksSettings {
+    pub fn key_len(&self) -> usize {
+        if self.method == "2022-blake3-aes-128-gcm" { 16 } else { 32 }
+    }
+
+    /// Only the 2022 ciphers support multiple users, and their PSK length is fixed
+    pub fn validate(&self) -> Result<(), String> {
+        use base64::Engine;
+        if !matches!(self.method.as_str(), "2022-blake3-aes-128-gcm" | "2022-blake3-aes-256-gcm" | "2022-blake3-chacha20-poly1305") {
+            return Err(format!("unsupported method '{}', use a 2022-blake3-* cipher", self.method));
+        }
+        match base64::engine::general_purpose::STANDARD.decode(&self.password) {
+            Ok(key) if key.len() == self.key_len() => Ok(()),
+            _ => Err(format!("password must be base64 of {} random bytes", self.key_len())),
+        }
+    }
+
+    /// Per-user PSK derived from the subscription credential, so rotating it rotates the key
+    pub fn user_key(&self, uuid: &str) -> String {
+        use base64::Engine;
+        use sha2::{Digest, Sha256};
+        let digest = Sha256::digest(uuid.as_bytes());
+        base64::engine::general_purpose::STANDARD.encode(&digest[..self.key_len()])
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShadowsocksUser {
+    pub name: String,
+    pub password: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TuicSettings {
+    #[serde(default)]
+    pub users: Vec<TuicUser>,
+    /// bbr | cubic | new_reno
+    #[serde(default = "default_tuic_congestion_control")]
+    pub congestion_control: String,
+    #[serde(default)]
+    pub zero_rtt_handshake: bool,
+}
+
+fn default_tuic_congestion_control() -> String {
+    "bbr".to_string()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TuicUser {
+    pub name: String,
+    pub uuid: String,
+    pub password: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Fallback {
     pub dest: String, // 80 or 8080 or domain
@@ -133,6 +223,23 @@ pub struct StreamSettings {
     pub security: Option<String>, // "none", "tls", "reality"
     pub tls_settings: Option<TlsSettings>,
     pub reality_settings: Option<RealitySettings>,
+    /// Only for network "xhttp" (Xray nodes)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub xhttp_settings: Option<XhttpSettings>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct XhttpSettings {
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    /// auto | packet-up | stream-up | stream-one
+    #[serde(default = "default_xhttp_mode")]
+    pub mode: String,
+}
+
+fn default_xhttp_mode() -> String {
+    "auto".to_string()
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -33,4 +33,42 @@ pub struct Node {
     pub config_block_ads: bool,
     #[sqlx(default)]
     pub config_block_porn: bool,
+
+    // sing-box lifecycle
+    #[sqlx(default)]
+    pub group_id: Option<i64>,
+    #[sqlx(default)]
+    pub singbox_version: Option<String>,
+    #[sqlx(default)]
+    pub singbox_capabilities: Option<String>,
+    #[sqlx(default)]
+    pub singbox_update_error: Option<String>,
+    #[sqlx(default)]
+    pub singbox_upgrade_status: Option<String>,
+
+    // Clash API secret, only known to the panel and the node's sing-box
+    #[sqlx(default)]
+    #[serde(skip_serializing, default)]
+    pub clash_secret: Option<String>,
+
+    /// Proxy core the node runs ("singbox" | "xray")
+    #[sqlx(default)]
+    #[serde(default)]
+    pub engine: String,
+}
+
+impl Node {
+    pub fn engine(&self) -> NodeEngine {
+        NodeEngine::parse(&self.engine)
+    }
+}
+
+pub use exarobot_shared::engine::NodeEngine;
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct NodeGroup {
+    pub id: i64,
+    pub name: String,
+    pub singbox_version: Option<String>,
+    pub singbox_sha256: Option<String>,
 }
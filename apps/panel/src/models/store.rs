@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use chrono::{DateTime, Utc};
+use crate::models::money::{Currency, Money};
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct User {
@@ -31,6 +32,7 @@ pub struct Plan {
     pub traffic_limit_gb: i32,
     pub device_limit: i32,
     pub is_trial: Option<bool>,
+    pub traffic_reset_period: String, // monthly | billing_cycle | never
     pub created_at: DateTime<Utc>,
     #[sqlx(skip)]
     pub durations: Vec<PlanDuration>,
@@ -63,6 +65,9 @@ pub struct Subscription {
     pub is_trial: Option<bool>,
     pub subscription_uuid: String,  // For subscription URLs
     pub last_sub_access: Option<DateTime<Utc>>, // Track subscription URL access
+    pub duration_id: Option<i64>, // Plan duration used for auto-renewal
+    pub traffic_extra_bytes: i64, // Add-on packs bought in the current traffic cycle
+    pub traffic_reset_at: Option<DateTime<Utc>>, // Start of the current traffic cycle
     pub created_at: DateTime<Utc>,
 }
 
@@ -71,11 +76,28 @@ pub struct Subscription {
 pub struct PromoCode {
     pub id: i64,
     pub code: String,
+    pub description: Option<String>,
     pub discount_percent: i32,
+    pub discount_amount: i64,
     pub bonus_amount: i64,
-    pub max_uses: i32,
-    pub current_uses: i32,
+    pub max_uses: i64, // 0 = unlimited
+    pub max_uses_per_user: i64, // 0 = unlimited
+    pub current_uses: i64,
+    pub plan_ids: String, // Comma separated, empty = every plan
+    pub duration_days: String, // Comma separated, empty = every duration
+    pub first_purchase_only: bool,
+    pub is_active: bool,
     pub expires_at: Option<DateTime<Utc>>,
+    pub created_by: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct TrafficPack {
+    pub id: i64,
+    pub traffic_gb: i32,
+    pub price: i64,
+    pub is_active: bool,
     pub created_at: DateTime<Utc>,
 }
 
@@ -99,10 +121,135 @@ pub struct Payment {
     pub method: String,
     pub amount: i64,
     pub external_id: Option<String>,
+    pub settled_amount: Option<i64>,
+    pub settled_currency: Option<String>,
+    pub status: String,
+    pub subscription_id: Option<i64>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Payment {
+    pub fn money(&self) -> Money {
+        Money::usd_cents(self.amount)
+    }
+
+    pub fn settled(&self) -> Option<Money> {
+        let currency = Currency::parse(self.settled_currency.as_deref()?)?;
+        Some(Money::new(self.settled_amount?, currency))
+    }
+}
+
+/// A refund or dispute against a payment; see `refunds`
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Refund {
+    pub id: i64,
+    pub payment_id: i64,
+    pub kind: String,
+    pub reference: Option<String>,
+    /// USD cents taken back from the user
+    pub amount: i64,
+    pub provider_amount: i64,
+    pub provider_currency: String,
+    pub status: String,
+    pub actor: String,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Refund {
+    pub fn money(&self) -> Money {
+        Money::usd_cents(self.amount)
+    }
+
+    pub fn provider_money(&self) -> Option<Money> {
+        Currency::parse(&self.provider_currency).map(|c| Money::new(self.provider_amount, c))
+    }
+}
+
+/// One balance movement; see `balance_ledger`
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct LedgerEntry {
+    pub id: i64,
+    pub user_id: i64,
+    /// USD cents, positive when the user was credited
+    pub amount: i64,
+    pub balance_after: i64,
+    pub contra_account: String,
+    pub reason: String,
+    pub ref_type: Option<String>,
+    pub ref_id: Option<i64>,
+    pub actor: String,
+    pub note: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl LedgerEntry {
+    pub fn money(&self) -> Money {
+        Money::usd_cents(self.amount)
+    }
+
+    pub fn balance(&self) -> Money {
+        Money::usd_cents(self.balance_after)
+    }
+}
+
+/// A confirmed provider payment as journaled before crediting; see `payment_events`
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct PaymentEvent {
+    pub id: i64,
+    pub provider: String,
+    pub external_id: String,
+    /// Settled amount in minor units of `currency`
+    pub amount: i64,
+    pub currency: String,
+    pub payload: String,
+    pub raw_body: String,
+    pub status: String,
+    pub attempts: i64,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub processed_at: Option<DateTime<Utc>>,
+}
+
+/// An invoice created at a payment provider; see `invoices`
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Invoice {
+    pub id: i64,
+    pub user_id: i64,
+    pub provider: String,
+    pub external_id: Option<String>,
+    /// Owed in USD cents
+    pub amount: i64,
+    /// Price at the provider, in minor units of `billed_currency`
+    pub billed_amount: i64,
+    pub billed_currency: String,
+    pub payload: String,
+    pub url: String,
     pub status: String,
+    pub expires_at: DateTime<Utc>,
+    pub last_checked_at: Option<DateTime<Utc>>,
+    pub paid_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
 }
 
+impl PaymentEvent {
+    pub fn settled(&self) -> Option<Money> {
+        Some(Money::new(self.amount, Currency::parse(&self.currency)?))
+    }
+}
+
+impl Invoice {
+    pub fn billed(&self) -> Option<Money> {
+        Some(Money::new(self.billed_amount, Currency::parse(&self.billed_currency)?))
+    }
+
+    pub fn amount(&self) -> Money {
+        Money::usd_cents(self.amount)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct Category {
     pub id: i64,
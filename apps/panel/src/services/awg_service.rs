@@ -0,0 +1,228 @@
+use anyhow::Result;
+use base64::Engine;
+use sqlx::SqlitePool;
+use std::collections::HashSet;
+use std::net::Ipv4Addr;
+use tracing::{info, warn};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::models::network::{AmneziaWgSettings, AwgPeer, Inbound, InboundType};
+use crate::models::store::Subscription;
+
+/// Resolvers written into client configs; the tunnel routes everything, so any public DNS works
+const CLIENT_DNS: &str = "1.1.1.1, 1.0.0.1";
+
+/// Ready-to-import AmneziaWG config for one node
+pub struct AwgClientConfig {
+    pub inbound_id: i64,
+    pub node_name: String,
+    pub conf: String,
+}
+
+/// AmneziaWG peer provisioning: per-inbound address management and client keys.
+/// Peers are created lazily, on the first config build or download that needs them.
+pub struct AwgService {
+    pool: SqlitePool,
+}
+
+impl AwgService {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+
+    /// Existing peer of the subscription on this inbound, or a new one with the lowest free address
+    pub async fn peer_for(&self, subscription_id: i64, inbound_id: i64, subnet: &str) -> Result<AwgPeer> {
+        let mut released_stale = false;
+
+        for _ in 0..5 {
+            if let Some(peer) = self.existing_peer(subscription_id, inbound_id).await? {
+                return Ok(peer);
+            }
+
+            let used: HashSet<String> = sqlx::query_scalar("SELECT client_ip FROM awg_peers WHERE inbound_id = ?")
+                .bind(inbound_id)
+                .fetch_all(&self.pool)
+                .await?
+                .into_iter()
+                .collect();
+
+            let Some(ip) = host_addresses(subnet)?.find(|ip| !used.contains(&ip.to_string())) else {
+                if released_stale {
+                    break;
+                }
+                // Addresses of ended subscriptions are kept so renewals get the same config back;
+                // reclaim them only when the subnet runs out
+                let res = sqlx::query(
+                    "DELETE FROM awg_peers WHERE inbound_id = ?
+                     AND subscription_id IN (SELECT id FROM subscriptions WHERE status NOT IN ('active', 'pending'))"
+                )
+                .bind(inbound_id)
+                .execute(&self.pool)
+                .await?;
+                warn!("AmneziaWG subnet {} of inbound {} is full, released {} stale peers", subnet, inbound_id, res.rows_affected());
+                released_stale = true;
+                continue;
+            };
+
+            let (private_key, public_key) = generate_keypair();
+            let preshared_key = base64::engine::general_purpose::STANDARD.encode(rand::random::<[u8; 32]>());
+
+            // A concurrent allocation may take the same address; the unique index decides and we retry
+            let inserted = sqlx::query_as::<_, AwgPeer>(
+                "INSERT INTO awg_peers (subscription_id, inbound_id, client_ip, private_key, public_key, preshared_key)
+                 VALUES (?, ?, ?, ?, ?, ?)
+                 ON CONFLICT DO NOTHING
+                 RETURNING *"
+            )
+            .bind(subscription_id)
+            .bind(inbound_id)
+            .bind(ip.to_string())
+            .bind(private_key)
+            .bind(public_key)
+            .bind(preshared_key)
+            .fetch_optional(&self.pool)
+            .await?;
+
+            if let Some(peer) = inserted {
+                info!("Allocated AmneziaWG address {} on inbound {} for subscription {}", peer.client_ip, inbound_id, subscription_id);
+                return Ok(peer);
+            }
+        }
+
+        Err(anyhow::anyhow!("No free AmneziaWG address in {} on inbound {}", subnet, inbound_id))
+    }
+
+    async fn existing_peer(&self, subscription_id: i64, inbound_id: i64) -> Result<Option<AwgPeer>> {
+        Ok(sqlx::query_as::<_, AwgPeer>("SELECT * FROM awg_peers WHERE subscription_id = ? AND inbound_id = ?")
+            .bind(subscription_id)
+            .bind(inbound_id)
+            .fetch_optional(&self.pool)
+            .await?)
+    }
+
+    /// One `.conf` per enabled AmneziaWG inbound of the subscription's plan
+    pub async fn client_configs(&self, sub: &Subscription) -> Result<Vec<AwgClientConfig>> {
+        let inbounds = sqlx::query_as::<_, Inbound>(
+            "SELECT i.* FROM inbounds i
+             JOIN plan_inbounds pi ON pi.inbound_id = i.id
+             JOIN nodes n ON n.id = i.node_id
+             WHERE pi.plan_id = ? AND i.enable = 1 AND i.protocol = 'amneziawg' AND n.status = 'active'
+             ORDER BY n.name"
+        )
+        .bind(sub.plan_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut configs = Vec::new();
+        for inbound in inbounds {
+            let Ok(InboundType::AmneziaWg(settings)) = serde_json::from_str::<InboundType>(&inbound.settings) else {
+                warn!("Skipping AmneziaWG inbound {} with unreadable settings", inbound.tag);
+                continue;
+            };
+            let (node_name, node_ip): (String, String) = sqlx::query_as("SELECT name, ip FROM nodes WHERE id = ?")
+                .bind(inbound.node_id)
+                .fetch_one(&self.pool)
+                .await?;
+
+            let peer = self.peer_for(sub.id, inbound.id, &settings.subnet).await?;
+            let host = if inbound.listen_ip == "::" || inbound.listen_ip == "0.0.0.0" { node_ip } else { inbound.listen_ip.clone() };
+            let endpoint = format!("{}:{}", host, inbound.listen_port);
+
+            configs.push(AwgClientConfig {
+                inbound_id: inbound.id,
+                conf: render_conf(&peer, &settings, &endpoint)?,
+                node_name,
+            });
+        }
+        Ok(configs)
+    }
+}
+
+/// Fresh x25519 keypair, base64 encoded as WireGuard expects
+pub fn generate_keypair() -> (String, String) {
+    let secret = StaticSecret::from(rand::random::<[u8; 32]>());
+    let public = PublicKey::from(&secret);
+    let b64 = base64::engine::general_purpose::STANDARD;
+    (b64.encode(secret.to_bytes()), b64.encode(public.as_bytes()))
+}
+
+/// Public key belonging to a base64 private key
+pub fn public_key(private_key: &str) -> Option<String> {
+    let b64 = base64::engine::general_purpose::STANDARD;
+    let bytes: [u8; 32] = b64.decode(private_key.trim()).ok()?.try_into().ok()?;
+    Some(b64.encode(PublicKey::from(&StaticSecret::from(bytes)).as_bytes()))
+}
+
+fn parse_subnet(subnet: &str) -> Result<(u32, u32)> {
+    let (addr, prefix) = subnet.trim().split_once('/').ok_or_else(|| anyhow::anyhow!("Subnet {} has no prefix length", subnet))?;
+    let addr: Ipv4Addr = addr.parse().map_err(|_| anyhow::anyhow!("Invalid IPv4 subnet {}", subnet))?;
+    let prefix: u32 = prefix.parse().map_err(|_| anyhow::anyhow!("Invalid prefix length in {}", subnet))?;
+    if !(8..=30).contains(&prefix) {
+        return Err(anyhow::anyhow!("Subnet {} must be between /8 and /30", subnet));
+    }
+    let mask = u32::MAX << (32 - prefix);
+    Ok((u32::from(addr) & mask, prefix))
+}
+
+/// Server side of the tunnel: first host of the subnet, with the subnet's prefix
+pub fn server_address(subnet: &str) -> Option<String> {
+    let (network, prefix) = parse_subnet(subnet).ok()?;
+    Some(format!("{}/{}", Ipv4Addr::from(network + 1), prefix))
+}
+
+/// Addresses available to clients: everything after the server address, minus broadcast
+fn host_addresses(subnet: &str) -> Result<impl Iterator<Item = Ipv4Addr>> {
+    let (network, prefix) = parse_subnet(subnet)?;
+    let broadcast = network | (u32::MAX >> prefix);
+    Ok((network + 2..broadcast).map(Ipv4Addr::from))
+}
+
+/// wg-quick style config with the AmneziaWG obfuscation parameters, as the Amnezia apps import it
+pub fn render_conf(peer: &AwgPeer, settings: &AmneziaWgSettings, endpoint: &str) -> Result<String> {
+    let server_public_key = public_key(&settings.private_key)
+        .ok_or_else(|| anyhow::anyhow!("AmneziaWG inbound has an invalid private key"))?;
+
+    let mut conf = format!(
+        "[Interface]\n\
+         PrivateKey = {}\n\
+         Address = {}/32\n\
+         DNS = {}\n\
+         Jc = {}\nJmin = {}\nJmax = {}\n\
+         S1 = {}\nS2 = {}\n\
+         H1 = {}\nH2 = {}\nH3 = {}\nH4 = {}\n\
+         \n\
+         [Peer]\n\
+         PublicKey = {}\n",
+        peer.private_key, peer.client_ip, CLIENT_DNS,
+        settings.jc, settings.jmin, settings.jmax,
+        settings.s1, settings.s2,
+        settings.h1, settings.h2, settings.h3, settings.h4,
+        server_public_key,
+    );
+    if let Some(psk) = &peer.preshared_key {
+        conf.push_str(&format!("PresharedKey = {}\n", psk));
+    }
+    conf.push_str(&format!(
+        "AllowedIPs = 0.0.0.0/0, ::/0\nEndpoint = {}\nPersistentKeepalive = 25\n",
+        endpoint
+    ));
+    Ok(conf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subnet_allocation_and_keys() {
+        assert_eq!(server_address("10.10.0.0/24").as_deref(), Some("10.10.0.1/24"));
+        let hosts: Vec<_> = host_addresses("10.10.0.0/29").unwrap().collect();
+        assert_eq!(hosts.first().unwrap().to_string(), "10.10.0.2");
+        assert_eq!(hosts.last().unwrap().to_string(), "10.10.0.6");
+        assert_eq!(hosts.len(), 5);
+        assert!(host_addresses("10.10.0.0").is_err());
+
+        let (private, public) = generate_keypair();
+        assert_eq!(public_key(&private).as_deref(), Some(public.as_str()));
+    }
+}
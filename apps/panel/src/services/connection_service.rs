@@ -1,59 +1,31 @@
-use anyhow::{Context, Result};
-use chrono::Utc;
-use serde::{Deserialize, Serialize};
+use anyhow::Result;
 use sqlx::SqlitePool;
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use tokio::time;
 use tracing::{error, info, warn};
+use exarobot_shared::api::ConnectionSnapshot;
 
+use crate::services::firewall_service::FirewallService;
 use crate::services::orchestration_service::OrchestrationService;
+use crate::services::pubsub_service::PubSubService;
+use crate::services::redis_service::RedisService;
 use crate::services::store_service::StoreService;
 
-/// Represents a single connection from the Clash API
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct ClashConnection {
-    pub id: String,
-    #[serde(rename = "metadata")]
-    pub metadata: ConnectionMetadata,
-    #[serde(default)]
-    pub chains: Vec<String>,
-}
-
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct ConnectionMetadata {
-    #[serde(default)]
-    pub network: String,
-    #[serde(rename = "type")]
-    pub conn_type: String,
-    #[serde(rename = "sourceIP")]
-    pub source_ip: String,
-    #[serde(rename = "sourcePort")]
-    pub source_port: String,
-    #[serde(rename = "destinationIP")]
-    pub destination_ip: String,
-    #[serde(rename = "destinationPort")]
-    pub destination_port: String,
-    #[serde(default)]
-    pub host: String,
-    #[serde(rename = "inboundIP", default)]
-    pub inbound_ip: String,
-    #[serde(rename = "inboundPort", default)]
-    pub inbound_port: String,
-}
-
-/// Clash API response for /connections
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct ClashConnectionsResponse {
-    #[serde(default)]
-    pub connections: Vec<ClashConnection>,
-}
+/// How long an agent connection snapshot stays valid (agents push every 60s)
+pub const SNAPSHOT_TTL_SECS: usize = 180;
 
-/// Connection monitoring and device limit enforcement service
+/// Connection monitoring and device limit enforcement service.
+///
+/// The sing-box controller is never reached directly: agents push per-user
+/// connection snapshots and close connections when asked via `node_events`.
 pub struct ConnectionService {
     pool: SqlitePool,
     orchestration: Arc<OrchestrationService>,
     store: Arc<StoreService>,
+    firewall: Arc<FirewallService>,
+    redis: Arc<RedisService>,
+    pubsub: Arc<PubSubService>,
 }
 
 impl ConnectionService {
@@ -61,11 +33,17 @@ impl ConnectionService {
         pool: SqlitePool,
         orchestration: Arc<OrchestrationService>,
         store: Arc<StoreService>,
+        firewall: Arc<FirewallService>,
+        redis: Arc<RedisService>,
+        pubsub: Arc<PubSubService>,
     ) -> Self {
         Self {
             pool,
             orchestration,
             store,
+            firewall,
+            redis,
+            pubsub,
         }
     }
 
@@ -76,7 +54,7 @@ impl ConnectionService {
 
         loop {
             interval.tick().await;
-            
+
             if let Err(e) = self.check_and_enforce_limits().await {
                 error!("Error in connection monitoring cycle: {:#}", e);
             }
@@ -85,6 +63,10 @@ impl ConnectionService {
             if let Err(e) = self.store.cleanup_old_ip_tracking().await {
                 error!("Error cleaning up old IP tracking: {:#}", e);
             }
+
+            if let Err(e) = self.firewall.cleanup_expired().await {
+                error!("Error cleaning up expired IP bans: {:#}", e);
+            }
         }
     }
 
@@ -92,228 +74,154 @@ impl ConnectionService {
     async fn check_and_enforce_limits(&self) -> Result<()> {
         info!("Running device limit enforcement cycle...");
 
-        // Get all active nodes
-        let nodes = self.orchestration.get_all_nodes().await?;
-        
-        if nodes.is_empty() {
-            warn!("No active nodes found, skipping device limit check");
+        let snapshots = self.collect_snapshots().await?;
+        if snapshots.is_empty() {
+            warn!("No connection snapshots from agents, skipping device limit check");
             return Ok(());
         }
 
-        // Collect connections from all nodes
-        let mut subscription_ips: HashMap<String, HashSet<String>> = HashMap::new();
-
-        for node in nodes {
-            if node.status != "active" {
-                continue;
-            }
-            match self.fetch_node_connections(&node.ip).await {
-                Ok(connections) => {
-                    info!("Fetched {} connections from node {}", connections.len(), node.ip);
-                    
-                    // Group IPs by subscription UUID (extracted from inbound chains)
-                    for conn in connections {
-                        if let Some(uuid) = extract_uuid_from_connection(&conn) {
-                            subscription_ips
-                                .entry(uuid)
-                                .or_insert_with(HashSet::new)
-                                .insert(conn.metadata.source_ip.clone());
-                        }
-                    }
-                }
-                Err(e) => {
-                    error!("Failed to fetch connections from node {}: {:#}", node.ip, e);
-                    continue;
-                }
+        // Group IPs by sing-box user (Telegram ID) across all nodes
+        let mut user_ips: HashMap<String, HashSet<String>> = HashMap::new();
+        for snapshot in snapshots {
+            for user in snapshot.users {
+                user_ips.entry(user.user).or_default().extend(user.ips);
             }
         }
 
-        info!("Collected IPs for {} subscriptions", subscription_ips.len());
+        info!("Collected IPs for {} users", user_ips.len());
 
-        // Check each subscription's device limit
-        for (uuid, ips) in subscription_ips {
-            if let Err(e) = self.enforce_subscription_limit(&uuid, ips).await {
-                error!("Failed to enforce limit for subscription {}: {:#}", uuid, e);
+        // Check each user's device limit
+        for (user, ips) in user_ips {
+            if let Err(e) = self.enforce_user_limit(&user, ips).await {
+                error!("Failed to enforce limit for user {}: {:#}", user, e);
             }
         }
 
         Ok(())
     }
 
-    /// Fetch active connections from a node's Clash API
-    async fn fetch_node_connections(&self, node_host: &str) -> Result<Vec<ClashConnection>> {
-        let url = format!("http://{}:9090/connections", node_host);
-        
-        let response = reqwest::get(&url)
-            .await
-            .with_context(|| format!("Failed to fetch connections from {}", node_host))?;
+    /// Latest snapshot of every active node that reported one
+    async fn collect_snapshots(&self) -> Result<Vec<ConnectionSnapshot>> {
+        let nodes = self.orchestration.get_all_nodes().await?;
+        let mut snapshots = Vec::new();
 
-        if !response.status().is_success() {
-            anyhow::bail!("Clash API returned error status: {}", response.status());
+        for node in nodes {
+            if node.status != "active" {
+                continue;
+            }
+            match self.redis.get_node_connections(node.id).await {
+                Ok(Some(snapshot)) => snapshots.push(snapshot),
+                Ok(None) => {}
+                Err(e) => error!("Failed to read connections of node {}: {:#}", node.name, e),
+            }
         }
+        Ok(snapshots)
+    }
 
-        let data: ClashConnectionsResponse = response
-            .json()
-            .await
-            .context("Failed to parse Clash API response")?;
+    /// Enforce device limit for the active subscription of a sing-box user
+    async fn enforce_user_limit(&self, user: &str, active_ips: HashSet<String>) -> Result<()> {
+        let Ok(tg_id) = user.parse::<i64>() else {
+            warn!("Unexpected sing-box user name: {}", user);
+            return Ok(());
+        };
 
-        Ok(data.connections)
-    }
+        let sub_id: Option<i64> = sqlx::query_scalar(
+            "SELECT s.id FROM subscriptions s JOIN users u ON s.user_id = u.id
+             WHERE u.tg_id = ? AND s.status = 'active' ORDER BY s.expires_at DESC LIMIT 1"
+        )
+        .bind(tg_id)
+        .fetch_optional(&self.pool)
+        .await?;
 
-    /// Enforce device limit for a single subscription
-    async fn enforce_subscription_limit(&self, uuid: &str, active_ips: HashSet<String>) -> Result<()> {
-        // Get subscription by UUID
-        let sub = match self.get_subscription_by_uuid(uuid).await? {
-            Some(s) => s,
-            None => {
-                warn!("Subscription not found for UUID: {}", uuid);
-                return Ok(());
-            }
+        let Some(sub_id) = sub_id else {
+            warn!("No active subscription for user {}", user);
+            return Ok(());
         };
 
         // Get device limit for this subscription
-        let device_limit = self.store.get_subscription_device_limit(sub.id).await?;
+        let device_limit = self.store.get_subscription_device_limit(sub_id).await?;
 
         let active_device_count = active_ips.len();
         let ips_vec: Vec<String> = active_ips.iter().cloned().collect();
 
+        // Devices seen before this cycle are considered the owner's; new ones are the sharing suspects
+        let known_ips: HashSet<String> = self.store.get_subscription_active_ips(sub_id).await?
+            .into_iter()
+            .map(|r| r.client_ip)
+            .collect();
+
         // Update IP tracking in database
-        self.store.update_subscription_ips(sub.id, ips_vec.clone()).await?;
+        self.store.update_subscription_ips(sub_id, ips_vec).await?;
 
         // Check if limit exceeded (0 for Unlimited)
         if device_limit > 0 && active_device_count > device_limit as usize {
             warn!(
-                "Subscription {} ({}) exceeded device limit: {}/{} devices. Enforcing limit.",
-                sub.id, uuid, active_device_count, device_limit
+                "Subscription {} (user {}) exceeded device limit: {}/{} devices. Enforcing limit.",
+                sub_id, user, active_device_count, device_limit
             );
 
-            // Kill all connections for this subscription to enforce re-login
-            // A more granular approach would be to kill only the "newest" IP, but we don't have timestamp per connection easily here without storing checks.
+            // Kill all connections for this user to enforce re-login
             // Killing all forces valid users to maybe reconnect, but kicks the sharers.
-            
-            self.kill_subscription_connections(uuid).await?;
-
-            // Optional: Send notification via Bot if linked
-            // self.orchestration.notify_user(sub.user_id, "Device limit exceeded. Connections reset.").await?;
-
+            self.kill_user_connections(user).await?;
+
+            // Temporarily block the newly appeared IPs on all nodes (if enabled in settings)
+            let ban_minutes = self.firewall.abuse_ban_minutes().await;
+            if ban_minutes > 0 && !known_ips.is_empty() {
+                for ip in active_ips.iter().filter(|ip| !known_ips.contains(*ip)) {
+                    let reason = format!("Device limit exceeded on subscription {}", sub_id);
+                    if let Err(e) = self.firewall.ban_ip(None, ip, &reason, ban_minutes).await {
+                        error!("Failed to ban {}: {:#}", ip, e);
+                    }
+                }
+            }
         } else if active_device_count > 0 {
             info!(
-                "Subscription {} ({}) within limit: {}/{} devices",
-                sub.id, uuid, active_device_count, if device_limit == 0 { "Unlimited".to_string() } else { device_limit.to_string() }
+                "Subscription {} (user {}) within limit: {}/{} devices",
+                sub_id, user, active_device_count, if device_limit == 0 { "Unlimited".to_string() } else { device_limit.to_string() }
             );
         }
 
         Ok(())
     }
 
-    /// Kill all active connections for a specific subscription across all nodes
+    /// Kill all active connections for a specific subscription across all nodes.
+    /// sing-box users are keyed by Telegram ID, so this resets every session of the owner.
     pub async fn kill_subscription_connections(&self, uuid: &str) -> Result<()> {
-        let nodes = self.orchestration.get_all_nodes().await?;
-        
-        for node in nodes {
-             // 1. Fetch connections again to get IDs (IDs change)
-             // Optimization: We could pass the connection list from the check phase if we refactored
-             match self.fetch_node_connections(&node.ip).await {
-                 Ok(connections) => {
-                     for conn in connections {
-                         // Check if this connection belongs to the UUID
-                         if let Some(conn_uuid) = extract_uuid_from_connection(&conn) {
-                             if conn_uuid == uuid {
-                                 // Kill it
-                                 info!("Killing connection {} on node {} for user {}", conn.id, node.name, uuid);
-                                 if let Err(e) = self.close_connection(&node.ip, &conn.id).await {
-                                     error!("Failed to close connection {} on {}: {}", conn.id, node.name, e);
-                                 }
-                             }
-                         }
-                     }
-                 },
-                 Err(e) => error!("Failed to fetch connections from {} during kill: {}", node.name, e)
-             }
-        }
-        Ok(())
-    }
-
-    /// Close a specific connection on a node via Clash API
-    async fn close_connection(&self, node_host: &str, connection_id: &str) -> Result<()> {
-        let url = format!("http://{}:9090/connections/{}", node_host, connection_id);
-        let client = reqwest::Client::new();
-        
-        let response = client.delete(&url)
-            .send()
-            .await
-            .with_context(|| format!("Failed to delete connection on {}", node_host))?;
-            
-        if !response.status().is_success() {
-            // 404 means already gone, which is fine
-            if response.status() == reqwest::StatusCode::NOT_FOUND {
-                 return Ok(());
-            }
-            anyhow::bail!("Clash API delete error: {}", response.status());
-        }
-        Ok(())
-    }
-
-    /// Get subscription by UUID
-    async fn get_subscription_by_uuid(&self, uuid: &str) -> Result<Option<Subscription>> {
-        let sub = sqlx::query_as::<_, Subscription>(
-            "SELECT * FROM subscriptions WHERE uuid = ? LIMIT 1"
+        let tg_id: Option<i64> = sqlx::query_scalar(
+            "SELECT u.tg_id FROM subscriptions s JOIN users u ON s.user_id = u.id WHERE s.vless_uuid = ?"
         )
         .bind(uuid)
         .fetch_optional(&self.pool)
-        .await
-        .context("Failed to fetch subscription by UUID")?;
+        .await?;
 
-        Ok(sub)
+        match tg_id {
+            Some(tg_id) => self.kill_user_connections(&tg_id.to_string()).await,
+            None => anyhow::bail!("Subscription not found for UUID {}", uuid),
+        }
     }
-}
 
-/// Extract UUID from connection metadata (inbound tag or chain)
-/// This assumes the inbound tag contains the subscription UUID
-fn extract_uuid_from_connection(conn: &ClashConnection) -> Option<String> {
-    // Check chains for UUID pattern (8-4-4-4-12 hex pattern)
-    for chain in &conn.chains {
-        if is_valid_uuid(chain) {
-            return Some(chain.clone());
+    /// Ask every agent to close the user's connections through its local controller
+    async fn kill_user_connections(&self, user: &str) -> Result<()> {
+        let nodes = self.orchestration.get_all_nodes().await?;
+        for node in nodes.iter().filter(|n| n.is_enabled) {
+            info!("Requesting connection reset on node {} for user {}", node.name, user);
+            if let Err(e) = self.pubsub.publish(&format!("node_events:{}", node.id), &format!("kill_user:{}", user)).await {
+                error!("Failed to notify node {}: {}", node.name, e);
+            }
         }
+        Ok(())
     }
 
-    // Fallback: check metadata fields
-    // This may need adjustment based on actual sing-box Clash API output
-    None
-}
-
-/// Simple UUID validation (format check only)
-fn is_valid_uuid(s: &str) -> bool {
-    let parts: Vec<&str> = s.split('-').collect();
-    if parts.len() != 5 {
-        return false;
+    /// Live (connections, upload, download) for a sing-box user across all nodes
+    pub async fn live_usage(&self, user: &str) -> Result<(u32, u64, u64)> {
+        let mut usage = (0, 0, 0);
+        for snapshot in self.collect_snapshots().await? {
+            for u in snapshot.users.iter().filter(|u| u.user == user) {
+                usage.0 += u.connections;
+                usage.1 += u.upload;
+                usage.2 += u.download;
+            }
+        }
+        Ok(usage)
     }
-
-    parts[0].len() == 8
-        && parts[1].len() == 4
-        && parts[2].len() == 4
-        && parts[3].len() == 4
-        && parts[4].len() == 12
-}
-
-/// Minimal subscription struct for this service
-#[derive(Debug, Clone, sqlx::FromRow)]
-struct Subscription {
-    #[allow(dead_code)]
-    id: i64,
-    #[allow(dead_code)]
-    user_id: i64,
-    #[allow(dead_code)]
-    plan_id: i64,
-    #[allow(dead_code)]
-    uuid: String,
-    #[allow(dead_code)]
-    traffic_used: i64,
-    #[allow(dead_code)]
-    traffic_limit: i64,
-    #[allow(dead_code)]
-    expires_at: chrono::DateTime<Utc>,
-    #[allow(dead_code)]
-    is_active: bool,
 }
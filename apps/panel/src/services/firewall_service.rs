@@ -0,0 +1,196 @@
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, SqlitePool};
+use std::sync::Arc;
+use tracing::{info, warn};
+use exarobot_shared::api::{FirewallState, IpBan, PortHopRange};
+
+use crate::models::network::{Inbound, InboundType};
+use crate::services::pubsub_service::PubSubService;
+use crate::settings::SettingsService;
+
+/// sing-box Clash API port (see ConfigGenerator)
+pub const CONTROLLER_PORT: u16 = 9090;
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct IpBanRecord {
+    pub id: i64,
+    pub node_id: Option<i64>,
+    pub ip: String,
+    pub reason: Option<String>,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+/// Builds the desired nftables state for each node and manages temporary IP bans
+pub struct FirewallService {
+    pool: SqlitePool,
+    settings: Arc<SettingsService>,
+    pubsub: Arc<PubSubService>,
+}
+
+impl FirewallService {
+    pub fn new(pool: SqlitePool, settings: Arc<SettingsService>, pubsub: Arc<PubSubService>) -> Self {
+        Self { pool, settings, pubsub }
+    }
+
+    /// Desired state served to the agent: enabled inbound ports, hopping ranges, controller allowlist, bans
+    pub async fn desired_state(&self, node_id: i64) -> Result<FirewallState> {
+        let enabled: bool = self.settings.get_or_default("firewall_enabled", "false").await.parse().unwrap_or(false);
+        let panel_ips = self.settings.get_or_default("firewall_panel_ips", "").await;
+        let (extra_tcp_ports, extra_udp_ports) = parse_extra_ports(&self.settings.get_or_default("firewall_extra_ports", "").await);
+
+        let inbounds: Vec<Inbound> = sqlx::query_as("SELECT * FROM inbounds WHERE node_id = ? AND enable = 1")
+            .bind(node_id)
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut state = FirewallState {
+            enabled,
+            controller_port: CONTROLLER_PORT,
+            controller_allow: panel_ips.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect(),
+            extra_tcp_ports,
+            extra_udp_ports,
+            ..Default::default()
+        };
+
+        for inbound in inbounds {
+            let Ok(port) = u16::try_from(inbound.listen_port) else {
+                warn!("Inbound {} has invalid port {}, skipping in firewall", inbound.id, inbound.listen_port);
+                continue;
+            };
+            match inbound.protocol.as_str() {
+                "hysteria2" | "amneziawg" | "tuic" => state.udp_ports.push(port),
+                // Shadowsocks relays UDP on the same port
+                "shadowsocks" => {
+                    state.tcp_ports.push(port);
+                    state.udp_ports.push(port);
+                }
+                _ => state.tcp_ports.push(port),
+            }
+
+            if let Ok(InboundType::Hysteria2(settings)) = serde_json::from_str::<InboundType>(&inbound.settings)
+                && let Some(range) = settings.port_hopping.as_deref()
+            {
+                match parse_port_range(range) {
+                    Some((start, end)) => state.port_hopping.push(PortHopRange { start, end, target: port }),
+                    None => warn!("Inbound {} has invalid port hopping range '{}'", inbound.id, range),
+                }
+            }
+        }
+        state.tcp_ports.sort_unstable();
+        state.tcp_ports.dedup();
+        state.udp_ports.sort_unstable();
+        state.udp_ports.dedup();
+
+        state.bans = self.active_bans(node_id).await?
+            .into_iter()
+            .map(|b| IpBan { ip: b.ip, expires_at: b.expires_at.timestamp() })
+            .collect();
+
+        Ok(state)
+    }
+
+    /// Active bans for a node, including global ones
+    pub async fn active_bans(&self, node_id: i64) -> Result<Vec<IpBanRecord>> {
+        let bans = sqlx::query_as::<_, IpBanRecord>(
+            "SELECT * FROM ip_bans WHERE (node_id IS NULL OR node_id = ?) AND expires_at > ? ORDER BY expires_at ASC"
+        )
+        .bind(node_id)
+        .bind(Utc::now())
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(bans)
+    }
+
+    pub async fn list_active_bans(&self) -> Result<Vec<IpBanRecord>> {
+        let bans = sqlx::query_as::<_, IpBanRecord>("SELECT * FROM ip_bans WHERE expires_at > ? ORDER BY expires_at DESC")
+            .bind(Utc::now())
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(bans)
+    }
+
+    /// Ban an IP for `minutes` on one node (or all nodes if `node_id` is None) and push it to the agents
+    pub async fn ban_ip(&self, node_id: Option<i64>, ip: &str, reason: &str, minutes: i64) -> Result<()> {
+        let ip: std::net::IpAddr = ip.trim().parse()
+            .map_err(|_| anyhow::anyhow!("Invalid IP address: {}", ip))?;
+        let expires_at = Utc::now() + Duration::minutes(minutes.max(1));
+
+        sqlx::query("INSERT INTO ip_bans (node_id, ip, reason, expires_at) VALUES (?, ?, ?, ?)")
+            .bind(node_id)
+            .bind(ip.to_string())
+            .bind(reason)
+            .bind(expires_at)
+            .execute(&self.pool)
+            .await?;
+
+        info!("Banned {} for {} min on {} ({})", ip, minutes, node_id.map(|id| format!("node {}", id)).unwrap_or_else(|| "all nodes".to_string()), reason);
+        self.notify(node_id).await
+    }
+
+    pub async fn unban(&self, ban_id: i64) -> Result<()> {
+        let node_id: Option<Option<i64>> = sqlx::query_scalar("DELETE FROM ip_bans WHERE id = ? RETURNING node_id")
+            .bind(ban_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        match node_id {
+            Some(node_id) => self.notify(node_id).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Ban length applied by abuse detection; 0 disables automatic bans
+    pub async fn abuse_ban_minutes(&self) -> i64 {
+        self.settings.get_or_default("firewall_abuse_ban_minutes", "0").await.parse().unwrap_or(0)
+    }
+
+    pub async fn cleanup_expired(&self) -> Result<u64> {
+        let res = sqlx::query("DELETE FROM ip_bans WHERE expires_at <= ?")
+            .bind(Utc::now())
+            .execute(&self.pool)
+            .await?;
+        Ok(res.rows_affected())
+    }
+
+    /// Tell the affected agents to reconcile now instead of on their next periodic check
+    pub async fn notify(&self, node_id: Option<i64>) -> Result<()> {
+        let node_ids: Vec<i64> = match node_id {
+            Some(id) => vec![id],
+            None => sqlx::query_scalar("SELECT id FROM nodes WHERE is_enabled = 1")
+                .fetch_all(&self.pool)
+                .await?,
+        };
+        for id in node_ids {
+            let _ = self.pubsub.publish(&format!("node_events:{}", id), "firewall_update").await;
+        }
+        Ok(())
+    }
+}
+
+/// "20000-40000" -> (20000, 40000)
+/// "8080, 10050/tcp, 161/udp" into TCP and UDP ports; a port without protocol is TCP
+fn parse_extra_ports(list: &str) -> (Vec<u16>, Vec<u16>) {
+    let (mut tcp, mut udp) = (Vec::new(), Vec::new());
+    for entry in list.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let (port, proto) = entry.split_once('/').unwrap_or((entry, "tcp"));
+        match (port.trim().parse::<u16>(), proto.trim().to_ascii_lowercase().as_str()) {
+            (Ok(port), "tcp") if port > 0 => tcp.push(port),
+            (Ok(port), "udp") if port > 0 => udp.push(port),
+            _ => warn!("Ignoring invalid extra firewall port '{}'", entry),
+        }
+    }
+    tcp.sort_unstable();
+    tcp.dedup();
+    udp.sort_unstable();
+    udp.dedup();
+    (tcp, udp)
+}
+
+pub fn parse_port_range(range: &str) -> Option<(u16, u16)> {
+    let (start, end) = range.trim().split_once(['-', ':'])?;
+    let start: u16 = start.trim().parse().ok()?;
+    let end: u16 = end.trim().parse().ok()?;
+    (start > 0 && start <= end).then_some((start, end))
+}
@@ -0,0 +1,100 @@
+use maxminddb::{geoip2, Reader};
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+use tracing::{info, warn};
+
+use crate::settings::SettingsService;
+
+/// What the local MaxMind databases know about an address
+#[derive(Debug, Clone, Default)]
+pub struct IpInfo {
+    pub asn: Option<u32>,
+    pub asn_org: Option<String>,
+    /// ISO 3166 alpha-2
+    pub country: Option<String>,
+}
+
+struct Database {
+    path: String,
+    reader: Option<Reader<Vec<u8>>>,
+}
+
+impl Database {
+    fn empty() -> Self {
+        Self { path: String::new(), reader: None }
+    }
+
+    /// Reopen when the configured path changed; a missing file just disables lookups
+    fn ensure(&mut self, path: &str) {
+        if self.path == path {
+            return;
+        }
+        self.path = path.to_string();
+        self.reader = if path.is_empty() {
+            None
+        } else {
+            match Reader::open_readfile(path) {
+                Ok(reader) => {
+                    info!("Loaded GeoIP database {}", path);
+                    Some(reader)
+                }
+                Err(e) => {
+                    warn!("GeoIP database {} unavailable: {}", path, e);
+                    None
+                }
+            }
+        };
+    }
+}
+
+/// Offline ASN / country lookups from GeoLite2 `.mmdb` files configured in settings
+pub struct GeoIpService {
+    settings: Arc<SettingsService>,
+    asn: RwLock<Database>,
+    country: RwLock<Database>,
+}
+
+impl GeoIpService {
+    pub fn new(settings: Arc<SettingsService>) -> Self {
+        Self {
+            settings,
+            asn: RwLock::new(Database::empty()),
+            country: RwLock::new(Database::empty()),
+        }
+    }
+
+    pub async fn lookup(&self, ip: IpAddr) -> IpInfo {
+        let asn_path = self.settings.get_or_default("geoip_asn_db", "geoip/GeoLite2-ASN.mmdb").await;
+        let country_path = self.settings.get_or_default("geoip_country_db", "geoip/GeoLite2-Country.mmdb").await;
+
+        let mut info = IpInfo::default();
+
+        if let Some(db) = Self::open(&self.asn, &asn_path)
+            && let Some(reader) = &db.reader
+            && let Ok(record) = reader.lookup::<geoip2::Asn>(ip)
+        {
+            info.asn = record.autonomous_system_number;
+            info.asn_org = record.autonomous_system_organization.map(str::to_string);
+        }
+
+        if let Some(db) = Self::open(&self.country, &country_path)
+            && let Some(reader) = &db.reader
+            && let Ok(record) = reader.lookup::<geoip2::Country>(ip)
+        {
+            info.country = record.country.and_then(|c| c.iso_code).map(str::to_string);
+        }
+
+        info
+    }
+
+    fn open<'a>(db: &'a RwLock<Database>, path: &str) -> Option<std::sync::RwLockReadGuard<'a, Database>> {
+        {
+            let current = db.read().ok()?;
+            if current.path == path {
+                return Some(current);
+            }
+        }
+        db.write().ok()?.ensure(path);
+        db.read().ok()
+    }
+}
@@ -0,0 +1,212 @@
+use anyhow::{anyhow, Result};
+use sqlx::{SqliteConnection, SqlitePool};
+use tracing::{info, warn};
+
+use crate::models::store::LedgerEntry;
+
+/// Why a balance moved. Each reason books against its own contra account unless the posting names one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    Topup,
+    Purchase,
+    Renewal,
+    Refund,
+    Referral,
+    /// Money taken back after a refund or chargeback of the payment that brought it in
+    Reversal,
+    /// Bonus balance from a promo code
+    Promo,
+    Adjustment,
+}
+
+impl Reason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Reason::Topup => "topup",
+            Reason::Purchase => "purchase",
+            Reason::Renewal => "renewal",
+            Reason::Refund => "refund",
+            Reason::Referral => "referral",
+            Reason::Reversal => "reversal",
+            Reason::Promo => "promo",
+            Reason::Adjustment => "adjustment",
+        }
+    }
+
+    fn contra_account(&self) -> &'static str {
+        match self {
+            Reason::Topup => "gateway",
+            Reason::Purchase | Reason::Renewal => "sales",
+            Reason::Refund => "refunds",
+            Reason::Referral => "referrals",
+            Reason::Reversal => "gateway",
+            Reason::Promo => "promotions",
+            Reason::Adjustment => "adjustments",
+        }
+    }
+}
+
+/// A balance movement about to be booked
+pub struct Posting {
+    pub user_id: i64,
+    /// USD cents, positive credits the user
+    pub amount: i64,
+    pub reason: Reason,
+    pub contra_account: String,
+    pub reference: Option<(&'static str, i64)>,
+    pub actor: String,
+    pub note: Option<String>,
+}
+
+impl Posting {
+    pub fn credit(user_id: i64, cents: i64, reason: Reason) -> Self {
+        Self {
+            user_id,
+            amount: cents,
+            reason,
+            contra_account: reason.contra_account().to_string(),
+            reference: None,
+            actor: "system".to_string(),
+            note: None,
+        }
+    }
+
+    pub fn debit(user_id: i64, cents: i64, reason: Reason) -> Self {
+        Self::credit(user_id, -cents, reason)
+    }
+
+    pub fn contra(mut self, account: impl Into<String>) -> Self {
+        self.contra_account = account.into();
+        self
+    }
+
+    /// What the movement is about: `payment`, `order`, `subscription` or `refund` plus its id
+    pub fn reference(mut self, kind: &'static str, id: i64) -> Self {
+        self.reference = Some((kind, id));
+        self
+    }
+
+    /// `system`, `user` or `admin:<username>`
+    pub fn actor(mut self, actor: impl Into<String>) -> Self {
+        self.actor = actor.into();
+        self
+    }
+
+    pub fn note(mut self, note: impl Into<String>) -> Self {
+        self.note = Some(note.into());
+        self
+    }
+}
+
+/// The only way balances change: every movement updates `users.balance` and appends to
+/// `balance_ledger` on the caller's transaction, so the two cannot drift apart.
+pub struct LedgerService;
+
+impl LedgerService {
+    /// Book a movement and return the new balance. Debits never take a balance below zero,
+    /// except admin adjustments and reversals, which may leave the user owing.
+    pub async fn post(conn: &mut SqliteConnection, posting: Posting) -> Result<i64> {
+        if posting.amount == 0 {
+            return sqlx::query_scalar("SELECT balance FROM users WHERE id = ?")
+                .bind(posting.user_id)
+                .fetch_optional(&mut *conn)
+                .await?
+                .ok_or_else(|| anyhow!("User not found"));
+        }
+
+        let allow_negative = matches!(posting.reason, Reason::Adjustment | Reason::Reversal);
+        let balance: Option<i64> = sqlx::query_scalar(
+            "UPDATE users SET balance = balance + ? WHERE id = ? AND (? OR balance + ? >= 0) RETURNING balance"
+        )
+        .bind(posting.amount)
+        .bind(posting.user_id)
+        .bind(allow_negative)
+        .bind(posting.amount)
+        .fetch_optional(&mut *conn)
+        .await?;
+
+        let Some(balance) = balance else {
+            let exists: Option<i64> = sqlx::query_scalar("SELECT id FROM users WHERE id = ?")
+                .bind(posting.user_id)
+                .fetch_optional(&mut *conn)
+                .await?;
+            return Err(match exists {
+                Some(_) => anyhow!("Insufficient balance"),
+                None => anyhow!("User not found"),
+            });
+        };
+
+        sqlx::query(
+            "INSERT INTO balance_ledger (user_id, amount, balance_after, contra_account, reason, ref_type, ref_id, actor, note)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)"
+        )
+        .bind(posting.user_id)
+        .bind(posting.amount)
+        .bind(balance)
+        .bind(&posting.contra_account)
+        .bind(posting.reason.as_str())
+        .bind(posting.reference.map(|(kind, _)| kind))
+        .bind(posting.reference.map(|(_, id)| id))
+        .bind(&posting.actor)
+        .bind(&posting.note)
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(balance)
+    }
+
+    /// Admin "set balance": books the difference as an adjustment
+    pub async fn adjust_to(conn: &mut SqliteConnection, user_id: i64, target: i64, actor: &str, note: Option<String>) -> Result<i64> {
+        let current: i64 = sqlx::query_scalar("SELECT balance FROM users WHERE id = ?")
+            .bind(user_id)
+            .fetch_optional(&mut *conn)
+            .await?
+            .ok_or_else(|| anyhow!("User not found"))?;
+
+        let mut posting = Posting::credit(user_id, target - current, Reason::Adjustment).actor(actor);
+        posting.note = note;
+        Self::post(conn, posting).await
+    }
+
+    /// Most recent entries first
+    pub async fn statement(pool: &SqlitePool, user_id: i64, limit: i64) -> Result<Vec<LedgerEntry>> {
+        Ok(sqlx::query_as::<_, LedgerEntry>(
+            "SELECT * FROM balance_ledger WHERE user_id = ? ORDER BY id DESC LIMIT ?"
+        )
+        .bind(user_id)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?)
+    }
+
+    pub async fn ledger_balance(pool: &SqlitePool, user_id: i64) -> Result<i64> {
+        Ok(sqlx::query_scalar("SELECT COALESCE(SUM(amount), 0) FROM balance_ledger WHERE user_id = ?")
+            .bind(user_id)
+            .fetch_one(pool)
+            .await?)
+    }
+
+    /// Users whose stored balance differs from their ledger: (user_id, stored, ledger)
+    pub async fn mismatches(pool: &SqlitePool) -> Result<Vec<(i64, i64, i64)>> {
+        Ok(sqlx::query_as(
+            "SELECT u.id, u.balance, COALESCE(l.total, 0)
+             FROM users u
+             LEFT JOIN (SELECT user_id, SUM(amount) AS total FROM balance_ledger GROUP BY user_id) l ON l.user_id = u.id
+             WHERE u.balance != COALESCE(l.total, 0)"
+        )
+        .fetch_all(pool)
+        .await?)
+    }
+
+    /// Startup check; balances edited behind the ledger's back show up here
+    pub async fn verify(pool: &SqlitePool) -> Result<()> {
+        let mismatches = Self::mismatches(pool).await?;
+        if mismatches.is_empty() {
+            info!("Balance ledger verified");
+        }
+        for (user_id, stored, ledger) in mismatches {
+            warn!("Balance of user {} is {} but its ledger sums to {}", user_id, stored, ledger);
+        }
+        Ok(())
+    }
+}
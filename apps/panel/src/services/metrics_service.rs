@@ -0,0 +1,124 @@
+use anyhow::Result;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use sqlx::SqlitePool;
+
+/// A node counts as "up" if its agent sent a heartbeat within this window.
+const NODE_UP_WINDOW: &str = "-2 minutes";
+
+/// Prometheus registry for the panel.
+/// Counters/histograms are updated inline by handlers; gauges backed by the
+/// database are refreshed on every scrape in `render`.
+pub struct MetricsService {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_duration: HistogramVec,
+    bot_updates: IntCounterVec,
+    payment_webhooks: IntCounterVec,
+    db_pool_size: IntGauge,
+    db_pool_idle: IntGauge,
+    active_subscriptions: IntGauge,
+    node_up: IntGaugeVec,
+}
+
+impl MetricsService {
+    pub fn new() -> Result<Self> {
+        let registry = Registry::new_custom(Some("exarobot".to_string()), None)?;
+
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests by route, method and status"),
+            &["method", "route", "status"],
+        )?;
+        let http_duration = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "HTTP request latency by route"),
+            &["method", "route"],
+        )?;
+        let bot_updates = IntCounterVec::new(
+            Opts::new("bot_updates_total", "Telegram updates received by the bot"),
+            &["kind"],
+        )?;
+        let payment_webhooks = IntCounterVec::new(
+            Opts::new("payment_webhooks_total", "Payment webhooks by provider and outcome"),
+            &["source", "outcome"],
+        )?;
+        let db_pool_size = IntGauge::new("db_pool_connections", "Open SQLite pool connections")?;
+        let db_pool_idle = IntGauge::new("db_pool_idle_connections", "Idle SQLite pool connections")?;
+        let active_subscriptions = IntGauge::new("active_subscriptions", "Subscriptions with status 'active'")?;
+        let node_up = IntGaugeVec::new(
+            Opts::new("node_up", "1 if the node agent sent a recent heartbeat, 0 otherwise"),
+            &["node_id", "name"],
+        )?;
+
+        registry.register(Box::new(http_requests.clone()))?;
+        registry.register(Box::new(http_duration.clone()))?;
+        registry.register(Box::new(bot_updates.clone()))?;
+        registry.register(Box::new(payment_webhooks.clone()))?;
+        registry.register(Box::new(db_pool_size.clone()))?;
+        registry.register(Box::new(db_pool_idle.clone()))?;
+        registry.register(Box::new(active_subscriptions.clone()))?;
+        registry.register(Box::new(node_up.clone()))?;
+
+        Ok(Self {
+            registry,
+            http_requests,
+            http_duration,
+            bot_updates,
+            payment_webhooks,
+            db_pool_size,
+            db_pool_idle,
+            active_subscriptions,
+            node_up,
+        })
+    }
+
+    pub fn observe_http(&self, method: &str, route: &str, status: u16, seconds: f64) {
+        self.http_requests
+            .with_label_values(&[method, route, &status.to_string()])
+            .inc();
+        self.http_duration
+            .with_label_values(&[method, route])
+            .observe(seconds);
+    }
+
+    pub fn record_bot_update(&self, kind: &str) {
+        self.bot_updates.with_label_values(&[kind]).inc();
+    }
+
+    /// `source` comes from the URL, so anything that isn't a known provider is counted as "unknown"
+    pub fn record_payment_webhook(&self, source: &str, success: bool) {
+        let provider = crate::services::payments::spec(source).map_or("unknown", |p| p.id);
+        let outcome = if success { "ok" } else { "error" };
+        self.payment_webhooks.with_label_values(&[provider, outcome]).inc();
+    }
+
+    /// Refresh DB-backed gauges and encode everything in the text exposition format.
+    pub async fn render(&self, pool: &SqlitePool) -> Result<String> {
+        self.db_pool_size.set(pool.size() as i64);
+        self.db_pool_idle.set(pool.num_idle() as i64);
+
+        let active: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM subscriptions WHERE status = 'active'")
+            .fetch_one(pool)
+            .await?;
+        self.active_subscriptions.set(active);
+
+        let nodes: Vec<(i64, String, bool)> = sqlx::query_as(
+            "SELECT id, name, (last_seen IS NOT NULL AND datetime(last_seen) > datetime('now', ?)) FROM nodes WHERE is_enabled = 1"
+        )
+            .bind(NODE_UP_WINDOW)
+            .fetch_all(pool)
+            .await?;
+
+        // Reset so deleted/disabled nodes drop out of the exposition
+        self.node_up.reset();
+        for (id, name, up) in nodes {
+            self.node_up
+                .with_label_values(&[&id.to_string(), &name])
+                .set(if up { 1 } else { 0 });
+        }
+
+        let mut buf = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buf)?;
+        Ok(String::from_utf8(buf)?)
+    }
+}
@@ -1,6 +1,10 @@
 pub mod store_service;
 pub mod orchestration_service;
 pub mod pay_service;
+pub mod payments;
+pub mod rate_service;
+pub mod ledger_service;
+pub mod promo_service;
 pub mod activity_service; // Legacy, to be replaced by logging_service
 pub mod logging_service; // NEW
 pub mod referral_service; // NEW
@@ -13,3 +17,9 @@ pub mod connection_service;
 pub mod channel_trial_service;  // NEW: Channel membership trial management
 pub mod export_service;  // NEW: Database and settings export/backup
 pub mod notification_service;
+pub mod metrics_service;
+pub mod firewall_service;
+pub mod routing_service;
+pub mod geoip_service;
+pub mod sub_access_service;
+pub mod awg_service;
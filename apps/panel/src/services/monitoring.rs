@@ -1,6 +1,8 @@
 use tracing::{info, error};
 use tokio::time::{interval, Duration};
 use crate::AppState;
+use crate::bot::utils::escape_md;
+use crate::services::store_service::RenewalPolicy;
 use chrono::Utc;
 
 pub struct MonitoringService {
@@ -45,11 +47,13 @@ impl MonitoringService {
     }
 
     async fn check_expirations(&self) -> anyhow::Result<()> {
-        let now = Utc::now();
+        // Subscriptions keep working through the grace period, giving failed renewals time to go through
+        let policy = RenewalPolicy::load(&self.state.settings).await;
+        let cutoff = Utc::now() - chrono::Duration::hours(policy.grace_hours);
         
         // Find active subscriptions that have expired
-        let expired_subs: Vec<(i64, i64)> = sqlx::query_as("SELECT id, user_id FROM subscriptions WHERE status = 'active' AND expires_at < ?")
-            .bind(now)
+        let expired_subs: Vec<(i64, i64)> = sqlx::query_as("SELECT id, user_id FROM subscriptions WHERE status IN ('active', 'limited') AND expires_at < ?")
+            .bind(cutoff)
             .fetch_all(&self.state.pool)
             .await?;
 
@@ -82,65 +86,81 @@ impl MonitoringService {
         Ok(())
     }
 
-    /// Process auto-renewals for subscriptions expiring in next 24h
+    /// Process auto-renewals for subscriptions expiring in next 24h or in their grace period
     async fn process_auto_renewals(&self) -> anyhow::Result<()> {
         use crate::services::store_service::RenewalResult;
         
-        
-       let results = self.state.store_service.process_auto_renewals().await?;
+        let policy = RenewalPolicy::load(&self.state.settings).await;
+        let results = self.state.store_service.process_auto_renewals(&policy).await?;
         
         if results.is_empty() {
             return Ok(());
         }
         
         info!("Processing {} auto-renewal results", results.len());
-        
+
+        let mut quota_changes = Vec::new();
         for result in results {
-            match result {
-                RenewalResult::Success { user_id, sub_id, amount, plan_name } => {
-                    // Get Telegram ID
-                    if let Ok(Some(user)) = sqlx::query_as::<_, (i64,)>("SELECT tg_id FROM users WHERE id = ?")
-                        .bind(user_id)
-                        .fetch_optional(&self.state.pool)
-                        .await {
-                        
-                        let msg = format!(
-                            "✅ *Auto\\-Renewed\\!*\n\n\
-                             💎 Plan: {}\n\
-                             💳 Charged: ${:.2}\n\
-                             📅 Valid for: 30 days",
-                            plan_name.replace("-", "\\-").replace(".", "\\."),
-                            amount as f64 / 100.0
-                        );
-                        
-                        let _ = self.state.bot_manager.send_notification(user.0, &msg).await;
-                        
-                        info!("Auto-renewed subscription {} for user {}, charged ${:.2}", sub_id, user_id, amount as f64 / 100.0);
-                    }
+            let (user_id, msg) = match result {
+                RenewalResult::Success { user_id, sub_id, amount, plan_name, days, expires_at, quota_change } => {
+                    info!("Auto-renewed subscription {} for user {}, charged ${:.2}", sub_id, user_id, amount as f64 / 100.0);
+                    // A billing-cycle plan starts the new period with a fresh quota
+                    let traffic = match &quota_change {
+                        Some(change) if !change.limited => "\n\n✅ Your traffic quota was reset and the subscription is active again\\.",
+                        Some(_) => "\n\n🚫 The subscription has used all of its traffic\\. Add a traffic pack in *My Services* to continue\\.",
+                        None => "",
+                    };
+                    quota_changes.extend(quota_change);
+                    (user_id, format!(
+                        "✅ *Auto\\-Renewed\\!*\n\n\
+                         💎 Plan: {}\n\
+                         💳 Charged: {}\n\
+                         📅 Valid for: {} days\n\
+                         ⌛ Expires: {}{}",
+                        escape_md(&plan_name),
+                        escape_md(&format!("${:.2}", amount as f64 / 100.0)),
+                        days,
+                        escape_md(&expires_at.format("%Y-%m-%d").to_string()),
+                        traffic
+                    ))
                 }
-                RenewalResult::InsufficientFunds { user_id, sub_id, required, available } => {
-                    if let Ok(Some(user)) = sqlx::query_as::<_, (i64,)>("SELECT tg_id FROM users WHERE id = ?")
-                        .bind(user_id)
-                        .fetch_optional(&self.state.pool)
-                        .await {
-                        
-                        let msg = format!(
-                            "⚠️ *Auto\\-Renewal Failed*\n\n\
-                             💰 Balance: ${:.2}\n\
-                             💳 Required: ${:.2}\n\n\
-                             Please top up your account to renew your subscription\\.",
-                            available as f64 / 100.0,
-                            required as f64 / 100.0
-                        );
-                        
-                        let _ = self.state.bot_manager.send_notification(user.0, &msg).await;
-                        
-                        info!("Auto-renewal failed for sub {} (user {}): insufficient funds", sub_id, user_id);
-                    }
+                RenewalResult::InsufficientFunds { user_id, sub_id, required, available, next_attempt, service_until } => {
+                    info!("Auto-renewal failed for sub {} (user {}): insufficient funds", sub_id, user_id);
+                    let retry = match next_attempt {
+                        Some(at) => format!("We'll try again at {} UTC\\.", escape_md(&at.format("%Y-%m-%d %H:%M").to_string())),
+                        None => "This was the last attempt, please renew manually\\.".to_string(),
+                    };
+                    (user_id, format!(
+                        "⚠️ *Auto\\-Renewal Failed*\n\n\
+                         💰 Balance: {}\n\
+                         💳 Required: {}\n\n\
+                         Please top up your account to renew your subscription\\. {}\n\
+                         Service stops at {} UTC\\.",
+                        escape_md(&format!("${:.2}", available as f64 / 100.0)),
+                        escape_md(&format!("${:.2}", required as f64 / 100.0)),
+                        retry,
+                        escape_md(&service_until.format("%Y-%m-%d %H:%M").to_string())
+                    ))
+                }
+                RenewalResult::Unavailable { user_id, sub_id, plan_name } => {
+                    info!("Auto-renewal disabled for sub {} (user {}): plan has no durations on sale", sub_id, user_id);
+                    (user_id, format!(
+                        "⚠️ *Auto\\-Renewal Disabled*\n\n\
+                         💎 Plan {} can no longer be renewed\\. Please choose another plan before your subscription expires\\.",
+                        escape_md(&plan_name)
+                    ))
                 }
+            };
+
+            if let Ok(Some(user)) = sqlx::query_as::<_, (i64,)>("SELECT tg_id FROM users WHERE id = ?")
+                .bind(user_id)
+                .fetch_optional(&self.state.pool)
+                .await {
+                let _ = self.state.bot_manager.send_notification(user.0, &msg).await;
             }
         }
-        
+
+        crate::services::traffic_service::publish_quota_changes(&self.state, &quota_changes).await;
         Ok(())
     }
 
@@ -172,7 +192,7 @@ impl MonitoringService {
                     AlertType::Traffic90 => {
                         "⚠️ *Traffic Alert*\n\n\
                          You've used *90%* of your traffic\\.\n\
-                         _Service will be paused at 100%\\._"
+                         _Service will be paused at 100%\\. Add a traffic pack in My Services to avoid that\\._"
                     }
                     AlertType::Expiry3Days => {
                         "⏰ *Expiry Alert*\n\n\
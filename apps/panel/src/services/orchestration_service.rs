@@ -4,14 +4,14 @@ use std::sync::Arc;
 
 
 // Removed unused Subscription import
-use crate::models::node::Node;
+use crate::models::node::{Node, NodeEngine, NodeGroup};
 use crate::singbox::{ConfigGenerator};
+use crate::xray::XrayConfigGenerator;
 use crate::services::store_service::StoreService;
 
 
 pub struct OrchestrationService {
     pub pool: SqlitePool,
-    #[allow(dead_code)]
     store_service: Arc<StoreService>,
 }
 
@@ -92,6 +92,7 @@ impl OrchestrationService {
                 short_ids: vec![short_id],
                 max_time_diff: Some(0), 
             }),
+            xhttp_settings: None,
         };
         let stream_json = serde_json::to_string(&stream_settings)?;
         
@@ -119,6 +120,7 @@ impl OrchestrationService {
              down_mbps: 100,
              obfs: None, // Disabled by default for better compatibility (matches Blitz)
              masquerade: Some("file:///opt/exarobot/apps/panel/assets/masquerade".to_string()),
+             port_hopping: None,
         };
 
         let hy2_json = serde_json::to_string(&InboundType::Hysteria2(hy2_settings_struct))?;
@@ -133,6 +135,7 @@ impl OrchestrationService {
                 certificates: None, // Will use auto-generated certs
             }),
             reality_settings: None,
+            xhttp_settings: None,
         };
         
         sqlx::query("INSERT INTO inbounds (node_id, tag, protocol, listen_port, settings, stream_settings, enable) VALUES (?, ?, 'hysteria2', 8443, ?, ?, 1)")
@@ -191,6 +194,7 @@ impl OrchestrationService {
             users: vec![],
             private_key: awg_priv,
             listen_port: 51820,
+            subnet: "10.10.0.0/24".to_string(),
             jc: awg_jc,
             jmin: awg_jmin,
             jmax: awg_jmax,
@@ -227,6 +231,18 @@ impl OrchestrationService {
                 e
             })?;
 
+        // Nodes created before per-node controller secrets get one on first config build
+        let mut node = node;
+        if node.clash_secret.is_none() {
+            let secret = uuid::Uuid::new_v4().simple().to_string();
+            sqlx::query("UPDATE nodes SET clash_secret = ? WHERE id = ?")
+                .bind(&secret)
+                .bind(node_id)
+                .execute(&self.pool)
+                .await?;
+            node.clash_secret = Some(secret);
+        }
+
         info!("Step 2: Fetching inbounds for node {}", node_id);
         // 2. Fetch Inbounds for this node
         let mut inbounds: Vec<crate::models::network::Inbound> = sqlx::query_as("SELECT * FROM inbounds WHERE node_id = ?")
@@ -281,6 +297,7 @@ impl OrchestrationService {
         // Helper struct for query
         #[derive(sqlx::FromRow)]
         struct SubWithUser {
+            id: i64,
             vless_uuid: Option<String>,
             tg_id: i64,
             username: Option<String>,
@@ -290,7 +307,7 @@ impl OrchestrationService {
         
         let query = format!(
             r#"
-            SELECT s.vless_uuid, u.tg_id, u.username
+            SELECT s.id, s.vless_uuid, u.tg_id, u.username
             FROM subscriptions s
             JOIN users u ON s.user_id = u.id
             WHERE LOWER(s.status) = 'active' AND s.plan_id IN ({})
@@ -304,7 +321,7 @@ impl OrchestrationService {
             
         info!("Found {} active subscriptions for inbound {}", active_subs.len(), inbound.tag);
 
-        use crate::models::network::{InboundType, VlessClient, Hysteria2User};
+        use crate::models::network::{InboundType, VlessClient, TrojanClient, Hysteria2User, AmneziaWgUser, ShadowsocksUser, TuicUser};
 
         match serde_json::from_str::<InboundType>(&inbound.settings) {
             Ok(mut settings) => {
@@ -330,6 +347,16 @@ impl OrchestrationService {
                             }
                         }
                     },
+                    InboundType::Trojan(trojan) => {
+                        for sub in &active_subs {
+                            if let Some(uuid) = &sub.vless_uuid {
+                                trojan.clients.push(TrojanClient {
+                                    password: uuid.clone(),
+                                    email: sub.tg_id.to_string(),
+                                });
+                            }
+                        }
+                    },
                     InboundType::Hysteria2(hy2) => {
                          for sub in &active_subs {
                             if let Some(uuid) = &sub.vless_uuid {
@@ -343,7 +370,43 @@ impl OrchestrationService {
                             }
                         }
                     },
-                    _ => {}
+                    InboundType::Shadowsocks(ss) => {
+                        for sub in &active_subs {
+                            if let Some(uuid) = &sub.vless_uuid {
+                                let password = ss.user_key(uuid);
+                                ss.users.push(ShadowsocksUser {
+                                    name: sub.tg_id.to_string(),
+                                    password,
+                                });
+                            }
+                        }
+                    },
+                    InboundType::Tuic(tuic) => {
+                        for sub in &active_subs {
+                            if let Some(uuid) = &sub.vless_uuid {
+                                tuic.users.push(TuicUser {
+                                    name: sub.tg_id.to_string(),
+                                    uuid: uuid.clone(),
+                                    password: uuid.replace("-", ""),
+                                });
+                            }
+                        }
+                    },
+                    InboundType::AmneziaWg(awg) => {
+                        // Each subscriber is a peer with its own tunnel address; the private key stays in the panel
+                        for sub in &active_subs {
+                            match self.store_service.awg().peer_for(sub.id, inbound.id, &awg.subnet).await {
+                                Ok(peer) => awg.users.push(AmneziaWgUser {
+                                    name: sub.tg_id.to_string(),
+                                    private_key: String::new(),
+                                    public_key: peer.public_key,
+                                    preshared_key: peer.preshared_key,
+                                    client_ip: format!("{}/32", peer.client_ip),
+                                }),
+                                Err(e) => error!("No AmneziaWG peer for subscription {} on {}: {}", sub.id, inbound.tag, e),
+                            }
+                        }
+                    },
                 }
                 inbound.settings = serde_json::to_string(&settings)?;
             },
@@ -353,16 +416,19 @@ impl OrchestrationService {
             }
         }
 
-        info!("Step 4: generating final sing-box config JSON");
-        // 4. Generate Config
-        // 4. Generate Config
-        let config = ConfigGenerator::generate_config(
-            &node,
-            inbounds
-        );
-        
+        info!("Step 4: generating final {} config JSON", node.engine().as_str());
+        let config = Self::render_config(&node, inbounds)?;
+
         info!("Config generation successful for node {}", node_id);
-        Ok((node, serde_json::to_value(&config)?))
+        Ok((node, config))
+    }
+
+    /// Renders the node's inbounds for the proxy core it runs
+    pub fn render_config(node: &Node, inbounds: Vec<crate::models::network::Inbound>) -> anyhow::Result<serde_json::Value> {
+        Ok(match node.engine() {
+            NodeEngine::Singbox => serde_json::to_value(ConfigGenerator::generate_config(node, inbounds))?,
+            NodeEngine::Xray => XrayConfigGenerator::generate_config(node, inbounds),
+        })
     }
 
     /// Get all nodes (for admin UI)
@@ -374,4 +440,12 @@ impl OrchestrationService {
         Ok(all_nodes)
     }
 
+    pub async fn get_node_groups(&self) -> anyhow::Result<Vec<NodeGroup>> {
+        let groups: Vec<NodeGroup> = sqlx::query_as("SELECT id, name, singbox_version, singbox_sha256 FROM node_groups ORDER BY name")
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(groups)
+    }
+
 }
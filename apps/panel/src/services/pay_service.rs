@@ -1,56 +1,27 @@
 use sqlx::SqlitePool;
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use tracing::{info, error};
+use tracing::{debug, info, warn, error};
 use std::sync::Arc;
-use chrono::Utc;
+use tokio::sync::RwLock;
+use axum::http::HeaderMap;
 use crate::services::store_service::StoreService;
+use crate::services::rate_service::RateService;
+use crate::services::ledger_service::{LedgerService, Posting, Reason};
+use crate::models::money::{Currency, Money};
+use crate::models::store::{Invoice, Payment, PaymentEvent, Refund};
+use crate::services::payments::{CreatedInvoice, InvoiceRequest, PaymentNotification, PaymentProvider, PaymentRegistry, PaymentStatus, RefundRequest};
+use crate::settings::SettingsService;
 use crate::bot_manager::BotManager;
+use crate::bot::utils::escape_md;
 use anyhow::anyhow;
-use base64::Engine;
+use tokio::time::{interval, Duration};
 
-#[derive(Debug, Serialize, Deserialize)]
-#[allow(dead_code)]
-pub struct CryptoBotInvoice {
-    pub asset: String,
-    pub amount: String,
-    pub description: Option<String>,
-    pub payload: Option<String>,
-    pub paid_btn_name: Option<String>,
-    pub paid_btn_url: Option<String>,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-#[allow(dead_code)]
-pub struct CryptoBotResponse<T> {
-    pub ok: bool,
-    pub result: Option<T>,
-    pub error: Option<serde_json::Value>,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-#[allow(dead_code)]
-pub struct CreateInvoiceResult {
-    pub invoice_id: i64,
-    pub bot_invoice_url: String,
-}
+/// How long a created invoice is offered to the user before it counts as expired
+const INVOICE_TTL_MINUTES: i64 = 60;
 
-#[derive(Debug, Serialize, Deserialize)]
-#[allow(dead_code)]
-pub struct NowPaymentInvoice {
-    pub price_amount: f64,
-    pub price_currency: String,
-    pub pay_currency: String,
-    pub ipn_callback_url: String,
-    pub order_id: String,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-#[allow(dead_code)]
-pub struct NowPaymentResponse {
-    pub payment_id: String,
-    pub invoice_url: String,
-}
+/// `refunds` statuses whose money has left (or is leaving) the payment
+const REVERSING_STATUSES: &str = "('pending', 'succeeded', 'open', 'lost')";
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum PaymentType {
@@ -67,691 +38,780 @@ impl PaymentType {
             PaymentType::SubscriptionPurchase(plan_id) => format!("{}:sub:{}", user_id, plan_id),
         }
     }
+
+    fn description(&self) -> &'static str {
+        match self {
+            PaymentType::BalanceTopup => "Balance Top-up",
+            PaymentType::OrderPurchase(_) => "Store Order",
+            PaymentType::SubscriptionPurchase(_) => "Subscription",
+        }
+    }
 }
 
+/// Billing entry point: invoices go out through the provider registry,
+/// verified provider callbacks come back in and credit the user.
 pub struct PayService {
     pool: SqlitePool,
-    #[allow(dead_code)]
     store_service: Arc<StoreService>,
     bot_manager: Arc<BotManager>,
-    bot_token: String, // NEW: Main bot token for Stars
-    cryptobot_token: String,
-    nowpayments_key: String,
-    crystalpay_login: String,
-    crystalpay_secret: String,
-    stripe_secret_key: String,
-    cryptomus_merchant_id: String,
-    cryptomus_payment_api_key: String,
-    aaio_merchant_id: String,
-    aaio_secret_1: String,
-    aaio_secret_2: String,
-    lava_project_id: String,
-    lava_secret_key: String,
-    is_testnet: bool,
+    settings: Arc<SettingsService>,
+    rates: Arc<RateService>,
+    registry: RwLock<Arc<PaymentRegistry>>,
 }
 
 impl PayService {
-    pub fn new(
-        pool: SqlitePool, 
-        store_service: Arc<StoreService>, 
-        bot_manager: Arc<BotManager>, 
-        bot_token: String, // NEW
-        cryptobot_token: String, 
-        nowpayments_key: String, 
-        crystalpay_login: String,
-        crystalpay_secret: String,
-
-        stripe_secret_key: String,
-        cryptomus_merchant_id: String,
-        cryptomus_payment_api_key: String,
-        aaio_merchant_id: String,
-        aaio_secret_1: String,
-        aaio_secret_2: String,
-        lava_project_id: String,
-        lava_secret_key: String,
-        is_testnet: bool
+    pub async fn new(
+        pool: SqlitePool,
+        store_service: Arc<StoreService>,
+        bot_manager: Arc<BotManager>,
+        settings: Arc<SettingsService>,
+        rates: Arc<RateService>,
     ) -> Self {
-        Self { 
-            pool, 
-            store_service, 
-            bot_manager, 
-            bot_token, // NEW
-            cryptobot_token, 
-            nowpayments_key, 
-            crystalpay_login,
-
-            crystalpay_secret,
-            stripe_secret_key,
-            cryptomus_merchant_id,
-            cryptomus_payment_api_key,
-            aaio_merchant_id,
-            aaio_secret_1,
-            aaio_secret_2,
-            lava_project_id,
-            lava_secret_key,
-            is_testnet 
-        }
-    }
-
-    // Webhook signature verification helpers
-    
-    /// Verify CryptoBot webhook signature (HMAC-SHA256)
-    fn verify_cryptobot_signature(&self, payload: &str, signature: Option<&str>) -> Result<()> {
-        let sig = signature.ok_or_else(|| anyhow!("Missing signature header"))?;
-        
-        use sha2::{Sha256, Digest};
-        let mut hasher = Sha256::new();
-        hasher.update(self.cryptobot_token.as_bytes());
-        hasher.update(payload.as_bytes());
-        let expected = hex::encode(hasher.finalize());
-        
-        if sig == expected {
-            Ok(())
-        } else {
-            Err(anyhow!("Invalid CryptoBot signature"))
+        let registry = PaymentRegistry::from_settings(&settings).await;
+        Self {
+            pool,
+            store_service,
+            bot_manager,
+            settings,
+            rates,
+            registry: RwLock::new(Arc::new(registry)),
         }
     }
-    
-    /// Verify NOWPayments IPN signature (HMAC-SHA512)
-    fn verify_nowpayments_signature(&self, payload: &str, signature: Option<&str>) -> Result<()> {
-        let sig = signature.ok_or_else(|| anyhow!("Missing x-nowpayments-sig header"))?;
-        
-        use hmac::{Hmac, Mac};
-        type HmacSha512 = Hmac<sha2::Sha512>;
-        
-        let mut mac = HmacSha512::new_from_slice(self.nowpayments_key.as_bytes())
-            .map_err(|e| anyhow!("Invalid HMAC key: {}", e))?;
-        mac.update(payload.as_bytes());
-        let expected = hex::encode(mac.finalize().into_bytes());
-        
-        if sig == expected {
-            Ok(())
-        } else {
-            Err(anyhow!("Invalid NOWPayments signature"))
-        }
+
+    /// Rebuild the provider set after payment settings changed
+    pub async fn reload_providers(&self) {
+        let registry = PaymentRegistry::from_settings(&self.settings).await;
+        *self.registry.write().await = Arc::new(registry);
     }
-    
-    /// Verify CrystalPay signature (MD5 hash)
-    fn verify_crystalpay_signature(&self, payload: &serde_json::Value) -> Result<()> {
-        // CrystalPay uses MD5(data + secret) for signature verification
-        let sign_from_callback = payload.get("signature")
-            .and_then(|s| s.as_str())
-            .ok_or_else(|| anyhow!("Missing signature in payload"))?;
-        
-        // Extract all fields except signature for hash calculation
-        let id = payload.get("id").and_then(|v| v.as_str()).unwrap_or("");
-        let state = payload.get("state").and_then(|v| v.as_str()).unwrap_or("");
-        let amount = payload.get("amount").and_then(|v| v.as_f64()).unwrap_or(0.0);
-        
-        let data = format!("{}{}{}{}", id, amount, state, self.crystalpay_secret);
-        let expected = format!("{:x}", md5::compute(data.as_bytes()));
-        
-        if sign_from_callback == expected {
-            Ok(())
-        } else {
-            Err(anyhow!("Invalid CrystalPay signature"))
-        }
+
+    /// Configured providers, in menu order
+    pub async fn providers(&self) -> Vec<Arc<dyn PaymentProvider>> {
+        self.registry.read().await.enabled().to_vec()
     }
-    
-    /// Verify Stripe webhook signature
-    fn verify_stripe_signature(&self, payload: &str, signature: Option<&str>, webhook_secret: &str) -> Result<()> {
-        let sig = signature.ok_or_else(|| anyhow!("Missing Stripe-Signature header"))?;
-        
-        let parts: Vec<&str> = sig.split(',').collect();
-        
-        let mut timestamp = "";
-        let mut sig_v1 = "";
-        
-        for p in parts {
-            if let Some(val) = p.strip_prefix("t=") {
-                timestamp = val;
-            } else if let Some(val) = p.strip_prefix("v1=") {
-                sig_v1 = val;
-            }
+
+    async fn provider(&self, id: &str) -> Result<Arc<dyn PaymentProvider>> {
+        self.registry.read().await.get(id)
+            .ok_or_else(|| anyhow!("Payment provider {} is not enabled", id))
+    }
+
+    /// Create an invoice at the provider and track it locally as pending. `amount` is in USD and is
+    /// converted to the provider's currency at today's rate; that price holds for the invoice's lifetime.
+    /// The local invoice id rides along at the end of the payload.
+    pub async fn create_invoice(&self, provider_id: &str, user_id: i64, amount: Money, payment_type: PaymentType) -> Result<CreatedInvoice> {
+        let provider = self.provider(provider_id).await?;
+        if amount.currency != Currency::Usd || !amount.is_positive() {
+            return Err(anyhow!("Invalid invoice amount {}", amount));
         }
-        
-        if timestamp.is_empty() { return Err(anyhow!("Missing timestamp in signature")); }
-        if sig_v1.is_empty() { return Err(anyhow!("Missing v1 signature")); }
+        let billed = self.rates.convert(amount, provider.spec().currency).await?;
+        info!("Creating {} invoice for user {}: {} = {} ({:?})", provider.spec().name, user_id, amount, billed, payment_type);
 
-        use hmac::{Hmac, Mac};
-        type HmacSha256 = Hmac<sha2::Sha256>;
-        
-        let signed_payload = format!("{}.{}", timestamp, payload);
-        let mut mac = HmacSha256::new_from_slice(webhook_secret.as_bytes())
-            .map_err(|e| anyhow!("Invalid HMAC key: {}", e))?;
-        mac.update(signed_payload.as_bytes());
-        let expected = hex::encode(mac.finalize().into_bytes());
-        
-        if sig_v1 == expected {
-            Ok(())
-        } else {
-            Err(anyhow!("Invalid Stripe signature"))
+        let invoice_id: i64 = sqlx::query_scalar(
+            "INSERT INTO invoices (user_id, provider, amount, billed_amount, billed_currency, payload, expires_at)
+             VALUES (?, ?, ?, ?, ?, '', datetime('now', ?))
+             RETURNING id"
+        )
+        .bind(user_id)
+        .bind(provider_id)
+        .bind(amount.minor)
+        .bind(billed.minor)
+        .bind(billed.currency.code())
+        .bind(format!("+{} minutes", INVOICE_TTL_MINUTES))
+        .fetch_one(&self.pool)
+        .await?;
+
+        let req = InvoiceRequest {
+            user_id,
+            amount: billed,
+            payload: format!("{}:{}", payment_type.to_payload_string(user_id), invoice_id),
+            description: format!("{} {}", payment_type.description(), amount),
+        };
+
+        match provider.create_invoice(&req).await {
+            Ok(created) => {
+                sqlx::query("UPDATE invoices SET external_id = ?, payload = ?, url = ? WHERE id = ?")
+                    .bind(&created.external_id)
+                    .bind(&req.payload)
+                    .bind(&created.url)
+                    .bind(invoice_id)
+                    .execute(&self.pool)
+                    .await?;
+                Ok(created)
+            }
+            Err(e) => {
+                sqlx::query("UPDATE invoices SET status = 'failed', payload = ? WHERE id = ?")
+                    .bind(&req.payload)
+                    .bind(invoice_id)
+                    .execute(&self.pool)
+                    .await?;
+                Err(e)
+            }
         }
     }
 
+    /// The user's unpaid invoices from the last day, newest first: pending ones can still be paid,
+    /// expired ones can be paid again with a fresh invoice
+    pub async fn open_invoices(&self, user_id: i64) -> Result<Vec<Invoice>> {
+        Ok(sqlx::query_as::<_, Invoice>(
+            "SELECT * FROM invoices
+             WHERE user_id = ? AND status IN ('pending', 'expired') AND url != '' AND created_at > datetime('now', '-1 day')
+             ORDER BY created_at DESC LIMIT 10"
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?)
+    }
 
-    /// Verify Cryptomus signature (MD5(base64(body) + key))
-    fn verify_cryptomus_signature(&self, payload: &str, signature: Option<&str>) -> Result<()> {
-        let sig = signature.ok_or_else(|| anyhow!("Missing sign header for Cryptomus"))?;
-        let encoded = base64::engine::general_purpose::STANDARD.encode(payload);
-        let to_hash = format!("{}{}", encoded, self.cryptomus_payment_api_key);
-        let expected = format!("{:x}", md5::compute(to_hash.as_bytes()));
-        
-        if sig == expected {
-            Ok(())
-        } else {
-            Err(anyhow!("Invalid Cryptomus signature"))
+    pub async fn handle_webhook(&self, source: &str, headers: &HeaderMap, body: &str) -> Result<()> {
+        let provider = self.provider(source).await?;
+        let Some(notification) = provider.parse_webhook(headers, body)? else {
+            return Ok(());
+        };
+
+        match notification.status {
+            PaymentStatus::Paid => {
+                self.record_payment(source, &notification.external_id, notification.amount, &notification.payload, body).await
+            }
+            status @ (PaymentStatus::Expired | PaymentStatus::Failed) => {
+                info!("{} payment {} is now {:?}", provider.spec().name, notification.external_id, status);
+                if let Some(invoice_id) = invoice_id_from_payload(&notification.payload) {
+                    self.close_invoice(invoice_id, status).await?;
+                }
+                Ok(())
+            }
+            PaymentStatus::Refunded | PaymentStatus::Disputed | PaymentStatus::DisputeWon | PaymentStatus::DisputeLost => {
+                self.handle_reversal(provider.as_ref(), notification).await
+            }
+            status => {
+                info!("{} payment {} is now {:?}", provider.spec().name, notification.external_id, status);
+                Ok(())
+            }
         }
     }
 
-    fn generate_cryptomus_signature(&self, payload: &str) -> String {
-        let encoded = base64::engine::general_purpose::STANDARD.encode(payload);
-        let to_hash = format!("{}{}", encoded, self.cryptomus_payment_api_key);
-        format!("{:x}", md5::compute(to_hash.as_bytes()))
+    /// Journal a confirmed payment and credit it. Provider retries of a processed (or in-flight)
+    /// payment succeed without crediting again; a failed one gets another attempt.
+    pub async fn record_payment(&self, provider: &str, external_id: &str, amount: Money, payload: &str, raw_body: &str) -> Result<()> {
+        let inserted: Option<i64> = sqlx::query_scalar(
+            "INSERT INTO payment_events (provider, external_id, amount, currency, payload, raw_body)
+             VALUES (?, ?, ?, ?, ?, ?)
+             ON CONFLICT(provider, external_id) DO NOTHING
+             RETURNING id"
+        )
+        .bind(provider)
+        .bind(external_id)
+        .bind(amount.minor)
+        .bind(amount.currency.code())
+        .bind(payload)
+        .bind(raw_body)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let event_id = match inserted {
+            Some(id) => id,
+            None => {
+                let existing: i64 = sqlx::query_scalar("SELECT id FROM payment_events WHERE provider = ? AND external_id = ?")
+                    .bind(provider)
+                    .bind(external_id)
+                    .fetch_one(&self.pool)
+                    .await?;
+                if !self.claim_event(existing).await? {
+                    info!("Ignoring replayed {} payment {}", provider, external_id);
+                    return Ok(());
+                }
+                existing
+            }
+        };
+
+        self.run_event(event_id).await
     }
 
-    /// Verify Aaio signature (SHA256(merchant_id:amount:currency:secret_2:order_id))
-    fn verify_aaio_signature(&self, merchant_id: &str, amount: &str, currency: &str, order_id: &str, sign: &str) -> Result<()> {
-        use sha2::{Sha256, Digest};
-        let mut hasher = Sha256::new();
-        let data = format!("{}:{}:{}:{}:{}", merchant_id, amount, currency, self.aaio_secret_2, order_id);
-        hasher.update(data.as_bytes());
-        let expected = hex::encode(hasher.finalize());
-        
-        if sign == expected {
-            Ok(())
-        } else {
-             Err(anyhow!("Invalid Aaio signature. Expected: {}, Got: {}", expected, sign))
+    /// Manually retry a failed (or stuck) journaled payment
+    pub async fn reprocess_event(&self, event_id: i64) -> Result<()> {
+        if !self.claim_event(event_id).await? {
+            return Err(anyhow!("Payment event {} is not in a retryable state", event_id));
         }
+        self.run_event(event_id).await
     }
 
-    fn get_cryptobot_url(&self) -> &str {
-        if self.is_testnet { "https://testnet-pay.crypt.bot/api" } else { "https://pay.crypt.bot/api" }
+    /// Move a failed event, or one stuck in processing for a while, back to processing.
+    /// Returns false when someone else owns it or it is already done.
+    async fn claim_event(&self, event_id: i64) -> Result<bool> {
+        let res = sqlx::query(
+            "UPDATE payment_events
+             SET status = 'processing', attempts = attempts + 1, error = NULL, updated_at = CURRENT_TIMESTAMP
+             WHERE id = ? AND (status = 'failed' OR (status = 'processing' AND updated_at < datetime('now', '-10 minutes')))"
+        )
+        .bind(event_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(res.rows_affected() == 1)
     }
 
-    pub async fn create_cryptobot_invoice(&self, user_id: i64, amount_usd: f64, payment_type: PaymentType) -> Result<String> {
-        info!("Creating CryptoPay invoice for user {}: ${} ({:?})", user_id, amount_usd, payment_type);
-        
-        let payload = payment_type.to_payload_string(user_id);
-        
-        // CryptoBot needs asset amount, not USD usually, but createInvoice allows 'amount' + 'fiat' or 'amount' + 'asset'.
-        // If we want USD, we use 'amount' and 'fiat' = 'USD' OR we use one of the crypto currencies.
-        // Actually typical CryptoPay usage is creating an invoice in USDT or allowing user to pay any asset.
-        // createInvoice endpoint: asset, amount, ...
-        // To bill in USD: we might need to use `createInvoice` with `fiat` param?
-        // Let's check typical usage. Often we request USDT.
-        
-        let invoice = serde_json::json!({
-             "asset": "USDT",
-             "amount": format!("{:.2}", amount_usd),
-             "description": "EXA ROBOT Top-up",
-             "payload": payload,
-             "allow_anonymous": false,
-             "allow_comments": false
-        });
-
-        let client = reqwest::Client::new();
-        let resp = client.post(format!("{}/createInvoice", self.get_cryptobot_url()))
-            .header("Crypto-Pay-API-Token", &self.cryptobot_token)
-            .json(&invoice)
-            .send()
+    async fn run_event(&self, event_id: i64) -> Result<()> {
+        let event = sqlx::query_as::<_, PaymentEvent>("SELECT * FROM payment_events WHERE id = ?")
+            .bind(event_id)
+            .fetch_one(&self.pool)
             .await?;
 
-        let body: serde_json::Value = resp.json().await?;
-        if body["ok"].as_bool().unwrap_or(false) {
-             Ok(body["result"]["bot_invoice_url"].as_str().unwrap_or("").to_string())
-        } else {
-             Err(anyhow::anyhow!("CryptoBot error: {:?}", body))
+        let settled = event.settled()
+            .ok_or_else(|| anyhow!("Payment event {} has unknown currency {}", event_id, event.currency))?;
+        let mut credit = None;
+
+        // The invoice is the second line of defence: a payment reported under another id
+        // (webhook vs. reconciler) must not credit the same invoice twice
+        let invoice_id = invoice_id_from_payload(&event.payload);
+        if let Some(invoice_id) = invoice_id {
+            let claimed = sqlx::query(
+                "UPDATE invoices SET status = 'paid', paid_at = CURRENT_TIMESTAMP, external_id = COALESCE(external_id, ?)
+                 WHERE id = ? AND status != 'paid'"
+            )
+            .bind(&event.external_id)
+            .bind(invoice_id)
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+            let exists: bool = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM invoices WHERE id = ?)")
+                .bind(invoice_id)
+                .fetch_one(&self.pool)
+                .await?;
+
+            if claimed == 0 && exists {
+                warn!("Invoice {} was already paid, not crediting {} {} again", invoice_id, event.provider, event.external_id);
+                sqlx::query("UPDATE payment_events SET status = 'processed', error = 'invoice already paid', processed_at = CURRENT_TIMESTAMP, updated_at = CURRENT_TIMESTAMP WHERE id = ?")
+                    .bind(event_id)
+                    .execute(&self.pool)
+                    .await?;
+                return Ok(());
+            }
+
+            // Paid in full in the billed currency: credit what was invoiced, whatever the rate is today
+            if let Some(invoice) = sqlx::query_as::<_, Invoice>("SELECT * FROM invoices WHERE id = ?")
+                .bind(invoice_id)
+                .fetch_optional(&self.pool)
+                .await?
+                && invoice.billed().is_some_and(|billed| billed.currency == settled.currency && settled.minor >= billed.minor) {
+                credit = Some(Money::usd_cents(invoice.amount));
+            }
+        }
+
+        let result = match credit {
+            Some(credit) => Ok(credit),
+            None => self.rates.convert(settled, Currency::Usd).await,
+        };
+        let result = match result {
+            Ok(credit) => self.process_any_payment(credit, settled, &event.provider, Some(event.external_id.clone()), &event.payload).await,
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(()) => {
+                sqlx::query("UPDATE payment_events SET status = 'processed', processed_at = CURRENT_TIMESTAMP, updated_at = CURRENT_TIMESTAMP WHERE id = ?")
+                    .bind(event_id)
+                    .execute(&self.pool)
+                    .await?;
+                Ok(())
+            }
+            Err(e) => {
+                error!("Payment event {} ({} {}) failed: {}", event_id, event.provider, event.external_id, e);
+                if let Some(invoice_id) = invoice_id {
+                    // Release the invoice so a retry can claim it again
+                    sqlx::query("UPDATE invoices SET status = 'pending', paid_at = NULL WHERE id = ?")
+                        .bind(invoice_id)
+                        .execute(&self.pool)
+                        .await?;
+                }
+                sqlx::query("UPDATE payment_events SET status = 'failed', error = ?, updated_at = CURRENT_TIMESTAMP WHERE id = ?")
+                    .bind(e.to_string())
+                    .bind(event_id)
+                    .execute(&self.pool)
+                    .await?;
+                Err(e)
+            }
         }
     }
 
-    pub async fn create_nowpayments_invoice(&self, user_id: i64, amount_usd: f64, payment_type: PaymentType) -> Result<String> {
-        info!("Creating NOWPayments invoice for user {}: ${}", user_id, amount_usd);
-        
-        let payload_base = payment_type.to_payload_string(user_id);
-        let unique_order_id = format!("{}_{}", payload_base, Utc::now().timestamp());
-
-        let invoice = serde_json::json!({
-            "price_amount": amount_usd,
-            "price_currency": "usd",
-            "pay_currency": "usdttrc20", // Default view
-            "order_id": unique_order_id,
-            "ipn_callback_url": "https://api.exa.robot/api/payments/nowpayments", 
-            "success_url": "https://t.me/exarobot_bot",
-            "cancel_url": "https://t.me/exarobot_bot"
-        });
-
-        let client = reqwest::Client::new();
-        let resp = client.post("https://api.nowpayments.io/v1/invoice")
-            .header("x-api-key", &self.nowpayments_key)
-            .json(&invoice)
-            .send()
+    async fn close_invoice(&self, invoice_id: i64, status: PaymentStatus) -> Result<()> {
+        let status = if status == PaymentStatus::Failed { "failed" } else { "expired" };
+        sqlx::query("UPDATE invoices SET status = ? WHERE id = ? AND status = 'pending'")
+            .bind(status)
+            .bind(invoice_id)
+            .execute(&self.pool)
             .await?;
+        Ok(())
+    }
 
-        let body: serde_json::Value = resp.json().await?;
-        if let Some(url) = body["invoice_url"].as_str() {
-            Ok(url.to_string())
-        } else {
-            Err(anyhow::anyhow!("NOWPayments error: {:?}", body))
+    pub async fn start_reconciler(&self) {
+        info!("Starting invoice reconciler...");
+        let mut interval = interval(Duration::from_secs(300)); // Every 5 minutes
+
+        loop {
+            interval.tick().await;
+            match self.reconcile_invoices().await {
+                Ok(0) => {}
+                Ok(settled) => info!("Reconciled {} invoices", settled),
+                Err(e) => error!("Invoice reconciliation failed: {}", e),
+            }
         }
     }
 
-    pub async fn create_crystalpay_invoice(&self, user_id: i64, amount_usd: f64, payment_type: PaymentType) -> Result<String> {
-        info!("Creating CrystalPay invoice for user {}: ${}", user_id, amount_usd);
-        
-        // CrystalPay creates a payment link.
-        // Endpoint: https://api.crystalpay.io/v2/invoice/create/
-        // Auth: login + secret
-        // Params: amount, type=purchase, lifetime, etc.
-        
-        // Convert USD to RUB for CrystalPay (assuming it accepts RUB primarily for SBP)
-        // Or check if they support USD invoices. They do support multi-currency but often convert.
-        // For simplicity, let's assume we bill in USD and they handle conversion or we set USD.
-        
-        let payload = payment_type.to_payload_string(user_id);
-        
-        let body_json = serde_json::json!({
-            "auth_login": self.crystalpay_login,
-            "auth_secret": self.crystalpay_secret,
-            "amount": amount_usd,
-            "amount_currency": "USD", // Request USD
-            "type": "purchase",
-            "description": format!("CARAMBA User {}", user_id),
-            "redirect_url": "https://t.me/exarobot_bot",
-            "callback_url": "https://api.exa.robot/api/payments/crystalpay",
-            "extra": payload 
-        });
-
-        let client = reqwest::Client::new();
-        let resp = client.post("https://api.crystalpay.io/v2/invoice/create/")
-            .json(&body_json)
-            .send()
-            .await?;
-            
-        let resp_json: serde_json::Value = resp.json().await?;
-        
-        // CrystalPay V2 response: {"error":false, "errors":[], "data": { "id": "...", "url": "..." }}
-        if resp_json["error"].as_bool().unwrap_or(true) {
-             Err(anyhow::anyhow!("CrystalPay Error: {:?}", resp_json))
-        } else {
-             Ok(resp_json["data"]["url"].as_str().unwrap_or("").to_string())
+    /// Ask providers about pending invoices, so a lost webhook doesn't mean a lost payment.
+    /// Invoices the provider can't be asked about expire an hour after their deadline.
+    pub async fn reconcile_invoices(&self) -> Result<usize> {
+        let pending = sqlx::query_as::<_, Invoice>(
+            "SELECT * FROM invoices WHERE status = 'pending' AND created_at < datetime('now', '-2 minutes')
+             ORDER BY last_checked_at IS NOT NULL, last_checked_at LIMIT 100"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut settled = 0;
+        for invoice in pending {
+            let overdue = invoice.expires_at < chrono::Utc::now() - chrono::Duration::hours(1);
+            let status = match (&invoice.external_id, self.registry.read().await.get(&invoice.provider)) {
+                (Some(external_id), Some(provider)) => provider.query_status(external_id).await,
+                _ => Err(anyhow!("status unavailable")),
+            };
+
+            sqlx::query("UPDATE invoices SET last_checked_at = CURRENT_TIMESTAMP WHERE id = ?")
+                .bind(invoice.id)
+                .execute(&self.pool)
+                .await?;
+
+            match status {
+                Ok(PaymentStatus::Paid) => {
+                    let external_id = invoice.external_id.clone().unwrap_or_default();
+                    info!("Invoice {} was paid at {} without a webhook", invoice.id, invoice.provider);
+                    let Some(billed) = invoice.billed() else {
+                        warn!("Invoice {} has unknown currency {}", invoice.id, invoice.billed_currency);
+                        continue;
+                    };
+                    if let Err(e) = self.record_payment(&invoice.provider, &external_id, billed, &invoice.payload, "reconciled").await {
+                        error!("Failed to settle reconciled invoice {}: {}", invoice.id, e);
+                        continue;
+                    }
+                    settled += 1;
+                }
+                Ok(status @ (PaymentStatus::Expired | PaymentStatus::Failed)) => {
+                    self.close_invoice(invoice.id, status).await?;
+                    settled += 1;
+                }
+                Ok(_) | Err(_) if overdue => {
+                    self.close_invoice(invoice.id, PaymentStatus::Expired).await?;
+                    settled += 1;
+                }
+                Ok(_) => {}
+                Err(e) => debug!("Could not check invoice {} at {}: {}", invoice.id, invoice.provider, e),
+            }
         }
+        Ok(settled)
     }
 
-    pub async fn create_stripe_session(&self, user_id: i64, amount_usd: f64, payment_type: PaymentType) -> Result<String> {
-        info!("Creating Stripe Session for user {}: ${}", user_id, amount_usd);
-        
-        let payload = payment_type.to_payload_string(user_id);
-        let amount_cents = (amount_usd * 100.0) as i64;
-        
-        let client = reqwest::Client::new();
-        let params = [
-            ("mode", "payment"),
-            ("success_url", "https://t.me/exarobot_bot"),
-            ("cancel_url", "https://t.me/exarobot_bot"),
-            ("client_reference_id", &payload),
-            ("line_items[0][price_data][currency]", "usd"),
-            ("line_items[0][price_data][product_data][name]", "Balance Top-up"),
-            ("line_items[0][price_data][unit_amount]", &amount_cents.to_string()),
-            ("line_items[0][quantity]", "1"),
-        ];
-
-        let resp = client.post("https://api.stripe.com/v1/checkout/sessions")
-            .basic_auth(&self.stripe_secret_key, None::<&str>)
-            .form(&params)
-            .send()
-            .await?;
+    async fn payment(&self, payment_id: i64) -> Result<Payment> {
+        sqlx::query_as::<_, Payment>("SELECT * FROM payments WHERE id = ?")
+            .bind(payment_id)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or_else(|| anyhow!("Payment {} not found", payment_id))
+    }
 
-        let body: serde_json::Value = resp.json().await?;
-        if let Some(url) = body["url"].as_str() {
-             Ok(url.to_string())
-        } else {
-             Err(anyhow::anyhow!("Stripe Error: {:?}", body))
+    /// What the provider received; payments from before settled amounts were recorded are converted
+    async fn settled_of(&self, payment: &Payment) -> Result<Money> {
+        match payment.settled() {
+            Some(settled) => Ok(settled),
+            None => {
+                let currency = crate::services::payments::spec(&payment.method)
+                    .map(|spec| spec.currency)
+                    .unwrap_or(Currency::Usd);
+                self.rates.convert(payment.money(), currency).await
+            }
         }
+    }
 
+    /// USD cents already refunded, being refunded or disputed
+    async fn reversed_amount(&self, payment_id: i64) -> Result<i64> {
+        Ok(sqlx::query_scalar(&format!(
+            "SELECT COALESCE(SUM(amount), 0) FROM refunds WHERE payment_id = ? AND status IN {}", REVERSING_STATUSES
+        ))
+        .bind(payment_id)
+        .fetch_one(&self.pool)
+        .await?)
     }
 
-    pub async fn create_cryptomus_invoice(&self, user_id: i64, amount_usd: f64, payment_type: PaymentType) -> Result<String> {
-        info!("Creating Cryptomus invoice for user {}: ${}", user_id, amount_usd);
-        
-        // Setup payload
-        let payload_str = payment_type.to_payload_string(user_id);
-        let order_id = format!("{}_{}", user_id, Utc::now().timestamp());
-        
-        let body_json = serde_json::json!({
-            "amount": amount_usd.to_string(), // Cryptomus often expects string for amount
-            "currency": "USD",
-            "order_id": order_id,
-            "url_callback": "https://api.exa.robot/api/payments/cryptomus",
-            "url_return": "https://t.me/exarobot_bot", // Redirect user back to bot
-            "additional_data": payload_str // Pass metadata here
-        });
-        
-        let body_str = serde_json::to_string(&body_json)?;
-        let sign = self.generate_cryptomus_signature(&body_str);
-        
-        let client = reqwest::Client::new();
-        let resp = client.post("https://api.cryptomus.com/v1/payment")
-            .header("merchant", &self.cryptomus_merchant_id)
-            .header("sign", sign)
-            .header("Content-Type", "application/json")
-            .body(body_str)
-            .send()
+    pub async fn payment_refunds(&self, payment_id: i64) -> Result<Vec<Refund>> {
+        Ok(sqlx::query_as::<_, Refund>("SELECT * FROM refunds WHERE payment_id = ? ORDER BY id")
+            .bind(payment_id)
+            .fetch_all(&self.pool)
+            .await?)
+    }
+
+    /// Refund a payment to where it came from. `amount` is USD; `None` refunds whatever is left of it.
+    pub async fn refund_payment(&self, payment_id: i64, amount: Option<Money>, actor: &str) -> Result<()> {
+        let payment = self.payment(payment_id).await?;
+        if !matches!(payment.status.as_str(), "paid" | "partially_refunded") {
+            return Err(anyhow!("Payment {} is {}", payment_id, payment.status));
+        }
+        let external_id = payment.external_id.clone()
+            .ok_or_else(|| anyhow!("Payment {} has no provider reference", payment_id))?;
+        let provider = self.provider(&payment.method).await?;
+        let settled = self.settled_of(&payment).await?;
+
+        let reversed = self.reversed_amount(payment_id).await?;
+        let refundable = payment.amount - reversed;
+        let amount = match amount {
+            Some(amount) if amount.currency != Currency::Usd => return Err(anyhow!("Refunds are entered in USD")),
+            Some(amount) => amount.minor,
+            None => refundable,
+        };
+        if amount <= 0 || amount > refundable {
+            return Err(anyhow!("Refund must be between $0.01 and {}", Money::usd_cents(refundable)));
+        }
+        let full = reversed == 0 && amount == payment.amount;
+        let provider_amount = if full {
+            settled
+        } else {
+            Money::new(proportional(settled.minor, amount, payment.amount), settled.currency)
+        };
+
+        let refund_id: i64 = sqlx::query_scalar(
+            "INSERT INTO refunds (payment_id, kind, amount, provider_amount, provider_currency, status, actor)
+             VALUES (?, 'refund', ?, ?, ?, 'pending', ?)
+             RETURNING id"
+        )
+        .bind(payment_id)
+        .bind(amount)
+        .bind(provider_amount.minor)
+        .bind(provider_amount.currency.code())
+        .bind(actor)
+        .fetch_one(&self.pool)
+        .await?;
+
+        let tg_id: i64 = sqlx::query_scalar("SELECT tg_id FROM users WHERE id = ?")
+            .bind(payment.user_id)
+            .fetch_one(&self.pool)
             .await?;
-            
-        let resp_json: serde_json::Value = resp.json().await?;
-        // Cryptomus response: { state: 0, result: { url: "...", ... } }
-        // Note: Check docs or common key names. result.url is common.
-        
-        if let Some(result) = resp_json.get("result") {
-            if let Some(url) = result.get("url").and_then(|u| u.as_str()) {
-                 return Ok(url.to_string());
+        let req = RefundRequest { refund_id, external_id, amount: provider_amount, full, tg_id };
+
+        warn!("Refunding {} ({}) of {} payment {} for {}", Money::usd_cents(amount), provider_amount, provider.spec().name, payment_id, actor);
+        match provider.refund(&req).await {
+            Ok(reference) => {
+                sqlx::query("UPDATE refunds SET reference = ?, updated_at = CURRENT_TIMESTAMP WHERE id = ?")
+                    .bind(reference)
+                    .bind(refund_id)
+                    .execute(&self.pool)
+                    .await?;
+                self.apply_reversal(refund_id).await
+            }
+            Err(e) => {
+                sqlx::query("UPDATE refunds SET status = 'failed', error = ?, updated_at = CURRENT_TIMESTAMP WHERE id = ?")
+                    .bind(e.to_string())
+                    .bind(refund_id)
+                    .execute(&self.pool)
+                    .await?;
+                Err(e)
             }
         }
-        
-        Err(anyhow::anyhow!("Cryptomus Error: {:?}", resp_json))
     }
 
-    pub async fn create_aaio_invoice(&self, user_id: i64, amount_usd: f64, payment_type: PaymentType) -> Result<String> {
-        info!("Creating Aaio invoice for user {}: ${}", user_id, amount_usd);
-        
-        let pay_desc = format!("Payment for User {}", user_id);
-        let order_id = format!("{}:{}:{}", user_id, Utc::now().timestamp(), payment_type.to_payload_string(user_id));
-        let currency = "USD";
-        let amount_str = format!("{:.2}", amount_usd);
-        
-        // Sign: SHA256(merchant_id:amount:currency:secret_1:order_id)
-        use sha2::{Sha256, Digest};
-        let mut hasher = Sha256::new();
-        let sign_data = format!("{}:{}:{}:{}:{}", self.aaio_merchant_id, amount_str, currency, self.aaio_secret_1, order_id);
-        hasher.update(sign_data.as_bytes());
-        let sign = hex::encode(hasher.finalize());
-        
-        let client = reqwest::Client::new();
-        let params = [
-            ("merchant_id", self.aaio_merchant_id.as_str()),
-            ("amount", amount_str.as_str()),
-            ("currency", currency),
-            ("order_id", order_id.as_str()),
-            ("sign", sign.as_str()),
-            ("desc", pay_desc.as_str()),
-            ("lang", "en"),
-        ];
-
-        // Ensure we send as form-encoded since typically they expect POST params or query string for link generation
-        // But wait, the docs say to generate a URL to redirect user to. 
-        // We can just construct the URL and return it if we don't need to create an order via API call first.
-        // Actually, Aaio is usually "Redirect to Payment URL".
-        // URL: https://aaio.so/merchant/pay?merchant_id=...
-        // Let's verify if 'get_pay_url' is an API that RETURNS a url or if we just build it.
-        // Tool said 'get_pay_url' endpoint.
-        // The script uses `https://aaio.so/merchant/get_pay_url` and gets a result.
-        
-        let resp = client.post("https://aaio.so/merchant/get_pay_url")
-            .form(&params)
-            .send()
-            .await?;
-            
-        // Response is usually providing the redirect URL.
-        // But if we just want to redirect, we can construct https://aaio.so/merchant/pay?...
-        // Let's try to get the URL from the API for better UX (it might return a specific session URL).
-        
-        if let Ok(url_str) = resp.text().await {
-             // Sometimes it returns just the URL string, sometimes JSON.
-             // If URL string starts with http, return it.
-             if url_str.starts_with("http") {
-                 return Ok(url_str);
-             }
-             // Try parsing JSON if needed, but docs say get_pay_url returns URL in body or JSON?
-             // Assuming URL for now.
-             return Ok(url_str); // Fallback
+    /// Refund and dispute events from a provider. Each refund or dispute is booked once,
+    /// including refunds we started ourselves, which the provider reports back.
+    async fn handle_reversal(&self, provider: &dyn PaymentProvider, notification: PaymentNotification) -> Result<()> {
+        let name = provider.spec().name;
+        let external_id = provider.payment_external_id(&notification.external_id).await?;
+        let Some(payment) = sqlx::query_as::<_, Payment>("SELECT * FROM payments WHERE method = ? AND external_id = ? ORDER BY id LIMIT 1")
+            .bind(provider.spec().id)
+            .bind(&external_id)
+            .fetch_optional(&self.pool)
+            .await?
+        else {
+            warn!("{} reported {:?} for unknown payment {}", name, notification.status, external_id);
+            return Ok(());
+        };
+
+        let settled = self.settled_of(&payment).await?;
+        if notification.amount.currency != settled.currency {
+            return Err(anyhow!("{} reported {} for payment {} settled in {}", name, notification.amount, payment.id, settled.currency));
+        }
+        let reported = notification.amount.minor.min(settled.minor);
+        let remaining = payment.amount - self.reversed_amount(payment.id).await?;
+
+        match notification.status {
+            PaymentStatus::Refunded => {
+                // Refund events carry the running total; only the part not booked yet is new
+                let booked: i64 = sqlx::query_scalar(
+                    "SELECT COALESCE(SUM(provider_amount), 0) FROM refunds
+                     WHERE payment_id = ? AND kind = 'refund' AND status IN ('pending', 'succeeded')"
+                )
+                .bind(payment.id)
+                .fetch_one(&self.pool)
+                .await?;
+                let delta = reported - booked;
+                if delta <= 0 {
+                    debug!("{} refund of payment {} is already booked", name, payment.id);
+                    return Ok(());
+                }
+
+                let refund_id: i64 = sqlx::query_scalar(
+                    "INSERT INTO refunds (payment_id, kind, amount, provider_amount, provider_currency, status)
+                     VALUES (?, 'refund', ?, ?, ?, 'pending')
+                     RETURNING id"
+                )
+                .bind(payment.id)
+                .bind(proportional(payment.amount, delta, settled.minor).min(remaining))
+                .bind(delta)
+                .bind(settled.currency.code())
+                .fetch_one(&self.pool)
+                .await?;
+                self.apply_reversal(refund_id).await
+            }
+            PaymentStatus::Disputed => {
+                let reference = notification.reference.ok_or_else(|| anyhow!("{} dispute without an id", name))?;
+                let refund_id: Option<i64> = sqlx::query_scalar(
+                    "INSERT INTO refunds (payment_id, kind, reference, amount, provider_amount, provider_currency, status)
+                     VALUES (?, 'dispute', ?, ?, ?, ?, 'open')
+                     ON CONFLICT DO NOTHING
+                     RETURNING id"
+                )
+                .bind(payment.id)
+                .bind(&reference)
+                .bind(proportional(payment.amount, reported, settled.minor).min(remaining))
+                .bind(reported)
+                .bind(settled.currency.code())
+                .fetch_optional(&self.pool)
+                .await?;
+                match refund_id {
+                    Some(refund_id) => self.apply_reversal(refund_id).await,
+                    None => Ok(()),
+                }
+            }
+            PaymentStatus::DisputeWon => {
+                let reference = notification.reference.ok_or_else(|| anyhow!("{} dispute without an id", name))?;
+                self.restore_dispute(&payment, &reference).await
+            }
+            PaymentStatus::DisputeLost => {
+                let reference = notification.reference.ok_or_else(|| anyhow!("{} dispute without an id", name))?;
+                sqlx::query(
+                    "UPDATE refunds SET status = 'lost', updated_at = CURRENT_TIMESTAMP
+                     WHERE payment_id = ? AND kind = 'dispute' AND reference = ? AND status = 'open'"
+                )
+                .bind(payment.id)
+                .bind(&reference)
+                .execute(&self.pool)
+                .await?;
+                warn!("Lost {} dispute {} on payment {}", name, reference, payment.id);
+                Ok(())
+            }
+            _ => Ok(()),
         }
-        
-        Err(anyhow::anyhow!("Aaio Error"))
     }
 
-    pub async fn create_lava_invoice(&self, user_id: i64, amount_usd: f64, payment_type: PaymentType) -> Result<String> {
-        info!("Creating Lava.top invoice for user {}: ${}", user_id, amount_usd);
-        
-        let order_id = format!("LAVA-{}-{}", user_id, Utc::now().timestamp());
-        let payload_str = payment_type.to_payload_string(user_id);
-        
-        // Lava API v2 Signature: HMAC-SHA256(json_body, secret_key)
-        // Endpoint: https://api.lava.ru/business/invoice/create
-        // Ensure you have correct structs or use json! macro
-        
-        let json_body = serde_json::json!({
-            "sum": amount_usd,
-            "orderId": order_id,
-            "shopId": self.lava_project_id,
-            "comment": format!("Payment for User {}", user_id),
-            "customFields": payload_str,
-            "expire": 3600
-        });
-        
-        let body_str = serde_json::to_string(&json_body)?;
-        
-        use hmac::{Hmac, Mac};
-        type HmacSha256 = Hmac<sha2::Sha256>;
-        let mut mac = HmacSha256::new_from_slice(self.lava_secret_key.as_bytes())
-            .map_err(|e| anyhow!("Invalid Lava Secret: {}", e))?;
-        mac.update(body_str.as_bytes());
-        let signature = hex::encode(mac.finalize().into_bytes());
-        
-        let client = reqwest::Client::new();
-        let res = client.post("https://api.lava.ru/business/invoice/create")
-            .header("Signature", signature)
-            .header("Accept", "application/json")
-            .header("Content-Type", "application/json")
-            .body(body_str)
-            .send()
+    /// Book a refund or dispute: revoke what the payment bought once nothing of it is left, take
+    /// back what of it is still on the user's balance, and claw back referral bonuses paid on it.
+    /// Users left owing money have all their subscriptions suspended.
+    async fn apply_reversal(&self, refund_id: i64) -> Result<()> {
+        let refund = sqlx::query_as::<_, Refund>("SELECT * FROM refunds WHERE id = ?")
+            .bind(refund_id)
+            .fetch_one(&self.pool)
             .await?;
-            
-        #[derive(Deserialize)]
-        struct LavaResponse {
-            data: Option<LavaData>,
-            error: Option<serde_json::Value>
+        let payment = self.payment(refund.payment_id).await?;
+        let dispute = refund.kind == "dispute";
+        let label = if dispute { "Chargeback" } else { "Refund" };
+
+        let mut tx = self.pool.begin().await?;
+        if !dispute {
+            sqlx::query("UPDATE refunds SET status = 'succeeded', updated_at = CURRENT_TIMESTAMP WHERE id = ?")
+                .bind(refund.id)
+                .execute(&mut *tx)
+                .await?;
         }
-        #[derive(Deserialize)]
-        struct LavaData {
-            url: String, 
-            _id: String
+
+        let reversed: i64 = sqlx::query_scalar(&format!(
+            "SELECT COALESCE(SUM(amount), 0) FROM refunds WHERE payment_id = ? AND status IN {}", REVERSING_STATUSES
+        ))
+        .bind(payment.id)
+        .fetch_one(&mut *tx)
+        .await?;
+        let fully = reversed >= payment.amount;
+        let revoke = fully || dispute;
+
+        let mut suspended = 0;
+        let mut spent = 0;
+        if revoke && let Some(sub_id) = payment.subscription_id {
+            suspended += sqlx::query("UPDATE subscriptions SET status = 'suspended' WHERE id = ? AND status = 'active'")
+                .bind(sub_id)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+            spent = sqlx::query_scalar(
+                "SELECT COALESCE(-SUM(amount), 0) FROM balance_ledger
+                 WHERE user_id = ? AND reason = 'purchase' AND ref_type = 'subscription' AND ref_id = ? AND amount < 0"
+            )
+            .bind(payment.user_id)
+            .bind(sub_id)
+            .fetch_one(&mut *tx)
+            .await?;
         }
-        
-        let lava_res: LavaResponse = res.json().await?;
-        
-        if let Some(data) = lava_res.data {
-            Ok(data.url)
-        } else {
-             Err(anyhow!("Failed to create Lava invoice (No URL returned): {:?}", lava_res.error))
+        if revoke {
+            sqlx::query("UPDATE orders SET status = 'refunded' WHERE payment_id = CAST(? AS TEXT) AND status = 'paid'")
+                .bind(payment.id)
+                .execute(&mut *tx)
+                .await?;
         }
-    }
 
-    pub async fn create_stars_invoice(&self, user_id: i64, amount_usd: f64, payment_type: PaymentType) -> Result<String> {
-        info!("Creating Telegram Stars invoice for user {}: ${}", user_id, amount_usd);
-        
-        // Rate: 1 USD = 50 Stars (Fixed Assumption for now)
-        let stars_amount = (amount_usd * 50.0).ceil() as i64;
-        
-        let payload = payment_type.to_payload_string(user_id);
-        
-        let client = reqwest::Client::new();
-        let bot_token = self.bot_token.clone(); 
-        if bot_token.is_empty() {
-            return Err(anyhow!("Bot token required for Stars"));
+        // Only money the payment left on the balance comes back from it; what it bought is revoked
+        // above instead, and order payments never reach the balance at all
+        let still_credited: i64 = sqlx::query_scalar(
+            "SELECT COALESCE(SUM(l.amount), 0) FROM balance_ledger l
+             WHERE l.user_id = ? AND (
+                 (l.reason = 'topup' AND l.ref_type = 'payment' AND l.ref_id = ?)
+                 OR (l.reason = 'reversal' AND l.ref_type = 'refund' AND l.ref_id IN (SELECT id FROM refunds WHERE payment_id = ?))
+             )"
+        )
+        .bind(payment.user_id)
+        .bind(payment.id)
+        .bind(payment.id)
+        .fetch_one(&mut *tx)
+        .await?;
+        let taken = refund.amount.min(still_credited - spent).max(0);
+        let balance = LedgerService::post(&mut tx, Posting::debit(payment.user_id, taken, Reason::Reversal)
+            .contra(format!("gateway:{}", payment.method))
+            .reference("refund", refund.id)
+            .actor(refund.actor.clone())
+            .note(format!("{} of payment #{}", label, payment.id))).await?;
+
+        let bonuses: Vec<(i64, i64)> = sqlx::query_as(
+            "SELECT user_id, amount FROM balance_ledger
+             WHERE reason = 'referral' AND ref_type = 'payment' AND ref_id = ? AND amount > 0"
+        )
+        .bind(payment.id)
+        .fetch_all(&mut *tx)
+        .await?;
+        for (referrer_id, bonus) in bonuses {
+            LedgerService::post(&mut tx, Posting::debit(referrer_id, proportional(bonus, refund.amount, payment.amount), Reason::Reversal)
+                .contra("referrals")
+                .reference("refund", refund.id)
+                .note(format!("Referral bonus of reversed payment #{}", payment.id))).await?;
         }
 
-        let url = format!("https://api.telegram.org/bot{}/createInvoiceLink", bot_token);
-        
-        let params = serde_json::json!({
-            "title": "Balance Top-up",
-            "description": format!("Top-up balance by ${:.2}", amount_usd),
-            "payload": payload,
-            "provider_token": "", // Empty for Stars
-            "currency": "XTR",
-            "prices": [{"label": "Top-up", "amount": stars_amount}]
-        });
-
-        let res = client.post(&url)
-            .json(&params)
-            .send()
+        let status = if dispute { "disputed" } else if fully { "refunded" } else { "partially_refunded" };
+        sqlx::query("UPDATE payments SET status = ?, updated_at = unixepoch() WHERE id = ?")
+            .bind(status)
+            .bind(payment.id)
+            .execute(&mut *tx)
             .await?;
-            
-        #[derive(Deserialize)]
-        struct TgResponse {
-            ok: bool,
-            result: Option<String>,
-            description: Option<String>
+        if fully {
+            sqlx::query("UPDATE referral_bonuses SET status = 'reversed' WHERE payment_id = ?")
+                .bind(payment.id)
+                .execute(&mut *tx)
+                .await?;
         }
-        
-        let tg_res: TgResponse = res.json().await?;
-        
-        if tg_res.ok && tg_res.result.is_some() {
-            Ok(tg_res.result.unwrap())
-        } else {
-            Err(anyhow!("Failed to create Stars invoice: {:?}", tg_res.description))
+
+        if balance < 0 {
+            suspended += sqlx::query("UPDATE subscriptions SET status = 'suspended' WHERE user_id = ? AND status = 'active'")
+                .bind(payment.user_id)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
         }
+        tx.commit().await?;
+
+        warn!("{} of {} on payment {} booked ({} subscriptions suspended)", label, refund.money(), payment.id, suspended);
+        let mut msg = format!("↩️ *{}*\n\n{} of your payment \\#{} was returned\\.", label, escape_md(&refund.money().to_string()), payment.id);
+        if suspended > 0 {
+            msg.push_str("\nThe subscription paid with it is suspended\\.");
+        }
+        self.notify(payment.user_id, &msg).await;
+        Ok(())
     }
 
-    pub async fn handle_webhook(
-        &self, 
-        source: &str, 
-        payload: &str,
-        crypto_sig: Option<&str>,
-        nowpayments_sig: Option<&str>,
-        stripe_sig: Option<&str>,
-        cryptomus_sig: Option<&str>,
-    ) -> Result<()> {
+    /// A dispute decided in our favour: give back exactly what the dispute took
+    async fn restore_dispute(&self, payment: &Payment, reference: &str) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        let refund_id: Option<i64> = sqlx::query_scalar(
+            "UPDATE refunds SET status = 'won', updated_at = CURRENT_TIMESTAMP
+             WHERE payment_id = ? AND kind = 'dispute' AND reference = ? AND status = 'open'
+             RETURNING id"
+        )
+        .bind(payment.id)
+        .bind(reference)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(refund_id) = refund_id else {
+            return Ok(());
+        };
 
-        let body: serde_json::Value = serde_json::from_str(payload)?;
-        
-        match source {
-            "cryptobot" => {
-                 // Verify signature
-                 self.verify_cryptobot_signature(payload, crypto_sig)?;
-                 
-                 if let Some(update_type) = body["update_type"].as_str() {
-                    if update_type == "invoice_paid" {
-                        let invoice = &body["update_payload"];
-                        if invoice["status"].as_str().unwrap_or("") == "paid" {
-                            let amount: f64 = invoice["amount"].as_str().unwrap_or("0").parse().unwrap_or(0.0);
-                            let payload_str = invoice["payload"].as_str().unwrap_or("");
-                            let id = invoice["invoice_id"].to_string();
-                            self.process_any_payment(amount, "cryptobot", Some(id), payload_str).await?;
-                        }
-                    }
-                 }
-            },
-            "nowpayments" => {
-                 // Verify signature
-                 self.verify_nowpayments_signature(payload, nowpayments_sig)?;
-                 
-                 if let Some(status) = body["payment_status"].as_str() {
-                     if status == "finished" {
-                         let amount: f64 = body["pay_amount"].as_f64().unwrap_or(0.0);
-                         let order_id = body["order_id"].as_str().unwrap_or("");
-                         let payload_str = order_id.split('_').next().unwrap_or("");
-                         let id = body["payment_id"].to_string();
-                         self.process_any_payment(amount, "nowpayments", Some(id), payload_str).await?;
-                     }
-                 }
-            },
-            "crystalpay" => {
-                // Verify signature from payload
-                self.verify_crystalpay_signature(&body)?;
-
-                // CrystalPay callback: type=payment, state=payed
-                if body["type"].as_str().unwrap_or("") == "payment" && body["state"].as_str().unwrap_or("") == "payed" {
-                    let amount: f64 = body["amount"].as_f64().unwrap_or(0.0); // usually in currency requested
-                    let extra = body["extra"].as_str().unwrap_or("");
-                    let id = body["id"].to_string();
-                    self.process_any_payment(amount, "crystalpay", Some(id), extra).await?;
-                }
-            },
-            "stripe" => {
-                // Get webhook secret from env
-                let webhook_secret = std::env::var("STRIPE_WEBHOOK_SECRET")
-                    .unwrap_or_default();
-                
-                // Verify signature
-                self.verify_stripe_signature(payload, stripe_sig, &webhook_secret)?;
-                
-                // Stripe sends Event object
-                if body["type"].as_str().unwrap_or("") == "checkout.session.completed" {
-                    let session = &body["data"]["object"];
-                    let amount_subtokens = session["amount_total"].as_i64().unwrap_or(0);
-                    let amount_usd = amount_subtokens as f64 / 100.0;
-                    let payload_str = session["client_reference_id"].as_str().unwrap_or("");
-                    let id = session["id"].to_string();
-                    self.process_any_payment(amount_usd, "stripe", Some(id), payload_str).await?;
-                }
-            },
-
-             "cryptomus" => {
-                 // Verify signature 
-                 self.verify_cryptomus_signature(payload, cryptomus_sig)?;
-
-                 // Check status
-                 // Payload structure from Cryptomus Webhook: { type: "payment", status: "paid", amount, order_id, additional_data, ... }
-                 // Note: Check actual structure. Usually:
-                 // { "type": "payment", "status": "paid", "amount": "10.00", "currency": "USD", "order_id": "...", "additional_data": "..." }
-                 
-                 let status = body["status"].as_str().unwrap_or("");
-                 if status == "paid" || status == "paid_over" {
-                     let amount: f64 = body["amount"].as_str().unwrap_or("0").parse().unwrap_or(0.0);
-                     let payload_str = body["additional_data"].as_str().unwrap_or("");
-                     let id = body["uuid"].as_str().unwrap_or("").to_string(); // Cryptomus UUID
-                     
-                     self.process_any_payment(amount, "cryptomus", Some(id), payload_str).await?;
-                 }
-             },
-             "aaio" => {
-                 // Aaio typically sends form-data. Need to parse body as form-urlencoded if it's not JSON?
-                 // Or it sends JSON?
-                 // Usually form-data POST.
-                 // We receive `payload` as string. If it is JSON, `serde_json` works.
-                 // If it is form-data, we need to parse it. 
-                 // We will attempt JSON first (some send JSON), else URL encoded.
-                 
-                 let data: serde_json::Value = if let Ok(v) = serde_json::from_str(payload) {
-                     v
-                 } else {
-                     // Try parsing query string
-                     let parsed: std::collections::HashMap<String, String> = serde_urlencoded::from_str(payload)
-                        .unwrap_or_default();
-                     serde_json::to_value(parsed).unwrap_or(serde_json::json!({}))
-                 };
-                 
-                 let merchant_id = data["merchant_id"].as_str().unwrap_or("");
-                 let amount = data["amount"].as_str().unwrap_or("");
-                 let currency = data["currency"].as_str().unwrap_or("");
-                 let order_id = data["order_id"].as_str().unwrap_or("");
-                 let sign = data["sign"].as_str().unwrap_or("");
-                 
-                 self.verify_aaio_signature(merchant_id, amount, currency, order_id, sign)?;
-                 
-                 // order_id format: user_id:timestamp:payload_string
-                 // Extract payload string
-                 let parts: Vec<&str> = order_id.splitn(3, ':').collect();
-                 if parts.len() == 3 {
-                     let amount_val: f64 = amount.parse().unwrap_or(0.0);
-                     let payload_str = parts[2];
-                     let id = data["invoice_id"].as_str().unwrap_or(order_id).to_string(); // or use aaio invoice id
-                     self.process_any_payment(amount_val, "aaio", Some(id), payload_str).await?;
-                 }
-             },
-            _ => {}
+        let taken: Vec<(i64, i64, String)> = sqlx::query_as(
+            "SELECT user_id, amount, contra_account FROM balance_ledger WHERE reason = 'reversal' AND ref_type = 'refund' AND ref_id = ?"
+        )
+        .bind(refund_id)
+        .fetch_all(&mut *tx)
+        .await?;
+        let mut balance = 0;
+        for (user_id, amount, contra) in taken {
+            let after = LedgerService::post(&mut tx, Posting::credit(user_id, -amount, Reason::Reversal)
+                .contra(contra)
+                .reference("refund", refund_id)
+                .note(format!("Dispute on payment #{} won", payment.id))).await?;
+            if user_id == payment.user_id {
+                balance = after;
+            }
         }
 
+        let reversed: i64 = sqlx::query_scalar(&format!(
+            "SELECT COALESCE(SUM(amount), 0) FROM refunds WHERE payment_id = ? AND status IN {}", REVERSING_STATUSES
+        ))
+        .bind(payment.id)
+        .fetch_one(&mut *tx)
+        .await?;
+        sqlx::query("UPDATE payments SET status = ?, updated_at = unixepoch() WHERE id = ?")
+            .bind(if reversed > 0 { "partially_refunded" } else { "paid" })
+            .bind(payment.id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("UPDATE referral_bonuses SET status = 'applied' WHERE payment_id = ? AND status = 'reversed'")
+            .bind(payment.id)
+            .execute(&mut *tx)
+            .await?;
+
+        if balance >= 0 {
+            sqlx::query(
+                "UPDATE subscriptions SET status = 'active'
+                 WHERE user_id = ? AND status = 'suspended' AND expires_at > CURRENT_TIMESTAMP"
+            )
+            .bind(payment.user_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        info!("Dispute {} on payment {} won, funds restored", reference, payment.id);
         Ok(())
     }
 
-    pub async fn process_any_payment(&self, amount_usd: f64, method: &str, external_id: Option<String>, payload: &str) -> Result<()> {
+    /// Bot message to a user by internal id; failures are only logged
+    async fn notify(&self, user_id: i64, text: &str) {
+        let tg_id: Option<i64> = sqlx::query_scalar("SELECT tg_id FROM users WHERE id = ?")
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await
+            .ok()
+            .flatten();
+        if let Some(tg_id) = tg_id
+            && let Err(e) = self.bot_manager.send_notification(tg_id, text).await {
+            warn!("Failed to notify user {}: {}", user_id, e);
+        }
+    }
+
+    /// Credit a confirmed payment: `amount` is the USD value to credit, `settled` what the provider received
+    pub async fn process_any_payment(&self, amount: Money, settled: Money, method: &str, external_id: Option<String>, payload: &str) -> Result<()> {
+        if amount.currency != Currency::Usd {
+            return Err(anyhow!("Payments are credited in USD, got {}", amount));
+        }
+
         let parts: Vec<&str> = payload.split(':').collect();
         if parts.len() < 3 {
             // Legacy/Simple fallback
             if let Ok(user_id) = payload.parse::<i64>() {
-                return self.process_balance_topup(user_id, amount_usd, method, external_id).await;
+                return self.process_balance_topup(user_id, amount, settled, method, external_id).await.map(|_| ());
             }
              return Err(anyhow::anyhow!("Invalid payload: {}", payload));
         }
@@ -763,30 +823,29 @@ impl PayService {
         if user_id == 0 { return Err(anyhow::anyhow!("Zero User ID")); }
 
         match type_code {
-            "bal" => self.process_balance_topup(user_id, amount_usd, method, external_id).await,
-            "ord" => self.process_order_purchase(user_id, target_id, amount_usd, method, external_id).await,
-            "sub" => self.process_subscription_purchase(user_id, target_id, amount_usd, method, external_id).await,
+            "bal" => self.process_balance_topup(user_id, amount, settled, method, external_id).await.map(|_| ()),
+            "ord" => self.process_order_purchase(user_id, target_id, amount, settled, method, external_id).await,
+            "sub" => self.process_subscription_purchase(user_id, target_id, amount, settled, method, external_id).await,
             _ => Err(anyhow::anyhow!("Unknown Type: {}", type_code)),
         }
     }
 
-    async fn process_order_purchase(&self, user_id: i64, order_id: i64, amount_usd: f64, method: &str, external_id: Option<String>) -> Result<()> {
-        info!("Processing ORDER payment #${} for user {}", order_id, user_id);
-        let amount_units = (amount_usd * 100.0) as i64;
-        self.store_service.log_payment(user_id, method, amount_units, external_id.as_deref(), "paid").await?;
-        self.store_service.process_order_payment(order_id).await?;
+    async fn process_order_purchase(&self, user_id: i64, order_id: i64, amount: Money, settled: Money, method: &str, external_id: Option<String>) -> Result<()> {
+        info!("Processing ORDER payment #{} for user {}: {}", order_id, user_id, amount);
+        let payment_id = self.store_service.log_payment(user_id, method, amount, settled, external_id.as_deref(), "paid").await?;
+        self.store_service.process_order_payment(order_id, payment_id).await?;
         
-        let _ = self.bot_manager.send_notification(user_id, "✅ Your order has been paid successfully!").await;
+        self.notify(user_id, "✅ Your order has been paid successfully\\!").await;
         
-        let _ = crate::services::analytics_service::AnalyticsService::track_revenue(&self.store_service.get_pool(), amount_units).await;
+        let _ = crate::services::analytics_service::AnalyticsService::track_revenue(&self.store_service.get_pool(), amount.minor).await;
         Ok(())
     }
 
-    async fn process_subscription_purchase(&self, user_id: i64, plan_id: i64, amount_usd: f64, method: &str, external_id: Option<String>) -> Result<()> {
+    async fn process_subscription_purchase(&self, user_id: i64, plan_id: i64, amount: Money, settled: Money, method: &str, external_id: Option<String>) -> Result<()> {
         info!("Processing SUBSCRIPTION payment for user {} (Plan: {})", user_id, plan_id);
         
         // 1. Top up balance first (to record the flow of money)
-        self.process_balance_topup(user_id, amount_usd, method, external_id.clone()).await?;
+        let payment_id = self.process_balance_topup(user_id, amount, settled, method, external_id.clone()).await?;
 
         // 2. Attempt to purchase the plan internally using the just-added balance
         // We need to find the plan duration ID or pass plan_id directly if supported.
@@ -802,47 +861,143 @@ impl PayService {
 
         if let Some(duration) = durations {
             match self.store_service.purchase_plan(user_id, duration.id).await {
-                Ok(_) => {
-                    let _ = self.bot_manager.send_notification(user_id, "✅ Subscription activated successfully!").await;
+                Ok(sub) => {
+                    // Remember what the payment bought, so a refund or chargeback can suspend it
+                    sqlx::query("UPDATE payments SET subscription_id = ? WHERE id = ?")
+                        .bind(sub.id)
+                        .bind(payment_id)
+                        .execute(&self.pool)
+                        .await?;
+                    self.notify(user_id, "✅ Subscription activated successfully\\!").await;
                 },
                 Err(e) => {
                     error!("Failed to auto-purchase subscription after payment: {}", e);
-                    let _ = self.bot_manager.send_notification(user_id, "⚠️ Payment received but subscription activation failed. Please contact support.").await;
+                    self.notify(user_id, "⚠️ Payment received but subscription activation failed\\. Please contact support\\.").await;
                 }
             }
         } else {
              error!("No duration found for plan {}", plan_id);
-             let _ = self.bot_manager.send_notification(user_id, "⚠️ Error: Plan duration not found. Balance credited.").await;
+             self.notify(user_id, "⚠️ Error: Plan duration not found\\. Balance credited\\.").await;
         }
         
         Ok(())
     }
 
-    async fn process_balance_topup(&self, user_id: i64, amount_usd: f64, method: &str, external_id: Option<String>) -> Result<()> {
-        info!("Processing BALANCE top-up of ${} for user {} via {}", amount_usd, user_id, method);
-        let amount_units = (amount_usd * 100.0) as i64; 
-        
-        let mut tx = self.pool.begin().await?;
-        sqlx::query("UPDATE users SET balance = balance + ? WHERE id = ?")
-            .bind(amount_units).bind(user_id).execute(&mut *tx).await?;
+    /// Credit the balance and record the payment; returns the payment id
+    async fn process_balance_topup(&self, user_id: i64, amount: Money, settled: Money, method: &str, external_id: Option<String>) -> Result<i64> {
+        info!("Processing BALANCE top-up of {} ({}) for user {} via {}", amount, settled, user_id, method);
+        let amount_units = amount.minor;
 
+        let mut tx = self.pool.begin().await?;
         let payment_id: i64 = sqlx::query_scalar(
-            "INSERT INTO payments (user_id, method, amount, external_id, status) VALUES (?, ?, ?, ?, 'paid') RETURNING id"
+            "INSERT INTO payments (user_id, method, amount, settled_amount, settled_currency, external_id, status, created_at, updated_at)
+             VALUES (?, ?, ?, ?, ?, ?, 'paid', unixepoch(), unixepoch()) RETURNING id"
         )
-            .bind(user_id).bind(method).bind(amount_units).bind(external_id).fetch_one(&mut *tx).await?;
+            .bind(user_id).bind(method).bind(amount_units).bind(settled.minor).bind(settled.currency.code()).bind(external_id).fetch_one(&mut *tx).await?;
 
-        if let Some((referrer_tg_id, bonus)) = self.store_service.apply_referral_bonus(&mut tx, user_id, amount_units, Some(payment_id)).await? {
-            let formatted_bonus = format!("{:.2}", bonus as f64 / 100.0);
-            let msg = format!("🎉 *Referral Bonus* from your invited user!\n+${}", formatted_bonus);
-            let _ = self.bot_manager.send_notification(referrer_tg_id, &msg).await;
+        let mut posting = Posting::credit(user_id, amount_units, Reason::Topup)
+            .contra(format!("gateway:{}", method))
+            .reference("payment", payment_id);
+        if settled.currency != amount.currency {
+            posting = posting.note(format!("Paid {}", settled));
         }
+        LedgerService::post(&mut tx, posting).await?;
+
+        let referral = self.store_service.apply_referral_bonus(&mut tx, user_id, amount_units, Some(payment_id)).await?;
 
         tx.commit().await?;
-        
-        // Notify user
-        let _ = self.bot_manager.send_notification(user_id, &format!("✅ Balance topped up: +${:.2}", amount_usd)).await;
+
+        // Notify once the money is booked
+        if let Some((referrer_tg_id, bonus)) = referral {
+            let msg = format!("🎉 *Referral Bonus* from your invited user\\!\n{}", escape_md(&format!("+{}", Money::usd_cents(bonus))));
+            let _ = self.bot_manager.send_notification(referrer_tg_id, &msg).await;
+        }
+        self.notify(user_id, &format!("✅ Balance topped up: {}", escape_md(&format!("+{}", amount)))).await;
         let _ = crate::services::analytics_service::AnalyticsService::track_revenue(&self.pool, amount_units).await;
         
-        Ok(())
+        Ok(payment_id)
+    }
+}
+
+/// `total * part / whole`, rounded to the nearest unit
+fn proportional(total: i64, part: i64, whole: i64) -> i64 {
+    if whole == 0 {
+        return 0;
+    }
+    ((total as i128 * part as i128 * 2 + whole as i128) / (whole as i128 * 2)) as i64
+}
+
+/// Local invoice id from the 4th payload field ("uid:type:target:invoice")
+fn invoice_id_from_payload(payload: &str) -> Option<i64> {
+    payload.split(':').nth(3)?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invoice_id_from_payload() {
+        let payload = format!("{}:{}", PaymentType::SubscriptionPurchase(3).to_payload_string(7), 42);
+        assert_eq!(payload, "7:sub:3:42");
+        assert_eq!(invoice_id_from_payload(&payload), Some(42));
+        assert_eq!(invoice_id_from_payload("7:bal:0"), None);
+        assert_eq!(invoice_id_from_payload("7"), None);
+    }
+
+    #[tokio::test]
+    async fn test_refunded_subscription_purchase_keeps_other_subscriptions() {
+        let pool = crate::db::test_pool().await;
+        let store = Arc::new(StoreService::new(pool.clone()));
+        let pay = PayService::new(
+            pool.clone(),
+            store.clone(),
+            Arc::new(BotManager::new()),
+            Arc::new(SettingsService::new(pool.clone()).await.unwrap()),
+            Arc::new(RateService::new(pool.clone()).await.unwrap()),
+        ).await;
+
+        let user_id: i64 = sqlx::query_scalar("INSERT INTO users (tg_id, username, balance) VALUES (2002, 'payer', 0) RETURNING id")
+            .fetch_one(&pool).await.unwrap();
+        let plan_id: i64 = sqlx::query_scalar("INSERT INTO plans (name, price, is_active) VALUES ('Basic', 0, 1) RETURNING id")
+            .fetch_one(&pool).await.unwrap();
+        sqlx::query("INSERT INTO plan_durations (plan_id, duration_days, price) VALUES (?, 30, 1000)")
+            .bind(plan_id)
+            .execute(&pool).await.unwrap();
+
+        // One subscription bought earlier, one paid for directly at the provider
+        let usd = Money::usd_cents(1000);
+        pay.process_any_payment(usd, usd, "stripe", Some("pi_1".to_string()), &format!("{}:sub:{}", user_id, plan_id)).await.unwrap();
+        pay.process_any_payment(usd, usd, "stripe", Some("pi_2".to_string()), &format!("{}:sub:{}", user_id, plan_id)).await.unwrap();
+        sqlx::query("UPDATE subscriptions SET status = 'active'").execute(&pool).await.unwrap();
+        let (payment_id, refunded_sub): (i64, i64) = sqlx::query_as("SELECT id, subscription_id FROM payments WHERE external_id = 'pi_2'")
+            .fetch_one(&pool).await.unwrap();
+
+        let refund_id: i64 = sqlx::query_scalar(
+            "INSERT INTO refunds (payment_id, amount, provider_amount, provider_currency) VALUES (?, 1000, 1000, 'USD') RETURNING id"
+        )
+        .bind(payment_id)
+        .fetch_one(&pool).await.unwrap();
+        pay.apply_reversal(refund_id).await.unwrap();
+
+        let balance: i64 = sqlx::query_scalar("SELECT balance FROM users WHERE id = ?")
+            .bind(user_id)
+            .fetch_one(&pool).await.unwrap();
+        assert_eq!(balance, 0, "the refunded money was spent on the revoked subscription, not left on the balance");
+        let statuses: Vec<(i64, String)> = sqlx::query_as("SELECT id, status FROM subscriptions ORDER BY id")
+            .fetch_all(&pool).await.unwrap();
+        for (sub_id, status) in statuses {
+            let expected = if sub_id == refunded_sub { "suspended" } else { "active" };
+            assert_eq!(status, expected, "subscription {}", sub_id);
+        }
+    }
+
+    #[test]
+    fn test_proportional() {
+        assert_eq!(proportional(1000, 250, 500), 500);
+        assert_eq!(proportional(333, 1, 3), 111);
+        assert_eq!(proportional(100, 1, 3), 33);
+        assert_eq!(proportional(200, 1, 3), 67);
+        assert_eq!(proportional(100, 1, 0), 0);
     }
 }
@@ -0,0 +1,145 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use axum::http::HeaderMap;
+use std::sync::Arc;
+
+use super::*;
+
+pub const SPEC: ProviderSpec = ProviderSpec {
+    id: "cryptobot",
+    name: "CryptoBot",
+    button: "🪙 Crypto (USDT/TON)",
+    currency: Currency::Usdt,
+    fields: &[SettingField { key: "payment_api_key", label: "CryptoBot API Token" }],
+    enabled_key: None,
+    build,
+};
+
+fn build(config: &ProviderConfig) -> Option<Arc<dyn PaymentProvider>> {
+    let [token] = config.require(["payment_api_key"])?;
+    Some(Arc::new(CryptoBot { token, testnet: config.testnet }))
+}
+
+/// Crypto Pay API of @CryptoBot; invoices are billed in USDT
+pub struct CryptoBot {
+    token: String,
+    testnet: bool,
+}
+
+impl CryptoBot {
+    fn api_url(&self) -> &'static str {
+        if self.testnet { "https://testnet-pay.crypt.bot/api" } else { "https://pay.crypt.bot/api" }
+    }
+
+    /// HMAC-SHA256 of the body, keyed with SHA256 of the API token
+    fn verify(&self, payload: &str, signature: Option<&str>) -> Result<()> {
+        use hmac::{Hmac, Mac};
+        use sha2::{Digest, Sha256};
+
+        let sig = signature.ok_or_else(|| anyhow!("Missing crypto-pay-api-signature header"))?;
+        let secret = Sha256::digest(self.token.as_bytes());
+        let mut mac = Hmac::<Sha256>::new_from_slice(&secret).map_err(|e| anyhow!("Invalid HMAC key: {}", e))?;
+        mac.update(payload.as_bytes());
+        let expected = hex::encode(mac.finalize().into_bytes());
+
+        if signature_eq(sig, &expected) { Ok(()) } else { Err(anyhow!("Invalid CryptoBot signature")) }
+    }
+}
+
+#[async_trait]
+impl PaymentProvider for CryptoBot {
+    fn spec(&self) -> &'static ProviderSpec {
+        &SPEC
+    }
+
+    async fn create_invoice(&self, req: &InvoiceRequest) -> Result<CreatedInvoice> {
+        let invoice = serde_json::json!({
+            "asset": req.amount.currency.code(),
+            "amount": req.amount.to_decimal_string(),
+            "description": req.description,
+            "payload": req.payload,
+            "allow_anonymous": false,
+            "allow_comments": false
+        });
+
+        let body: serde_json::Value = http().post(format!("{}/createInvoice", self.api_url()))
+            .header("Crypto-Pay-API-Token", &self.token)
+            .json(&invoice)
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        if body["ok"].as_bool().unwrap_or(false) {
+            Ok(CreatedInvoice {
+                external_id: Some(json_id(&body["result"]["invoice_id"])),
+                url: body["result"]["bot_invoice_url"].as_str().unwrap_or("").to_string(),
+            })
+        } else {
+            Err(anyhow!("CryptoBot error: {:?}", body))
+        }
+    }
+
+    fn parse_webhook(&self, headers: &HeaderMap, body: &str) -> Result<Option<PaymentNotification>> {
+        self.verify(body, header(headers, "crypto-pay-api-signature"))?;
+        let body: serde_json::Value = serde_json::from_str(body)?;
+
+        if body["update_type"].as_str() != Some("invoice_paid") {
+            return Ok(None);
+        }
+        let invoice = &body["update_payload"];
+        if invoice["status"].as_str() != Some("paid") {
+            return Ok(None);
+        }
+        Ok(Some(PaymentNotification {
+            external_id: json_id(&invoice["invoice_id"]),
+            status: PaymentStatus::Paid,
+            amount: json_money(&invoice["amount"], json_currency(&invoice["asset"], SPEC.currency)?)?,
+            payload: invoice["payload"].as_str().unwrap_or("").to_string(),
+            reference: None,
+        }))
+    }
+
+    async fn query_status(&self, external_id: &str) -> Result<PaymentStatus> {
+        let body: serde_json::Value = http().get(format!("{}/getInvoices", self.api_url()))
+            .header("Crypto-Pay-API-Token", &self.token)
+            .query(&[("invoice_ids", external_id)])
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        match body["result"]["items"][0]["status"].as_str() {
+            Some("paid") => Ok(PaymentStatus::Paid),
+            Some("expired") => Ok(PaymentStatus::Expired),
+            Some("active") => Ok(PaymentStatus::Pending),
+            _ => Err(anyhow!("CryptoBot invoice {} not found: {:?}", external_id, body)),
+        }
+    }
+
+    /// Crypto Pay has no refunds; the amount is transferred from the app balance to the payer's
+    /// Telegram account instead. `spend_id` makes retries safe.
+    async fn refund(&self, req: &RefundRequest) -> Result<Option<String>> {
+        let transfer = serde_json::json!({
+            "user_id": req.tg_id,
+            "asset": req.amount.currency.code(),
+            "amount": req.amount.to_decimal_string(),
+            "spend_id": format!("refund-{}", req.refund_id),
+            "comment": format!("Refund of invoice {}", req.external_id),
+        });
+
+        let body: serde_json::Value = http().post(format!("{}/transfer", self.api_url()))
+            .header("Crypto-Pay-API-Token", &self.token)
+            .json(&transfer)
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        if body["ok"].as_bool().unwrap_or(false) {
+            Ok(Some(json_id(&body["result"]["transfer_id"])))
+        } else {
+            Err(anyhow!("CryptoBot transfer failed: {:?}", body["error"]))
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use axum::http::HeaderMap;
+use base64::Engine;
+use chrono::Utc;
+use std::sync::Arc;
+
+use super::*;
+
+pub const SPEC: ProviderSpec = ProviderSpec {
+    id: "cryptomus",
+    name: "Cryptomus",
+    button: "🛡 Crypto (Cryptomus)",
+    currency: Currency::Usd,
+    fields: &[
+        SettingField { key: "cryptomus_merchant_id", label: "Cryptomus Merchant ID" },
+        SettingField { key: "cryptomus_payment_api_key", label: "Cryptomus Payment API Key" },
+    ],
+    enabled_key: None,
+    build,
+};
+
+fn build(config: &ProviderConfig) -> Option<Arc<dyn PaymentProvider>> {
+    let [merchant_id, api_key] = config.require(["cryptomus_merchant_id", "cryptomus_payment_api_key"])?;
+    Some(Arc::new(Cryptomus {
+        merchant_id,
+        api_key,
+        callback_url: config.callback_url(SPEC.id),
+        return_url: config.return_url.clone(),
+    }))
+}
+
+pub struct Cryptomus {
+    merchant_id: String,
+    api_key: String,
+    callback_url: String,
+    return_url: String,
+}
+
+impl Cryptomus {
+    /// MD5(base64(body) + api_key), used both for requests and callbacks
+    fn sign(&self, body: &str) -> String {
+        let encoded = base64::engine::general_purpose::STANDARD.encode(body);
+        format!("{:x}", md5::compute(format!("{}{}", encoded, self.api_key).as_bytes()))
+    }
+
+    /// Callbacks carry `sign` inside the JSON, computed over the body without it
+    /// (PHP-encoded, so slashes are escaped). A `sign` header over the raw body is accepted too.
+    fn verify(&self, headers: &HeaderMap, raw: &str, body: &serde_json::Value) -> Result<()> {
+        if let Some(sig) = header(headers, "sign") {
+            return if signature_eq(sig, &self.sign(raw)) { Ok(()) } else { Err(anyhow!("Invalid Cryptomus signature")) };
+        }
+
+        let sig = body["sign"].as_str().ok_or_else(|| anyhow!("Missing Cryptomus sign"))?;
+        let mut unsigned = body.clone();
+        if let Some(map) = unsigned.as_object_mut() {
+            map.remove("sign");
+        }
+        let canonical = serde_json::to_string(&unsigned)?.replace('/', "\\/");
+        if signature_eq(sig, &self.sign(&canonical)) { Ok(()) } else { Err(anyhow!("Invalid Cryptomus signature")) }
+    }
+
+    async fn call(&self, path: &str, body: serde_json::Value) -> Result<serde_json::Value> {
+        let body_str = serde_json::to_string(&body)?;
+        Ok(http().post(format!("https://api.cryptomus.com/v1/{}", path))
+            .header("merchant", &self.merchant_id)
+            .header("sign", self.sign(&body_str))
+            .header("Content-Type", "application/json")
+            .body(body_str)
+            .send()
+            .await?
+            .json()
+            .await?)
+    }
+}
+
+fn map_status(status: &str) -> Option<PaymentStatus> {
+    match status {
+        "paid" | "paid_over" => Some(PaymentStatus::Paid),
+        "fail" | "system_fail" | "wrong_amount" => Some(PaymentStatus::Failed),
+        "cancel" => Some(PaymentStatus::Expired),
+        "refund_paid" => Some(PaymentStatus::Refunded),
+        "check" | "process" | "confirm_check" | "wrong_amount_waiting" => Some(PaymentStatus::Pending),
+        _ => None,
+    }
+}
+
+#[async_trait]
+impl PaymentProvider for Cryptomus {
+    fn spec(&self) -> &'static ProviderSpec {
+        &SPEC
+    }
+
+    async fn create_invoice(&self, req: &InvoiceRequest) -> Result<CreatedInvoice> {
+        let resp = self.call("payment", serde_json::json!({
+            "amount": req.amount.to_decimal_string(),
+            "currency": req.amount.currency.code(),
+            "order_id": format!("{}_{}", req.user_id, Utc::now().timestamp_millis()),
+            "url_callback": self.callback_url,
+            "url_return": self.return_url,
+            "additional_data": req.payload
+        })).await?;
+
+        match resp["result"]["url"].as_str() {
+            Some(url) => Ok(CreatedInvoice { external_id: Some(json_id(&resp["result"]["uuid"])), url: url.to_string() }),
+            None => Err(anyhow!("Cryptomus Error: {:?}", resp)),
+        }
+    }
+
+    fn parse_webhook(&self, headers: &HeaderMap, raw: &str) -> Result<Option<PaymentNotification>> {
+        let body: serde_json::Value = serde_json::from_str(raw)?;
+        self.verify(headers, raw, &body)?;
+
+        let Some(status) = body["status"].as_str().and_then(map_status) else {
+            return Ok(None);
+        };
+        Ok(Some(PaymentNotification {
+            external_id: json_id(&body["uuid"]),
+            status,
+            amount: json_money(&body["amount"], json_currency(&body["currency"], SPEC.currency)?)?,
+            payload: body["additional_data"].as_str().unwrap_or("").to_string(),
+            reference: None,
+        }))
+    }
+
+    async fn query_status(&self, external_id: &str) -> Result<PaymentStatus> {
+        let resp = self.call("payment/info", serde_json::json!({ "uuid": external_id })).await?;
+        resp["result"]["payment_status"].as_str()
+            .and_then(map_status)
+            .ok_or_else(|| anyhow!("Cryptomus payment {} not found: {:?}", external_id, resp))
+    }
+}
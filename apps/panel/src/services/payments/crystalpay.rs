@@ -0,0 +1,138 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use axum::http::HeaderMap;
+use std::sync::Arc;
+
+use super::*;
+
+pub const SPEC: ProviderSpec = ProviderSpec {
+    id: "crystalpay",
+    name: "CrystalPay",
+    button: "🇷🇺 Cards (RUB/SBP)",
+    currency: Currency::Usd,
+    fields: &[
+        SettingField { key: "crystalpay_login", label: "CrystalPay Login" },
+        SettingField { key: "crystalpay_secret", label: "CrystalPay Secret" },
+    ],
+    enabled_key: None,
+    build,
+};
+
+fn build(config: &ProviderConfig) -> Option<Arc<dyn PaymentProvider>> {
+    let [login, secret] = config.require(["crystalpay_login", "crystalpay_secret"])?;
+    Some(Arc::new(CrystalPay {
+        login,
+        secret,
+        callback_url: config.callback_url(SPEC.id),
+        return_url: config.return_url.clone(),
+    }))
+}
+
+pub struct CrystalPay {
+    login: String,
+    secret: String,
+    callback_url: String,
+    return_url: String,
+}
+
+impl CrystalPay {
+    /// MD5(id + amount + state + secret)
+    fn verify(&self, payload: &serde_json::Value) -> Result<()> {
+        let sign = payload["signature"].as_str().ok_or_else(|| anyhow!("Missing signature in payload"))?;
+        let id = payload["id"].as_str().unwrap_or("");
+        let state = payload["state"].as_str().unwrap_or("");
+        let amount = payload["amount"].as_f64().unwrap_or(0.0);
+
+        let expected = format!("{:x}", md5::compute(format!("{}{}{}{}", id, amount, state, self.secret).as_bytes()));
+        if signature_eq(sign, &expected) { Ok(()) } else { Err(anyhow!("Invalid CrystalPay signature")) }
+    }
+}
+
+fn map_state(state: &str) -> Option<PaymentStatus> {
+    match state {
+        "payed" => Some(PaymentStatus::Paid),
+        "notpayed" | "processing" => Some(PaymentStatus::Pending),
+        "failed" | "wrongamount" => Some(PaymentStatus::Failed),
+        "expired" => Some(PaymentStatus::Expired),
+        _ => None,
+    }
+}
+
+#[async_trait]
+impl PaymentProvider for CrystalPay {
+    fn spec(&self) -> &'static ProviderSpec {
+        &SPEC
+    }
+
+    async fn create_invoice(&self, req: &InvoiceRequest) -> Result<CreatedInvoice> {
+        let body_json = serde_json::json!({
+            "auth_login": self.login,
+            "auth_secret": self.secret,
+            "amount": req.amount.to_decimal_string(),
+            "amount_currency": req.amount.currency.code(),
+            "type": "purchase",
+            "description": req.description,
+            "redirect_url": self.return_url,
+            "callback_url": self.callback_url,
+            "extra": req.payload
+        });
+
+        let resp: serde_json::Value = http().post("https://api.crystalpay.io/v2/invoice/create/")
+            .json(&body_json)
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        // {"error":false, "errors":[], "data": { "id": "...", "url": "..." }}
+        if resp["error"].as_bool().unwrap_or(true) {
+            Err(anyhow!("CrystalPay Error: {:?}", resp))
+        } else {
+            Ok(CreatedInvoice {
+                external_id: Some(json_id(&resp["data"]["id"])),
+                url: resp["data"]["url"].as_str().unwrap_or("").to_string(),
+            })
+        }
+    }
+
+    fn parse_webhook(&self, _headers: &HeaderMap, body: &str) -> Result<Option<PaymentNotification>> {
+        let body: serde_json::Value = serde_json::from_str(body)?;
+        self.verify(&body)?;
+
+        if body["type"].as_str() != Some("payment") {
+            return Ok(None);
+        }
+        let Some(status) = body["state"].as_str().and_then(map_state) else {
+            return Ok(None);
+        };
+        Ok(Some(PaymentNotification {
+            external_id: json_id(&body["id"]),
+            status,
+            // initial_* is the invoice amount in the currency it was created in
+            amount: match body.get("initial_amount") {
+                Some(initial) => json_money(initial, json_currency(&body["initial_currency"], SPEC.currency)?)?,
+                None => json_money(&body["amount"], SPEC.currency)?,
+            },
+            payload: body["extra"].as_str().unwrap_or("").to_string(),
+            reference: None,
+        }))
+    }
+
+    async fn query_status(&self, external_id: &str) -> Result<PaymentStatus> {
+        let resp: serde_json::Value = http().post("https://api.crystalpay.io/v2/invoice/info/")
+            .json(&serde_json::json!({
+                "auth_login": self.login,
+                "auth_secret": self.secret,
+                "id": external_id
+            }))
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        resp["state"].as_str()
+            .or_else(|| resp["data"]["state"].as_str())
+            .and_then(map_state)
+            .ok_or_else(|| anyhow!("CrystalPay invoice {} not found: {:?}", external_id, resp))
+    }
+}
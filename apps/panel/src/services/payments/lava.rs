@@ -0,0 +1,127 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use axum::http::HeaderMap;
+use chrono::Utc;
+use std::sync::Arc;
+
+use super::*;
+
+const API: &str = "https://api.lava.ru/business";
+
+pub const SPEC: ProviderSpec = ProviderSpec {
+    id: "lava",
+    name: "Lava.top",
+    button: "🔥 Cards (Lava)",
+    currency: Currency::Rub,
+    fields: &[
+        SettingField { key: "lava_project_id", label: "Lava Project ID" },
+        SettingField { key: "lava_secret_key", label: "Lava Secret Key" },
+    ],
+    enabled_key: None,
+    build,
+};
+
+fn build(config: &ProviderConfig) -> Option<Arc<dyn PaymentProvider>> {
+    let [project_id, secret_key] = config.require(["lava_project_id", "lava_secret_key"])?;
+    Some(Arc::new(Lava {
+        project_id,
+        secret_key,
+        callback_url: config.callback_url(SPEC.id),
+        return_url: config.return_url.clone(),
+    }))
+}
+
+pub struct Lava {
+    project_id: String,
+    secret_key: String,
+    callback_url: String,
+    return_url: String,
+}
+
+impl Lava {
+    /// HMAC-SHA256(body, secret_key), hex; same scheme for requests and callbacks
+    fn sign(&self, body: &str) -> Result<String> {
+        use hmac::{Hmac, Mac};
+        let mut mac = Hmac::<sha2::Sha256>::new_from_slice(self.secret_key.as_bytes())
+            .map_err(|e| anyhow!("Invalid Lava Secret: {}", e))?;
+        mac.update(body.as_bytes());
+        Ok(hex::encode(mac.finalize().into_bytes()))
+    }
+
+    async fn call(&self, path: &str, body: serde_json::Value) -> Result<serde_json::Value> {
+        let body_str = serde_json::to_string(&body)?;
+        Ok(http().post(format!("{}/{}", API, path))
+            .header("Signature", self.sign(&body_str)?)
+            .header("Accept", "application/json")
+            .header("Content-Type", "application/json")
+            .body(body_str)
+            .send()
+            .await?
+            .json()
+            .await?)
+    }
+}
+
+fn map_status(status: &str) -> Option<PaymentStatus> {
+    match status {
+        "success" => Some(PaymentStatus::Paid),
+        "expired" => Some(PaymentStatus::Expired),
+        "created" => Some(PaymentStatus::Pending),
+        _ => None,
+    }
+}
+
+#[async_trait]
+impl PaymentProvider for Lava {
+    fn spec(&self) -> &'static ProviderSpec {
+        &SPEC
+    }
+
+    async fn create_invoice(&self, req: &InvoiceRequest) -> Result<CreatedInvoice> {
+        let resp = self.call("invoice/create", serde_json::json!({
+            // Lava bills in rubles
+            "sum": req.amount.to_decimal_string().parse::<f64>().unwrap_or(0.0),
+            "orderId": format!("LAVA-{}-{}", req.user_id, Utc::now().timestamp_millis()),
+            "shopId": self.project_id,
+            "comment": req.description,
+            "customFields": req.payload,
+            "hookUrl": self.callback_url,
+            "successUrl": self.return_url,
+            "expire": 60
+        })).await?;
+
+        match resp["data"]["url"].as_str() {
+            Some(url) => Ok(CreatedInvoice { external_id: Some(json_id(&resp["data"]["id"])), url: url.to_string() }),
+            None => Err(anyhow!("Failed to create Lava invoice (No URL returned): {:?}", resp)),
+        }
+    }
+
+    fn parse_webhook(&self, headers: &HeaderMap, body: &str) -> Result<Option<PaymentNotification>> {
+        let sig = header(headers, "authorization").ok_or_else(|| anyhow!("Missing Lava signature header"))?;
+        if !signature_eq(sig, &self.sign(body)?) {
+            return Err(anyhow!("Invalid Lava signature"));
+        }
+        let body: serde_json::Value = serde_json::from_str(body)?;
+
+        let Some(status) = body["status"].as_str().and_then(map_status) else {
+            return Ok(None);
+        };
+        Ok(Some(PaymentNotification {
+            external_id: json_id(&body["invoice_id"]),
+            status,
+            amount: json_money(&body["amount"], SPEC.currency)?,
+            payload: body["custom_fields"].as_str().unwrap_or("").to_string(),
+            reference: None,
+        }))
+    }
+
+    async fn query_status(&self, external_id: &str) -> Result<PaymentStatus> {
+        let resp = self.call("invoice/status", serde_json::json!({
+            "shopId": self.project_id,
+            "invoiceId": external_id
+        })).await?;
+        resp["data"]["status"].as_str()
+            .and_then(map_status)
+            .ok_or_else(|| anyhow!("Lava invoice {} not found: {:?}", external_id, resp))
+    }
+}
@@ -0,0 +1,278 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use axum::http::HeaderMap;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::models::money::{Currency, Money};
+use crate::settings::SettingsService;
+
+pub mod aaio;
+pub mod crystalpay;
+pub mod cryptobot;
+pub mod cryptomus;
+pub mod lava;
+pub mod nowpayments;
+pub mod stars;
+pub mod stripe;
+
+/// What the customer is paying for; the provider carries `payload` back in its webhook
+pub struct InvoiceRequest {
+    pub user_id: i64,
+    /// Already converted to the provider's `ProviderSpec::currency`
+    pub amount: Money,
+    pub payload: String,
+    pub description: String,
+}
+
+pub struct CreatedInvoice {
+    /// Provider-side id, when the provider returns one at creation time
+    pub external_id: Option<String>,
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaymentStatus {
+    Pending,
+    Paid,
+    Failed,
+    Expired,
+    Refunded,
+    /// A chargeback was opened; the provider has already pulled the funds
+    Disputed,
+    DisputeWon,
+    DisputeLost,
+}
+
+/// A verified webhook, reduced to what the billing side needs
+#[derive(Debug)]
+pub struct PaymentNotification {
+    /// The payment this is about, as the provider knows it
+    pub external_id: String,
+    pub status: PaymentStatus,
+    /// What the provider settled, in the provider's currency. For `Refunded` the total refunded
+    /// so far, for disputes the disputed amount.
+    pub amount: Money,
+    pub payload: String,
+    /// Dispute id, for dispute events
+    pub reference: Option<String>,
+}
+
+/// Money going back to the payer of an earlier payment
+pub struct RefundRequest {
+    /// Local refund id; providers use it as an idempotency key
+    pub refund_id: i64,
+    pub external_id: String,
+    /// In the provider's currency
+    pub amount: Money,
+    /// Whether `amount` is the whole payment
+    pub full: bool,
+    /// Telegram id of the payer, for providers that pay out to Telegram accounts
+    pub tg_id: i64,
+}
+
+/// A payment gateway. Implementations hold their own credentials and never touch the database.
+#[async_trait]
+pub trait PaymentProvider: Send + Sync {
+    fn spec(&self) -> &'static ProviderSpec;
+
+    async fn create_invoice(&self, req: &InvoiceRequest) -> Result<CreatedInvoice>;
+
+    /// Check the signature and parse the callback. `Ok(None)` means a valid event we don't act on.
+    fn parse_webhook(&self, headers: &HeaderMap, body: &str) -> Result<Option<PaymentNotification>>;
+
+    async fn query_status(&self, external_id: &str) -> Result<PaymentStatus> {
+        let _ = external_id;
+        Err(anyhow!("{} does not support status queries", self.spec().name))
+    }
+
+    /// Send money back; returns the provider's refund id when it has one
+    async fn refund(&self, req: &RefundRequest) -> Result<Option<String>> {
+        let _ = req;
+        Err(anyhow!("{} does not support refunds", self.spec().name))
+    }
+
+    /// Map the payment a refund or dispute event refers to onto the id the payment was recorded under
+    async fn payment_external_id(&self, reference: &str) -> Result<String> {
+        Ok(reference.to_string())
+    }
+}
+
+pub struct SettingField {
+    pub key: &'static str,
+    pub label: &'static str,
+}
+
+/// Static description of a provider: how it shows up in the bot and on the settings page,
+/// and how to build it from settings
+pub struct ProviderSpec {
+    /// Stable id: webhook path `/api/payments/<id>` and `payments.method`
+    pub id: &'static str,
+    pub name: &'static str,
+    /// Top-up menu button in the bot
+    pub button: &'static str,
+    /// Currency invoices are billed in
+    pub currency: Currency,
+    pub fields: &'static [SettingField],
+    /// Boolean setting that must be "true" for the provider to load (for providers without credentials)
+    pub enabled_key: Option<&'static str>,
+    pub build: fn(&ProviderConfig) -> Option<Arc<dyn PaymentProvider>>,
+}
+
+/// Every provider the panel knows about, in menu order
+pub const PROVIDERS: &[&ProviderSpec] = &[
+    &cryptobot::SPEC,
+    &nowpayments::SPEC,
+    &crystalpay::SPEC,
+    &stripe::SPEC,
+    &cryptomus::SPEC,
+    &aaio::SPEC,
+    &lava::SPEC,
+    &stars::SPEC,
+];
+
+pub fn spec(id: &str) -> Option<&'static ProviderSpec> {
+    PROVIDERS.iter().copied().find(|p| p.id == id)
+}
+
+/// Settings a provider is built from: its own fields plus the shared callback/return URLs
+pub struct ProviderConfig {
+    values: HashMap<&'static str, String>,
+    pub callback_base: String,
+    pub return_url: String,
+    pub testnet: bool,
+    pub bot_token: String,
+}
+
+impl ProviderConfig {
+    pub fn get(&self, key: &str) -> &str {
+        self.values.get(key).map(|v| v.as_str()).unwrap_or("")
+    }
+
+    /// All of `keys`, or None if any is empty (the provider is then not configured)
+    pub fn require<const N: usize>(&self, keys: [&str; N]) -> Option<[String; N]> {
+        let values = keys.map(|k| self.get(k).trim().to_string());
+        values.iter().all(|v| !v.is_empty()).then_some(values)
+    }
+
+    pub fn callback_url(&self, id: &str) -> String {
+        format!("{}/api/payments/{}", self.callback_base, id)
+    }
+}
+
+/// Providers that are configured and enabled, built from settings
+pub struct PaymentRegistry {
+    providers: Vec<Arc<dyn PaymentProvider>>,
+}
+
+impl PaymentRegistry {
+    pub async fn from_settings(settings: &SettingsService) -> Self {
+        let callback_base = settings.get_or_default("payment_ipn_url", "https://api.exa.robot").await;
+        let bot_username = settings.get_or_default("bot_username", "exarobot_bot").await;
+        let callback_base = callback_base.trim().trim_end_matches('/').trim_end_matches("/api/payments").to_string();
+        let return_url = format!("https://t.me/{}", bot_username.trim_start_matches('@'));
+        let testnet = settings.get_or_default("payment_testnet", "true").await == "true";
+        let bot_token = settings.get_or_default("bot_token", "").await;
+
+        let mut providers = Vec::new();
+        for spec in PROVIDERS {
+            if let Some(key) = spec.enabled_key
+                && settings.get_or_default(key, "false").await != "true" {
+                continue;
+            }
+            let mut values = HashMap::new();
+            for field in spec.fields {
+                values.insert(field.key, settings.get_or_default(field.key, "").await);
+            }
+            let config = ProviderConfig {
+                values,
+                callback_base: callback_base.clone(),
+                return_url: return_url.clone(),
+                testnet,
+                bot_token: bot_token.clone(),
+            };
+            if let Some(provider) = (spec.build)(&config) {
+                providers.push(provider);
+            }
+        }
+
+        tracing::info!("Payment providers enabled: {}", providers.iter().map(|p| p.spec().id).collect::<Vec<_>>().join(", "));
+        Self { providers }
+    }
+
+    pub fn get(&self, id: &str) -> Option<Arc<dyn PaymentProvider>> {
+        self.providers.iter().find(|p| p.spec().id == id).cloned()
+    }
+
+    pub fn enabled(&self) -> &[Arc<dyn PaymentProvider>] {
+        &self.providers
+    }
+}
+
+/// Shared HTTP client for gateway calls
+pub(crate) fn http() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(20))
+        .build()
+        .unwrap_or_default()
+}
+
+/// Ids arrive as JSON strings or numbers depending on the gateway
+pub(crate) fn json_id(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+/// Amount field of a webhook; gateways send JSON numbers or decimal strings
+pub(crate) fn json_money(value: &serde_json::Value, currency: Currency) -> Result<Money> {
+    Money::from_json(value, currency).ok_or_else(|| anyhow!("Invalid amount {} in webhook", value))
+}
+
+/// Currency field of a webhook, falling back to what the provider bills in
+pub(crate) fn json_currency(value: &serde_json::Value, default: Currency) -> Result<Currency> {
+    match value.as_str() {
+        None | Some("") => Ok(default),
+        Some(code) => Currency::parse(code).ok_or_else(|| anyhow!("Unsupported currency {}", code)),
+    }
+}
+
+pub(crate) fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|h| h.to_str().ok())
+}
+
+/// Constant-time comparison for signatures
+pub(crate) fn signature_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_provider_specs_and_config() {
+        let mut ids: Vec<_> = PROVIDERS.iter().map(|p| p.id).collect();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), PROVIDERS.len());
+
+        let config = ProviderConfig {
+            values: HashMap::from([("lava_project_id", "p1".to_string()), ("lava_secret_key", " ".to_string())]),
+            callback_base: "https://panel.example".to_string(),
+            return_url: String::new(),
+            testnet: false,
+            bot_token: String::new(),
+        };
+        assert!(config.require(["lava_project_id", "lava_secret_key"]).is_none());
+        assert_eq!(config.require(["lava_project_id"]), Some(["p1".to_string()]));
+        assert!((lava::SPEC.build)(&config).is_none());
+        assert_eq!(config.callback_url("lava"), "https://panel.example/api/payments/lava");
+        assert_eq!(json_id(&serde_json::json!(42)), "42");
+        assert_eq!(json_money(&serde_json::json!("5.50"), Currency::Usd).unwrap(), Money::usd_cents(550));
+        assert_eq!(json_currency(&serde_json::json!("usdt"), Currency::Usd).unwrap(), Currency::Usdt);
+        assert!(json_currency(&serde_json::json!("BTC"), Currency::Usd).is_err());
+    }
+}
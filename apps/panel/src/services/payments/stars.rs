@@ -0,0 +1,93 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use axum::http::HeaderMap;
+use serde::Deserialize;
+use std::sync::Arc;
+
+use super::*;
+
+pub const SPEC: ProviderSpec = ProviderSpec {
+    id: "stars",
+    name: "Telegram Stars",
+    button: "⭐️ Telegram Stars",
+    currency: Currency::Xtr,
+    fields: &[],
+    enabled_key: Some("telegram_stars_enabled"),
+    build,
+};
+
+fn build(config: &ProviderConfig) -> Option<Arc<dyn PaymentProvider>> {
+    if config.bot_token.is_empty() {
+        return None;
+    }
+    Some(Arc::new(Stars { bot_token: config.bot_token.clone() }))
+}
+
+/// Telegram Stars (XTR). Invoices are bot invoice links; payments arrive as
+/// `successful_payment` messages in the bot, not as webhooks.
+pub struct Stars {
+    bot_token: String,
+}
+
+#[async_trait]
+impl PaymentProvider for Stars {
+    fn spec(&self) -> &'static ProviderSpec {
+        &SPEC
+    }
+
+    async fn create_invoice(&self, req: &InvoiceRequest) -> Result<CreatedInvoice> {
+        let params = serde_json::json!({
+            "title": "Balance Top-up",
+            "description": req.description,
+            "payload": req.payload,
+            "provider_token": "",
+            "currency": "XTR",
+            "prices": [{"label": "Top-up", "amount": req.amount.minor.max(1)}]
+        });
+
+        #[derive(Deserialize)]
+        struct TgResponse {
+            ok: bool,
+            result: Option<String>,
+            description: Option<String>,
+        }
+
+        let tg_res: TgResponse = http().post(format!("https://api.telegram.org/bot{}/createInvoiceLink", self.bot_token))
+            .json(&params)
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        match (tg_res.ok, tg_res.result) {
+            (true, Some(url)) => Ok(CreatedInvoice { external_id: None, url }),
+            _ => Err(anyhow!("Failed to create Stars invoice: {:?}", tg_res.description)),
+        }
+    }
+
+    fn parse_webhook(&self, _headers: &HeaderMap, _body: &str) -> Result<Option<PaymentNotification>> {
+        Err(anyhow!("Stars payments arrive through the bot, not webhooks"))
+    }
+
+    /// `refundStarPayment` always returns the whole payment
+    async fn refund(&self, req: &RefundRequest) -> Result<Option<String>> {
+        if !req.full {
+            return Err(anyhow!("Stars payments can only be refunded in full"));
+        }
+        let body: serde_json::Value = http().post(format!("https://api.telegram.org/bot{}/refundStarPayment", self.bot_token))
+            .json(&serde_json::json!({
+                "user_id": req.tg_id,
+                "telegram_payment_charge_id": req.external_id,
+            }))
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        if body["ok"].as_bool().unwrap_or(false) {
+            Ok(None)
+        } else {
+            Err(anyhow!("Stars refund failed: {:?}", body["description"]))
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use axum::http::HeaderMap;
+use chrono::Utc;
+use std::sync::Arc;
+
+use super::*;
+
+const API: &str = "https://api.stripe.com/v1";
+/// Webhooks signed longer ago than this are treated as replays
+const SIGNATURE_TOLERANCE_SECS: i64 = 300;
+
+pub const SPEC: ProviderSpec = ProviderSpec {
+    id: "stripe",
+    name: "Stripe",
+    button: "🌍 Global Cards (USD)",
+    currency: Currency::Usd,
+    fields: &[
+        SettingField { key: "stripe_secret_key", label: "Stripe Secret Key" },
+        SettingField { key: "stripe_webhook_secret", label: "Stripe Webhook Secret" },
+    ],
+    enabled_key: None,
+    build,
+};
+
+fn build(config: &ProviderConfig) -> Option<Arc<dyn PaymentProvider>> {
+    let [secret_key] = config.require(["stripe_secret_key"])?;
+    let webhook_secret = config.require(["stripe_webhook_secret"])
+        .map(|[s]| s)
+        .unwrap_or_else(|| std::env::var("STRIPE_WEBHOOK_SECRET").unwrap_or_default());
+    Some(Arc::new(Stripe { secret_key, webhook_secret, return_url: config.return_url.clone() }))
+}
+
+pub struct Stripe {
+    secret_key: String,
+    webhook_secret: String,
+    return_url: String,
+}
+
+impl Stripe {
+    /// `Stripe-Signature: t=<ts>,v1=<hmac-sha256("<ts>.<body>")>`
+    fn verify(&self, payload: &str, signature: Option<&str>) -> Result<()> {
+        use hmac::{Hmac, Mac};
+
+        let sig = signature.ok_or_else(|| anyhow!("Missing Stripe-Signature header"))?;
+        if self.webhook_secret.is_empty() {
+            return Err(anyhow!("Stripe webhook secret is not configured"));
+        }
+
+        let mut timestamp = "";
+        let mut candidates = Vec::new();
+        for part in sig.split(',') {
+            if let Some(val) = part.strip_prefix("t=") {
+                timestamp = val;
+            } else if let Some(val) = part.strip_prefix("v1=") {
+                candidates.push(val);
+            }
+        }
+        let ts: i64 = timestamp.parse().map_err(|_| anyhow!("Missing timestamp in signature"))?;
+        if (Utc::now().timestamp() - ts).abs() > SIGNATURE_TOLERANCE_SECS {
+            return Err(anyhow!("Stripe signature timestamp outside tolerance"));
+        }
+
+        let mut mac = Hmac::<sha2::Sha256>::new_from_slice(self.webhook_secret.as_bytes())
+            .map_err(|e| anyhow!("Invalid HMAC key: {}", e))?;
+        mac.update(format!("{}.{}", timestamp, payload).as_bytes());
+        let expected = hex::encode(mac.finalize().into_bytes());
+
+        if candidates.iter().any(|c| signature_eq(c, &expected)) {
+            Ok(())
+        } else {
+            Err(anyhow!("Invalid Stripe signature"))
+        }
+    }
+
+    async fn session(&self, id: &str) -> Result<serde_json::Value> {
+        let body: serde_json::Value = http().get(format!("{}/checkout/sessions/{}", API, id))
+            .basic_auth(&self.secret_key, None::<&str>)
+            .send()
+            .await?
+            .json()
+            .await?;
+        if body.get("error").is_some() {
+            return Err(anyhow!("Stripe Error: {:?}", body["error"]));
+        }
+        Ok(body)
+    }
+}
+
+#[async_trait]
+impl PaymentProvider for Stripe {
+    fn spec(&self) -> &'static ProviderSpec {
+        &SPEC
+    }
+
+    async fn create_invoice(&self, req: &InvoiceRequest) -> Result<CreatedInvoice> {
+        let currency = req.amount.currency.code().to_lowercase();
+        let unit_amount = req.amount.minor.to_string();
+        let params = [
+            ("mode", "payment"),
+            ("success_url", self.return_url.as_str()),
+            ("cancel_url", self.return_url.as_str()),
+            ("client_reference_id", req.payload.as_str()),
+            ("line_items[0][price_data][currency]", currency.as_str()),
+            ("line_items[0][price_data][product_data][name]", req.description.as_str()),
+            ("line_items[0][price_data][unit_amount]", unit_amount.as_str()),
+            ("line_items[0][quantity]", "1"),
+        ];
+
+        let body: serde_json::Value = http().post(format!("{}/checkout/sessions", API))
+            .basic_auth(&self.secret_key, None::<&str>)
+            .form(&params)
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        match body["url"].as_str() {
+            Some(url) => Ok(CreatedInvoice { external_id: Some(json_id(&body["id"])), url: url.to_string() }),
+            None => Err(anyhow!("Stripe Error: {:?}", body)),
+        }
+    }
+
+    /// Checkout events report the session; refund (`charge.refunded`) and dispute (`charge.dispute.*`)
+    /// events report the payment intent, which `payment_external_id` maps back to its session
+    fn parse_webhook(&self, headers: &HeaderMap, body: &str) -> Result<Option<PaymentNotification>> {
+        self.verify(body, header(headers, "stripe-signature"))?;
+        let body: serde_json::Value = serde_json::from_str(body)?;
+
+        let object = &body["data"]["object"];
+        let currency = json_currency(&object["currency"], SPEC.currency)?;
+        let notification = |external_id: &serde_json::Value, status, amount: &serde_json::Value, reference: Option<String>| {
+            Some(PaymentNotification {
+                external_id: json_id(external_id),
+                status,
+                amount: Money::new(amount.as_i64().unwrap_or(0), currency),
+                payload: object["client_reference_id"].as_str().unwrap_or("").to_string(),
+                reference,
+            })
+        };
+
+        Ok(match body["type"].as_str() {
+            Some("checkout.session.completed") if object["payment_status"].as_str() == Some("paid") => {
+                notification(&object["id"], PaymentStatus::Paid, &object["amount_total"], None)
+            }
+            Some("checkout.session.expired") => notification(&object["id"], PaymentStatus::Expired, &object["amount_total"], None),
+            Some("charge.refunded") => notification(&object["payment_intent"], PaymentStatus::Refunded, &object["amount_refunded"], None),
+            Some("charge.dispute.created") => {
+                notification(&object["payment_intent"], PaymentStatus::Disputed, &object["amount"], Some(json_id(&object["id"])))
+            }
+            Some("charge.dispute.closed") => {
+                let status = match object["status"].as_str() {
+                    Some("won") => PaymentStatus::DisputeWon,
+                    Some("lost") => PaymentStatus::DisputeLost,
+                    _ => return Ok(None),
+                };
+                notification(&object["payment_intent"], status, &object["amount"], Some(json_id(&object["id"])))
+            }
+            _ => None,
+        })
+    }
+
+    async fn query_status(&self, external_id: &str) -> Result<PaymentStatus> {
+        let session = self.session(external_id).await?;
+        Ok(match (session["status"].as_str(), session["payment_status"].as_str()) {
+            (_, Some("paid")) => PaymentStatus::Paid,
+            (Some("expired"), _) => PaymentStatus::Expired,
+            _ => PaymentStatus::Pending,
+        })
+    }
+
+    async fn refund(&self, req: &RefundRequest) -> Result<Option<String>> {
+        let session = self.session(&req.external_id).await?;
+        let intent = session["payment_intent"].as_str()
+            .ok_or_else(|| anyhow!("Stripe session {} has no payment", req.external_id))?;
+
+        let mut params = vec![("payment_intent", intent.to_string())];
+        if !req.full {
+            params.push(("amount", req.amount.minor.to_string()));
+        }
+        let body: serde_json::Value = http().post(format!("{}/refunds", API))
+            .basic_auth(&self.secret_key, None::<&str>)
+            .header("Idempotency-Key", format!("refund-{}", req.refund_id))
+            .form(&params)
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        match body["status"].as_str() {
+            Some("succeeded") | Some("pending") => Ok(Some(json_id(&body["id"]))),
+            _ => Err(anyhow!("Stripe refund failed: {:?}", body)),
+        }
+    }
+
+    async fn payment_external_id(&self, reference: &str) -> Result<String> {
+        if !reference.starts_with("pi_") {
+            return Ok(reference.to_string());
+        }
+        let body: serde_json::Value = http().get(format!("{}/checkout/sessions", API))
+            .basic_auth(&self.secret_key, None::<&str>)
+            .query(&[("payment_intent", reference)])
+            .send()
+            .await?
+            .json()
+            .await?;
+        body["data"][0]["id"].as_str()
+            .map(|id| id.to_string())
+            .ok_or_else(|| anyhow!("No Stripe checkout session for {}", reference))
+    }
+}
@@ -0,0 +1,506 @@
+use anyhow::{anyhow, bail, Result};
+use chrono::Utc;
+use serde::Deserialize;
+use sqlx::{FromRow, SqliteConnection, SqlitePool};
+use tracing::info;
+
+use crate::models::money::{Currency, Money};
+use crate::models::store::{PlanDuration, PromoCode};
+use crate::services::ledger_service::{LedgerService, Posting, Reason};
+
+/// A code with how it has performed
+#[derive(Debug, Clone, FromRow)]
+pub struct PromoCodeStats {
+    #[sqlx(flatten)]
+    pub promo: PromoCode,
+    pub redemptions: i64,
+    /// Redemptions whose discount went into a purchase
+    pub purchases: i64,
+    pub discount_total: i64,
+    pub bonus_total: i64,
+    pub revenue_total: i64,
+}
+
+impl PromoCodeStats {
+    pub fn promo(&self) -> &PromoCode {
+        &self.promo
+    }
+
+    /// Share of redemptions that ended in a purchase, for codes that discount anything
+    pub fn conversion_percent(&self) -> i64 {
+        if self.redemptions == 0 {
+            return 0;
+        }
+        self.purchases * 100 / self.redemptions
+    }
+
+    pub fn discount_total_display(&self) -> String {
+        Money::usd_cents(self.discount_total).to_string()
+    }
+
+    pub fn bonus_total_display(&self) -> String {
+        Money::usd_cents(self.bonus_total).to_string()
+    }
+
+    pub fn revenue_total_display(&self) -> String {
+        Money::usd_cents(self.revenue_total).to_string()
+    }
+}
+
+/// What entering a code did
+#[derive(Debug, Clone)]
+pub struct Redeemed {
+    pub code: String,
+    pub bonus: i64,
+    /// Discount waiting for the next qualifying purchase, e.g. "20%"
+    pub discount: Option<String>,
+}
+
+/// A reserved discount priced against one plan duration
+#[derive(Debug, Clone)]
+pub struct Discount {
+    pub redemption_id: i64,
+    pub code: String,
+    pub amount: i64,
+}
+
+/// Values of the admin form; lists are comma separated
+#[derive(Debug, Deserialize)]
+pub struct PromoCodeForm {
+    pub code: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub discount_percent: String,
+    /// Dollars
+    #[serde(default)]
+    pub discount_amount: String,
+    /// Dollars
+    #[serde(default)]
+    pub bonus_amount: String,
+    #[serde(default)]
+    pub max_uses: String,
+    #[serde(default)]
+    pub max_uses_per_user: String,
+    #[serde(default)]
+    pub plan_ids: String,
+    #[serde(default)]
+    pub duration_days: String,
+    /// `YYYY-MM-DD`, end of that day UTC
+    #[serde(default)]
+    pub expires_at: String,
+    pub first_purchase_only: Option<String>,
+}
+
+fn split_ids(value: &str) -> impl Iterator<Item = i64> + '_ {
+    value.split([',', '\n']).filter_map(|s| s.trim().parse().ok())
+}
+
+fn normalize_ids(value: &str) -> String {
+    split_ids(value).map(|id| id.to_string()).collect::<Vec<_>>().join(",")
+}
+
+fn parse_cents(value: &str) -> Result<i64> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(0);
+    }
+    Money::parse(value, Currency::Usd)
+        .map(|m| m.minor)
+        .filter(|cents| *cents >= 0)
+        .ok_or_else(|| anyhow!("Invalid amount: {}", value))
+}
+
+fn parse_count(value: &str, default: i64) -> Result<i64> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(default);
+    }
+    value.parse::<i64>().ok().filter(|n| *n >= 0).ok_or_else(|| anyhow!("Invalid number: {}", value))
+}
+
+fn amount_input(cents: i64) -> String {
+    if cents == 0 { String::new() } else { Money::usd_cents(cents).to_decimal_string() }
+}
+
+impl PromoCode {
+    pub fn discounts(&self) -> bool {
+        self.discount_percent > 0 || self.discount_amount > 0
+    }
+
+    /// Whether the code's plan and duration restrictions allow this duration
+    pub fn applies_to(&self, duration: &PlanDuration) -> bool {
+        let plan_ok = self.plan_ids.trim().is_empty() || split_ids(&self.plan_ids).any(|id| id == duration.plan_id);
+        let days_ok = self.duration_days.trim().is_empty()
+            || split_ids(&self.duration_days).any(|days| days == duration.duration_days as i64);
+        plan_ok && days_ok
+    }
+
+    /// Cents off `price`: the percentage first, then the fixed amount, never below zero
+    pub fn discount_on(&self, price: i64) -> i64 {
+        let percent = price * i64::from(self.discount_percent.clamp(0, 100)) / 100;
+        (percent + self.discount_amount).min(price)
+    }
+
+    /// The discount part, e.g. "20% + $1.00"; empty without one
+    pub fn discount_label(&self) -> String {
+        let mut off = Vec::new();
+        if self.discount_percent > 0 {
+            off.push(format!("{}%", self.discount_percent));
+        }
+        if self.discount_amount > 0 {
+            off.push(Money::usd_cents(self.discount_amount).to_string());
+        }
+        off.join(" + ")
+    }
+
+    /// What the code gives, e.g. "20% + $1.00 off, $5.00 bonus"
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if self.discounts() {
+            parts.push(format!("{} off", self.discount_label()));
+        }
+        if self.bonus_amount > 0 {
+            parts.push(format!("{} bonus", Money::usd_cents(self.bonus_amount)));
+        }
+        parts.join(", ")
+    }
+
+    /// Amounts as the admin form shows them, empty when zero
+    pub fn discount_amount_input(&self) -> String {
+        amount_input(self.discount_amount)
+    }
+
+    pub fn bonus_amount_input(&self) -> String {
+        amount_input(self.bonus_amount)
+    }
+
+    pub fn expires_input(&self) -> String {
+        self.expires_at.map(|at| at.format("%Y-%m-%d").to_string()).unwrap_or_default()
+    }
+}
+
+/// Promo codes: admins manage them, users redeem them in the bot, and plan purchases
+/// consume the reserved discount on the purchase's own transaction.
+pub struct PromoService {
+    pool: SqlitePool,
+}
+
+impl PromoService {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn list_with_stats(&self) -> Result<Vec<PromoCodeStats>> {
+        Ok(sqlx::query_as::<_, PromoCodeStats>(
+            "SELECT p.*,
+                    COUNT(r.id) AS redemptions,
+                    COUNT(r.subscription_id) AS purchases,
+                    COALESCE(SUM(r.discount_amount), 0) AS discount_total,
+                    COALESCE(SUM(r.bonus_amount), 0) AS bonus_total,
+                    COALESCE(SUM(r.purchase_amount), 0) AS revenue_total
+             FROM promo_codes p
+             LEFT JOIN promo_redemptions r ON r.promo_code_id = p.id
+             GROUP BY p.id
+             ORDER BY p.is_active DESC, p.created_at DESC"
+        )
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    /// Insert (id = None) or update a code
+    pub async fn save(&self, id: Option<i64>, form: &PromoCodeForm, actor: &str) -> Result<i64> {
+        let code = form.code.trim().to_uppercase();
+        if code.is_empty() || code.contains(char::is_whitespace) {
+            bail!("Code is required and may not contain spaces");
+        }
+        if code.starts_with("EXA-GIFT-") {
+            bail!("EXA-GIFT- is reserved for gift codes");
+        }
+        let discount_percent = parse_count(&form.discount_percent, 0)?;
+        if discount_percent > 100 {
+            bail!("Discount can't exceed 100%");
+        }
+        let discount_amount = parse_cents(&form.discount_amount)?;
+        let bonus_amount = parse_cents(&form.bonus_amount)?;
+        if discount_percent == 0 && discount_amount == 0 && bonus_amount == 0 {
+            bail!("A code needs a discount or a bonus");
+        }
+        let expires_at = match form.expires_at.trim() {
+            "" => None,
+            date => Some(
+                chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                    .map_err(|_| anyhow!("Invalid expiry date: {}", date))?
+                    .and_hms_opt(23, 59, 59)
+                    .expect("valid time")
+                    .and_utc(),
+            ),
+        };
+
+        let query = match id {
+            Some(_) => {
+                "UPDATE promo_codes SET code = ?, description = ?, discount_percent = ?, discount_amount = ?, bonus_amount = ?,
+                 max_uses = ?, max_uses_per_user = ?, plan_ids = ?, duration_days = ?, first_purchase_only = ?, expires_at = ?
+                 WHERE id = ? RETURNING id"
+            }
+            None => {
+                "INSERT INTO promo_codes (code, description, discount_percent, discount_amount, bonus_amount,
+                 max_uses, max_uses_per_user, plan_ids, duration_days, first_purchase_only, expires_at, created_by)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?) RETURNING id"
+            }
+        };
+        let mut q = sqlx::query_scalar::<_, i64>(query)
+            .bind(&code)
+            .bind(Some(form.description.trim()).filter(|d| !d.is_empty()))
+            .bind(discount_percent)
+            .bind(discount_amount)
+            .bind(bonus_amount)
+            .bind(parse_count(&form.max_uses, 0)?)
+            .bind(parse_count(&form.max_uses_per_user, 1)?)
+            .bind(normalize_ids(&form.plan_ids))
+            .bind(normalize_ids(&form.duration_days))
+            .bind(form.first_purchase_only.is_some())
+            .bind(expires_at);
+        q = match id {
+            Some(id) => q.bind(id),
+            None => q.bind(actor),
+        };
+
+        q.fetch_optional(&self.pool)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::Database(db) if db.is_unique_violation() => anyhow!("Code {} already exists", code),
+                e => e.into(),
+            })?
+            .ok_or_else(|| anyhow!("Promo code not found"))
+    }
+
+    pub async fn set_active(&self, id: i64, active: bool) -> Result<()> {
+        sqlx::query("UPDATE promo_codes SET is_active = ? WHERE id = ?")
+            .bind(active)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        if !active {
+            sqlx::query("UPDATE promo_redemptions SET status = 'released' WHERE promo_code_id = ? AND status = 'reserved'")
+                .bind(id)
+                .execute(&self.pool)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Codes nobody redeemed are deleted; the others only deactivated, to keep their history
+    pub async fn delete(&self, id: i64) -> Result<bool> {
+        let used: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM promo_redemptions WHERE promo_code_id = ?")
+            .bind(id)
+            .fetch_one(&self.pool)
+            .await?;
+        if used > 0 {
+            self.set_active(id, false).await?;
+            return Ok(false);
+        }
+        sqlx::query("DELETE FROM promo_codes WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(true)
+    }
+
+    /// The code a user has waiting for their next purchase
+    pub async fn reserved(&self, user_id: i64) -> Result<Option<PromoCode>> {
+        Ok(sqlx::query_as::<_, PromoCode>(
+            "SELECT p.* FROM promo_redemptions r JOIN promo_codes p ON p.id = r.promo_code_id
+             WHERE r.user_id = ? AND r.status = 'reserved' AND p.is_active = 1
+               AND (p.expires_at IS NULL OR p.expires_at > CURRENT_TIMESTAMP)
+             ORDER BY r.id DESC LIMIT 1"
+        )
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    /// Enter a code: the bonus is credited now, the discount reserved for the next plan purchase
+    /// (replacing one reserved before). Each entry counts against the code's usage caps.
+    pub async fn redeem(&self, user_id: i64, code: &str) -> Result<Redeemed> {
+        let mut tx = self.pool.begin().await?;
+
+        let promo = sqlx::query_as::<_, PromoCode>(
+            "SELECT * FROM promo_codes WHERE code = ? AND is_active = 1"
+        )
+        .bind(code.trim())
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| anyhow!("Unknown promo code"))?;
+
+        if promo.expires_at.is_some_and(|at| at <= Utc::now()) {
+            bail!("This promo code has expired");
+        }
+        if promo.max_uses > 0 && promo.current_uses >= promo.max_uses {
+            bail!("This promo code has been used up");
+        }
+        if promo.max_uses_per_user > 0 {
+            let used: i64 = sqlx::query_scalar(
+                "SELECT COUNT(*) FROM promo_redemptions WHERE promo_code_id = ? AND user_id = ?"
+            )
+            .bind(promo.id)
+            .bind(user_id)
+            .fetch_one(&mut *tx)
+            .await?;
+            if used >= promo.max_uses_per_user {
+                bail!("You have already used this promo code");
+            }
+        }
+        if promo.first_purchase_only && Self::has_purchased(&mut tx, user_id).await? {
+            bail!("This promo code is for first purchases only");
+        }
+
+        // Counted with a guard, so concurrent redemptions can't overshoot the cap
+        let claimed = sqlx::query(
+            "UPDATE promo_codes SET current_uses = current_uses + 1 WHERE id = ? AND (max_uses = 0 OR current_uses < max_uses)"
+        )
+        .bind(promo.id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        if claimed == 0 {
+            bail!("This promo code has been used up");
+        }
+
+        let discount_reserved = promo.discounts();
+        if discount_reserved {
+            sqlx::query("UPDATE promo_redemptions SET status = 'released' WHERE user_id = ? AND status = 'reserved'")
+                .bind(user_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        let redemption_id: i64 = sqlx::query_scalar(
+            "INSERT INTO promo_redemptions (promo_code_id, user_id, status, bonus_amount, applied_at)
+             VALUES (?, ?, ?, ?, CASE WHEN ? THEN NULL ELSE CURRENT_TIMESTAMP END) RETURNING id"
+        )
+        .bind(promo.id)
+        .bind(user_id)
+        .bind(if discount_reserved { "reserved" } else { "applied" })
+        .bind(promo.bonus_amount)
+        .bind(discount_reserved)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        LedgerService::post(&mut tx, Posting::credit(user_id, promo.bonus_amount, Reason::Promo)
+            .reference("promo", redemption_id)
+            .actor("user")
+            .note(format!("Promo code {}", promo.code))).await?;
+
+        tx.commit().await?;
+        info!("User {} redeemed promo code {}", user_id, promo.code);
+
+        Ok(Redeemed {
+            code: promo.code.clone(),
+            bonus: promo.bonus_amount,
+            discount: discount_reserved.then(|| promo.discount_label()),
+        })
+    }
+
+    /// Whether the user ever bought a plan. The ledger only covers purchases since it was
+    /// introduced, so any non-trial subscription counts as well.
+    async fn has_purchased(conn: &mut SqliteConnection, user_id: i64) -> Result<bool> {
+        let purchased: bool = sqlx::query_scalar(
+            "SELECT EXISTS(SELECT 1 FROM balance_ledger WHERE user_id = ? AND reason IN ('purchase', 'renewal') AND ref_type = 'subscription')
+                 OR EXISTS(SELECT 1 FROM subscriptions WHERE user_id = ? AND COALESCE(is_trial, 0) = 0)"
+        )
+        .bind(user_id)
+        .bind(user_id)
+        .fetch_one(&mut *conn)
+        .await?;
+        Ok(purchased)
+    }
+
+    /// The user's reserved discount priced for `duration`, if the code still allows it
+    pub async fn discount_for(conn: &mut SqliteConnection, user_id: i64, duration: &PlanDuration) -> Result<Option<Discount>> {
+        let reserved = sqlx::query_as::<_, (i64, i64)>(
+            "SELECT r.id, p.id FROM promo_redemptions r JOIN promo_codes p ON p.id = r.promo_code_id
+             WHERE r.user_id = ? AND r.status = 'reserved' AND p.is_active = 1
+               AND (p.expires_at IS NULL OR p.expires_at > CURRENT_TIMESTAMP)
+             ORDER BY r.id DESC LIMIT 1"
+        )
+        .bind(user_id)
+        .fetch_optional(&mut *conn)
+        .await?;
+        let Some((redemption_id, promo_id)) = reserved else {
+            return Ok(None);
+        };
+
+        let promo = sqlx::query_as::<_, PromoCode>("SELECT * FROM promo_codes WHERE id = ?")
+            .bind(promo_id)
+            .fetch_one(&mut *conn)
+            .await?;
+        if !promo.applies_to(duration) || (promo.first_purchase_only && Self::has_purchased(conn, user_id).await?) {
+            return Ok(None);
+        }
+
+        Ok(Some(Discount { redemption_id, code: promo.code.clone(), amount: promo.discount_on(duration.price) }))
+    }
+
+    /// Mark a reserved discount as spent on a purchase
+    pub async fn apply(conn: &mut SqliteConnection, discount: &Discount, charged: i64, subscription_id: i64) -> Result<()> {
+        sqlx::query(
+            "UPDATE promo_redemptions
+             SET status = 'applied', discount_amount = ?, purchase_amount = ?, subscription_id = ?, applied_at = CURRENT_TIMESTAMP
+             WHERE id = ? AND status = 'reserved'"
+        )
+        .bind(discount.amount)
+        .bind(charged)
+        .bind(subscription_id)
+        .bind(discount.redemption_id)
+        .execute(&mut *conn)
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn promo(percent: i32, amount: i64, plan_ids: &str, duration_days: &str) -> PromoCode {
+        PromoCode {
+            id: 1,
+            code: "SPRING".to_string(),
+            description: None,
+            discount_percent: percent,
+            discount_amount: amount,
+            bonus_amount: 0,
+            max_uses: 0,
+            max_uses_per_user: 1,
+            current_uses: 0,
+            plan_ids: plan_ids.to_string(),
+            duration_days: duration_days.to_string(),
+            first_purchase_only: false,
+            is_active: true,
+            expires_at: None,
+            created_by: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    fn duration(plan_id: i64, days: i32) -> PlanDuration {
+        PlanDuration { id: 1, plan_id, duration_days: days, price: 1000, created_at: Utc::now() }
+    }
+
+    #[test]
+    fn test_discount_on() {
+        assert_eq!(promo(20, 0, "", "").discount_on(999), 199);
+        assert_eq!(promo(10, 150, "", "").discount_on(1000), 250);
+        assert_eq!(promo(0, 5000, "", "").discount_on(1000), 1000);
+        assert_eq!(promo(150, 0, "", "").discount_on(1000), 1000);
+    }
+
+    #[test]
+    fn test_applies_to() {
+        assert!(promo(10, 0, "", "").applies_to(&duration(3, 30)));
+        assert!(promo(10, 0, "2,3", "").applies_to(&duration(3, 30)));
+        assert!(!promo(10, 0, "2", "").applies_to(&duration(3, 30)));
+        assert!(promo(10, 0, "", "90, 180").applies_to(&duration(3, 90)));
+        assert!(!promo(10, 0, "3", "90").applies_to(&duration(3, 30)));
+    }
+}
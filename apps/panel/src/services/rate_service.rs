@@ -0,0 +1,137 @@
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use sqlx::{FromRow, SqlitePool};
+use std::collections::HashMap;
+use tokio::sync::RwLock;
+use tokio::time::{interval, Duration};
+use tracing::{error, info, warn};
+
+use crate::models::money::{Currency, Money};
+
+/// Free, keyless USD reference rates; only fiat currencies are taken from it.
+/// USDT is pegged and Stars are priced by Telegram, so those stay admin-managed.
+const RATES_API: &str = "https://open.er-api.com/v6/latest/USD";
+const FETCHED: [Currency; 2] = [Currency::Eur, Currency::Rub];
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct ExchangeRate {
+    pub currency: String,
+    pub per_usd: f64,
+    pub manual_per_usd: Option<f64>,
+    pub source: String,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl ExchangeRate {
+    pub fn effective(&self) -> f64 {
+        self.manual_per_usd.unwrap_or(self.per_usd)
+    }
+}
+
+/// Exchange rates against USD, cached in memory. Fetched periodically; an admin override wins.
+pub struct RateService {
+    pool: SqlitePool,
+    cache: RwLock<HashMap<Currency, f64>>,
+}
+
+impl RateService {
+    pub async fn new(pool: SqlitePool) -> Result<Self> {
+        let service = Self { pool, cache: RwLock::new(HashMap::new()) };
+        service.reload().await?;
+        Ok(service)
+    }
+
+    async fn reload(&self) -> Result<()> {
+        let mut rates = HashMap::new();
+        for rate in self.list().await? {
+            if let Some(currency) = Currency::parse(&rate.currency)
+                && rate.effective() > 0.0 {
+                rates.insert(currency, rate.effective());
+            }
+        }
+        rates.insert(Currency::Usd, 1.0);
+        *self.cache.write().await = rates;
+        Ok(())
+    }
+
+    pub async fn list(&self) -> Result<Vec<ExchangeRate>> {
+        Ok(sqlx::query_as::<_, ExchangeRate>("SELECT * FROM exchange_rates ORDER BY currency = 'USD' DESC, currency")
+            .fetch_all(&self.pool)
+            .await?)
+    }
+
+    /// Pin a rate, or go back to the fetched value with `None`
+    pub async fn set_manual(&self, currency: Currency, per_usd: Option<f64>) -> Result<()> {
+        if currency == Currency::Usd {
+            return Err(anyhow!("USD is the base currency"));
+        }
+        if per_usd.is_some_and(|r| !(r.is_finite() && r > 0.0)) {
+            return Err(anyhow!("Rate must be a positive number"));
+        }
+        sqlx::query(
+            "INSERT INTO exchange_rates (currency, per_usd, manual_per_usd) VALUES (?, COALESCE(?, 1.0), ?)
+             ON CONFLICT(currency) DO UPDATE SET manual_per_usd = excluded.manual_per_usd, updated_at = CURRENT_TIMESTAMP"
+        )
+        .bind(currency.code())
+        .bind(per_usd)
+        .bind(per_usd)
+        .execute(&self.pool)
+        .await?;
+        self.reload().await
+    }
+
+    pub async fn per_usd(&self, currency: Currency) -> Result<f64> {
+        self.cache.read().await.get(&currency).copied()
+            .ok_or_else(|| anyhow!("No exchange rate for {}", currency))
+    }
+
+    /// Convert at the current rate, rounding to the nearest minor unit of `to`
+    pub async fn convert(&self, amount: Money, to: Currency) -> Result<Money> {
+        if amount.currency == to {
+            return Ok(amount);
+        }
+        let from_rate = self.per_usd(amount.currency).await?;
+        let to_rate = self.per_usd(to).await?;
+        let major = amount.minor as f64 / 10f64.powi(amount.currency.exponent() as i32);
+        let converted = major / from_rate * to_rate * 10f64.powi(to.exponent() as i32);
+        Ok(Money::new(converted.round() as i64, to))
+    }
+
+    pub async fn start(&self) {
+        info!("Starting exchange rate updater...");
+        let mut interval = interval(Duration::from_secs(6 * 3600)); // Every 6 hours
+
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.refresh().await {
+                warn!("Failed to fetch exchange rates: {}", e);
+            }
+        }
+    }
+
+    pub async fn refresh(&self) -> Result<()> {
+        let body: serde_json::Value = reqwest::Client::new()
+            .get(RATES_API)
+            .timeout(std::time::Duration::from_secs(20))
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        for currency in FETCHED {
+            let Some(rate) = body["rates"][currency.code()].as_f64().filter(|r| *r > 0.0) else {
+                error!("Rates API has no {} rate", currency);
+                continue;
+            };
+            sqlx::query(
+                "INSERT INTO exchange_rates (currency, per_usd, source) VALUES (?, ?, 'api')
+                 ON CONFLICT(currency) DO UPDATE SET per_usd = excluded.per_usd, source = 'api', updated_at = CURRENT_TIMESTAMP"
+            )
+            .bind(currency.code())
+            .bind(rate)
+            .execute(&self.pool)
+            .await?;
+        }
+        self.reload().await
+    }
+}
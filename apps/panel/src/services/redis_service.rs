@@ -1,6 +1,9 @@
 use anyhow::{Context, Result};
 use bb8_redis::{bb8, RedisConnectionManager};
 use tracing::info;
+use exarobot_shared::api::{ConnectionSnapshot, NodeLogLine};
+
+const NODE_LOGS_MAX: usize = 1000;
 
 #[derive(Clone)]
 pub struct RedisService {
@@ -74,9 +77,101 @@ impl RedisService {
         self.get(&key).await
     }
 
+    /// Drops every cached variant (client format, node, routing preset) of a subscription
     pub async fn invalidate_subscription(&self, sub_uuid: &str) -> Result<()> {
-        let key = format!("sub_config:{}", sub_uuid);
-        self.del(&key).await
+        self.del(&format!("sub_config:{}", sub_uuid)).await?;
+        self.del_matching(&format!("sub_config:{}:*", sub_uuid)).await
+    }
+
+    /// DEL every key matching a glob pattern (SCAN, so Redis is not blocked)
+    pub async fn del_matching(&self, pattern: &str) -> Result<()> {
+        let mut conn = self.pool.get().await.context("Failed to get Redis connection")?;
+        let mut cursor: u64 = 0;
+        loop {
+            let (next, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(pattern)
+                .arg("COUNT")
+                .arg(100)
+                .query_async(&mut *conn)
+                .await
+                .context("Redis SCAN failed")?;
+            if !keys.is_empty() {
+                let _: () = redis::cmd("DEL")
+                    .arg(&keys)
+                    .query_async(&mut *conn)
+                    .await
+                    .context("Redis DEL failed")?;
+            }
+            if next == 0 {
+                return Ok(());
+            }
+            cursor = next;
+        }
+    }
+
+    // --- Node Logs ---
+
+    /// Append shipped agent log lines, assigning panel-side sequence numbers
+    /// (agent counters reset on restart). Keeps the newest NODE_LOGS_MAX lines.
+    pub async fn append_node_logs(&self, node_id: i64, lines: &[NodeLogLine], ttl_seconds: usize) -> Result<()> {
+        if lines.is_empty() {
+            return Ok(());
+        }
+        let mut conn = self.pool.get().await.context("Failed to get Redis connection")?;
+        let key = format!("node_logs:{}", node_id);
+        let seq_key = format!("node_logs_seq:{}", node_id);
+
+        let last: u64 = redis::cmd("INCRBY")
+            .arg(&seq_key)
+            .arg(lines.len())
+            .query_async(&mut *conn)
+            .await
+            .context("Redis INCRBY failed")?;
+        let first = last + 1 - lines.len() as u64;
+
+        let mut push = redis::cmd("RPUSH");
+        push.arg(&key);
+        for (i, line) in lines.iter().enumerate() {
+            let mut line = line.clone();
+            line.seq = first + i as u64;
+            push.arg(serde_json::to_string(&line)?);
+        }
+
+        let _: () = redis::pipe()
+            .add_command(push).ignore()
+            .cmd("LTRIM").arg(&key).arg(-(NODE_LOGS_MAX as i64)).arg(-1).ignore()
+            .cmd("EXPIRE").arg(&key).arg(ttl_seconds).ignore()
+            .cmd("EXPIRE").arg(&seq_key).arg(ttl_seconds).ignore()
+            .query_async(&mut *conn)
+            .await
+            .context("Redis log append failed")?;
+        Ok(())
+    }
+
+    pub async fn get_node_logs(&self, node_id: i64) -> Result<Vec<NodeLogLine>> {
+        let mut conn = self.pool.get().await.context("Failed to get Redis connection")?;
+        let raw: Vec<String> = redis::cmd("LRANGE")
+            .arg(format!("node_logs:{}", node_id))
+            .arg(0)
+            .arg(-1)
+            .query_async(&mut *conn)
+            .await
+            .context("Redis LRANGE failed")?;
+        Ok(raw.iter().filter_map(|l| serde_json::from_str(l).ok()).collect())
+    }
+
+    // --- Node Connections ---
+
+    /// Latest per-user connection snapshot pushed by a node's agent
+    pub async fn set_node_connections(&self, node_id: i64, snapshot: &ConnectionSnapshot, ttl_seconds: usize) -> Result<()> {
+        self.set(&format!("node_conns:{}", node_id), &serde_json::to_string(snapshot)?, ttl_seconds).await
+    }
+
+    pub async fn get_node_connections(&self, node_id: i64) -> Result<Option<ConnectionSnapshot>> {
+        Ok(self.get(&format!("node_conns:{}", node_id)).await?
+            .and_then(|raw| serde_json::from_str(&raw).ok()))
     }
 
     // --- Rate Limiting ---
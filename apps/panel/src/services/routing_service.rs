@@ -0,0 +1,238 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, SqlitePool};
+use tracing::warn;
+
+use crate::singbox::routing::{RoutingPolicy, RuleSetRef};
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct RoutingPreset {
+    pub id: i64,
+    pub code: String,
+    pub name: String,
+    pub direct_geosites: String,
+    pub direct_geoips: String,
+    pub bypass_domains: String,
+    pub remote_dns: String,
+    pub local_dns: Option<String>,
+    pub block_ads: bool,
+    pub is_default: bool,
+}
+
+impl RoutingPreset {
+    pub fn policy(&self) -> RoutingPolicy {
+        let mut direct: Vec<RuleSetRef> = split_list(&self.direct_geosites).map(RuleSetRef::geosite).collect();
+        direct.extend(split_list(&self.direct_geoips).map(RuleSetRef::geoip));
+
+        RoutingPolicy {
+            direct,
+            bypass_domains: split_list(&self.bypass_domains).map(|d| d.trim_start_matches('.').to_string()).collect(),
+            remote_dns: self.remote_dns.clone(),
+            local_dns: self.local_dns.clone().filter(|d| !d.trim().is_empty()),
+            block_ads: self.block_ads,
+        }
+    }
+}
+
+/// Values of the admin form; lists are comma or newline separated
+#[derive(Debug, Deserialize)]
+pub struct RoutingPresetForm {
+    pub code: String,
+    pub name: String,
+    #[serde(default)]
+    pub direct_geosites: String,
+    #[serde(default)]
+    pub direct_geoips: String,
+    #[serde(default)]
+    pub bypass_domains: String,
+    pub remote_dns: String,
+    #[serde(default)]
+    pub local_dns: String,
+    pub block_ads: Option<String>,
+    pub is_default: Option<String>,
+}
+
+fn split_list(value: &str) -> impl Iterator<Item = &str> {
+    value.split([',', '\n']).map(str::trim).filter(|s| !s.is_empty())
+}
+
+fn normalize_list(value: &str) -> String {
+    split_list(value).map(|s| s.to_lowercase()).collect::<Vec<_>>().join(",")
+}
+
+/// Preset code for a Telegram language, used when neither the user nor the plan picked one
+fn region_for_language(language_code: &str) -> Option<&'static str> {
+    match language_code.split(['-', '_']).next()?.to_ascii_lowercase().as_str() {
+        "ru" | "be" => Some("ru"),
+        "fa" => Some("ir"),
+        "zh" => Some("cn"),
+        _ => None,
+    }
+}
+
+/// Named client routing presets and which one applies to a subscriber
+pub struct RoutingService {
+    pool: SqlitePool,
+}
+
+impl RoutingService {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn list(&self) -> Result<Vec<RoutingPreset>> {
+        Ok(sqlx::query_as::<_, RoutingPreset>("SELECT * FROM routing_presets ORDER BY is_default DESC, name ASC")
+            .fetch_all(&self.pool)
+            .await?)
+    }
+
+    pub async fn get(&self, id: i64) -> Result<Option<RoutingPreset>> {
+        Ok(sqlx::query_as::<_, RoutingPreset>("SELECT * FROM routing_presets WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?)
+    }
+
+    /// Insert (id = None) or update a preset. Marking one as default unmarks the others.
+    pub async fn save(&self, id: Option<i64>, form: &RoutingPresetForm) -> Result<i64> {
+        let code = form.code.trim().to_lowercase();
+        if code.is_empty() || form.name.trim().is_empty() || form.remote_dns.trim().is_empty() {
+            anyhow::bail!("Code, name and remote DNS are required");
+        }
+        let local_dns = Some(form.local_dns.trim()).filter(|d| !d.is_empty());
+        let is_default = form.is_default.is_some();
+
+        let mut tx = self.pool.begin().await?;
+        if is_default {
+            sqlx::query("UPDATE routing_presets SET is_default = 0").execute(&mut *tx).await?;
+        }
+
+        let id = match id {
+            Some(id) => {
+                sqlx::query(
+                    "UPDATE routing_presets SET code = ?, name = ?, direct_geosites = ?, direct_geoips = ?, bypass_domains = ?,
+                     remote_dns = ?, local_dns = ?, block_ads = ?, is_default = ? WHERE id = ?"
+                )
+                .bind(&code)
+                .bind(form.name.trim())
+                .bind(normalize_list(&form.direct_geosites))
+                .bind(normalize_list(&form.direct_geoips))
+                .bind(normalize_list(&form.bypass_domains))
+                .bind(form.remote_dns.trim())
+                .bind(local_dns)
+                .bind(form.block_ads.is_some())
+                .bind(is_default)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+                id
+            }
+            None => {
+                sqlx::query_scalar(
+                    "INSERT INTO routing_presets (code, name, direct_geosites, direct_geoips, bypass_domains, remote_dns, local_dns, block_ads, is_default)
+                     VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?) RETURNING id"
+                )
+                .bind(&code)
+                .bind(form.name.trim())
+                .bind(normalize_list(&form.direct_geosites))
+                .bind(normalize_list(&form.direct_geoips))
+                .bind(normalize_list(&form.bypass_domains))
+                .bind(form.remote_dns.trim())
+                .bind(local_dns)
+                .bind(form.block_ads.is_some())
+                .bind(is_default)
+                .fetch_one(&mut *tx)
+                .await?
+            }
+        };
+
+        tx.commit().await?;
+        Ok(id)
+    }
+
+    /// Users and plans pointing at it fall back to automatic selection
+    pub async fn delete(&self, id: i64) -> Result<()> {
+        sqlx::query("DELETE FROM routing_presets WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// `None` returns the user to automatic selection
+    pub async fn set_user_preset(&self, user_id: i64, preset_id: Option<i64>) -> Result<()> {
+        sqlx::query("UPDATE users SET routing_preset_id = ? WHERE id = ?")
+            .bind(preset_id)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn user_preset_id(&self, user_id: i64) -> Result<Option<i64>> {
+        Ok(sqlx::query_scalar("SELECT routing_preset_id FROM users WHERE id = ?")
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await?
+            .flatten())
+    }
+
+    /// Preset for a subscriber: the user's own choice, then the plan's, then the region
+    /// guessed from the Telegram language, then the default preset
+    pub async fn resolve(&self, user_id: i64, plan_id: Option<i64>) -> Result<Option<RoutingPreset>> {
+        let (user_preset, language_code): (Option<i64>, Option<String>) =
+            sqlx::query_as("SELECT routing_preset_id, language_code FROM users WHERE id = ?")
+                .bind(user_id)
+                .fetch_optional(&self.pool)
+                .await?
+                .unwrap_or((None, None));
+
+        if let Some(preset) = self.get_opt(user_preset).await? {
+            return Ok(Some(preset));
+        }
+
+        if let Some(plan_id) = plan_id {
+            let plan_preset: Option<i64> = sqlx::query_scalar("SELECT routing_preset_id FROM plans WHERE id = ?")
+                .bind(plan_id)
+                .fetch_optional(&self.pool)
+                .await?
+                .flatten();
+            if let Some(preset) = self.get_opt(plan_preset).await? {
+                return Ok(Some(preset));
+            }
+        }
+
+        if let Some(code) = language_code.as_deref().and_then(region_for_language) {
+            let preset = sqlx::query_as::<_, RoutingPreset>("SELECT * FROM routing_presets WHERE code = ?")
+                .bind(code)
+                .fetch_optional(&self.pool)
+                .await?;
+            if preset.is_some() {
+                return Ok(preset);
+            }
+        }
+
+        Ok(sqlx::query_as::<_, RoutingPreset>("SELECT * FROM routing_presets WHERE is_default = 1 LIMIT 1")
+            .fetch_optional(&self.pool)
+            .await?)
+    }
+
+    /// Routing policy to render into the subscriber's client profiles
+    pub async fn policy_for(&self, user_id: i64, plan_id: Option<i64>) -> RoutingPolicy {
+        match self.resolve(user_id, plan_id).await {
+            Ok(Some(preset)) => preset.policy(),
+            Ok(None) => RoutingPolicy::global(),
+            Err(e) => {
+                warn!("Failed to resolve routing preset for user {}: {:#}", user_id, e);
+                RoutingPolicy::global()
+            }
+        }
+    }
+
+    async fn get_opt(&self, id: Option<i64>) -> Result<Option<RoutingPreset>> {
+        match id {
+            Some(id) => self.get(id).await,
+            None => Ok(None),
+        }
+    }
+}
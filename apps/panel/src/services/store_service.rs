@@ -1,7 +1,10 @@
 use sqlx::SqlitePool;
 use anyhow::{Context, Result};
-use crate::models::store::{User, Plan, Subscription, GiftCode, PlanDuration};
-use chrono::{Utc, Duration};
+use crate::models::store::{User, Plan, Subscription, GiftCode, PlanDuration, TrafficPack};
+use crate::models::money::Money;
+use crate::services::ledger_service::{LedgerService, Posting, Reason};
+use crate::services::promo_service::{Discount, PromoService};
+use chrono::{DateTime, Months, Utc, Duration};
 use tracing::{info, error};
 use uuid::Uuid;
 use serde::{Serialize, Deserialize};
@@ -9,10 +12,72 @@ use serde::{Serialize, Deserialize};
 // Quick Wins enums
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum RenewalResult {
-    Success { user_id: i64, sub_id: i64, amount: i64, plan_name: String },
-    InsufficientFunds { user_id: i64, sub_id: i64, required: i64, available: i64 },
+    /// `quota_change` is set when the new period moved the subscription out of (or into) `limited`
+    Success {
+        user_id: i64,
+        sub_id: i64,
+        amount: i64,
+        plan_name: String,
+        days: i32,
+        expires_at: chrono::DateTime<chrono::Utc>,
+        quota_change: Option<QuotaChange>,
+    },
+    /// `next_attempt` is None once the retries are used up; the service stops at `service_until`
+    InsufficientFunds {
+        user_id: i64,
+        sub_id: i64,
+        required: i64,
+        available: i64,
+        next_attempt: Option<chrono::DateTime<chrono::Utc>>,
+        service_until: chrono::DateTime<chrono::Utc>,
+    },
+    /// The plan no longer sells any duration; auto-renewal was switched off
+    Unavailable { user_id: i64, sub_id: i64, plan_name: String },
 }
 
+/// When renewals are attempted and how long an unpaid subscription keeps working
+#[derive(Debug, Clone, Copy)]
+pub struct RenewalPolicy {
+    pub grace_hours: i64,
+    pub retry_hours: i64,
+    pub max_attempts: i64,
+}
+
+impl RenewalPolicy {
+    pub async fn load(settings: &crate::settings::SettingsService) -> Self {
+        Self {
+            grace_hours: settings.get_or_default("renewal_grace_hours", "24").await.parse().unwrap_or(24).max(0),
+            retry_hours: settings.get_or_default("renewal_retry_hours", "6").await.parse().unwrap_or(6).max(1),
+            max_attempts: settings.get_or_default("renewal_max_attempts", "4").await.parse().unwrap_or(4).max(1),
+        }
+    }
+}
+
+/// A subscription whose quota moved it between `active` and `limited`
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct QuotaChange {
+    pub sub_id: i64,
+    pub user_id: i64,
+    pub plan_id: i64,
+    /// Quota used up; false when the subscription has traffic again
+    pub limited: bool,
+}
+
+impl QuotaChange {
+    pub fn status(&self) -> &'static str {
+        if self.limited { "limited" } else { "active" }
+    }
+}
+
+const GIB: i64 = 1024 * 1024 * 1024;
+
+/// Start of the ledger note of a traffic pack, which is charged to the subscription but buys no time
+const TRAFFIC_PACK_NOTE: &str = "Traffic pack";
+
+/// True for a `subscriptions` row that used its plan's quota plus add-on packs this cycle
+const QUOTA_EXHAUSTED: &str = "EXISTS (SELECT 1 FROM plans p WHERE p.id = subscriptions.plan_id AND p.traffic_limit_gb > 0
+    AND subscriptions.used_traffic >= p.traffic_limit_gb * 1073741824 + subscriptions.traffic_extra_bytes)";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum AlertType {
     Traffic80,
@@ -34,6 +99,22 @@ pub struct DetailedReferral {
     pub total_earned: i64,
 }
 
+/// What moving a subscription to another plan's duration costs right now
+#[derive(Debug, Clone, Serialize)]
+pub struct PlanChangeQuote {
+    pub subscription_id: i64,
+    pub plan_id: i64,
+    pub plan_name: String,
+    pub duration_id: i64,
+    pub days: i32,
+    pub price: i64,
+    /// Unused value of the current subscription, credited back
+    pub credit: i64,
+    /// `price - credit`; negative when the switch leaves money on the balance
+    pub due: i64,
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SubscriptionWithDetails {
     #[serde(flatten)]
@@ -41,6 +122,25 @@ pub struct SubscriptionWithDetails {
     pub plan_name: String,
     pub plan_description: Option<String>,
     pub traffic_limit_gb: Option<i32>,
+    pub traffic_reset_period: String,
+}
+
+impl SubscriptionWithDetails {
+    /// Traffic allowed in the current cycle including add-on packs, None when unlimited
+    pub fn traffic_quota_bytes(&self) -> Option<i64> {
+        self.traffic_limit_gb
+            .filter(|gb| *gb > 0)
+            .map(|gb| gb as i64 * GIB + self.sub.traffic_extra_bytes)
+    }
+
+    /// When the traffic counter starts over, if the plan resets it
+    pub fn next_traffic_reset(&self) -> Option<DateTime<Utc>> {
+        match self.traffic_reset_period.as_str() {
+            "monthly" => Some(monthly_cycle(self.sub.traffic_reset_at.unwrap_or(self.sub.created_at), Utc::now()).1),
+            "billing_cycle" => Some(self.sub.expires_at),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -57,6 +157,11 @@ impl StoreService {
         self.pool.clone()
     }
 
+    /// AmneziaWG peer provisioning on the same pool
+    pub fn awg(&self) -> crate::services::awg_service::AwgService {
+        crate::services::awg_service::AwgService::new(self.pool.clone())
+    }
+
     pub async fn get_categories(&self) -> Result<Vec<crate::models::store::Category>> {
         sqlx::query_as::<_, crate::models::store::Category>(
             "SELECT id, name, description, is_active, sort_order, created_at FROM categories WHERE is_active = 1 ORDER BY sort_order ASC"
@@ -303,7 +408,7 @@ impl StoreService {
 
     pub async fn get_active_plans(&self) -> Result<Vec<Plan>> {
         let mut plans = match sqlx::query_as::<_, Plan>(
-        "SELECT id, name, description, is_active, created_at, device_limit, traffic_limit_gb FROM plans WHERE is_active = 1"
+        "SELECT * FROM plans WHERE is_active = 1"
     )
     .fetch_all(&self.pool)
     .await {
@@ -354,19 +459,14 @@ impl StoreService {
             .fetch_one(&mut *tx)
             .await?;
 
-        // 2. Check balance
-        if user.balance < duration.price {
+        // 2. Check balance, less any promo discount the user has reserved
+        let discount = PromoService::discount_for(&mut tx, user_id, &duration).await?;
+        let price = duration.price - discount.as_ref().map_or(0, |d| d.amount);
+        if user.balance < price {
             return Err(anyhow::anyhow!("Insufficient balance"));
         }
 
-        // 3. Deduct balance
-        sqlx::query("UPDATE users SET balance = balance - ? WHERE id = ?")
-            .bind(duration.price)
-            .bind(user_id)
-            .execute(&mut *tx)
-            .await?;
-
-        // 4. Create NEW subscription (Pending)
+        // 3. Create NEW subscription (Pending)
         // We store the intended expiration duration by setting expires_at = now + duration.
         // When activated, we will recalculate: duration = expires_at - created_at, then NewExpiry = Now + duration.
         let expires_at = Utc::now() + Duration::days(duration.duration_days as i64);
@@ -374,18 +474,29 @@ impl StoreService {
 
         let sub = sqlx::query_as::<_, Subscription>(
             r#"
-            INSERT INTO subscriptions (user_id, plan_id, vless_uuid, expires_at, status)
-            VALUES (?, ?, ?, ?, 'pending')
-            RETURNING id, user_id, plan_id, node_id, vless_uuid, expires_at, status, used_traffic, traffic_updated_at, created_at, note
+            INSERT INTO subscriptions (user_id, plan_id, vless_uuid, subscription_uuid, expires_at, status, duration_id)
+            VALUES (?, ?, ?, ?, ?, 'pending', ?)
+            RETURNING *
             "#
         )
         .bind(user_id)
         .bind(duration.plan_id)
         .bind(vless_uuid)
+        .bind(Uuid::new_v4().to_string())
         .bind(expires_at)
+        .bind(duration.id)
         .fetch_one(&mut *tx)
         .await?;
 
+        // 4. Deduct balance
+        LedgerService::post(&mut tx, Posting::debit(user_id, price, Reason::Purchase)
+            .reference("subscription", sub.id)
+            .actor("user")
+            .note(promo_note(format!("{} days", duration.duration_days), discount.as_ref()))).await?;
+        if let Some(discount) = &discount {
+            PromoService::apply(&mut tx, discount, price, sub.id).await?;
+        }
+
         tx.commit().await?;
 
         // Analytics
@@ -414,12 +525,13 @@ impl StoreService {
         let updated_sub = sqlx::query_as::<_, Subscription>(
             r#"
             UPDATE subscriptions 
-            SET expires_at = ?, status = 'active' 
+            SET expires_at = ?, status = 'active', traffic_reset_at = ?
             WHERE id = ? 
-            RETURNING id, user_id, plan_id, node_id, vless_uuid, expires_at, status, used_traffic, traffic_updated_at, created_at, note
+            RETURNING *
             "#
         )
         .bind(new_expires_at)
+        .bind(Utc::now())
         .bind(sub_id)
         .fetch_one(&mut *tx)
         .await?;
@@ -495,14 +607,15 @@ impl StoreService {
 
         let sub = sqlx::query_as::<_, Subscription>(
             r#"
-            INSERT INTO subscriptions (user_id, plan_id, vless_uuid, expires_at, status)
-            VALUES (?, ?, ?, ?, 'pending')
-            RETURNING id, user_id, plan_id, node_id, vless_uuid, expires_at, status, used_traffic, traffic_updated_at, created_at, note
+            INSERT INTO subscriptions (user_id, plan_id, vless_uuid, subscription_uuid, expires_at, status)
+            VALUES (?, ?, ?, ?, ?, 'pending')
+            RETURNING *
             "#
         )
         .bind(user_id)
         .bind(plan_id)
         .bind(vless_uuid)
+        .bind(Uuid::new_v4().to_string())
         .bind(expires_at)
         .fetch_one(&mut *tx)
         .await?;
@@ -518,6 +631,74 @@ impl StoreService {
         Ok(sub)
     }
 
+    /// Issue a new subscription link and protocol credentials for a subscription whose link leaked.
+    /// The Hysteria2 password is derived from the VLESS UUID, so it rotates too.
+    /// Returns the updated subscription and the previous link UUID (for cache invalidation).
+    pub async fn rotate_subscription_credentials(&self, sub_id: i64, user_id: i64, cooldown_hours: i64) -> Result<(Subscription, String)> {
+        let mut tx = self.pool.begin().await?;
+
+        let sub = sqlx::query_as::<_, Subscription>("SELECT * FROM subscriptions WHERE id = ? AND user_id = ?")
+            .bind(sub_id)
+            .bind(user_id)
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Subscription not found"))?;
+
+        if sub.status != "active" {
+            return Err(anyhow::anyhow!("Only active subscriptions can be reset"));
+        }
+
+        let last_rotated: Option<chrono::DateTime<Utc>> = sqlx::query_scalar("SELECT credentials_rotated_at FROM subscriptions WHERE id = ?")
+            .bind(sub_id)
+            .fetch_one(&mut *tx)
+            .await?;
+        if let Some(last) = last_rotated {
+            let next_allowed = last + Duration::hours(cooldown_hours);
+            if next_allowed > Utc::now() {
+                let wait = next_allowed - Utc::now();
+                return Err(anyhow::anyhow!(
+                    "The link was reset recently. Try again in {}h {}m",
+                    wait.num_hours(),
+                    wait.num_minutes() % 60
+                ));
+            }
+        }
+
+        let updated = sqlx::query_as::<_, Subscription>(
+            r#"
+            UPDATE subscriptions
+            SET subscription_uuid = ?, vless_uuid = ?, credentials_rotated_at = CURRENT_TIMESTAMP
+            WHERE id = ?
+            RETURNING *
+            "#
+        )
+        .bind(Uuid::new_v4().to_string())
+        .bind(Uuid::new_v4().to_string())
+        .bind(sub_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        // New AmneziaWG keys are issued on the next config build
+        sqlx::query("DELETE FROM awg_peers WHERE subscription_id = ?")
+            .bind(sub_id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        info!("Rotated link and credentials of subscription {} for user {}", sub_id, user_id);
+        Ok((updated, sub.subscription_uuid))
+    }
+
+    /// Nodes serving any inbound of the plan
+    pub async fn get_plan_node_ids(&self, plan_id: i64) -> Result<Vec<i64>> {
+        Ok(sqlx::query_scalar(
+            "SELECT DISTINCT i.node_id FROM inbounds i JOIN plan_inbounds pi ON pi.inbound_id = i.id WHERE pi.plan_id = ?"
+        )
+        .bind(plan_id)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
     pub async fn transfer_subscription(&self, sub_id: i64, current_user_id: i64, target_username: &str) -> Result<Subscription> {
         let mut tx = self.pool.begin().await?;
 
@@ -561,7 +742,7 @@ impl StoreService {
             UPDATE subscriptions 
             SET user_id = ? 
             WHERE id = ? 
-            RETURNING id, user_id, plan_id, node_id, vless_uuid, expires_at, status, used_traffic, traffic_updated_at, created_at, note
+            RETURNING *
             "#
         )
         .bind(target_user.id)
@@ -599,11 +780,9 @@ impl StoreService {
             .await?;
 
         // 3. Credit User
-        sqlx::query("UPDATE users SET balance = balance + ? WHERE id = ?")
-            .bind(amount)
-            .bind(sub.user_id)
-            .execute(&mut *tx)
-            .await?;
+        LedgerService::post(&mut tx, Posting::credit(sub.user_id, amount, Reason::Refund)
+            .reference("subscription", sub_id)
+            .actor("admin")).await?;
 
         tx.commit().await?;
         Ok(())
@@ -636,15 +815,16 @@ impl StoreService {
         // 3. Create Active Subscription
         let sub = sqlx::query_as::<_, Subscription>(
             r#"
-            INSERT INTO subscriptions (user_id, plan_id, node_id, vless_uuid, expires_at, status, created_at)
-            VALUES (?, ?, ?, ?, ?, 'active', CURRENT_TIMESTAMP)
-            RETURNING id, user_id, plan_id, node_id, vless_uuid, expires_at, status, used_traffic, traffic_updated_at, created_at, note
+            INSERT INTO subscriptions (user_id, plan_id, node_id, vless_uuid, subscription_uuid, expires_at, status, created_at)
+            VALUES (?, ?, ?, ?, ?, ?, 'active', CURRENT_TIMESTAMP)
+            RETURNING *
             "#
         )
         .bind(user_id)
         .bind(plan_id)
         .bind(node_id)
         .bind(vless_uuid)
+        .bind(Uuid::new_v4().to_string())
         .bind(expires_at)
         .fetch_one(&mut *tx)
         .await?;
@@ -668,21 +848,16 @@ impl StoreService {
             .fetch_one(&mut *tx)
             .await?;
 
-        // 2. Check balance
-        if user.balance < duration.price {
+        // 2. Check balance, less any promo discount the user has reserved
+        let discount = PromoService::discount_for(&mut tx, user_id, &duration).await?;
+        let price = duration.price - discount.as_ref().map_or(0, |d| d.amount);
+        if user.balance < price {
             return Err(anyhow::anyhow!("Insufficient balance"));
         }
 
-        // 3. Deduct balance
-        sqlx::query("UPDATE users SET balance = balance - ? WHERE id = ?")
-            .bind(duration.price)
-            .bind(user_id)
-            .execute(&mut *tx)
-            .await?;
-
-        // 4. Check for existing active subscription
+        // 3. Check for existing active subscription
         let existing_sub = sqlx::query_as::<_, Subscription>(
-            "SELECT id, user_id, plan_id, node_id, vless_uuid, expires_at, status, used_traffic, traffic_updated_at, created_at, note FROM subscriptions WHERE user_id = ? AND plan_id = ? AND status = 'active'"
+            "SELECT * FROM subscriptions WHERE user_id = ? AND plan_id = ? AND status IN ('active', 'limited')"
         )
         .bind(user_id)
         .bind(duration.plan_id)
@@ -700,16 +875,21 @@ impl StoreService {
             let updated_sub = sqlx::query_as::<_, Subscription>(
                 r#"
                 UPDATE subscriptions 
-                SET expires_at = ? 
+                SET expires_at = ?, duration_id = ?, renewal_attempts = 0, next_renewal_at = NULL
                 WHERE id = ? 
-                RETURNING id, user_id, plan_id, node_id, vless_uuid, expires_at, status, used_traffic, traffic_updated_at, created_at, note
+                RETURNING *
                 "#
             )
             .bind(new_expires_at)
+            .bind(duration.id)
             .bind(active_sub.id)
             .fetch_one(&mut *tx)
             .await?;
-            
+
+            let mut updated_sub = updated_sub;
+            if let Some(change) = Self::start_paid_traffic_period(&mut tx, updated_sub.id).await? {
+                updated_sub.status = change.status().to_string();
+            }
             updated_sub
         } else {
             // Create new if none found (fallback)
@@ -718,27 +898,166 @@ impl StoreService {
 
             sqlx::query_as::<_, Subscription>(
                 r#"
-                INSERT INTO subscriptions (user_id, plan_id, vless_uuid, expires_at, status)
-                VALUES (?, ?, ?, ?, 'active')
-                RETURNING id, user_id, plan_id, node_id, vless_uuid, expires_at, status, used_traffic, traffic_updated_at, created_at, note
+                INSERT INTO subscriptions (user_id, plan_id, vless_uuid, subscription_uuid, expires_at, status, duration_id)
+                VALUES (?, ?, ?, ?, ?, 'active', ?)
+                RETURNING *
                 "#
             )
             .bind(user_id)
             .bind(duration.plan_id)
             .bind(vless_uuid)
+            .bind(Uuid::new_v4().to_string())
             .bind(expires_at)
+            .bind(duration.id)
             .fetch_one(&mut *tx)
             .await?
         };
 
+        // 4. Deduct balance
+        LedgerService::post(&mut tx, Posting::debit(user_id, price, Reason::Purchase)
+            .reference("subscription", sub.id)
+            .actor("user")
+            .note(promo_note(format!("Extended by {} days", duration.duration_days), discount.as_ref()))).await?;
+        if let Some(discount) = &discount {
+            PromoService::apply(&mut tx, discount, price, sub.id).await?;
+        }
+
         tx.commit().await?;
         Ok(sub)
     }
 
+    /// Price a switch of an active subscription to `duration_id` of another plan. The current
+    /// subscription is valued at what the user paid for its remaining time, never at list price.
+    async fn plan_change_quote(conn: &mut sqlx::SqliteConnection, user_id: i64, sub_id: i64, duration_id: i64) -> Result<PlanChangeQuote> {
+        let sub = sqlx::query_as::<_, Subscription>("SELECT * FROM subscriptions WHERE id = ? AND user_id = ?")
+            .bind(sub_id)
+            .bind(user_id)
+            .fetch_optional(&mut *conn)
+            .await?
+            .context("Subscription not found")?;
+        if sub.status != "active" && sub.status != "limited" {
+            return Err(anyhow::anyhow!("Only active subscriptions can change plan"));
+        }
+
+        let (duration_id, plan_id, days, price, plan_name) = sqlx::query_as::<_, (i64, i64, i32, i64, String)>(
+            "SELECT pd.id, pd.plan_id, pd.duration_days, pd.price, p.name
+             FROM plan_durations pd JOIN plans p ON p.id = pd.plan_id
+             WHERE pd.id = ? AND COALESCE(pd.is_active, 1) = 1 AND p.is_active = 1 AND COALESCE(p.is_trial, 0) = 0"
+        )
+        .bind(duration_id)
+        .fetch_optional(&mut *conn)
+        .await?
+        .context("Plan is not available")?;
+        if plan_id == sub.plan_id {
+            return Err(anyhow::anyhow!("Subscription is already on this plan, extend it instead"));
+        }
+
+        let credit = if sub.is_trial.unwrap_or(false) {
+            0
+        } else {
+            // Charges and plan-change credits booked on this subscription; packs buy traffic, not time
+            let movements: Vec<(i64, DateTime<Utc>)> = sqlx::query_as(
+                "SELECT amount, created_at FROM balance_ledger
+                 WHERE ref_type = 'subscription' AND ref_id = ? AND reason IN ('purchase', 'renewal', 'refund')
+                   AND COALESCE(note, '') NOT LIKE ?
+                 ORDER BY id"
+            )
+            .bind(sub.id)
+            .bind(format!("{}%", TRAFFIC_PACK_NOTE))
+            .fetch_all(&mut *conn)
+            .await?;
+            unused_paid_value(&movements, sub.expires_at, Utc::now())
+        };
+
+        Ok(PlanChangeQuote {
+            subscription_id: sub.id,
+            plan_id,
+            plan_name,
+            duration_id,
+            days,
+            price,
+            credit,
+            due: price - credit,
+            expires_at: Utc::now() + Duration::days(days as i64),
+        })
+    }
+
+    pub async fn quote_plan_change(&self, user_id: i64, sub_id: i64, duration_id: i64) -> Result<PlanChangeQuote> {
+        let mut conn = self.pool.acquire().await?;
+        Self::plan_change_quote(&mut conn, user_id, sub_id, duration_id).await
+    }
+
+    /// Every duration of the other plans the subscription can switch to, priced
+    pub async fn plan_change_options(&self, user_id: i64, sub_id: i64) -> Result<Vec<PlanChangeQuote>> {
+        let duration_ids: Vec<i64> = sqlx::query_scalar(
+            "SELECT pd.id FROM plan_durations pd
+             JOIN plans p ON p.id = pd.plan_id
+             WHERE p.is_active = 1 AND COALESCE(p.is_trial, 0) = 0 AND COALESCE(pd.is_active, 1) = 1
+               AND p.id != (SELECT plan_id FROM subscriptions WHERE id = ? AND user_id = ?)
+             ORDER BY p.sort_order, p.id, pd.duration_days"
+        )
+        .bind(sub_id)
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut conn = self.pool.acquire().await?;
+        let mut quotes = Vec::with_capacity(duration_ids.len());
+        for duration_id in duration_ids {
+            quotes.push(Self::plan_change_quote(&mut conn, user_id, sub_id, duration_id).await?);
+        }
+        Ok(quotes)
+    }
+
+    /// Upgrade or downgrade in place: the unused value is credited, the new duration charged,
+    /// and the subscription moves to the new plan keeping its link and credentials.
+    /// Returns the old plan id so its nodes can drop the user.
+    pub async fn change_plan(&self, user_id: i64, sub_id: i64, duration_id: i64) -> Result<(Subscription, PlanChangeQuote, i64)> {
+        let mut tx = self.pool.begin().await?;
+        let quote = Self::plan_change_quote(&mut tx, user_id, sub_id, duration_id).await?;
+
+        let old_plan: (i64, String) = sqlx::query_as(
+            "SELECT p.id, p.name FROM subscriptions s JOIN plans p ON p.id = s.plan_id WHERE s.id = ?"
+        )
+        .bind(sub_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        // Credit first so the charge is checked against the balance the user ends up with
+        LedgerService::post(&mut tx, Posting::credit(user_id, quote.credit, Reason::Refund)
+            .reference("subscription", sub_id)
+            .actor("user")
+            .note(format!("Unused time of {}", old_plan.1))).await?;
+        LedgerService::post(&mut tx, Posting::debit(user_id, quote.price, Reason::Purchase)
+            .reference("subscription", sub_id)
+            .actor("user")
+            .note(format!("Switched to {}, {} days", quote.plan_name, quote.days))).await?;
+
+        let mut sub = sqlx::query_as::<_, Subscription>(
+            "UPDATE subscriptions
+             SET plan_id = ?, duration_id = ?, expires_at = ?, alerts_sent = '[]', renewal_attempts = 0, next_renewal_at = NULL
+             WHERE id = ?
+             RETURNING *"
+        )
+        .bind(quote.plan_id)
+        .bind(quote.duration_id)
+        .bind(quote.expires_at)
+        .bind(sub_id)
+        .fetch_one(&mut *tx)
+        .await?;
+        if let Some(change) = Self::start_paid_traffic_period(&mut tx, sub_id).await? {
+            sub.status = change.status().to_string();
+        }
+
+        tx.commit().await?;
+        info!("Subscription {} of user {} moved from plan {} to {}", sub_id, user_id, old_plan.0, quote.plan_id);
+        Ok((sub, quote, old_plan.0))
+    }
+
     pub async fn get_user_subscriptions(&self, user_id: i64) -> Result<Vec<SubscriptionWithDetails>> {
         // 1. Fetch Subscriptions
         let subs = sqlx::query_as::<_, Subscription>(
-            "SELECT id, user_id, plan_id, node_id, vless_uuid, expires_at, status, created_at, used_traffic, traffic_updated_at, note FROM subscriptions WHERE user_id = ? ORDER BY created_at DESC"
+            "SELECT * FROM subscriptions WHERE user_id = ? ORDER BY created_at DESC"
         )
         .bind(user_id)
         .fetch_all(&self.pool)
@@ -757,7 +1076,7 @@ impl StoreService {
         for sub in subs {
             let plan = plans.iter().find(|p| p.id == sub.plan_id);
             
-            let (name, desc, limit) = if let Some(p) = plan {
+            let (name, desc, limit, reset_period) = if let Some(p) = plan {
                 // Find duration with closest days to (expires_at - created_at)
                 let actual_days = (sub.expires_at - sub.created_at).num_days();
                 
@@ -766,9 +1085,9 @@ impl StoreService {
                 
                 let limit = Some(p.traffic_limit_gb);
                 
-                (p.name.clone(), p.description.clone(), limit)
+                (p.name.clone(), p.description.clone(), limit, p.traffic_reset_period.clone())
             } else {
-                ("Unknown Plan".to_string(), None, None)
+                ("Unknown Plan".to_string(), None, None, "never".to_string())
             };
 
             result.push(SubscriptionWithDetails {
@@ -776,6 +1095,7 @@ impl StoreService {
                 plan_name: name,
                 plan_description: desc,
                 traffic_limit_gb: limit,
+                traffic_reset_period: reset_period,
             });
         }
 
@@ -803,19 +1123,24 @@ impl StoreService {
     }
 
 
-    pub async fn log_payment(&self, user_id: i64, method: &str, amount_cents: i64, external_id: Option<&str>, status: &str) -> Result<()> {
-        sqlx::query(
-            "INSERT INTO payments (user_id, method, amount, external_id, status) VALUES (?, ?, ?, ?, ?)"
+    /// Record a payment: `amount` is the USD value credited, `settled` what the provider received
+    /// Record a payment that doesn't go through the balance; returns its id
+    pub async fn log_payment(&self, user_id: i64, method: &str, amount: Money, settled: Money, external_id: Option<&str>, status: &str) -> Result<i64> {
+        let id = sqlx::query_scalar(
+            "INSERT INTO payments (user_id, method, amount, settled_amount, settled_currency, external_id, status, created_at, updated_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?, unixepoch(), unixepoch()) RETURNING id"
         )
         .bind(user_id)
         .bind(method)
-        .bind(amount_cents)
+        .bind(amount.minor)
+        .bind(settled.minor)
+        .bind(settled.currency.code())
         .bind(external_id)
         .bind(status)
-        .execute(&self.pool)
+        .fetch_one(&self.pool)
         .await
         .context("Failed to log payment")?;
-        Ok(())
+        Ok(id)
     }
 
     pub async fn apply_referral_bonus(&self, pool: &mut sqlx::Transaction<'_, sqlx::Sqlite>, user_id: i64, amount_cents: i64, payment_id: Option<i64>) -> Result<Option<(i64, i64)>> {
@@ -829,14 +1154,15 @@ impl StoreService {
             let bonus = amount_cents / 10;
             if bonus > 0 {
                 // 1. Update balance
-                sqlx::query("UPDATE users SET balance = balance + ? WHERE id = ?")
-                    .bind(bonus)
-                    .bind(referrer_id)
-                    .execute(&mut **pool)
-                    .await?;
+                let mut posting = Posting::credit(referrer_id, bonus, Reason::Referral)
+                    .note(format!("10% of user {} top-up", user_id));
+                if let Some(payment_id) = payment_id {
+                    posting = posting.reference("payment", payment_id);
+                }
+                LedgerService::post(pool, posting).await?;
                 
                 // 2. Log to referral_bonuses
-                sqlx::query("INSERT INTO referral_bonuses (referrer_id, referred_id, amount, payment_id) VALUES (?, ?, ?, ?)")
+                sqlx::query("INSERT INTO referral_bonuses (user_id, referred_user_id, bonus_type, bonus_value, status, applied_at, payment_id) VALUES (?, ?, 'topup_percent', ?, 'applied', CURRENT_TIMESTAMP, ?)")
                     .bind(referrer_id)
                     .bind(user_id)
                     .bind(bonus)
@@ -875,7 +1201,7 @@ impl StoreService {
                 u.created_at,
                 COALESCE(CAST(SUM(rb.bonus_value) AS INTEGER), 0) as total_earned
             FROM users u
-            LEFT JOIN referral_bonuses rb ON u.id = rb.referred_user_id AND rb.user_id = ?
+            LEFT JOIN referral_bonuses rb ON u.id = rb.referred_user_id AND rb.user_id = ? AND rb.status != 'reversed'
             WHERE u.referrer_id = ?
             GROUP BY u.id
             ORDER BY u.created_at DESC
@@ -889,7 +1215,7 @@ impl StoreService {
     }
 
     pub async fn get_user_referral_earnings(&self, referrer_id: i64) -> Result<i64> {
-        let total: Option<i64> = sqlx::query_scalar("SELECT CAST(SUM(bonus_value) AS INTEGER) FROM referral_bonuses WHERE user_id = ?")
+        let total: Option<i64> = sqlx::query_scalar("SELECT CAST(SUM(bonus_value) AS INTEGER) FROM referral_bonuses WHERE user_id = ? AND status != 'reversed'")
             .bind(referrer_id)
             .fetch_one(&self.pool)
             .await
@@ -905,20 +1231,11 @@ impl StoreService {
         Ok(count as i64)
     }
 
-    pub async fn validate_promo(&self, code: &str) -> Result<Option<crate::models::store::PromoCode>> {
-        sqlx::query_as::<_, crate::models::store::PromoCode>(
-            "SELECT id, code, discount_percent, bonus_amount, max_uses, current_uses, expires_at, created_at FROM promo_codes WHERE code = ? AND (expires_at IS NULL OR expires_at > ?) AND current_uses < max_uses"
-        )
-        .bind(code)
-        .bind(Utc::now())
-        .fetch_optional(&self.pool)
-        .await
-        .context("Failed to validate promo code")
-    }
-
-    pub async fn process_order_payment(&self, order_id: i64) -> Result<()> {
-        sqlx::query("UPDATE orders SET status = 'paid', paid_at = ? WHERE id = ?")
+    /// Mark an order paid by `payment_id`, which a refund of that payment uses to revoke it
+    pub async fn process_order_payment(&self, order_id: i64, payment_id: i64) -> Result<()> {
+        sqlx::query("UPDATE orders SET status = 'paid', paid_at = ?, payment_id = ? WHERE id = ?")
             .bind(Utc::now())
+            .bind(payment_id.to_string())
             .bind(order_id)
             .execute(&self.pool)
             .await
@@ -948,14 +1265,7 @@ impl StoreService {
              return Err(anyhow::anyhow!("Insufficient balance"));
         }
 
-        // 4. Deduct Balance
-        sqlx::query("UPDATE users SET balance = balance - ? WHERE id = ?")
-            .bind(product.price)
-            .bind(user_id)
-            .execute(&mut *tx)
-            .await?;
-
-        // 5. Create Order
+        // 4. Create Order
         use sqlx::Row;
         let order_id: i64 = sqlx::query("INSERT INTO orders (user_id, total_amount, status, paid_at) VALUES (?, ?, 'paid', ?) RETURNING id")
             .bind(user_id)
@@ -965,6 +1275,12 @@ impl StoreService {
             .await?
             .get(0);
 
+        // 5. Deduct Balance
+        LedgerService::post(&mut tx, Posting::debit(user_id, product.price, Reason::Purchase)
+            .reference("order", order_id)
+            .actor("user")
+            .note(product.name.clone())).await?;
+
         // 6. Create Order Item
         sqlx::query("INSERT INTO order_items (order_id, product_id, price) VALUES (?, ?, ?)")
             .bind(order_id)
@@ -1035,6 +1351,7 @@ impl StoreService {
                     security: Some("none".to_string()),
                     tls_settings: None,
                     reality_settings: None,
+                    xhttp_settings: None,
                 });
                 let security = stream.security.as_deref().unwrap_or("none");
                 let network = stream.network.as_deref().unwrap_or("tcp");
@@ -1090,6 +1407,14 @@ impl StoreService {
                         }
                         
                         params.push(format!("type={}", network));
+
+                        if network == "xhttp" && let Some(xhttp) = &stream.xhttp_settings {
+                            params.push(format!("path={}", urlencoding::encode(&xhttp.path)));
+                            params.push(format!("mode={}", xhttp.mode));
+                            if let Some(host) = &xhttp.host {
+                                params.push(format!("host={}", host));
+                            }
+                        }
                         
                         if network == "tcp" {
                              params.push("headerType=none".to_string());
@@ -1118,6 +1443,9 @@ impl StoreService {
                                 params.push(format!("obfs-password={}", obfs.password));
                             }
                         }
+                        if let Some(range) = settings.port_hopping {
+                            params.push(format!("mport={}", range));
+                        }
                     }
 
                     // Fetch TG ID for auth name
@@ -1144,161 +1472,255 @@ impl StoreService {
 
 
 
-    pub async fn generate_subscription_file(&self, user_id: i64) -> Result<String> {
+    pub async fn generate_subscription_file(&self, user_id: i64, routing: &crate::singbox::routing::RoutingPolicy) -> Result<String> {
+        use crate::singbox::client_generator::ClientGenerator;
+
         // 1. Get active subscriptions
         let subs = self.get_user_subscriptions(user_id).await?;
-        let active_subs: Vec<_> = subs.into_iter().filter(|s| s.sub.status == "active").collect();
+        let mut client_outbounds = Vec::new();
+        for sub in subs.into_iter().filter(|s| s.sub.status == "active") {
+            client_outbounds.extend(self.subscription_outbounds(&sub.sub, None).await?);
+        }
 
-        use crate::singbox::client_generator::{ClientGenerator, ClientOutbound, ClientVlessOutbound, ClientHysteria2Outbound, ClientTlsConfig, ClientRealityConfig, ClientObfs};
+        let profile = ClientGenerator::generate(client_outbounds, routing);
+        Ok(serde_json::to_string_pretty(&profile)?)
+    }
+
+    /// Client outbounds for every enabled inbound of the subscription's plan, optionally limited to one node
+    pub async fn subscription_outbounds(&self, sub: &Subscription, node_id: Option<i64>) -> Result<Vec<crate::singbox::client_generator::ClientOutbound>> {
+        use crate::singbox::client_generator::{ClientOutbound, ClientVlessOutbound, ClientHysteria2Outbound, ClientTlsConfig, ClientRealityConfig, ClientObfs};
         let mut client_outbounds = Vec::new();
 
-        for sub in active_subs {
-            let uuid = sub.sub.vless_uuid.clone().unwrap_or_default();
-            
-            // 2. Get Inbounds for this Plan
-            // Copied from links generation logic
-            let inbounds = sqlx::query_as::<_, crate::models::network::Inbound>(
-                r#"
-                SELECT i.* FROM inbounds i
-                JOIN plan_inbounds pi ON pi.inbound_id = i.id
-                WHERE pi.plan_id = ? AND i.enable = 1
-                "#
-            )
-            .bind(sub.sub.plan_id)
-            .fetch_all(&self.pool)
-            .await?;
-            
-            for inbound in inbounds {
-                use crate::models::network::{StreamSettings, InboundType};
-                let stream: StreamSettings = serde_json::from_str(&inbound.stream_settings).unwrap_or(StreamSettings {
-                    network: Some("tcp".to_string()),
-                    security: Some("none".to_string()),
-                    tls_settings: None,
-                    reality_settings: None,
-                });
-                let security = stream.security.as_deref().unwrap_or("none");
-                let _network = stream.network.as_deref().unwrap_or("tcp");
+        let uuid = sub.vless_uuid.clone().unwrap_or_default();
+        
+        // 2. Get Inbounds for this Plan
+        // Copied from links generation logic
+        let inbounds = sqlx::query_as::<_, crate::models::network::Inbound>(
+            r#"
+            SELECT i.* FROM inbounds i
+            JOIN plan_inbounds pi ON pi.inbound_id = i.id
+            WHERE pi.plan_id = ? AND i.enable = 1 AND (? IS NULL OR i.node_id = ?)
+            "#
+        )
+        .bind(sub.plan_id)
+        .bind(node_id)
+        .bind(node_id)
+        .fetch_all(&self.pool)
+        .await?;
+        
+        for inbound in inbounds {
+            use crate::models::network::{StreamSettings, InboundType};
+            let stream: StreamSettings = serde_json::from_str(&inbound.stream_settings).unwrap_or(StreamSettings {
+                network: Some("tcp".to_string()),
+                security: Some("none".to_string()),
+                tls_settings: None,
+                reality_settings: None,
+                xhttp_settings: None,
+            });
+            let security = stream.security.as_deref().unwrap_or("none");
+            let network = stream.network.as_deref().unwrap_or("tcp");
 
-                let (address, reality_pub) = if inbound.listen_ip == "::" || inbound.listen_ip == "0.0.0.0" {
-                    let node_details: Option<(String, Option<String>)> = sqlx::query_as("SELECT ip, reality_pub FROM nodes WHERE id = ?")
-                        .bind(inbound.node_id)
-                        .fetch_optional(&self.pool)
-                        .await?;
-                    if let Some((ip, pub_key)) = node_details { (ip, pub_key) } else { (inbound.listen_ip.clone(), None) }
-                } else {
-                     let pub_key: Option<String> = sqlx::query_scalar("SELECT reality_pub FROM nodes WHERE id = ?")
-                        .bind(inbound.node_id)
-                        .fetch_optional(&self.pool)
-                        .await?;
-                    (inbound.listen_ip.clone(), pub_key)
-                };
+            let (address, reality_pub) = if inbound.listen_ip == "::" || inbound.listen_ip == "0.0.0.0" {
+                let node_details: Option<(String, Option<String>)> = sqlx::query_as("SELECT ip, reality_pub FROM nodes WHERE id = ?")
+                    .bind(inbound.node_id)
+                    .fetch_optional(&self.pool)
+                    .await?;
+                if let Some((ip, pub_key)) = node_details { (ip, pub_key) } else { (inbound.listen_ip.clone(), None) }
+            } else {
+                 let pub_key: Option<String> = sqlx::query_scalar("SELECT reality_pub FROM nodes WHERE id = ?")
+                    .bind(inbound.node_id)
+                    .fetch_optional(&self.pool)
+                    .await?;
+                (inbound.listen_ip.clone(), pub_key)
+            };
 
-                let port = inbound.listen_port as u16;
-                let tag = inbound.tag.clone();
+            let port = inbound.listen_port as u16;
+            let tag = inbound.tag.clone();
+
+            match inbound.protocol.as_str() {
+                // sing-box has no XHTTP transport; those inbounds are reachable through the share links
+                "vless" if network == "xhttp" => {}
+                "vless" => {
+                    if security == "reality" {
+                        if let Some(reality) = stream.reality_settings {
+                            let names = if reality.server_names.is_empty() {
+                                vec!["".to_string()]
+                            } else {
+                                reality.server_names.clone()
+                            };
 
-                match inbound.protocol.as_str() {
-                    "vless" => {
-                        if security == "reality" {
-                            if let Some(reality) = stream.reality_settings {
-                                let names = if reality.server_names.is_empty() {
-                                    vec!["".to_string()]
+                            for (_idx, sni) in names.iter().enumerate() {
+                                let display_tag = if names.len() > 1 {
+                                    format!("{} ({})", tag, sni)
                                 } else {
-                                    reality.server_names.clone()
+                                    tag.clone()
                                 };
 
-                                for (_idx, sni) in names.iter().enumerate() {
-                                    let display_tag = if names.len() > 1 {
-                                        format!("{} ({})", tag, sni)
-                                    } else {
-                                        tag.clone()
-                                    };
-
-                                    let tls_config = ClientTlsConfig {
+                                let tls_config = ClientTlsConfig {
+                                    enabled: true,
+                                    server_name: sni.clone(),
+                                    insecure: false,
+                                    alpn: Some(vec!["h2".to_string(), "http/1.1".to_string()]),
+                                    utls: Some(crate::singbox::client_generator::UtlsConfig { enabled: true, fingerprint: "chrome".to_string() }),
+                                    reality: Some(ClientRealityConfig {
                                         enabled: true,
-                                        server_name: sni.clone(),
-                                        insecure: false,
-                                        alpn: Some(vec!["h2".to_string(), "http/1.1".to_string()]),
-                                        utls: Some(crate::singbox::client_generator::UtlsConfig { enabled: true, fingerprint: "chrome".to_string() }),
-                                        reality: Some(ClientRealityConfig {
-                                            enabled: true,
-                                            public_key: reality_pub.clone().unwrap_or_default(),
-                                            short_id: reality.short_ids.first().cloned().unwrap_or_default(),
-                                        })
-                                    };
-
-                                    client_outbounds.push(ClientOutbound::Vless(ClientVlessOutbound {
-                                         tag: display_tag,
-                                         server: address.clone(),
-                                         server_port: port,
-                                         uuid: uuid.clone(),
-                                         flow: Some("xtls-rprx-vision".to_string()),
-                                         packet_encoding: Some("xudp".to_string()),
-                                         tls: Some(tls_config),
-                                    }));
-                                }
-                            }
-                        } else {
-                             // Standard TLS or None
-                             let mut tls_config = None;
-                             if security == "tls" {
-                                 if let Some(tls) = stream.tls_settings {
-                                     tls_config = Some(ClientTlsConfig {
-                                         enabled: true,
-                                         server_name: tls.server_name,
-                                         insecure: false, // Assume true certs
-                                         alpn: None,
-                                         utls: None,
-                                         reality: None,
-                                     });
-                                 }
-                             }
+                                        public_key: reality_pub.clone().unwrap_or_default(),
+                                        short_id: reality.short_ids.first().cloned().unwrap_or_default(),
+                                    })
+                                };
 
-                            client_outbounds.push(ClientOutbound::Vless(ClientVlessOutbound {
-                                 tag,
-                                 server: address,
-                                 server_port: port,
-                                 uuid: uuid.clone(),
-                                 flow: None,
-                                 packet_encoding: Some("xudp".to_string()),
-                                 tls: tls_config,
-                            }));
-                        }
-                    },
-                    "hysteria2" => {
-                        let mut server_name = "drive.google.com".to_string();
-                        let mut insecure = true;
-                        
-                        if let Some(tls) = stream.tls_settings {
-                            server_name = tls.server_name.clone();
-                            if server_name != "drive.google.com" && server_name != "www.yahoo.com" {
-                                insecure = false;
+                                client_outbounds.push(ClientOutbound::Vless(ClientVlessOutbound {
+                                     tag: display_tag,
+                                     server: address.clone(),
+                                     server_port: port,
+                                     uuid: uuid.clone(),
+                                     flow: Some("xtls-rprx-vision".to_string()),
+                                     packet_encoding: Some("xudp".to_string()),
+                                     tls: Some(tls_config),
+                                }));
                             }
                         }
+                    } else {
+                         // Standard TLS or None
+                         let mut tls_config = None;
+                         if security == "tls" {
+                             if let Some(tls) = stream.tls_settings {
+                                 tls_config = Some(ClientTlsConfig {
+                                     enabled: true,
+                                     server_name: tls.server_name,
+                                     insecure: false, // Assume true certs
+                                     alpn: None,
+                                     utls: None,
+                                     reality: None,
+                                 });
+                             }
+                         }
+
+                        client_outbounds.push(ClientOutbound::Vless(ClientVlessOutbound {
+                             tag,
+                             server: address,
+                             server_port: port,
+                             uuid: uuid.clone(),
+                             flow: None,
+                             packet_encoding: Some("xudp".to_string()),
+                             tls: tls_config,
+                        }));
+                    }
+                },
+                // Trojan always runs over TLS or Reality; the password is the subscription UUID
+                "trojan" if network == "xhttp" => {}
+                "trojan" => {
+                    let tls = match security {
+                        "reality" => stream.reality_settings.map(|reality| ClientTlsConfig {
+                            enabled: true,
+                            server_name: reality.server_names.first().cloned().unwrap_or_default(),
+                            insecure: false,
+                            alpn: Some(vec!["h2".to_string(), "http/1.1".to_string()]),
+                            utls: Some(crate::singbox::client_generator::UtlsConfig { enabled: true, fingerprint: "chrome".to_string() }),
+                            reality: Some(ClientRealityConfig {
+                                enabled: true,
+                                public_key: reality_pub.clone().unwrap_or_default(),
+                                short_id: reality.short_ids.first().cloned().unwrap_or_default(),
+                            }),
+                        }),
+                        "tls" => stream.tls_settings.map(|tls| ClientTlsConfig {
+                            enabled: true,
+                            server_name: tls.server_name,
+                            insecure: false,
+                            alpn: None,
+                            utls: Some(crate::singbox::client_generator::UtlsConfig { enabled: true, fingerprint: "chrome".to_string() }),
+                            reality: None,
+                        }),
+                        _ => None,
+                    };
+
+                    if let Some(tls) = tls {
+                        use crate::singbox::client_generator::ClientTrojanOutbound;
+                        client_outbounds.push(ClientOutbound::Trojan(ClientTrojanOutbound {
+                            tag,
+                            server: address,
+                            server_port: port,
+                            password: uuid.clone(),
+                            tls,
+                        }));
+                    }
+                },
+                "hysteria2" => {
+                    let mut server_name = "drive.google.com".to_string();
+                    let mut insecure = true;
+                    
+                    if let Some(tls) = stream.tls_settings {
+                        server_name = tls.server_name.clone();
+                        if server_name != "drive.google.com" && server_name != "www.yahoo.com" {
+                            insecure = false;
+                        }
+                    }
 
-                        let tg_id: i64 = sqlx::query_scalar("SELECT tg_id FROM users WHERE id = ?")
-                            .bind(sub.sub.user_id)
-                            .fetch_optional(&self.pool)
-                            .await?
-                            .unwrap_or(0);
-                        
-                        // Auth is "user:pass"
-                        let password = format!("{}:{}", tg_id, uuid.replace("-", ""));
-                        
-                        let mut obfs = None;
-                        if let Ok(InboundType::Hysteria2(settings)) = serde_json::from_str::<InboundType>(&inbound.settings) {
-                            if let Some(o) = settings.obfs {
-                                obfs = Some(ClientObfs {
-                                    ttype: o.ttype,
-                                    password: o.password,
-                                });
-                            }
+                    let tg_id: i64 = sqlx::query_scalar("SELECT tg_id FROM users WHERE id = ?")
+                        .bind(sub.user_id)
+                        .fetch_optional(&self.pool)
+                        .await?
+                        .unwrap_or(0);
+                    
+                    // Auth is "user:pass"
+                    let password = format!("{}:{}", tg_id, uuid.replace("-", ""));
+                    
+                    let mut obfs = None;
+                    if let Ok(InboundType::Hysteria2(settings)) = serde_json::from_str::<InboundType>(&inbound.settings) {
+                        if let Some(o) = settings.obfs {
+                            obfs = Some(ClientObfs {
+                                ttype: o.ttype,
+                                password: o.password,
+                            });
                         }
+                    }
 
-                        client_outbounds.push(ClientOutbound::Hysteria2(ClientHysteria2Outbound {
+                    client_outbounds.push(ClientOutbound::Hysteria2(ClientHysteria2Outbound {
+                        tag,
+                        server: address,
+                        server_port: port,
+                        password,
+                        tls: ClientTlsConfig {
+                            enabled: true,
+                            server_name,
+                            insecure,
+                            alpn: Some(vec!["h3".to_string()]),
+                            utls: None,
+                            reality: None,
+                        },
+                        obfs,
+                    }));
+                },
+                "shadowsocks" => {
+                    if let Ok(InboundType::Shadowsocks(settings)) = serde_json::from_str::<InboundType>(&inbound.settings) {
+                        use crate::singbox::client_generator::ClientShadowsocksOutbound;
+                        client_outbounds.push(ClientOutbound::Shadowsocks(ClientShadowsocksOutbound {
                             tag,
                             server: address,
                             server_port: port,
-                            password,
+                            password: format!("{}:{}", settings.password, settings.user_key(&uuid)),
+                            method: settings.method,
+                        }));
+                    }
+                },
+                "tuic" => {
+                    let (server_name, insecure) = match stream.tls_settings {
+                        Some(tls) => {
+                            let insecure = tls.server_name == "drive.google.com" || tls.server_name == "www.yahoo.com";
+                            (tls.server_name, insecure)
+                        }
+                        None => ("drive.google.com".to_string(), true),
+                    };
+                    if let Ok(InboundType::Tuic(settings)) = serde_json::from_str::<InboundType>(&inbound.settings) {
+                        use crate::singbox::client_generator::ClientTuicOutbound;
+                        client_outbounds.push(ClientOutbound::Tuic(ClientTuicOutbound {
+                            tag,
+                            server: address,
+                            server_port: port,
+                            uuid: uuid.clone(),
+                            password: uuid.replace("-", ""),
+                            congestion_control: Some(settings.congestion_control),
+                            udp_relay_mode: Some("native".to_string()),
                             tls: ClientTlsConfig {
                                 enabled: true,
                                 server_name,
@@ -1307,71 +1729,43 @@ impl StoreService {
                                 utls: None,
                                 reality: None,
                             },
-                            obfs,
                         }));
-                    },
-                    "amneziawg" => {
-                        // Parse AmneziaWG settings to get obfuscation params
-                        if let Ok(InboundType::AmneziaWg(settings)) = serde_json::from_str::<InboundType>(&inbound.settings) {
-                            // Generate client keypair
-                            let (client_priv, _client_pub) = {
-                                use std::process::Command;
-                                let output = Command::new("sing-box")
-                                    .args(&["generate", "wireguard-keypair"])
-                                    .output();
-                                
-                                if let Ok(output) = output {
-                                    if output.status.success() {
-                                        let output_str = String::from_utf8_lossy(&output.stdout);
-                                        let mut priv_key = String::new();
-                                        let mut pub_key = String::new();
-                                        
-                                        for line in output_str.lines() {
-                                            if let Some(key) = line.strip_prefix("PrivateKey:") {
-                                                priv_key = key.trim().to_string();
-                                            } else if let Some(key) = line.strip_prefix("PublicKey:") {
-                                                pub_key = key.trim().to_string();
-                                            }
-                                        }
-                                        (priv_key, pub_key)
-                                    } else {
-                                        continue;
-                                    }
-                                } else {
-                                    continue;
-                                }
-                            };
-
-                            let client_ip = format!("10.10.0.{}/32", 2 + client_outbounds.len());
-                            
-                            use crate::singbox::client_generator::ClientAmneziaWgOutbound;
-                            client_outbounds.push(ClientOutbound::AmneziaWg(ClientAmneziaWgOutbound {
-                                tag,
-                                server: address,
-                                server_port: port,
-                                local_address: vec![client_ip],
-                                private_key: client_priv,
-                                peer_public_key: settings.private_key,
-                                preshared_key: None,
-                                jc: settings.jc,
-                                jmin: settings.jmin,
-                                jmax: settings.jmax,
-                                s1: settings.s1,
-                                s2: settings.s2,
-                                h1: settings.h1,
-                                h2: settings.h2,
-                                h3: settings.h3,
-                                h4: settings.h4,
-                            }));
-                        }
-                    },
-                    _ => {}
-                }
+                    }
+                },
+                "amneziawg" => {
+                    if let Ok(InboundType::AmneziaWg(settings)) = serde_json::from_str::<InboundType>(&inbound.settings) {
+                        let Some(peer_public_key) = crate::services::awg_service::public_key(&settings.private_key) else {
+                            error!("AmneziaWG inbound {} has an invalid private key", inbound.tag);
+                            continue;
+                        };
+                        let peer = self.awg().peer_for(sub.id, inbound.id, &settings.subnet).await?;
+
+                        use crate::singbox::client_generator::ClientAmneziaWgOutbound;
+                        client_outbounds.push(ClientOutbound::AmneziaWg(ClientAmneziaWgOutbound {
+                            tag,
+                            server: address,
+                            server_port: port,
+                            local_address: vec![format!("{}/32", peer.client_ip)],
+                            private_key: peer.private_key,
+                            peer_public_key,
+                            preshared_key: peer.preshared_key,
+                            jc: settings.jc,
+                            jmin: settings.jmin,
+                            jmax: settings.jmax,
+                            s1: settings.s1,
+                            s2: settings.s2,
+                            h1: settings.h1,
+                            h2: settings.h2,
+                            h3: settings.h3,
+                            h4: settings.h4,
+                        }));
+                    }
+                },
+                _ => {}
             }
         }
 
-        let profile = ClientGenerator::generate(client_outbounds, "ru");
-        Ok(serde_json::to_string_pretty(&profile)?)
+        Ok(client_outbounds)
     }
 
     pub async fn generate_subscription_links(&self, user_id: i64) -> Result<Vec<String>> {
@@ -1404,6 +1798,7 @@ impl StoreService {
                     security: Some("none".to_string()),
                     tls_settings: None,
                     reality_settings: None,
+                    xhttp_settings: None,
                 });
                 let security = stream.security.as_deref().unwrap_or("none");
                 let network = stream.network.as_deref().unwrap_or("tcp");
@@ -1451,6 +1846,14 @@ impl StoreService {
                         }
                         
                         params.push(format!("type={}", network));
+
+                        if network == "xhttp" && let Some(xhttp) = &stream.xhttp_settings {
+                            params.push(format!("path={}", urlencoding::encode(&xhttp.path)));
+                            params.push(format!("mode={}", xhttp.mode));
+                            if let Some(host) = &xhttp.host {
+                                params.push(format!("host={}", host));
+                            }
+                        }
                         
                         if network == "tcp" {
                              params.push("headerType=none".to_string());
@@ -1489,6 +1892,9 @@ impl StoreService {
                                     params.push(format!("obfs-password={}", obfs.password));
                                 }
                             }
+                            if let Some(range) = settings.port_hopping {
+                                params.push(format!("mport={}", range));
+                            }
                         }
 
                         // Use TG ID just like in config generation
@@ -1503,6 +1909,38 @@ impl StoreService {
                         let link = format!("hysteria2://{}@{}:{}?{}#{}", auth, address, port, params.join("&"), remark);
                         links.push(link);
                     },
+                    "shadowsocks" => {
+                        // SIP002 with a 2022 cipher: userinfo is percent-encoded "method:server_psk:user_psk", not base64
+                        use crate::models::network::InboundType;
+                        if let Ok(InboundType::Shadowsocks(settings)) = serde_json::from_str::<InboundType>(&inbound.settings) {
+                            let userinfo = format!(
+                                "{}:{}",
+                                settings.method,
+                                urlencoding::encode(&format!("{}:{}", settings.password, settings.user_key(&uuid)))
+                            );
+                            links.push(format!("ss://{}@{}:{}#{}", userinfo, address, port, remark));
+                        }
+                    },
+                    "tuic" => {
+                        // tuic://uuid:password@ip:port?congestion_control=bbr&alpn=h3&sni=...#remark
+                        use crate::models::network::InboundType;
+                        if let Ok(InboundType::Tuic(settings)) = serde_json::from_str::<InboundType>(&inbound.settings) {
+                            let mut params = vec![
+                                format!("congestion_control={}", settings.congestion_control),
+                                "udp_relay_mode=native".to_string(),
+                                "alpn=h3".to_string(),
+                            ];
+                            if let Some(tls) = stream.tls_settings {
+                                let insecure = matches!(tls.server_name.as_str(), "drive.google.com" | "www.yahoo.com");
+                                params.push(format!("sni={}", tls.server_name));
+                                params.push(format!("allow_insecure={}", if insecure { "1" } else { "0" }));
+                            } else {
+                                params.push("allow_insecure=1".to_string());
+                            }
+                            let link = format!("tuic://{}:{}@{}:{}?{}#{}", uuid, uuid.replace("-", ""), address, port, params.join("&"), remark);
+                            links.push(link);
+                        }
+                    },
                     _ => {}
                 }
             }
@@ -1664,11 +2102,9 @@ impl StoreService {
                  
                  if refund_amount_cents > 0 {
                      // Credit User
-                     sqlx::query("UPDATE users SET balance = balance + ? WHERE id = ?")
-                        .bind(refund_amount_cents)
-                        .bind(sub.user_id)
-                        .execute(&mut *tx)
-                        .await?;
+                     LedgerService::post(&mut tx, Posting::credit(sub.user_id, refund_amount_cents, Reason::Refund)
+                        .reference("subscription", sub.id)
+                        .note(format!("Plan deleted, {} days unused", remaining_days))).await?;
                         
                      // Log Transaction/Activity
                      // We don't have a transaction log table for "refunds" specifically yet in schema shown, 
@@ -1712,18 +2148,20 @@ impl StoreService {
 
     // ========== Quick Wins Features ==========
     
-    /// Toggle auto-renewal for a subscription
-    pub async fn toggle_auto_renewal(&self, subscription_id: i64) -> Result<bool> {
-        let current: bool = sqlx::query_scalar::<_, Option<i32>>("SELECT auto_renew FROM subscriptions WHERE id = ?")
+    /// Toggle auto-renewal for one of the user's subscriptions
+    pub async fn toggle_auto_renewal(&self, user_id: i64, subscription_id: i64) -> Result<bool> {
+        let current: bool = sqlx::query_scalar::<_, Option<i32>>("SELECT auto_renew FROM subscriptions WHERE id = ? AND user_id = ?")
             .bind(subscription_id)
-            .fetch_one(&self.pool)
+            .bind(user_id)
+            .fetch_optional(&self.pool)
             .await?
+            .context("Subscription not found")?
             .map(|v| v != 0)
             .unwrap_or(false);
         
         let new_value = !current;
         
-        sqlx::query("UPDATE subscriptions SET auto_renew = ? WHERE id = ?")
+        sqlx::query("UPDATE subscriptions SET auto_renew = ?, renewal_attempts = 0, next_renewal_at = NULL WHERE id = ?")
             .bind(new_value as i32)
             .bind(subscription_id)
             .execute(&self.pool)
@@ -1731,47 +2169,157 @@ impl StoreService {
         
         Ok(new_value)
     }
+
+    /// Turn auto-renewal on with the given duration of the subscription's plan
+    pub async fn set_renewal_duration(&self, user_id: i64, subscription_id: i64, duration_id: i64) -> Result<PlanDuration> {
+        let duration = sqlx::query_as::<_, PlanDuration>(
+            "SELECT pd.* FROM plan_durations pd
+             JOIN subscriptions s ON s.plan_id = pd.plan_id
+             WHERE pd.id = ? AND s.id = ? AND s.user_id = ? AND COALESCE(pd.is_active, 1) = 1"
+        )
+        .bind(duration_id)
+        .bind(subscription_id)
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await?
+        .context("Duration not available for this subscription")?;
+
+        sqlx::query("UPDATE subscriptions SET auto_renew = 1, duration_id = ?, renewal_attempts = 0, next_renewal_at = NULL WHERE id = ?")
+            .bind(duration.id)
+            .bind(subscription_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(duration)
+    }
+
+    /// One of the user's subscriptions with the durations it can renew with
+    pub async fn get_renewal_options(&self, user_id: i64, subscription_id: i64) -> Result<(Subscription, Vec<PlanDuration>)> {
+        let sub = sqlx::query_as::<_, Subscription>("SELECT * FROM subscriptions WHERE id = ? AND user_id = ?")
+            .bind(subscription_id)
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await?
+            .context("Subscription not found")?;
+
+        let durations = sqlx::query_as::<_, PlanDuration>(
+            "SELECT * FROM plan_durations WHERE plan_id = ? AND COALESCE(is_active, 1) = 1 ORDER BY duration_days"
+        )
+        .bind(sub.plan_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok((sub, durations))
+    }
+
+    /// The duration a renewal charges: the remembered one while it is still sold,
+    /// otherwise the plan's default, otherwise its shortest
+    pub async fn renewal_duration(&self, plan_id: i64, duration_id: Option<i64>) -> Result<Option<PlanDuration>> {
+        Ok(sqlx::query_as::<_, PlanDuration>(
+            "SELECT * FROM plan_durations
+             WHERE plan_id = ? AND COALESCE(is_active, 1) = 1
+             ORDER BY (id = ?) DESC, COALESCE(is_default, 0) DESC, duration_days
+             LIMIT 1"
+        )
+        .bind(plan_id)
+        .bind(duration_id)
+        .fetch_optional(&self.pool)
+        .await?)
+    }
     
-    /// Process all auto-renewals for subscriptions expiring in next 24h
-    pub async fn process_auto_renewals(&self) -> Result<Vec<RenewalResult>> {
-        let subs = sqlx::query_as::<_, (i64, i64, i64, String, i64)>(
-            "SELECT s.id, s.user_id, s.plan_id, p.name, u.balance 
+    /// Renew auto-renewing subscriptions that expire within 24h or sit in their grace period.
+    /// A renewal that can't be paid is retried every `retry_hours`, up to `max_attempts` times.
+    pub async fn process_auto_renewals(&self, policy: &RenewalPolicy) -> Result<Vec<RenewalResult>> {
+        // Subscriptions extended some other way start the next cycle with a clean slate
+        sqlx::query(
+            "UPDATE subscriptions SET renewal_attempts = 0, next_renewal_at = NULL
+             WHERE renewal_attempts > 0 AND datetime(expires_at) > datetime('now', '+1 day')"
+        )
+        .execute(&self.pool)
+        .await?;
+
+        let subs = sqlx::query_as::<_, (i64, i64, i64, String, Option<i64>, i64, chrono::DateTime<Utc>)>(
+            "SELECT s.id, s.user_id, s.plan_id, p.name, s.duration_id, s.renewal_attempts, s.expires_at
              FROM subscriptions s
-             JOIN users u ON s.user_id = u.id
              JOIN plans p ON s.plan_id = p.id
              WHERE COALESCE(s.auto_renew, 0) = 1
-             AND s.status = 'active'
-             AND datetime(s.expires_at) BETWEEN datetime('now') AND datetime('now', '+1 day')"
+             AND s.status IN ('active', 'limited')
+             AND datetime(s.expires_at) <= datetime('now', '+1 day')
+             AND (s.next_renewal_at IS NULL OR datetime(s.next_renewal_at) <= datetime('now'))
+             AND s.renewal_attempts < ?"
         )
+        .bind(policy.max_attempts)
         .fetch_all(&self.pool)
         .await?;
         
         let mut results = vec![];
         
-        for (sub_id, user_id, plan_id, plan_name, balance) in subs {
-            let price = sqlx::query_scalar::<_, i64>(
-                "SELECT price FROM plan_durations WHERE plan_id = ? ORDER BY duration_days LIMIT 1"
-            )
-            .bind(plan_id)
-            .fetch_one(&self.pool)
-            .await?;
-            
-            if balance >= price {
-                sqlx::query("UPDATE subscriptions SET expires_at = datetime(expires_at, '+30 days') WHERE id = ?")
+        for (sub_id, user_id, plan_id, plan_name, duration_id, attempts, expires_at) in subs {
+            let Some(duration) = self.renewal_duration(plan_id, duration_id).await? else {
+                sqlx::query("UPDATE subscriptions SET auto_renew = 0 WHERE id = ?")
                     .bind(sub_id)
                     .execute(&self.pool)
                     .await?;
+                results.push(RenewalResult::Unavailable { user_id, sub_id, plan_name });
+                continue;
+            };
+
+            let mut tx = self.pool.begin().await?;
+            let balance: i64 = sqlx::query_scalar("SELECT balance FROM users WHERE id = ?")
+                .bind(user_id)
+                .fetch_one(&mut *tx)
+                .await?;
+
+            if balance >= duration.price {
+                // Extended from the old expiry, so days spent in grace are part of the paid period
+                let new_expires_at: chrono::DateTime<Utc> = sqlx::query_scalar(
+                    "UPDATE subscriptions
+                     SET expires_at = datetime(expires_at, '+' || ? || ' days'), duration_id = ?, renewal_attempts = 0, next_renewal_at = NULL
+                     WHERE id = ?
+                     RETURNING expires_at"
+                )
+                .bind(duration.duration_days)
+                .bind(duration.id)
+                .bind(sub_id)
+                .fetch_one(&mut *tx)
+                .await?;
                 
-                sqlx::query("UPDATE users SET balance = balance - ? WHERE id = ?")
-                    .bind(price)
-                    .bind(user_id)
-                    .execute(&self.pool)
-                    .await?;
+                LedgerService::post(&mut tx, Posting::debit(user_id, duration.price, Reason::Renewal)
+                    .reference("subscription", sub_id)
+                    .note(format!("Auto-renewal, {} days", duration.duration_days))).await?;
+                let quota_change = Self::start_paid_traffic_period(&mut tx, sub_id).await?;
+                tx.commit().await?;
                 
-                info!("Auto-renewed subscription {} for user {}", sub_id, user_id);
-                results.push(RenewalResult::Success { user_id, sub_id, amount: price, plan_name });
+                info!("Auto-renewed subscription {} for user {} ({} days)", sub_id, user_id, duration.duration_days);
+                results.push(RenewalResult::Success {
+                    user_id,
+                    sub_id,
+                    amount: duration.price,
+                    plan_name,
+                    days: duration.duration_days,
+                    expires_at: new_expires_at,
+                    quota_change,
+                });
             } else {
-                results.push(RenewalResult::InsufficientFunds { user_id, sub_id, required: price, available: balance });
+                let attempts = attempts + 1;
+                let next_attempt = (attempts < policy.max_attempts)
+                    .then(|| Utc::now() + Duration::hours(policy.retry_hours));
+                sqlx::query("UPDATE subscriptions SET renewal_attempts = ?, next_renewal_at = ? WHERE id = ?")
+                    .bind(attempts)
+                    .bind(next_attempt)
+                    .bind(sub_id)
+                    .execute(&mut *tx)
+                    .await?;
+                tx.commit().await?;
+
+                results.push(RenewalResult::InsufficientFunds {
+                    user_id,
+                    sub_id,
+                    required: duration.price,
+                    available: balance,
+                    next_attempt,
+                    service_until: expires_at + Duration::hours(policy.grace_hours),
+                });
             }
         }
         
@@ -1810,12 +2358,14 @@ impl StoreService {
     pub async fn create_trial_subscription(&self, user_id: i64, plan_id: i64, duration_days: i64) -> Result<i64> {
         let sub_id: i64 = sqlx::query_scalar(
             "INSERT INTO subscriptions 
-             (user_id, plan_id, status, expires_at, used_traffic, is_trial, created_at) 
-             VALUES (?, ?, 'active', datetime('now', '+' || ? || ' days'), 0, 1, CURRENT_TIMESTAMP) 
+             (user_id, plan_id, vless_uuid, subscription_uuid, status, expires_at, used_traffic, is_trial, created_at) 
+             VALUES (?, ?, ?, ?, 'active', datetime('now', '+' || ? || ' days'), 0, 1, CURRENT_TIMESTAMP) 
              RETURNING id"
         )
         .bind(user_id)
         .bind(plan_id)
+        .bind(Uuid::new_v4().to_string())
+        .bind(Uuid::new_v4().to_string())
         .bind(duration_days)
         .fetch_one(&self.pool)
         .await?;
@@ -1824,23 +2374,229 @@ impl StoreService {
         Ok(sub_id)
     }
     
+    pub async fn get_traffic_packs(&self) -> Result<Vec<TrafficPack>> {
+        Ok(sqlx::query_as::<_, TrafficPack>("SELECT * FROM traffic_packs WHERE is_active = 1 ORDER BY traffic_gb ASC")
+            .fetch_all(&self.pool)
+            .await?)
+    }
+
+    pub async fn add_traffic_pack(&self, traffic_gb: i32, price: i64) -> Result<()> {
+        if traffic_gb <= 0 || price < 0 {
+            return Err(anyhow::anyhow!("Traffic and price must be positive"));
+        }
+        sqlx::query("INSERT INTO traffic_packs (traffic_gb, price) VALUES (?, ?)")
+            .bind(traffic_gb)
+            .bind(price)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Packs are retired rather than deleted, so past purchases keep their context
+    pub async fn remove_traffic_pack(&self, id: i64) -> Result<()> {
+        sqlx::query("UPDATE traffic_packs SET is_active = 0 WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Pay for a traffic pack from the balance and add it to the subscription's current cycle.
+    /// A `limited` subscription with room again is reactivated; the change is returned.
+    pub async fn buy_traffic_pack(&self, user_id: i64, sub_id: i64, pack_id: i64) -> Result<(Subscription, TrafficPack, Option<QuotaChange>)> {
+        let mut tx = self.pool.begin().await?;
+
+        let sub = sqlx::query_as::<_, Subscription>("SELECT * FROM subscriptions WHERE id = ? AND user_id = ?")
+            .bind(sub_id)
+            .bind(user_id)
+            .fetch_optional(&mut *tx)
+            .await?
+            .context("Subscription not found")?;
+        if sub.status != "active" && sub.status != "limited" {
+            return Err(anyhow::anyhow!("Only active subscriptions can get extra traffic"));
+        }
+        let limit_gb: i32 = sqlx::query_scalar("SELECT traffic_limit_gb FROM plans WHERE id = ?")
+            .bind(sub.plan_id)
+            .fetch_one(&mut *tx)
+            .await?;
+        if limit_gb <= 0 {
+            return Err(anyhow::anyhow!("This plan has unlimited traffic"));
+        }
+
+        let pack = sqlx::query_as::<_, TrafficPack>("SELECT * FROM traffic_packs WHERE id = ? AND is_active = 1")
+            .bind(pack_id)
+            .fetch_optional(&mut *tx)
+            .await?
+            .context("Traffic pack is not available")?;
+
+        LedgerService::post(&mut tx, Posting::debit(user_id, pack.price, Reason::Purchase)
+            .reference("subscription", sub_id)
+            .actor("user")
+            .note(format!("{} +{} GB", TRAFFIC_PACK_NOTE, pack.traffic_gb))).await?;
+
+        // Alerts re-arm against the raised quota
+        sqlx::query("UPDATE subscriptions SET traffic_extra_bytes = traffic_extra_bytes + ?, alerts_sent = '[]' WHERE id = ?")
+            .bind(pack.traffic_gb as i64 * GIB)
+            .bind(sub_id)
+            .execute(&mut *tx)
+            .await?;
+        let change = Self::sync_quota_status(&mut tx, Some(sub_id)).await?.pop();
+
+        let sub = sqlx::query_as::<_, Subscription>("SELECT * FROM subscriptions WHERE id = ?")
+            .bind(sub_id)
+            .fetch_one(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        info!("User {} added {} GB to subscription {}", user_id, pack.traffic_gb, sub_id);
+        Ok((sub, pack, change))
+    }
+
+    /// Move subscriptions between `active` and `limited` by their quota, one or all of them
+    async fn sync_quota_status(conn: &mut sqlx::SqliteConnection, sub_id: Option<i64>) -> Result<Vec<QuotaChange>> {
+        let mut changes = sqlx::query_as::<_, QuotaChange>(&format!(
+            "UPDATE subscriptions SET status = 'limited'
+             WHERE status = 'active' AND {QUOTA_EXHAUSTED} AND (? IS NULL OR id = ?)
+             RETURNING id AS sub_id, user_id, plan_id, 1 AS limited"
+        ))
+        .bind(sub_id)
+        .bind(sub_id)
+        .fetch_all(&mut *conn)
+        .await?;
+
+        changes.extend(sqlx::query_as::<_, QuotaChange>(&format!(
+            "UPDATE subscriptions SET status = 'active'
+             WHERE status = 'limited' AND NOT {QUOTA_EXHAUSTED} AND (? IS NULL OR id = ?)
+             RETURNING id AS sub_id, user_id, plan_id, 0 AS limited"
+        ))
+        .bind(sub_id)
+        .bind(sub_id)
+        .fetch_all(&mut *conn)
+        .await?);
+
+        Ok(changes)
+    }
+
+    /// Limit subscriptions that used up their quota and release those with traffic again
+    pub async fn enforce_traffic_quotas(&self) -> Result<Vec<QuotaChange>> {
+        let mut conn = self.pool.acquire().await?;
+        Self::sync_quota_status(&mut conn, None).await
+    }
+
+    /// Book the traffic a node reported since its last report. Agents name users by Telegram ID,
+    /// so the bytes go to the user's oldest active subscription whose plan includes the node.
+    pub async fn book_node_traffic(&self, node_id: i64, traffic: &[exarobot_shared::api::UserTraffic]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        let mut total: i64 = 0;
+
+        for usage in traffic {
+            let Ok(tg_id) = usage.user.parse::<i64>() else { continue };
+            let bytes = usage.upload.saturating_add(usage.download).min(i64::MAX as u64) as i64;
+            if bytes == 0 {
+                continue;
+            }
+
+            let booked = sqlx::query(
+                "UPDATE subscriptions SET used_traffic = used_traffic + ?, traffic_updated_at = CURRENT_TIMESTAMP
+                 WHERE id = (
+                     SELECT s.id FROM subscriptions s
+                     JOIN users u ON u.id = s.user_id
+                     WHERE u.tg_id = ? AND s.status = 'active'
+                       AND EXISTS (
+                           SELECT 1 FROM plan_inbounds pi JOIN inbounds i ON i.id = pi.inbound_id
+                           WHERE pi.plan_id = s.plan_id AND i.node_id = ?
+                       )
+                     ORDER BY s.id LIMIT 1
+                 )"
+            )
+            .bind(bytes)
+            .bind(tg_id)
+            .bind(node_id)
+            .execute(&mut *tx)
+            .await?;
+
+            if booked.rows_affected() > 0 {
+                total = total.saturating_add(bytes);
+            }
+        }
+
+        tx.commit().await?;
+
+        if total > 0 {
+            crate::services::analytics_service::AnalyticsService::track_traffic(&self.pool, total).await?;
+        }
+        Ok(())
+    }
+
+    /// Start a new traffic cycle at `start`: usage and add-on packs go back to zero
+    async fn reset_traffic_cycle(conn: &mut sqlx::SqliteConnection, sub_id: i64, start: DateTime<Utc>) -> Result<Option<QuotaChange>> {
+        sqlx::query(
+            "UPDATE subscriptions SET used_traffic = 0, traffic_extra_bytes = 0, traffic_reset_at = ?, alerts_sent = '[]' WHERE id = ?"
+        )
+        .bind(start)
+        .bind(sub_id)
+        .execute(&mut *conn)
+        .await?;
+        Ok(Self::sync_quota_status(conn, Some(sub_id)).await?.pop())
+    }
+
+    /// A newly paid period restarts the quota of billing-cycle plans; other plans only get
+    /// their quota re-checked, e.g. after moving to a plan with a different limit
+    async fn start_paid_traffic_period(conn: &mut sqlx::SqliteConnection, sub_id: i64) -> Result<Option<QuotaChange>> {
+        let period: String = sqlx::query_scalar(
+            "SELECT p.traffic_reset_period FROM subscriptions s JOIN plans p ON p.id = s.plan_id WHERE s.id = ?"
+        )
+        .bind(sub_id)
+        .fetch_one(&mut *conn)
+        .await?;
+
+        if period == "billing_cycle" {
+            Self::reset_traffic_cycle(conn, sub_id, Utc::now()).await
+        } else {
+            Ok(Self::sync_quota_status(conn, Some(sub_id)).await?.pop())
+        }
+    }
+
+    /// Reset monthly quotas whose month is over, counting from the previous reset
+    pub async fn reset_monthly_traffic(&self) -> Result<Vec<QuotaChange>> {
+        let subs = sqlx::query_as::<_, (i64, DateTime<Utc>)>(
+            "SELECT s.id, COALESCE(s.traffic_reset_at, s.created_at)
+             FROM subscriptions s
+             JOIN plans p ON p.id = s.plan_id
+             WHERE p.traffic_reset_period = 'monthly' AND s.status IN ('active', 'limited')"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let now = Utc::now();
+        let mut changes = Vec::new();
+        for (sub_id, anchor) in subs {
+            let (start, _) = monthly_cycle(anchor, now);
+            if start == anchor {
+                continue;
+            }
+            let mut tx = self.pool.begin().await?;
+            changes.extend(Self::reset_traffic_cycle(&mut tx, sub_id, start).await?);
+            tx.commit().await?;
+            info!("Monthly traffic reset of subscription {}", sub_id);
+        }
+        Ok(changes)
+    }
+
     /// Check and send traffic/expiry alerts (returns list of users who need alerts)
     pub async fn check_traffic_alerts(&self) -> Result<Vec<(i64, AlertType, i64)>> {
         let mut alerts_to_send = vec![];
         
         let subs = sqlx::query_as::<_, (i64, i64, i64, i64, String)>(
-            "SELECT s.id, s.user_id, s.used_traffic, pd.traffic_gb, COALESCE(s.alerts_sent, '[]') 
+            "SELECT s.id, s.user_id, s.used_traffic, p.traffic_limit_gb * 1073741824 + s.traffic_extra_bytes, COALESCE(s.alerts_sent, '[]') 
              FROM subscriptions s
-             JOIN plan_durations pd ON s.plan_id = pd.plan_id
-             WHERE s.status = 'active' AND pd.traffic_gb > 0"
+             JOIN plans p ON s.plan_id = p.id
+             WHERE s.status = 'active' AND p.traffic_limit_gb > 0"
         )
         .fetch_all(&self.pool)
         .await?;
         
-        for (sub_id, user_id, used_bytes, traffic_gb, alerts_json) in subs {
-            if traffic_gb == 0 { continue; }
-            
-            let total_bytes = traffic_gb as i64 * 1024 * 1024 * 1024;
+        for (sub_id, user_id, used_bytes, total_bytes, alerts_json) in subs {
             let percentage = (used_bytes as f64 / total_bytes as f64) * 100.0;
             
             let mut alerts: Vec<String> = serde_json::from_str(&alerts_json).unwrap_or_default();
@@ -1928,13 +2684,6 @@ impl StoreService {
             return Err(anyhow::anyhow!("Insufficient balance. Need {}, have {}", total_price, balance));
         }
 
-        // Deduct balance
-        sqlx::query("UPDATE users SET balance = balance - ? WHERE id = ?")
-            .bind(total_price)
-            .bind(user_id)
-            .execute(&mut *tx)
-            .await?;
-
         // Create order
         let order_id: i64 = sqlx::query_scalar("INSERT INTO orders (user_id, total_amount, status) VALUES (?, ?, 'completed') RETURNING id")
             .bind(user_id)
@@ -1942,6 +2691,12 @@ impl StoreService {
             .fetch_one(&mut *tx)
             .await?;
 
+        // Deduct balance
+        LedgerService::post(&mut tx, Posting::debit(user_id, total_price, Reason::Purchase)
+            .reference("order", order_id)
+            .actor("user")
+            .note("Cart checkout")).await?;
+
         // Add order items
         for item in cart {
             sqlx::query("INSERT INTO order_items (order_id, product_id, quantity, price) VALUES (?, ?, ?, ?)")
@@ -1979,3 +2734,136 @@ pub struct CartItem {
     pub product_name: String,
     pub price: i64,
 }
+
+/// What is left of the money paid for a subscription, rounded down. `movements` are its ledger
+/// amounts in booking order: charges are negative, and a credit means an earlier plan change
+/// settled everything paid before it. The remaining charges are spread evenly from the first of
+/// them to `expires_at`, so gifted or admin-granted time dilutes the value instead of adding to it.
+fn unused_paid_value(movements: &[(i64, DateTime<Utc>)], expires_at: DateTime<Utc>, now: DateTime<Utc>) -> i64 {
+    let mut paid: i64 = 0;
+    let mut since = None;
+    for &(amount, at) in movements {
+        if amount > 0 {
+            paid = 0;
+            since = None;
+        } else {
+            paid += -amount;
+            since.get_or_insert(at);
+        }
+    }
+
+    let Some(since) = since else { return 0 };
+    let span = (expires_at - since).num_seconds();
+    let remaining = (expires_at - now).num_seconds();
+    if paid <= 0 || span <= 0 || remaining <= 0 {
+        return 0;
+    }
+    (paid as i128 * remaining.min(span) as i128 / span as i128) as i64
+}
+
+/// The month-long traffic cycle, counted from `anchor`, that `now` falls in: its start and the next reset
+fn monthly_cycle(anchor: DateTime<Utc>, now: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+    let mut start = anchor;
+    // Always added to the anchor, so a cycle begun on the 31st returns to the 31st after shorter months
+    for months in 1.. {
+        match anchor.checked_add_months(Months::new(months)) {
+            Some(next) if next > now => return (start, next),
+            Some(next) => start = next,
+            None => break,
+        }
+    }
+    (start, start)
+}
+
+/// Ledger note of a purchase, naming the promo code that discounted it
+fn promo_note(note: String, discount: Option<&Discount>) -> String {
+    match discount {
+        Some(d) => format!("{}, promo {} -{}", note, d.code, Money::usd_cents(d.amount)),
+        None => note,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unused_paid_value() {
+        let day = Duration::days(1);
+        let start = DateTime::parse_from_rfc3339("2026-01-01T00:00:00Z").unwrap().with_timezone(&Utc);
+        let expires = start + day * 30;
+
+        assert_eq!(unused_paid_value(&[(-1000, start)], expires, start + day * 15), 500);
+        assert_eq!(unused_paid_value(&[(-1000, start)], expires, start + day * 29), 33);
+        assert_eq!(unused_paid_value(&[(-1000, start)], expires, expires + day), 0);
+        // Extended with a second period: both charges are still largely ahead
+        assert_eq!(unused_paid_value(&[(-1000, start), (-1000, start + day * 10)], expires + day * 30, start + day * 15), 1500);
+        // Admin-granted days after a single paid month only stretch what was paid
+        assert_eq!(unused_paid_value(&[(-1000, start)], expires + day * 30, start), 1000);
+        // Gifted or admin subscriptions have no charge to give back
+        assert_eq!(unused_paid_value(&[], expires, start), 0);
+        // A plan change settled the old charge; only the new one counts
+        assert_eq!(unused_paid_value(&[(-1000, start), (800, start + day), (-600, start + day)], start + day * 31, start + day), 600);
+    }
+
+    #[tokio::test]
+    async fn test_plan_change_credits_no_more_than_paid() {
+        let pool = crate::db::test_pool().await;
+        let store = StoreService::new(pool.clone());
+
+        let user_id: i64 = sqlx::query_scalar("INSERT INTO users (tg_id, username, balance) VALUES (1001, 'buyer', 0) RETURNING id")
+            .fetch_one(&pool).await.unwrap();
+        let mut durations = Vec::new();
+        for name in ["Basic", "Pro"] {
+            let plan_id: i64 = sqlx::query_scalar("INSERT INTO plans (name, price, is_active) VALUES (?, 0, 1) RETURNING id")
+                .bind(name)
+                .fetch_one(&pool).await.unwrap();
+            let duration_id: i64 = sqlx::query_scalar(
+                "INSERT INTO plan_durations (plan_id, duration_days, price) VALUES (?, 30, 1000) RETURNING id"
+            )
+            .bind(plan_id)
+            .fetch_one(&pool).await.unwrap();
+            durations.push((plan_id, duration_id));
+        }
+
+        // A month of Basic bought for 500 instead of 1000 with a promo code
+        let sub_id: i64 = sqlx::query_scalar(
+            "INSERT INTO subscriptions (user_id, plan_id, vless_uuid, subscription_uuid, expires_at, status, duration_id)
+             VALUES (?, ?, 'u', 's', ?, 'active', ?) RETURNING id"
+        )
+        .bind(user_id)
+        .bind(durations[0].0)
+        .bind(Utc::now() + Duration::days(30))
+        .bind(durations[0].1)
+        .fetch_one(&pool).await.unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        LedgerService::post(&mut conn, Posting::credit(user_id, 500, Reason::Topup)).await.unwrap();
+        LedgerService::post(&mut conn, Posting::debit(user_id, 500, Reason::Purchase)
+            .reference("subscription", sub_id)
+            .note("30 days, promo HALF -$5.00")).await.unwrap();
+        drop(conn);
+
+        let quote = store.quote_plan_change(user_id, sub_id, durations[1].1).await.unwrap();
+        assert!(quote.credit <= 500, "credit {} exceeds the 500 paid", quote.credit);
+
+        // Switching back and forth never turns the discount into balance
+        LedgerService::post(&mut pool.acquire().await.unwrap(), Posting::credit(user_id, 1000, Reason::Topup)).await.unwrap();
+        store.change_plan(user_id, sub_id, durations[1].1).await.unwrap();
+        store.change_plan(user_id, sub_id, durations[0].1).await.unwrap();
+        let balance: i64 = sqlx::query_scalar("SELECT balance FROM users WHERE id = ?")
+            .bind(user_id)
+            .fetch_one(&pool).await.unwrap();
+        // 1500 topped up, and a month of Basic at list price is now paid for in full
+        assert!(balance <= 500, "balance {} kept part of the promo discount", balance);
+    }
+
+    #[test]
+    fn test_monthly_cycle() {
+        let at = |s: &str| DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc);
+        let anchor = at("2026-01-31T10:00:00Z");
+
+        assert_eq!(monthly_cycle(anchor, at("2026-02-15T00:00:00Z")), (anchor, at("2026-02-28T10:00:00Z")));
+        assert_eq!(monthly_cycle(anchor, at("2026-03-01T00:00:00Z")), (at("2026-02-28T10:00:00Z"), at("2026-03-31T10:00:00Z")));
+        assert_eq!(monthly_cycle(anchor, at("2026-05-31T10:00:00Z")), (at("2026-05-31T10:00:00Z"), at("2026-06-30T10:00:00Z")));
+    }
+}
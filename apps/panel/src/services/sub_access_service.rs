@@ -0,0 +1,307 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use exarobot_shared::client_detect::{ClientProfile, SubFormat};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use sqlx::{FromRow, SqlitePool};
+use std::net::IpAddr;
+use std::sync::Arc;
+use tokio::time::{interval, Duration};
+use tracing::{error, info, warn};
+
+use crate::services::geoip_service::GeoIpService;
+use crate::services::store_service::StoreService;
+use crate::settings::SettingsService;
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct SharingFlag {
+    pub id: i64,
+    pub subscription_id: i64,
+    pub window_hours: i64,
+    pub fetches: i64,
+    pub distinct_networks: i64,
+    pub distinct_countries: i64,
+    pub distinct_clients: i64,
+    pub action: String,
+    pub created_at: DateTime<Utc>,
+    pub dismissed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct AccessLogEntry {
+    pub subscription_id: i64,
+    pub network: String,
+    pub asn_org: Option<String>,
+    pub country: Option<String>,
+    pub user_agent: Option<String>,
+    pub client: String,
+    pub accessed_at: DateTime<Utc>,
+}
+
+/// What the detector does besides recording a flag
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SharingAction {
+    Flag,
+    /// Also bump the user's warning counter
+    Warn,
+}
+
+impl SharingAction {
+    fn parse(value: &str) -> Self {
+        match value {
+            "warn" => Self::Warn,
+            _ => Self::Flag,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Flag => "flag",
+            Self::Warn => "warn",
+        }
+    }
+}
+
+#[derive(FromRow)]
+struct Suspect {
+    subscription_id: i64,
+    user_id: i64,
+    fetches: i64,
+    distinct_networks: i64,
+    distinct_countries: i64,
+    distinct_clients: i64,
+}
+
+/// Subscription fetch log and link-sharing detection
+pub struct SubAccessService {
+    pool: SqlitePool,
+    settings: Arc<SettingsService>,
+    geoip: Arc<GeoIpService>,
+    store_service: Arc<StoreService>,
+    /// HMAC key for IP hashes, so the log cannot be reversed with a rainbow table
+    ip_key: String,
+}
+
+impl SubAccessService {
+    pub fn new(
+        pool: SqlitePool,
+        settings: Arc<SettingsService>,
+        geoip: Arc<GeoIpService>,
+        store_service: Arc<StoreService>,
+        ip_key: String,
+    ) -> Self {
+        Self { pool, settings, geoip, store_service, ip_key }
+    }
+
+    /// Log one fetch. A retention of 0 days turns logging off.
+    pub async fn record(&self, subscription_id: i64, ip: Option<IpAddr>, user_agent: Option<&str>, profile: &ClientProfile) -> Result<()> {
+        if self.retention_days().await <= 0 {
+            return Ok(());
+        }
+
+        let (ip_hash, network, asn_org, country) = match ip {
+            Some(ip) => {
+                let info = self.geoip.lookup(ip).await;
+                let network = match info.asn {
+                    Some(asn) => format!("AS{}", asn),
+                    None => format!("net:{}", self.hash(&network_prefix(ip))),
+                };
+                (self.hash(&ip.to_string()), network, info.asn_org, info.country)
+            }
+            None => ("unknown".to_string(), "unknown".to_string(), None, None),
+        };
+
+        sqlx::query(
+            "INSERT INTO subscription_access_log (subscription_id, ip_hash, network, asn_org, country, user_agent, client)
+             VALUES (?, ?, ?, ?, ?, ?, ?)"
+        )
+        .bind(subscription_id)
+        .bind(ip_hash)
+        .bind(network)
+        .bind(asn_org)
+        .bind(country)
+        .bind(user_agent.map(|ua| ua.chars().take(256).collect::<String>()))
+        .bind(client_family(user_agent, profile))
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn start(&self) {
+        info!("Starting subscription sharing detector...");
+        let mut interval = interval(Duration::from_secs(3600)); // Every hour
+
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.prune().await {
+                error!("Failed to prune subscription access log: {}", e);
+            }
+            match self.detect().await {
+                Ok(0) => {}
+                Ok(flagged) => info!("Flagged {} subscriptions for possible link sharing", flagged),
+                Err(e) => error!("Sharing detection failed: {}", e),
+            }
+        }
+    }
+
+    async fn retention_days(&self) -> i64 {
+        self.settings.get_or_default("sub_access_retention_days", "30").await.parse().unwrap_or(30)
+    }
+
+    pub async fn prune(&self) -> Result<u64> {
+        let days = self.retention_days().await.max(0);
+        let res = sqlx::query("DELETE FROM subscription_access_log WHERE accessed_at < datetime('now', ?)")
+            .bind(format!("-{} days", days))
+            .execute(&self.pool)
+            .await?;
+        Ok(res.rows_affected())
+    }
+
+    /// Flag active subscriptions fetched from more networks or client apps than a single
+    /// customer plausibly uses within the window. A subscription is flagged at most once per window.
+    pub async fn detect(&self) -> Result<usize> {
+        let window_hours: i64 = self.settings.get_or_default("sharing_window_hours", "24").await.parse().unwrap_or(24);
+        let max_networks: i64 = self.settings.get_or_default("sharing_max_networks", "5").await.parse().unwrap_or(5);
+        let max_clients: i64 = self.settings.get_or_default("sharing_max_clients", "3").await.parse().unwrap_or(3);
+        let action = SharingAction::parse(&self.settings.get_or_default("sharing_action", "flag").await);
+
+        if window_hours <= 0 || (max_networks <= 0 && max_clients <= 0) {
+            return Ok(0);
+        }
+        let since = format!("-{} hours", window_hours);
+
+        let suspects = sqlx::query_as::<_, Suspect>(
+            "SELECT l.subscription_id, s.user_id, COUNT(*) AS fetches,
+                    COUNT(DISTINCT l.network) AS distinct_networks,
+                    COUNT(DISTINCT l.country) AS distinct_countries,
+                    COUNT(DISTINCT CASE WHEN l.client != 'browser' THEN l.client END) AS distinct_clients
+             FROM subscription_access_log l
+             JOIN subscriptions s ON s.id = l.subscription_id
+             WHERE l.accessed_at >= datetime('now', ?1) AND s.status = 'active'
+               AND NOT EXISTS (
+                   SELECT 1 FROM subscription_sharing_flags f
+                   WHERE f.subscription_id = l.subscription_id AND f.created_at >= datetime('now', ?1)
+               )
+             GROUP BY l.subscription_id, s.user_id
+             HAVING (?2 > 0 AND distinct_networks > ?2) OR (?3 > 0 AND distinct_clients > ?3)"
+        )
+        .bind(&since)
+        .bind(max_networks)
+        .bind(max_clients)
+        .fetch_all(&self.pool)
+        .await?;
+
+        for suspect in &suspects {
+            warn!(
+                "Possible link sharing on subscription {}: {} networks, {} countries, {} clients in {}h",
+                suspect.subscription_id, suspect.distinct_networks, suspect.distinct_countries, suspect.distinct_clients, window_hours
+            );
+
+            sqlx::query(
+                "INSERT INTO subscription_sharing_flags
+                 (subscription_id, window_hours, fetches, distinct_networks, distinct_countries, distinct_clients, action)
+                 VALUES (?, ?, ?, ?, ?, ?, ?)"
+            )
+            .bind(suspect.subscription_id)
+            .bind(window_hours)
+            .bind(suspect.fetches)
+            .bind(suspect.distinct_networks)
+            .bind(suspect.distinct_countries)
+            .bind(suspect.distinct_clients)
+            .bind(action.as_str())
+            .execute(&self.pool)
+            .await?;
+
+            if action == SharingAction::Warn
+                && let Err(e) = self.store_service.increment_warning_count(suspect.user_id).await
+            {
+                error!("Failed to warn user {}: {}", suspect.user_id, e);
+            }
+        }
+
+        Ok(suspects.len())
+    }
+
+    pub async fn flags_for_user(&self, user_id: i64) -> Result<Vec<SharingFlag>> {
+        Ok(sqlx::query_as::<_, SharingFlag>(
+            "SELECT f.* FROM subscription_sharing_flags f
+             JOIN subscriptions s ON s.id = f.subscription_id
+             WHERE s.user_id = ? ORDER BY f.created_at DESC LIMIT 20"
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    pub async fn recent_for_user(&self, user_id: i64, limit: i64) -> Result<Vec<AccessLogEntry>> {
+        Ok(sqlx::query_as::<_, AccessLogEntry>(
+            "SELECT l.subscription_id, l.network, l.asn_org, l.country, l.user_agent, l.client, l.accessed_at
+             FROM subscription_access_log l
+             JOIN subscriptions s ON s.id = l.subscription_id
+             WHERE s.user_id = ? ORDER BY l.accessed_at DESC LIMIT ?"
+        )
+        .bind(user_id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    pub async fn dismiss_flag(&self, flag_id: i64) -> Result<()> {
+        sqlx::query("UPDATE subscription_sharing_flags SET dismissed_at = CURRENT_TIMESTAMP WHERE id = ?")
+            .bind(flag_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    fn hash(&self, value: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.ip_key.as_bytes()).expect("HMAC accepts any key length");
+        mac.update(value.as_bytes());
+        hex::encode(&mac.finalize().into_bytes()[..12])
+    }
+}
+
+/// /24 for IPv4, /48 for IPv6: roughly one household or one small provider block
+fn network_prefix(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(v4) => {
+            let o = v4.octets();
+            format!("{}.{}.{}.0/24", o[0], o[1], o[2])
+        }
+        IpAddr::V6(v6) => {
+            let s = v6.segments();
+            format!("{:x}:{:x}:{:x}::/48", s[0], s[1], s[2])
+        }
+    }
+}
+
+/// App family for counting distinct clients: the User-Agent product token ("v2rayng",
+/// "clashmetaforandroid"), so versions of one app count once. Landing page visits are "browser".
+fn client_family(user_agent: Option<&str>, profile: &ClientProfile) -> String {
+    if profile.format == SubFormat::Landing {
+        return "browser".to_string();
+    }
+    user_agent
+        .and_then(|ua| ua.split(['/', ' ', ';', '(']).map(str::trim).find(|t| !t.is_empty()))
+        .map(|token| token.chars().take(32).collect::<String>().to_ascii_lowercase())
+        .unwrap_or_else(|| profile.name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exarobot_shared::client_detect::detect;
+
+    #[test]
+    fn test_client_family_and_prefix() {
+        let ua = "v2rayNG/1.8.19";
+        assert_eq!(client_family(Some(ua), &detect(ua)), "v2rayng");
+        let chrome = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) Chrome/126.0";
+        assert_eq!(client_family(Some(chrome), &detect(chrome)), "browser");
+        assert_eq!(client_family(None, &detect("")), "singbox");
+
+        assert_eq!(network_prefix("203.0.113.77".parse().unwrap()), "203.0.113.0/24");
+        assert_eq!(network_prefix("2001:db8:abcd:12::1".parse().unwrap()), "2001:db8:abcd::/48");
+    }
+}
@@ -1,9 +1,7 @@
 use tracing::{info, error};
 use tokio::time::{interval, Duration};
 use crate::AppState;
-use chrono::Utc;
-use crate::models::node::Node;
-use crate::services::analytics_service::AnalyticsService;
+use crate::services::store_service::QuotaChange;
 
 pub struct TrafficService {
     state: AppState,
@@ -14,96 +12,67 @@ impl TrafficService {
         Self { state }
     }
 
+    /// Agents book usage with every connection report, so quotas are checked at the same pace
     pub async fn start(&self) {
         info!("Starting background traffic monitoring service...");
-        let mut interval = interval(Duration::from_secs(600)); // Every 10 minutes
+        let mut interval = interval(Duration::from_secs(60));
 
         loop {
             interval.tick().await;
-            if let Err(e) = self.sync_traffic().await {
+            if let Err(e) = self.enforce_quotas().await {
                 error!("Traffic monitoring error: {}", e);
             }
         }
     }
 
-    async fn sync_traffic(&self) -> anyhow::Result<()> {
-        info!("Syncing traffic usage from all active nodes...");
-        
-        let active_nodes: Vec<Node> = sqlx::query_as("SELECT * FROM nodes WHERE status = 'active'")
-            .fetch_all(&self.state.pool)
-            .await?;
+    /// Start monthly traffic cycles that are due, then limit subscriptions over their quota and
+    /// release those with traffic again.
+    async fn enforce_quotas(&self) -> anyhow::Result<()> {
+        let mut changes = self.state.store_service.reset_monthly_traffic().await?;
+        changes.extend(self.state.store_service.enforce_traffic_quotas().await?);
 
-        for node in active_nodes {
-            // TODO: Implement traffic fetching via Agent API
-            // For now, traffic is tracked via Clash API directly
-            info!("Node {} traffic sync skipped (Agent API not implemented yet)", node.id);
+        if changes.is_empty() {
+            return Ok(());
         }
 
-        // After syncing, enforce quotas
-        self.enforce_quotas().await?;
+        info!("Traffic quota changed the status of {} subscriptions", changes.len());
 
-        Ok(())
-    }
+        for change in &changes {
+            let msg = if change.limited {
+                info!("Subscription {} for user {} limited (quota reached)", change.sub_id, change.user_id);
+                "🚫 *Traffic Limit Reached*\n\n\
+                 Your subscription has used all of its traffic\\.\n\
+                 Add a traffic pack in *My Services* to continue\\."
+            } else {
+                info!("Subscription {} for user {} has traffic again", change.sub_id, change.user_id);
+                "✅ *Traffic Available*\n\n\
+                 Your subscription has traffic again and is active\\."
+            };
 
-    #[allow(dead_code)]
-    async fn process_node_usage(&self, _node_id: i64, usage: serde_json::Value) -> anyhow::Result<()> {
-        if let Some(users_usage) = usage.get("users") {
-            if let Some(users_map) = users_usage.as_object() {
-                let mut tx = self.state.pool.begin().await?;
-                for (user_tag, bytes_val) in users_map {
-                    if let Some(bytes) = bytes_val.as_u64() {
-                        if user_tag.starts_with("user_") {
-                            if let Ok(sub_id) = user_tag[5..].parse::<i64>() {
-                                sqlx::query("UPDATE subscriptions SET used_traffic = used_traffic + ?, traffic_updated_at = ? WHERE id = ?")
-                                    .bind(bytes as i64)
-                                    .bind(Utc::now())
-                                    .bind(sub_id)
-                                    .execute(&mut *tx)
-                                    .await?;
-                                
-                                // Track traffic in analytics
-                                let _ = AnalyticsService::track_traffic(&self.state.pool, bytes as i64).await;
-                            }
-                        }
-                    }
-                }
-                tx.commit().await?;
+            if let Ok(Some((tg_id,))) = sqlx::query_as::<_, (i64,)>("SELECT tg_id FROM users WHERE id = ?")
+                .bind(change.user_id)
+                .fetch_optional(&self.state.pool)
+                .await {
+                let _ = self.state.bot_manager.send_notification(tg_id, msg).await;
             }
         }
-        
+
+        publish_quota_changes(&self.state, &changes).await;
         Ok(())
     }
+}
 
-    async fn enforce_quotas(&self) -> anyhow::Result<()> {
-        // Find subscriptions that exceeded their plan's traffic limit
-        let overloaded_subs: Vec<(i64, i64, i32)> = sqlx::query_as(
-            "SELECT s.id, s.user_id, p.traffic_limit_gb 
-             FROM subscriptions s
-             JOIN plans p ON s.plan_id = p.id
-             WHERE s.status = 'active' 
-             AND p.traffic_limit_gb > 0 
-             AND s.used_traffic >= (CAST(p.traffic_limit_gb AS BIGINT) * 1024 * 1024 * 1024)"
-        )
-        .fetch_all(&self.state.pool)
-        .await?;
-
-        if overloaded_subs.is_empty() {
-            return Ok(());
-        }
-
-        info!("Found {} subscriptions exceeding quota. Suspending...", overloaded_subs.len());
+/// Tell the nodes of the changed subscriptions' plans to rebuild their user lists
+pub async fn publish_quota_changes(state: &AppState, changes: &[QuotaChange]) {
+    let mut plan_ids: Vec<i64> = changes.iter().map(|c| c.plan_id).collect();
+    plan_ids.sort_unstable();
+    plan_ids.dedup();
 
-        for (sub_id, user_id, limit) in overloaded_subs {
-            sqlx::query("UPDATE subscriptions SET status = 'expired' WHERE id = ?")
-                .bind(sub_id)
-                .execute(&self.state.pool)
-                .await?;
-            
-            info!("Subscription {} for user {} suspended (Limit: {} GB reached)", sub_id, user_id, limit);
+    for plan_id in plan_ids {
+        for node_id in state.store_service.get_plan_node_ids(plan_id).await.unwrap_or_default() {
+            if let Err(e) = state.pubsub.publish(&format!("node_events:{}", node_id), "update").await {
+                error!("Failed to notify node {} about traffic quotas: {}", node_id, e);
+            }
         }
-
-        // Agents pull config automatically - no sync needed
-
-        Ok(())
     }
 }
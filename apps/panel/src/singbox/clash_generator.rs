@@ -0,0 +1,265 @@
+use anyhow::Result;
+use serde_json::{json, Value};
+use crate::singbox::client_generator::{ClientGenerator, ClientOutbound, ClientTlsConfig, LATENCY_TEST_URL};
+use crate::singbox::routing::{ads_rule_set, RoutingPolicy, RuleSetKind, RuleSetRef};
+
+const PROXY_GROUP: &str = "🚀 Proxy";
+const AUTO_GROUP: &str = "⚡ Auto";
+const FALLBACK_GROUP: &str = "🛟 Fallback";
+
+/// Clash Meta (Mihomo) profile built from the same outbounds and routing as the sing-box profile
+pub struct ClashGenerator;
+
+impl ClashGenerator {
+    pub fn generate(proxies: &[ClientOutbound], routing: &RoutingPolicy) -> Result<String> {
+        let clash_proxies: Vec<Value> = proxies.iter().filter_map(Self::proxy).collect();
+        let names: Vec<String> = clash_proxies.iter()
+            .filter_map(|p| p["name"].as_str().map(|s| s.to_string()))
+            .collect();
+        let groups = ClientGenerator::protocol_groups(proxies);
+        // Mihomo rejects url-test/fallback groups without members
+        let tested = if names.is_empty() { vec!["DIRECT".to_string()] } else { names.clone() };
+
+        // Groups: manual selector, then latency/fallback over everything, then per protocol
+        let mut selector = vec![AUTO_GROUP.to_string(), FALLBACK_GROUP.to_string()];
+        selector.extend(groups.iter().map(|(tag, _)| tag.to_string()));
+        selector.extend(names);
+
+        let mut proxy_groups = vec![
+            json!({ "name": PROXY_GROUP, "type": "select", "proxies": selector }),
+            json!({
+                "name": AUTO_GROUP, "type": "url-test", "proxies": tested,
+                "url": LATENCY_TEST_URL, "interval": 180, "tolerance": 50
+            }),
+            json!({
+                "name": FALLBACK_GROUP, "type": "fallback", "proxies": tested,
+                "url": LATENCY_TEST_URL, "interval": 180
+            }),
+        ];
+        for (tag, members) in groups {
+            proxy_groups.push(json!({
+                "name": tag, "type": "url-test", "proxies": members,
+                "url": LATENCY_TEST_URL, "interval": 600, "tolerance": 50
+            }));
+        }
+
+        // Regional direct routing; private ranges always go direct
+        let mut rule_providers = serde_json::Map::new();
+        let mut rules = Vec::new();
+        let private = RuleSetRef::geoip("private");
+        for rule_set in std::iter::once(&private).chain(routing.rule_sets().iter()) {
+            let tag = rule_set.tag();
+            rule_providers.insert(tag.clone(), json!({
+                "type": "http",
+                "behavior": rule_set.mihomo_behavior(),
+                "format": "mrs",
+                "url": rule_set.mihomo_url(),
+                "path": format!("./ruleset/{}.mrs", tag),
+                "interval": 86400
+            }));
+        }
+        rules.push(format!("RULE-SET,{},DIRECT,no-resolve", private.tag()));
+        if routing.block_ads {
+            rules.push(format!("RULE-SET,{},REJECT", ads_rule_set().tag()));
+        }
+        for domain in &routing.bypass_domains {
+            rules.push(format!("DOMAIN-SUFFIX,{},DIRECT", domain));
+        }
+        for rule_set in &routing.direct {
+            match rule_set.kind {
+                RuleSetKind::Geosite => rules.push(format!("RULE-SET,{},DIRECT", rule_set.tag())),
+                RuleSetKind::Geoip => rules.push(format!("RULE-SET,{},DIRECT,no-resolve", rule_set.tag())),
+            }
+        }
+        rules.push(format!("MATCH,{}", PROXY_GROUP));
+
+        let config = json!({
+            "mixed-port": 7890,
+            "allow-lan": false,
+            "mode": "rule",
+            "log-level": "warning",
+            "ipv6": false,
+            "unified-delay": true,
+            "tcp-concurrent": true,
+            "dns": Self::dns(routing),
+            "proxies": clash_proxies,
+            "proxy-groups": proxy_groups,
+            "rule-providers": rule_providers,
+            "rules": rules
+        });
+
+        Ok(serde_yaml::to_string(&config)?)
+    }
+
+    /// fake-ip DNS; proxied names resolve through the tunnel, direct regional names locally
+    fn dns(routing: &RoutingPolicy) -> Value {
+        let remote = format!("{}#{}", routing.remote_dns, PROXY_GROUP);
+        let bootstrap = routing.local_dns.clone().unwrap_or_else(|| "1.1.1.1".to_string());
+
+        let mut dns = json!({
+            "enable": true,
+            "ipv6": false,
+            "enhanced-mode": "fake-ip",
+            "fake-ip-range": "198.18.0.1/16",
+            "fake-ip-filter": ["*.lan", "*.local", "+.msftconnecttest.com", "+.msftncsi.com", "time.*.com", "ntp.*.com"],
+            "default-nameserver": [bootstrap],
+            "proxy-server-nameserver": [bootstrap],
+            "nameserver": [remote]
+        });
+
+        if let Some(local_dns) = &routing.local_dns {
+            let policy: serde_json::Map<String, Value> = routing.direct_geosites()
+                .map(|r| format!("rule-set:{}", r.tag()))
+                .chain(routing.bypass_domains.iter().map(|d| format!("+.{}", d)))
+                .map(|key| (key, json!(local_dns)))
+                .collect();
+            if !policy.is_empty() {
+                dns["nameserver-policy"] = Value::Object(policy);
+            }
+        }
+        dns
+    }
+
+    fn proxy(outbound: &ClientOutbound) -> Option<Value> {
+        let proxy = match outbound {
+            ClientOutbound::Vless(v) => {
+                let mut p = json!({
+                    "name": v.tag, "type": "vless", "server": v.server, "port": v.server_port,
+                    "uuid": v.uuid, "network": "tcp", "udp": true,
+                    "tls": v.tls.as_ref().is_some_and(|t| t.enabled)
+                });
+                if let Some(flow) = &v.flow {
+                    p["flow"] = json!(flow);
+                }
+                if let Some(tls) = &v.tls {
+                    apply_tls(&mut p, tls, "servername");
+                    if let Some(reality) = &tls.reality {
+                        p["reality-opts"] = json!({ "public-key": reality.public_key, "short-id": reality.short_id });
+                    }
+                }
+                p
+            }
+            ClientOutbound::Hysteria2(h) => {
+                let mut p = json!({
+                    "name": h.tag, "type": "hysteria2", "server": h.server, "port": h.server_port,
+                    "password": h.password
+                });
+                apply_tls(&mut p, &h.tls, "sni");
+                if let Some(obfs) = &h.obfs {
+                    p["obfs"] = json!(obfs.ttype);
+                    p["obfs-password"] = json!(obfs.password);
+                }
+                p
+            }
+            ClientOutbound::Trojan(t) => {
+                let mut p = json!({
+                    "name": t.tag, "type": "trojan", "server": t.server, "port": t.server_port,
+                    "password": t.password, "udp": true
+                });
+                apply_tls(&mut p, &t.tls, "sni");
+                if let Some(reality) = &t.tls.reality {
+                    p["reality-opts"] = json!({ "public-key": reality.public_key, "short-id": reality.short_id });
+                }
+                p
+            }
+            ClientOutbound::Tuic(t) => {
+                let mut p = json!({
+                    "name": t.tag, "type": "tuic", "server": t.server, "port": t.server_port,
+                    "uuid": t.uuid, "password": t.password,
+                    "congestion-controller": t.congestion_control.as_deref().unwrap_or("bbr"),
+                    "udp-relay-mode": t.udp_relay_mode.as_deref().unwrap_or("native"),
+                    "reduce-rtt": true
+                });
+                apply_tls(&mut p, &t.tls, "sni");
+                p
+            }
+            ClientOutbound::Shadowsocks(ss) => json!({
+                "name": ss.tag, "type": "ss", "server": ss.server, "port": ss.server_port,
+                "cipher": ss.method, "password": ss.password, "udp": true
+            }),
+            ClientOutbound::AmneziaWg(a) => {
+                let ip = a.local_address.first().map(|addr| addr.split('/').next().unwrap_or(addr).to_string());
+                let mut p = json!({
+                    "name": a.tag, "type": "wireguard", "server": a.server, "port": a.server_port,
+                    "ip": ip, "private-key": a.private_key, "public-key": a.peer_public_key,
+                    "udp": true, "mtu": 1280,
+                    "amnezia-wg-option": {
+                        "jc": a.jc, "jmin": a.jmin, "jmax": a.jmax, "s1": a.s1, "s2": a.s2,
+                        "h1": a.h1, "h2": a.h2, "h3": a.h3, "h4": a.h4
+                    }
+                });
+                if let Some(psk) = &a.preshared_key {
+                    p["pre-shared-key"] = json!(psk);
+                }
+                p
+            }
+            _ => return None,
+        };
+        Some(proxy)
+    }
+}
+
+/// SNI, certificate check, ALPN and uTLS fingerprint. VLESS calls the SNI field `servername`.
+fn apply_tls(proxy: &mut Value, tls: &ClientTlsConfig, sni_key: &str) {
+    if !tls.server_name.is_empty() {
+        proxy[sni_key] = json!(tls.server_name);
+    }
+    proxy["skip-cert-verify"] = json!(tls.insecure);
+    if let Some(alpn) = &tls.alpn {
+        proxy["alpn"] = json!(alpn);
+    }
+    if let Some(utls) = tls.utls.as_ref().filter(|u| u.enabled) {
+        proxy["client-fingerprint"] = json!(utls.fingerprint);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::singbox::client_generator::{ClientRealityConfig, ClientTrojanOutbound};
+
+    fn profile(proxies: &[ClientOutbound]) -> Value {
+        let yaml = ClashGenerator::generate(proxies, &RoutingPolicy::global()).unwrap();
+        serde_yaml::from_str(&yaml).unwrap()
+    }
+
+    #[test]
+    fn test_trojan_reality_proxy() {
+        let trojan = ClientOutbound::Trojan(ClientTrojanOutbound {
+            tag: "de-trojan".to_string(),
+            server: "203.0.113.5".to_string(),
+            server_port: 443,
+            password: "secret".to_string(),
+            tls: ClientTlsConfig {
+                enabled: true,
+                server_name: "www.microsoft.com".to_string(),
+                insecure: false,
+                alpn: None,
+                utls: None,
+                reality: Some(ClientRealityConfig { enabled: true, public_key: "pbk".to_string(), short_id: "ab".to_string() }),
+            },
+        });
+
+        let config = profile(&[trojan]);
+        let proxy = &config["proxies"][0];
+        assert_eq!(proxy["type"], "trojan");
+        assert_eq!(proxy["sni"], "www.microsoft.com");
+        assert_eq!(proxy["reality-opts"]["public-key"], "pbk");
+
+        let trojan_group = config["proxy-groups"].as_array().unwrap().iter()
+            .find(|g| g["name"] == "⚡ Trojan")
+            .unwrap();
+        assert_eq!(trojan_group["proxies"][0], "de-trojan");
+    }
+
+    #[test]
+    fn test_empty_profile_falls_back_to_direct() {
+        let config = profile(&[]);
+        let groups = config["proxy-groups"].as_array().unwrap();
+        assert_eq!(groups.len(), 3);
+        for group in &groups[1..] {
+            assert_eq!(group["proxies"], json!(["DIRECT"]));
+        }
+        assert_eq!(config["rules"].as_array().unwrap().last().unwrap(), &json!(format!("MATCH,{}", PROXY_GROUP)));
+    }
+}
@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
-use crate::singbox::config::{LogConfig, RouteConfig, RouteRule};
+use crate::singbox::config::LogConfig;
+use crate::singbox::routing::{ads_rule_set, RoutingPolicy};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ClientProfile {
@@ -7,13 +8,15 @@ pub struct ClientProfile {
     pub dns: Option<DnsConfig>,
     pub inbounds: Vec<ClientInbound>,
     pub outbounds: Vec<ClientOutbound>,
-    pub route: RouteConfig,
+    pub route: ClientRoute,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct DnsConfig {
     pub servers: Vec<DnsServer>,
     pub rules: Vec<DnsRule>,
+    #[serde(rename = "final", skip_serializing_if = "Option::is_none")]
+    pub final_server: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -23,12 +26,51 @@ pub struct DnsServer {
     pub detour: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct DnsRule {
-    pub outbound: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rule_set: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub domain_suffix: Option<Vec<String>>,
     pub server: String,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ClientRoute {
+    pub rules: Vec<ClientRouteRule>,
+    pub rule_set: Vec<ClientRuleSet>,
+    #[serde(rename = "final")]
+    pub final_outbound: String,
+    pub auto_detect_interface: bool,
+}
+
+/// sing-box 1.11+ route rule (rule actions instead of the removed `dns` outbound)
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ClientRouteRule {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub action: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub outbound: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip_is_private: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub domain_suffix: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rule_set: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ClientRuleSet {
+    #[serde(rename = "type")]
+    pub ttype: String, // remote
+    pub tag: String,
+    pub format: String, // binary
+    pub url: String,
+    pub download_detour: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "type")]
 pub enum ClientInbound {
@@ -76,6 +118,41 @@ pub enum ClientOutbound {
     Hysteria2(ClientHysteria2Outbound),
     #[serde(rename = "wireguard")]
     AmneziaWg(ClientAmneziaWgOutbound),
+    #[serde(rename = "trojan")]
+    Trojan(ClientTrojanOutbound),
+    #[serde(rename = "tuic")]
+    Tuic(ClientTuicOutbound),
+    #[serde(rename = "shadowsocks")]
+    Shadowsocks(ClientShadowsocksOutbound),
+}
+
+impl ClientOutbound {
+    pub fn tag(&self) -> &str {
+        match self {
+            ClientOutbound::Selector { tag, .. }
+            | ClientOutbound::UrlTest { tag, .. }
+            | ClientOutbound::Direct { tag } => tag,
+            ClientOutbound::Vless(v) => &v.tag,
+            ClientOutbound::Hysteria2(h) => &h.tag,
+            ClientOutbound::AmneziaWg(a) => &a.tag,
+            ClientOutbound::Trojan(t) => &t.tag,
+            ClientOutbound::Tuic(t) => &t.tag,
+            ClientOutbound::Shadowsocks(s) => &s.tag,
+        }
+    }
+
+    /// Latency group a proxy belongs to; None for groups and `direct`
+    pub fn protocol_group(&self) -> Option<&'static str> {
+        match self {
+            ClientOutbound::Vless(_) => Some("⚡ Reality"),
+            ClientOutbound::Hysteria2(_) => Some("⚡ Hysteria2"),
+            ClientOutbound::AmneziaWg(_) => Some("⚡ AmneziaWG"),
+            ClientOutbound::Trojan(_) => Some("⚡ Trojan"),
+            ClientOutbound::Tuic(_) => Some("⚡ TUIC"),
+            ClientOutbound::Shadowsocks(_) => Some("⚡ Shadowsocks"),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -122,6 +199,36 @@ pub struct ClientHysteria2Outbound {
     pub obfs: Option<ClientObfs>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ClientTrojanOutbound {
+    pub tag: String,
+    pub server: String,
+    pub server_port: u16,
+    pub password: String,
+    pub tls: ClientTlsConfig,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ClientTuicOutbound {
+    pub tag: String,
+    pub server: String,
+    pub server_port: u16,
+    pub uuid: String,
+    pub password: String,
+    pub congestion_control: Option<String>, // bbr, cubic, new_reno
+    pub udp_relay_mode: Option<String>, // native, quic
+    pub tls: ClientTlsConfig,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ClientShadowsocksOutbound {
+    pub tag: String,
+    pub server: String,
+    pub server_port: u16,
+    pub method: String,
+    pub password: String, // "server_psk:user_psk" for 2022 multi-user
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ClientTlsConfig {
     pub enabled: bool,
@@ -152,140 +259,123 @@ pub struct ClientObfs {
     pub password: String,
 }
 
+pub const LATENCY_TEST_URL: &str = "http://www.gstatic.com/generate_204";
+
 pub struct ClientGenerator;
 
 impl ClientGenerator {
+    /// Proxy tags per protocol group, in a stable order. Shared with the Clash profile.
+    pub fn protocol_groups(proxies: &[ClientOutbound]) -> Vec<(&'static str, Vec<String>)> {
+        let mut groups: Vec<(&'static str, Vec<String>)> = Vec::new();
+        for p in proxies {
+            let Some(group) = p.protocol_group() else { continue };
+            match groups.iter_mut().find(|(tag, _)| *tag == group) {
+                Some((_, members)) => members.push(p.tag().to_string()),
+                None => groups.push((group, vec![p.tag().to_string()])),
+            }
+        }
+        groups
+    }
+
     /// Generates a full Sing-box Client Profile (JSON)
     /// Aggregates multiple proxies into a "Best Latency" group and a "Select" group.
     pub fn generate(
-        proxies: Vec<ClientOutbound>, 
-        country_code: &str // "ru" might trigger specific optimized DNS
+        proxies: Vec<ClientOutbound>,
+        routing: &RoutingPolicy,
     ) -> ClientProfile {
         
-        let mut outbounds = Vec::new();
-        let mut all_proxy_tags = Vec::new();
-        let mut reality_tags = Vec::new();
-        let mut hysteria2_tags = Vec::new();
-        let mut awg_tags = Vec::new();
+        let groups = Self::protocol_groups(&proxies);
+        let all_proxy_tags: Vec<String> = proxies.iter()
+            .filter(|p| p.protocol_group().is_some())
+            .map(|p| p.tag().to_string())
+            .collect();
 
-        // 1. Add Actual Proxies + Group by Protocol
-        for p in proxies {
-            match &p {
-                ClientOutbound::Vless(v) => {
-                    all_proxy_tags.push(v.tag.clone());
-                    reality_tags.push(v.tag.clone());
-                },
-                ClientOutbound::Hysteria2(h) => {
-                    all_proxy_tags.push(h.tag.clone());
-                    hysteria2_tags.push(h.tag.clone());
-                },
-                ClientOutbound::AmneziaWg(a) => {
-                    all_proxy_tags.push(a.tag.clone());
-                    awg_tags.push(a.tag.clone());
-                },
-                _ => {}
-            }
-            outbounds.push(p);
-        }
-
-        // 2. Create Protocol-Specific UrlTest Groups
-        let mut protocol_group_tags = Vec::new();
+        // 1. Selector (Manual): Protocol Groups First, then Individual Servers
+        let mut selector_tags = vec!["⚡ Auto".to_string()];
+        selector_tags.extend(groups.iter().map(|(tag, _)| tag.to_string()));
+        selector_tags.extend(all_proxy_tags.clone());
 
-        if !reality_tags.is_empty() {
-            let reality_group = ClientOutbound::UrlTest {
-                tag: "⚡ Reality".to_string(),
-                outbounds: reality_tags,
-                url: Some("http://www.gstatic.com/generate_204".to_string()),
-                interval: Some("10m".to_string()),
-                tolerance: Some(50),
-            };
-            protocol_group_tags.push("⚡ Reality".to_string());
-            outbounds.insert(0, reality_group);
-        }
+        let mut outbounds = vec![ClientOutbound::Selector {
+            tag: "🚀 Proxy".to_string(),
+            outbounds: selector_tags,
+            default: Some("⚡ Auto".to_string()),
+        }];
 
-        if !hysteria2_tags.is_empty() {
-            let hy2_group = ClientOutbound::UrlTest {
-                tag: "⚡ Hysteria2".to_string(),
-                outbounds: hysteria2_tags,
-                url: Some("http://www.gstatic.com/generate_204".to_string()),
-                interval: Some("10m".to_string()),
-                tolerance: Some(50),
-            };
-            protocol_group_tags.push("⚡ Hysteria2".to_string());
-            outbounds.insert(0, hy2_group);
-        }
+        // 2. "Auto Fast" (All Protocols); sing-box rejects an empty group, so fall back to direct
+        let auto_tags = if all_proxy_tags.is_empty() { vec!["direct".to_string()] } else { all_proxy_tags };
+        outbounds.push(ClientOutbound::UrlTest {
+            tag: "⚡ Auto".to_string(),
+            outbounds: auto_tags,
+            url: Some(LATENCY_TEST_URL.to_string()),
+            interval: Some("3m".to_string()),
+            tolerance: Some(50),
+        });
 
-        if !awg_tags.is_empty() {
-            let awg_group = ClientOutbound::UrlTest {
-                tag: "⚡ AmneziaWG".to_string(),
-                outbounds: awg_tags,
-                url: Some("http://www.gstatic.com/generate_204".to_string()),
+        // 3. Protocol-Specific UrlTest Groups
+        for (tag, members) in groups {
+            outbounds.push(ClientOutbound::UrlTest {
+                tag: tag.to_string(),
+                outbounds: members,
+                url: Some(LATENCY_TEST_URL.to_string()),
                 interval: Some("10m".to_string()),
                 tolerance: Some(50),
-            };
-            protocol_group_tags.push("⚡ AmneziaWG".to_string());
-            outbounds.insert(0, awg_group);
+            });
         }
 
-        // 3. Create "Auto Fast" (All Protocols)
-        let auto_group = ClientOutbound::UrlTest {
-            tag: "⚡ Auto".to_string(),
-            outbounds: all_proxy_tags.clone(),
-            url: Some("http://www.gstatic.com/generate_204".to_string()),
-            interval: Some("3m".to_string()),
-            tolerance: Some(50), 
-        };
-        outbounds.insert(0, auto_group);
-
-        // 4. Selector (Manual): Protocol Groups First, then Individual Servers
-        let mut selector_tags = vec!["⚡ Auto".to_string()];
-        selector_tags.extend(protocol_group_tags);
-        selector_tags.extend(all_proxy_tags);
-        
-        let select_group = ClientOutbound::Selector {
-            tag: "🚀 Proxy".to_string(),
-            outbounds: selector_tags,
-            default: Some("⚡ Auto".to_string()),
-        };
-        outbounds.insert(0, select_group);
+        // 4. Actual Proxies
+        outbounds.extend(proxies);
 
         // Direct
         outbounds.push(ClientOutbound::Direct { tag: "direct".to_string() });
 
-        // 3. DNS (Optimized for RU)
-        let dns = if country_code == "ru" {
-            Some(DnsConfig {
-                servers: vec![
-                    DnsServer { tag: "google".to_string(), address: "8.8.8.8".to_string(), detour: Some("🚀 Proxy".to_string()) },
-                    DnsServer { tag: "local".to_string(), address: "local".to_string(), detour: Some("direct".to_string()) },
-                ],
-                rules: vec![
-                    DnsRule { outbound: Some("direct".to_string()), server: "local".to_string() }, // Fallback logic or rule sets needed
-                ]
-            })
-        } else {
-            None
-        };
+        // 5. DNS: remote through the tunnel, regional names through the local resolver
+        let direct_geosite_tags: Vec<String> = routing.direct_geosites().map(|r| r.tag()).collect();
+        let mut dns_servers = vec![
+            DnsServer { tag: "remote".to_string(), address: routing.remote_dns.clone(), detour: Some("🚀 Proxy".to_string()) },
+        ];
+        let mut dns_rules = Vec::new();
+        if let Some(local_dns) = &routing.local_dns {
+            dns_servers.push(DnsServer { tag: "local".to_string(), address: local_dns.clone(), detour: Some("direct".to_string()) });
+            if !direct_geosite_tags.is_empty() {
+                dns_rules.push(DnsRule { rule_set: Some(direct_geosite_tags.clone()), server: "local".to_string(), ..Default::default() });
+            }
+            if !routing.bypass_domains.is_empty() {
+                dns_rules.push(DnsRule { domain_suffix: Some(routing.bypass_domains.clone()), server: "local".to_string(), ..Default::default() });
+            }
+        }
+        let dns = Some(DnsConfig { servers: dns_servers, rules: dns_rules, final_server: Some("remote".to_string()) });
+
+        // 6. Routes
+        let mut rules = vec![
+            ClientRouteRule { action: Some("sniff".to_string()), ..Default::default() },
+            ClientRouteRule { protocol: Some(vec!["dns".to_string()]), action: Some("hijack-dns".to_string()), ..Default::default() },
+            ClientRouteRule { ip_is_private: Some(true), outbound: Some("direct".to_string()), ..Default::default() },
+        ];
+        if routing.block_ads {
+            rules.push(ClientRouteRule { rule_set: Some(vec![ads_rule_set().tag()]), action: Some("reject".to_string()), ..Default::default() });
+        }
+        if !routing.bypass_domains.is_empty() {
+            rules.push(ClientRouteRule { domain_suffix: Some(routing.bypass_domains.clone()), outbound: Some("direct".to_string()), ..Default::default() });
+        }
+        if !routing.direct.is_empty() {
+            rules.push(ClientRouteRule {
+                rule_set: Some(routing.direct.iter().map(|r| r.tag()).collect()),
+                outbound: Some("direct".to_string()),
+                ..Default::default()
+            });
+        }
 
-        // 4. Routes
-        let route = RouteConfig {
-            rules: vec![
-                RouteRule {
-                    protocol: Some(vec!["dns".to_string()]),
-                    outbound: Some("dns-out".to_string()),
-                    action: None, port: None, domain: None, geosite: None, geoip: None
-                },
-                RouteRule {
-                    outbound: Some("direct".to_string()),
-                    domain: Some(vec!["geosite:cn".to_string(), "geosite:private".to_string()]), 
-                    action: None, port: None, protocol: None, geosite: None, geoip: None
-                },
-                // Default rule (implicit in sing-box if no match? No, needs default)
-                // Actually sing-box routes to first outbound used in "rules" or just select first one?
-                // Sing-box needs explicit final rule or it loops?
-                // Usually it picks the first outbound in the list as default if no rules match.
-                // Our first outbound is "🚀 Proxy".
-            ]
+        let route = ClientRoute {
+            rules,
+            rule_set: routing.rule_sets().iter().map(|r| ClientRuleSet {
+                ttype: "remote".to_string(),
+                tag: r.tag(),
+                format: "binary".to_string(),
+                url: r.singbox_url(),
+                download_detour: Some("🚀 Proxy".to_string()),
+            }).collect(),
+            final_outbound: "🚀 Proxy".to_string(),
+            auto_detect_interface: true,
         };
 
         ClientProfile {
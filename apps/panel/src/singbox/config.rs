@@ -39,6 +39,8 @@ pub enum Inbound {
     Vless(VlessInbound),
     Hysteria2(Hysteria2Inbound),
     AmneziaWg(AmneziaWgInbound),
+    Shadowsocks(ShadowsocksInbound),
+    Tuic(TuicInbound),
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -136,6 +138,9 @@ pub struct AmneziaWgInbound {
     pub listen_port: u16,
     pub users: Vec<AmneziaWgUser>,
     pub private_key: String,
+    /// Server tunnel address, e.g. 10.10.0.1/24
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub address: Vec<String>,
     // AmneziaWG specific fields
     pub jc: u16,
     pub jmin: u16,
@@ -155,9 +160,45 @@ pub struct AmneziaWgUser {
     pub public_key: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub preshared_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_ips: Vec<String>,
 }
 
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ShadowsocksInbound {
+    pub tag: String,
+    pub listen: String,
+    pub listen_port: u16,
+    pub method: String,
+    pub password: String,
+    pub users: Vec<ShadowsocksUser>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ShadowsocksUser {
+    pub name: String,
+    pub password: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TuicInbound {
+    pub tag: String,
+    pub listen: String,
+    pub listen_port: u16,
+    pub users: Vec<TuicUser>,
+    pub congestion_control: String,
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub zero_rtt_handshake: bool,
+    pub tls: Hysteria2TlsConfig,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TuicUser {
+    pub name: String,
+    pub uuid: String,
+    pub password: String,
+}
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "type", rename_all = "lowercase")]
 pub enum Outbound {
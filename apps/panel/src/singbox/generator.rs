@@ -1,5 +1,5 @@
 use crate::singbox::config::*;
-use crate::models::network::{StreamSettings as DbStreamSettings, InboundType};
+use crate::models::network::{StreamSettings as DbStreamSettings, TlsSettings as DbTlsSettings, InboundType};
 use tracing::{error, warn};
 
 pub struct ConfigGenerator;
@@ -81,36 +81,7 @@ impl ConfigGenerator {
                     }));
                 },
                 InboundType::Hysteria2(hy2) => {
-                    let mut tls_config = Hysteria2TlsConfig {
-                        enabled: true,
-                        server_name: "drive.google.com".to_string(), // Default or from stream
-                        key_path: Some("/etc/sing-box/certs/key.pem".to_string()),
-                        certificate_path: Some("/etc/sing-box/certs/cert.pem".to_string()),
-                        alpn: Some(vec!["h3".to_string()]),
-                    };
-
-                    if let Some(tls) = stream_settings.tls_settings {
-                         tls_config.server_name = tls.server_name;
-                         if let Some(certs) = tls.certificates {
-                             if let Some(first) = certs.first() {
-                                 // Only overwrite if not empty
-                                 if !first.key_path.is_empty() {
-                                     tls_config.key_path = Some(first.key_path.clone());
-                                 }
-                                 if !first.certificate_path.is_empty() {
-                                     tls_config.certificate_path = Some(first.certificate_path.clone());
-                                 }
-                             }
-                         }
-                    }
-
-                    // FINAL SAFEGUARD: If still None, force defaults
-                    if tls_config.key_path.is_none() {
-                        tls_config.key_path = Some("/etc/sing-box/certs/key.pem".to_string());
-                    }
-                    if tls_config.certificate_path.is_none() {
-                        tls_config.certificate_path = Some("/etc/sing-box/certs/cert.pem".to_string());
-                    }
+                    let tls_config = Self::quic_tls(stream_settings.tls_settings, "h3");
 
                     let users = hy2.users.iter().map(|u| Hysteria2User {
                         name: u.name.clone(),
@@ -154,6 +125,7 @@ impl ConfigGenerator {
                         name: Some(u.name.clone()),
                         public_key: u.public_key.clone(),
                         preshared_key: u.preshared_key.clone(),
+                        allowed_ips: vec![u.client_ip.clone()],
                     }).collect();
 
                     generated_inbounds.push(Inbound::AmneziaWg(AmneziaWgInbound {
@@ -161,6 +133,7 @@ impl ConfigGenerator {
                         listen: inbound.listen_ip,
                         listen_port: inbound.listen_port as u16,
                         users,
+                        address: crate::services::awg_service::server_address(&awg.subnet).into_iter().collect(),
                         private_key: awg.private_key,
                         // AmneziaWG specific fields
                         jc: awg.jc,
@@ -175,6 +148,38 @@ impl ConfigGenerator {
                     }));
                 },
 
+                InboundType::Shadowsocks(ss) => {
+                    let users = ss.users.iter().map(|u| ShadowsocksUser {
+                        name: u.name.clone(),
+                        password: u.password.clone(),
+                    }).collect();
+
+                    generated_inbounds.push(Inbound::Shadowsocks(ShadowsocksInbound {
+                        tag: inbound.tag,
+                        listen: inbound.listen_ip,
+                        listen_port: inbound.listen_port as u16,
+                        method: ss.method,
+                        password: ss.password,
+                        users,
+                    }));
+                },
+                InboundType::Tuic(tuic) => {
+                    let users = tuic.users.iter().map(|u| TuicUser {
+                        name: u.name.clone(),
+                        uuid: u.uuid.clone(),
+                        password: u.password.clone(),
+                    }).collect();
+
+                    generated_inbounds.push(Inbound::Tuic(TuicInbound {
+                        tag: inbound.tag,
+                        listen: inbound.listen_ip,
+                        listen_port: inbound.listen_port as u16,
+                        users,
+                        congestion_control: tuic.congestion_control,
+                        zero_rtt_handshake: tuic.zero_rtt_handshake,
+                        tls: Self::quic_tls(stream_settings.tls_settings, "h3"),
+                    }));
+                },
                 _ => {
                     warn!("Unsupported protocol for inbound {}", inbound.tag);
                 }
@@ -238,16 +243,50 @@ impl ConfigGenerator {
                     rules
                 }
             }),
-            // Enable Clash API for device monitoring and limit enforcement
+            // Enable Clash API for device monitoring and limit enforcement (proxied by the agent)
             experimental: Some(ExperimentalConfig {
                 clash_api: ClashApiConfig {
                     external_controller: "127.0.0.1:9090".to_string(),
-                    secret: None, // Internal access only, no auth needed
+                    secret: node.clash_secret.clone(), // Only the local agent talks to the controller
                     external_ui: None,
                 },
             }),
         }
     }
 
+    /// TLS for QUIC-based inbounds (Hysteria2, TUIC): node certificate unless the stream settings name one
+    fn quic_tls(tls_settings: Option<DbTlsSettings>, alpn: &str) -> Hysteria2TlsConfig {
+        let mut tls_config = Hysteria2TlsConfig {
+            enabled: true,
+            server_name: "drive.google.com".to_string(), // Default or from stream
+            key_path: Some("/etc/sing-box/certs/key.pem".to_string()),
+            certificate_path: Some("/etc/sing-box/certs/cert.pem".to_string()),
+            alpn: Some(vec![alpn.to_string()]),
+        };
+
+        if let Some(tls) = tls_settings {
+            tls_config.server_name = tls.server_name;
+            // Only overwrite if not empty
+            if let Some(first) = tls.certificates.as_ref().and_then(|certs| certs.first()) {
+                if !first.key_path.is_empty() {
+                    tls_config.key_path = Some(first.key_path.clone());
+                }
+                if !first.certificate_path.is_empty() {
+                    tls_config.certificate_path = Some(first.certificate_path.clone());
+                }
+            }
+        }
+
+        // FINAL SAFEGUARD: If still None, force defaults
+        if tls_config.key_path.is_none() {
+            tls_config.key_path = Some("/etc/sing-box/certs/key.pem".to_string());
+        }
+        if tls_config.certificate_path.is_none() {
+            tls_config.certificate_path = Some("/etc/sing-box/certs/cert.pem".to_string());
+        }
+
+        tls_config
+    }
+
 
 }
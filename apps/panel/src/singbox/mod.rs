@@ -2,6 +2,8 @@ pub mod config;
 pub mod reality;
 pub mod generator;
 pub mod client_generator;
+pub mod clash_generator;
+pub mod routing;
 pub mod subscription_generator;
 
 pub use generator::ConfigGenerator;
@@ -0,0 +1,128 @@
+/// Where a rule set's data comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleSetKind {
+    Geosite,
+    Geoip,
+}
+
+/// A community-maintained rule set, published for both sing-box (`.srs`) and Mihomo (`.mrs`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleSetRef {
+    pub kind: RuleSetKind,
+    /// Name inside the geosite/geoip database, e.g. "category-ru" or "ru"
+    pub name: String,
+}
+
+impl RuleSetRef {
+    pub fn geosite(name: &str) -> Self {
+        Self { kind: RuleSetKind::Geosite, name: name.to_string() }
+    }
+
+    pub fn geoip(name: &str) -> Self {
+        Self { kind: RuleSetKind::Geoip, name: name.to_string() }
+    }
+
+    /// Tag shared by both clients ("geosite-category-ru")
+    pub fn tag(&self) -> String {
+        match self.kind {
+            RuleSetKind::Geosite => format!("geosite-{}", self.name),
+            RuleSetKind::Geoip => format!("geoip-{}", self.name),
+        }
+    }
+
+    pub fn singbox_url(&self) -> String {
+        match self.kind {
+            RuleSetKind::Geosite => format!("https://raw.githubusercontent.com/SagerNet/sing-geosite/rule-set/geosite-{}.srs", self.name),
+            RuleSetKind::Geoip => format!("https://raw.githubusercontent.com/SagerNet/sing-geoip/rule-set/geoip-{}.srs", self.name),
+        }
+    }
+
+    pub fn mihomo_url(&self) -> String {
+        match self.kind {
+            RuleSetKind::Geosite => format!("https://github.com/MetaCubeX/meta-rules-dat/raw/meta/geo/geosite/{}.mrs", self.name),
+            RuleSetKind::Geoip => format!("https://github.com/MetaCubeX/meta-rules-dat/raw/meta/geo/geoip/{}.mrs", self.name),
+        }
+    }
+
+    /// Mihomo rule-provider behavior
+    pub fn mihomo_behavior(&self) -> &'static str {
+        match self.kind {
+            RuleSetKind::Geosite => "domain",
+            RuleSetKind::Geoip => "ipcidr",
+        }
+    }
+}
+
+/// Client-side routing shared by the sing-box and Clash profiles.
+/// Private ranges always go direct; each client handles them natively.
+#[derive(Debug, Clone)]
+pub struct RoutingPolicy {
+    /// Traffic matching these goes around the tunnel
+    pub direct: Vec<RuleSetRef>,
+    /// Domain suffixes that go around the tunnel
+    pub bypass_domains: Vec<String>,
+    /// Resolver for proxied traffic (sent through the tunnel)
+    pub remote_dns: String,
+    /// Resolver for direct traffic; None resolves everything remotely
+    pub local_dns: Option<String>,
+    pub block_ads: bool,
+}
+
+impl RoutingPolicy {
+    /// Everything through the tunnel. Used when no preset is configured.
+    pub fn global() -> Self {
+        Self {
+            direct: Vec::new(),
+            bypass_domains: Vec::new(),
+            remote_dns: "tls://8.8.8.8".to_string(),
+            local_dns: None,
+            block_ads: false,
+        }
+    }
+
+    pub fn direct_geosites(&self) -> impl Iterator<Item = &RuleSetRef> {
+        self.direct.iter().filter(|r| r.kind == RuleSetKind::Geosite)
+    }
+
+    /// Rule sets referenced by the profile: direct ones plus the ad list when blocking
+    pub fn rule_sets(&self) -> Vec<RuleSetRef> {
+        let mut sets = self.direct.clone();
+        if self.block_ads {
+            sets.push(ads_rule_set());
+        }
+        sets
+    }
+}
+
+pub fn ads_rule_set() -> RuleSetRef {
+    RuleSetRef::geosite("category-ads-all")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rule_set_sources() {
+        let ru = RuleSetRef::geoip("ru");
+        assert_eq!(ru.tag(), "geoip-ru");
+        assert_eq!(ru.mihomo_behavior(), "ipcidr");
+        assert!(ru.singbox_url().ends_with("/geoip-ru.srs"));
+        assert!(ru.mihomo_url().ends_with("/geoip/ru.mrs"));
+
+        let sites = RuleSetRef::geosite("category-ru");
+        assert_eq!(sites.tag(), "geosite-category-ru");
+        assert_eq!(sites.mihomo_behavior(), "domain");
+    }
+
+    #[test]
+    fn test_rule_sets_include_ads_only_when_blocking() {
+        let mut policy = RoutingPolicy::global();
+        policy.direct = vec![RuleSetRef::geosite("category-ru"), RuleSetRef::geoip("ru")];
+        assert_eq!(policy.rule_sets().len(), 2);
+        assert_eq!(policy.direct_geosites().count(), 1);
+
+        policy.block_ads = true;
+        assert_eq!(policy.rule_sets().last(), Some(&ads_rule_set()));
+    }
+}
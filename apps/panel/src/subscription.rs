@@ -1,29 +1,36 @@
 use axum::{
-    extract::{Path, Query, State},
-    http::{header, StatusCode},
+    extract::{ConnectInfo, Path, Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
     response::{IntoResponse, Response},
 };
+use exarobot_shared::subscription::SubscriptionHeaders;
 use serde::Deserialize;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::LazyLock;
 use tracing::{info, error};
 use crate::AppState;
 use crate::singbox::subscription_generator::{
-    generate_clash_config,
     generate_v2ray_config,
-    generate_singbox_config,
     UserKeys,
     NodeInfo,
 };
+use crate::singbox::clash_generator::ClashGenerator;
+use crate::singbox::client_generator::{ClientGenerator, ClientOutbound};
+use crate::singbox::routing::RoutingPolicy;
+use exarobot_shared::client_detect::{self, ClientProfile, SubFormat};
 
 #[derive(Deserialize)]
 pub struct SubParams {
-    pub client: Option<String>, // "clash" | "v2ray" | "singbox"
+    pub client: Option<String>, // "clash" | "v2ray" | "singbox" | "html", overrides User-Agent detection
     pub node_id: Option<i64>,
 }
 
 pub async fn subscription_handler(
     Path(uuid): Path<String>,
     Query(params): Query<SubParams>,
-    State(state): State<AppState>
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request_headers: HeaderMap,
 ) -> Response {
     // 0. Rate Limit (30 req / min per UUID)
     let rate_key = format!("rate:sub:{}", uuid);
@@ -40,7 +47,9 @@ pub async fn subscription_handler(
         }
     }
 
-    info!("Subscription request: UUID={}, client={:?}, node_id={:?}", uuid, params.client, params.node_id);
+    let user_agent = request_headers.get(header::USER_AGENT).and_then(|v| v.to_str().ok());
+    let profile = client_detect::negotiate(params.client.as_deref(), user_agent);
+    info!("Subscription request: UUID={}, client={:?}, UA={:?} -> {}, node_id={:?}", uuid, params.client, user_agent, profile.name, params.node_id);
     
     // 1. Get subscription by UUID
     let sub = match sqlx::query_as::<_, crate::models::store::Subscription>(
@@ -61,8 +70,9 @@ pub async fn subscription_handler(
         }
     };
     
-    // 2. Check if active
-    if sub.status != "active" {
+    // 2. Check if active. Limited subscriptions (traffic quota used up) keep their landing
+    // page so the subscriber can see why and top up; only the configs are withheld below.
+    if sub.status != "active" && sub.status != "limited" {
         info!("Subscription {} is inactive (status: {})", uuid, sub.status);
         return (StatusCode::FORBIDDEN, "Subscription inactive or expired").into_response();
     }
@@ -72,23 +82,28 @@ pub async fn subscription_handler(
         .bind(sub.id)
         .execute(&state.pool)
         .await;
+
+    // Access log for sharing detection
+    log_access(&state, sub.id, Some(client_ip(&request_headers, peer, &TRUSTED_PROXIES)), user_agent, profile);
     
-    // 4. Get user keys
-    let user_keys = match sqlx::query_as::<_, (String, String)>(
-        "SELECT user_uuid, hy2_password FROM users WHERE id = ?"
-    )
-    .bind(sub.user_id)
-    .fetch_optional(&state.pool)
-    .await
-    {
-        Ok(Some((user_uuid, hy2_password))) => UserKeys {
-            user_uuid,
-            hy2_password,
-            _awg_private_key: None, // Client generates this
+    // 4. Credentials are per subscription: VLESS uses the UUID, Hysteria2 authenticates as
+    // "tg_id:uuid-without-dashes" (the same values orchestration pushes to the nodes)
+    let tg_id: i64 = sqlx::query_scalar("SELECT tg_id FROM users WHERE id = ?")
+        .bind(sub.user_id)
+        .fetch_optional(&state.pool)
+        .await
+        .ok()
+        .flatten()
+        .unwrap_or(0);
+    let user_keys = match sub.vless_uuid.clone() {
+        Some(vless_uuid) => UserKeys {
+            hy2_password: format!("{}:{}", tg_id, vless_uuid.replace('-', "")),
+            user_uuid: vless_uuid,
+            _awg_private_key: None, // AmneziaWG peers are provisioned per inbound by AwgService
         },
-        _ => {
-            error!("User keys not found for user_id={}", sub.user_id);
-            return (StatusCode::INTERNAL_SERVER_ERROR, "User not found").into_response()
+        None => {
+            error!("Subscription {} has no credentials", sub.id);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Subscription has no credentials").into_response()
         }
     };
     
@@ -115,76 +130,335 @@ pub async fn subscription_handler(
     // Convert to NodeInfo
     let node_infos: Vec<NodeInfo> = nodes.iter().map(NodeInfo::from).collect();
     
-    // 6. Check Redis Cache & Generate
-    let client_type = params.client.as_deref().unwrap_or("singbox");
-    let cache_key = format!("sub_config:{}:{}:{}", uuid, client_type, params.node_id.unwrap_or(0));
+    // 6. People opening the link in a browser get a page instead of a config
+    if profile.format == SubFormat::Landing {
+        return crate::subscription_page::render(&state, &sub, &node_infos, &user_keys, &request_headers).await;
+    }
+
+    if sub.status == "limited" {
+        info!("Subscription {} is out of traffic, withholding configs", uuid);
+        return quota_exhausted(&state, &sub).await;
+    }
+
+    // Only hand out protocols the client can parse
+    let node_infos: Vec<NodeInfo> = node_infos.into_iter().map(|n| restrict_to(n, &profile)).collect();
+
+    // 7. Check Redis Cache & Generate
+    let format = profile.format;
+    let preset = match state.routing.resolve(sub.user_id, Some(sub.plan_id)).await {
+        Ok(preset) => preset,
+        Err(e) => {
+            error!("Failed to resolve routing preset: {}", e);
+            None
+        }
+    };
+    let routing = preset.as_ref().map(|p| p.policy()).unwrap_or_else(RoutingPolicy::global);
+    let cache_key = format!(
+        "sub_config:{}:{}:{}:{}",
+        uuid, cache_tag(&profile), params.node_id.unwrap_or(0), preset.as_ref().map(|p| p.id).unwrap_or(0)
+    );
 
     if let Ok(Some(cached_config)) = state.redis.get(&cache_key).await {
         info!("Hit Redis cache for subscription {}", uuid);
-        let filename = match client_type {
-            "clash" => "config.yaml",
-            "v2ray" => "config.txt",
-            _ => "config.json",
-        };
-        let content_type = match client_type {
-            "clash" => "application/yaml",
-            "v2ray" => "text/plain",
-            _ => "application/json",
-        };
-        
-        return (
-            StatusCode::OK,
-            [
-                (header::CONTENT_TYPE, content_type),
-                (header::CONTENT_DISPOSITION, format!("inline; filename={}", filename).as_str()),
-            ],
-            cached_config
-        ).into_response();
-    }
-    let (content, content_type, filename) = match client_type {
-        "clash" => {
-            match generate_clash_config(&sub, &node_infos, &user_keys) {
-                Ok(yaml) => (yaml, "application/yaml", "config.yaml"),
-                Err(e) => {
-                    error!("Failed to generate Clash config: {}", e);
-                    return (StatusCode::INTERNAL_SERVER_ERROR, "Config generation failed").into_response()
-                }
-            }
+        return respond(&state, &sub, format.content_type(), format.filename(), cached_config).await;
+    }
+    let generated = match format {
+        SubFormat::Links => generate_v2ray_config(&sub, &node_infos, &user_keys),
+        _ => client_profile(&state, &sub, params.node_id, &profile, &routing).await,
+    };
+    let content = match generated {
+        Ok(content) => content,
+        Err(e) => {
+            error!("Failed to generate {:?} config: {}", format, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Config generation failed").into_response()
+        }
+    };
+
+    info!("Generated {:?} config for {} on subscription {} ({} bytes)", format, profile.name, uuid, content.len());
+
+    // Cache the result
+    if let Err(e) = state.redis.set(&cache_key, &content, 3600).await {
+        error!("Failed to cache config in Redis: {}", e);
+    }
+
+    // 8. Return with proper headers (usage, expiry, profile info)
+    respond(&state, &sub, format.content_type(), format.filename(), content).await
+}
+
+async fn respond(
+    state: &AppState,
+    sub: &crate::models::store::Subscription,
+    content_type: &'static str,
+    filename: &str,
+    content: String,
+) -> Response {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+    // The same URL serves different bodies depending on the client
+    headers.insert(header::VARY, HeaderValue::from_static("User-Agent"));
+    if let Ok(v) = HeaderValue::from_str(&format!("inline; filename={}", filename)) {
+        headers.insert(header::CONTENT_DISPOSITION, v);
+    }
+    for (name, value) in subscription_headers(state, sub).await.header_pairs() {
+        match HeaderValue::from_str(&value) {
+            Ok(v) => { headers.insert(name, v); }
+            Err(_) => error!("Invalid value for header {}: {:?}", name, value),
+        }
+    }
+
+    (StatusCode::OK, headers, content).into_response()
+}
+
+/// 403 for config requests of a limited subscription. The usage headers still go out so
+/// clients that show them tell the subscriber the quota is used up.
+async fn quota_exhausted(state: &AppState, sub: &crate::models::store::Subscription) -> Response {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("text/plain; charset=utf-8"));
+    headers.insert(header::VARY, HeaderValue::from_static("User-Agent"));
+    for (name, value) in subscription_headers(state, sub).await.header_pairs() {
+        if let Ok(v) = HeaderValue::from_str(&value) {
+            headers.insert(name, v);
+        }
+    }
+
+    (StatusCode::FORBIDDEN, headers, "Traffic quota exhausted, add a traffic pack to continue").into_response()
+}
+
+/// Full sing-box or Mihomo profile from the plan's inbounds and the subscriber's routing preset
+async fn client_profile(
+    state: &AppState,
+    sub: &crate::models::store::Subscription,
+    node_id: Option<i64>,
+    profile: &ClientProfile,
+    routing: &RoutingPolicy,
+) -> anyhow::Result<String> {
+    let outbounds: Vec<ClientOutbound> = state.store_service.subscription_outbounds(sub, node_id).await?
+        .into_iter()
+        .filter(|o| profile.hysteria2 || !matches!(o, ClientOutbound::Hysteria2(_)))
+        .collect();
+
+    match profile.format {
+        SubFormat::Clash => ClashGenerator::generate(&outbounds, routing),
+        _ => Ok(serde_json::to_string_pretty(&ClientGenerator::generate(outbounds, routing))?),
+    }
+}
+
+/// Drop protocols the client cannot parse
+fn restrict_to(mut node: NodeInfo, profile: &ClientProfile) -> NodeInfo {
+    if !profile.hysteria2 {
+        node.hy2_port = None;
+    }
+    node
+}
+
+/// Cache variant: format plus the protocol subset it was generated for
+fn cache_tag(profile: &ClientProfile) -> String {
+    format!("{:?}{}", profile.format, if profile.hysteria2 { "" } else { "-nohy2" }).to_lowercase()
+}
+
+/// Usage and profile headers for a subscription (plan limit, expiry and the "Subscription Profile" settings)
+pub async fn subscription_headers(state: &AppState, sub: &crate::models::store::Subscription) -> SubscriptionHeaders {
+    let traffic_limit_gb: i64 = sqlx::query_scalar("SELECT traffic_limit_gb FROM plans WHERE id = ?")
+        .bind(sub.plan_id)
+        .fetch_optional(&state.pool)
+        .await
+        .ok()
+        .flatten()
+        .unwrap_or(0);
+    // Add-on packs only count on top of a limited plan
+    let total = if traffic_limit_gb > 0 { traffic_limit_gb * 1024 * 1024 * 1024 + sub.traffic_extra_bytes } else { 0 };
+
+    let setting = |key: &'static str| async move {
+        Some(state.settings.get_or_default(key, "").await).filter(|v| !v.trim().is_empty())
+    };
+
+    SubscriptionHeaders {
+        // Traffic is accounted as a single counter
+        upload: 0,
+        download: sub.used_traffic,
+        total,
+        expire: Some(sub.expires_at.timestamp()),
+        update_interval_hours: state.settings.get_or_default("sub_update_interval_hours", "24").await.parse().unwrap_or(24),
+        profile_title: setting("sub_profile_title").await,
+        profile_web_page_url: setting("sub_profile_web_page_url").await,
+        support_url: setting("sub_support_url").await,
+        announce: setting("sub_announce").await,
+    }
+}
+
+/// AmneziaWG `.conf` of one node, linked from the subscription page
+pub async fn awg_config_handler(
+    Path((uuid, inbound_id)): Path<(String, i64)>,
+    State(state): State<AppState>,
+) -> Response {
+    let sub = match sqlx::query_as::<_, crate::models::store::Subscription>(
+        "SELECT * FROM subscriptions WHERE subscription_uuid = ?"
+    )
+    .bind(&uuid)
+    .fetch_optional(&state.pool)
+    .await
+    {
+        Ok(Some(s)) if s.status == "active" => s,
+        Ok(_) => return (StatusCode::NOT_FOUND, "Subscription not found").into_response(),
+        Err(e) => {
+            error!("DB error fetching subscription: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Internal error").into_response()
         }
-        "v2ray" => {
-            match generate_v2ray_config(&sub, &node_infos, &user_keys) {
-                Ok(b64) => (b64, "text/plain", "config.txt"),
-                Err(e) => {
-                    error!("Failed to generate V2Ray config: {}", e);
-                    return (StatusCode::INTERNAL_SERVER_ERROR, "Config generation failed").into_response()
+    };
+
+    match state.store_service.awg().client_configs(&sub).await {
+        Ok(configs) => match configs.into_iter().find(|c| c.inbound_id == inbound_id) {
+            Some(config) => {
+                let mut headers = HeaderMap::new();
+                headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("text/plain; charset=utf-8"));
+                if let Ok(v) = HeaderValue::from_str(&format!("attachment; filename=\"{}\"", awg_file_name(&config.node_name))) {
+                    headers.insert(header::CONTENT_DISPOSITION, v);
                 }
+                (StatusCode::OK, headers, config.conf).into_response()
             }
+            None => (StatusCode::NOT_FOUND, "No AmneziaWG config for this server").into_response(),
+        },
+        Err(e) => {
+            error!("Failed to build AmneziaWG config for subscription {}: {}", sub.id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Config generation failed").into_response()
         }
-        "singbox" | _ => {
-            match generate_singbox_config(&sub, &node_infos, &user_keys) {
-                Ok(json) => (json, "application/json", "config.json"),
-                Err(e) => {
-                    error!("Failed to generate Sing-box config: {}", e);
-                    return (StatusCode::INTERNAL_SERVER_ERROR, "Config generation failed").into_response()
-                }
+    }
+}
+
+/// Tunnel name the Amnezia apps show: file stem, letters/digits only and at most 15 chars (Linux ifname limit)
+pub fn awg_file_name(node_name: &str) -> String {
+    let stem: String = node_name.chars().filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_').take(15).collect();
+    format!("{}.conf", if stem.is_empty() { "amneziawg" } else { &stem })
+}
+
+/// Link handed to customers; PANEL_URL may omit the scheme
+pub fn public_url(sub_uuid: &str) -> String {
+    let domain = std::env::var("PANEL_URL").unwrap_or_else(|_| "panel.example.com".to_string());
+    let base_url = if domain.starts_with("http") { domain } else { format!("https://{}", domain) };
+    format!("{}/sub/{}", base_url.trim_end_matches('/'), sub_uuid)
+}
+
+/// Self-service "reset link": new subscription URL and protocol credentials, pushed to the
+/// plan's nodes. Existing sessions are dropped and cached profiles for the old URL removed.
+pub async fn reset_link(state: &AppState, user_id: i64, sub_id: i64) -> anyhow::Result<crate::models::store::Subscription> {
+    let cooldown_hours: i64 = state.settings.get_or_default("credential_rotation_cooldown_hours", "24").await.parse().unwrap_or(24);
+    let (sub, old_sub_uuid) = state.store_service.rotate_subscription_credentials(sub_id, user_id, cooldown_hours).await?;
+
+    if let Err(e) = state.redis.invalidate_subscription(&old_sub_uuid).await {
+        error!("Failed to invalidate cached subscription {}: {}", old_sub_uuid, e);
+    }
+
+    // Nodes rebuild their user lists from the panel on "update"
+    for node_id in state.store_service.get_plan_node_ids(sub.plan_id).await.unwrap_or_default() {
+        if let Err(e) = state.pubsub.publish(&format!("node_events:{}", node_id), "update").await {
+            error!("Failed to notify node {} about rotated credentials: {}", node_id, e);
+        }
+    }
+
+    if let Some(uuid) = &sub.vless_uuid
+        && let Err(e) = state.connection_service.kill_subscription_connections(uuid).await
+    {
+        error!("Failed to reset sessions of subscription {}: {}", sub.id, e);
+    }
+
+    Ok(sub)
+}
+
+/// Move a subscription to another plan with proration. The link stays the same; the cached
+/// profile is dropped and both plans' nodes are told to rebuild their user lists.
+pub async fn change_plan(state: &AppState, user_id: i64, sub_id: i64, duration_id: i64) -> anyhow::Result<(crate::models::store::Subscription, crate::services::store_service::PlanChangeQuote)> {
+    let (sub, quote, old_plan_id) = state.store_service.change_plan(user_id, sub_id, duration_id).await?;
+
+    if let Err(e) = state.redis.invalidate_subscription(&sub.subscription_uuid).await {
+        error!("Failed to invalidate cached subscription {}: {}", sub.subscription_uuid, e);
+    }
+
+    let mut node_ids = state.store_service.get_plan_node_ids(old_plan_id).await.unwrap_or_default();
+    node_ids.extend(state.store_service.get_plan_node_ids(sub.plan_id).await.unwrap_or_default());
+    node_ids.sort_unstable();
+    node_ids.dedup();
+    for node_id in node_ids {
+        if let Err(e) = state.pubsub.publish(&format!("node_events:{}", node_id), "update").await {
+            error!("Failed to notify node {} about plan change: {}", node_id, e);
+        }
+    }
+
+    Ok((sub, quote))
+}
+
+/// Buy a traffic pack for a subscription; nodes are told to take it back if it was limited
+pub async fn add_traffic_pack(state: &AppState, user_id: i64, sub_id: i64, pack_id: i64) -> anyhow::Result<(crate::models::store::Subscription, crate::models::store::TrafficPack)> {
+    let (sub, pack, change) = state.store_service.buy_traffic_pack(user_id, sub_id, pack_id).await?;
+
+    if change.is_some() {
+        for node_id in state.store_service.get_plan_node_ids(sub.plan_id).await.unwrap_or_default() {
+            if let Err(e) = state.pubsub.publish(&format!("node_events:{}", node_id), "update").await {
+                error!("Failed to notify node {} about reactivated subscription: {}", node_id, e);
             }
         }
-    };
-    
-    info!("Generated {} config for subscription {} ({} bytes)", client_type, uuid, content.len());
-    
-    // Cache the result
-    if let Err(e) = state.redis.set(&cache_key, &content, 3600).await {
-        error!("Failed to cache config in Redis: {}", e);
     }
-    
-    // 7. Return with proper headers
-    (
-        StatusCode::OK,
-        [
-            (header::CONTENT_TYPE, content_type),
-            (header::CONTENT_DISPOSITION, format!("inline; filename={}", filename).as_str()),
-        ],
-        content
-    ).into_response()
+
+    Ok((sub, pack))
+}
+
+/// Reverse proxies allowed to report the client address, from `TRUSTED_PROXIES` (comma-separated IPs)
+static TRUSTED_PROXIES: LazyLock<Vec<IpAddr>> = LazyLock::new(|| {
+    std::env::var("TRUSTED_PROXIES")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|ip| ip.trim().parse().ok())
+        .collect()
+});
+
+/// The client address for access logging. X-Forwarded-For is only believed when the peer is a
+/// trusted proxy, and then the nearest hop that isn't one of our proxies wins: anything further
+/// left was written by the client itself.
+fn client_ip(headers: &HeaderMap, peer: SocketAddr, trusted: &[IpAddr]) -> IpAddr {
+    if !trusted.contains(&peer.ip()) {
+        return peer.ip();
+    }
+    let hops: Vec<IpAddr> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|ip| ip.trim().parse().ok())
+        .collect();
+    hops.into_iter().rev().find(|ip| !trusted.contains(ip)).unwrap_or(peer.ip())
+}
+
+/// Logs a subscription fetch for sharing detection in the background, keeping the fetch fast
+pub fn log_access(state: &AppState, sub_id: i64, ip: Option<IpAddr>, user_agent: Option<&str>, profile: ClientProfile) {
+    let sub_access = state.sub_access.clone();
+    let user_agent = user_agent.map(str::to_string);
+    tokio::spawn(async move {
+        if let Err(e) = sub_access.record(sub_id, ip, user_agent.as_deref(), &profile).await {
+            error!("Failed to log subscription access: {}", e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn forwarded(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_client_ip_trusts_only_configured_proxies() {
+        let proxy: IpAddr = "10.0.0.2".parse().unwrap();
+        let peer = SocketAddr::new(proxy, 443);
+        let headers = forwarded("198.51.100.1, 203.0.113.9");
+
+        // Direct connection: the header is whatever the client made up
+        assert_eq!(client_ip(&headers, SocketAddr::new("192.0.2.7".parse().unwrap(), 443), &[proxy]), "192.0.2.7".parse::<IpAddr>().unwrap());
+        // Through the proxy: the hop it appended, not the spoofable first one
+        assert_eq!(client_ip(&headers, peer, &[proxy]), "203.0.113.9".parse::<IpAddr>().unwrap());
+        assert_eq!(client_ip(&HeaderMap::new(), peer, &[proxy]), proxy);
+        assert_eq!(client_ip(&headers, peer, &[]), proxy);
+    }
 }
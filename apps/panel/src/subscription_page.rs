@@ -0,0 +1,285 @@
+use askama::Template;
+use axum::{
+    http::{header, HeaderMap, HeaderValue},
+    response::{Html, IntoResponse, Response},
+};
+use base64::Engine;
+use qrcode::{render::svg, QrCode};
+use tracing::error;
+use crate::AppState;
+use crate::models::store::Subscription;
+use crate::singbox::subscription_generator::{generate_links, NodeInfo, UserKeys};
+
+/// Page copy per language. Anything that is not Russian falls back to English.
+struct Strings {
+    lang: &'static str,
+    default_title: &'static str,
+    expires: &'static str,
+    traffic_left: &'static str,
+    unlimited: &'static str,
+    quota_exhausted: &'static str,
+    import_heading: &'static str,
+    import_hint: &'static str,
+    copy_hint: &'static str,
+    servers_heading: &'static str,
+    servers_hint: &'static str,
+    steps_heading: &'static str,
+    steps: [&'static str; 3],
+    awg_hint: &'static str,
+    awg_download: &'static str,
+}
+
+const EN: Strings = Strings {
+    lang: "en",
+    default_title: "VPN Subscription",
+    expires: "Expires",
+    traffic_left: "Traffic left",
+    unlimited: "Unlimited",
+    quota_exhausted: "Your traffic quota is used up. Buy a traffic pack in My Services in the bot to reconnect.",
+    import_heading: "Add to your app",
+    import_hint: "Tap your app to import the subscription. It will keep servers up to date automatically.",
+    copy_hint: "Or copy the subscription link:",
+    servers_heading: "Servers",
+    servers_hint: "Scan a code from another device or copy a single server link.",
+    steps_heading: "How to connect",
+    steps: [
+        "Install one of the apps above from your app store.",
+        "Open this page on the same device and tap the app's button, or scan the subscription QR code from inside the app.",
+        "Pick a server in the app and connect. Update the subscription in the app if servers change.",
+    ],
+    awg_hint: "For the Amnezia and AmneziaWG apps: scan the code or download the .conf file and import it as a tunnel.",
+    awg_download: "Download .conf",
+};
+
+const RU: Strings = Strings {
+    lang: "ru",
+    default_title: "VPN-подписка",
+    expires: "Действует до",
+    traffic_left: "Осталось трафика",
+    unlimited: "Безлимит",
+    quota_exhausted: "Трафик по подписке закончился. Купите пакет трафика в разделе «My Services» в боте, чтобы снова подключиться.",
+    import_heading: "Добавить в приложение",
+    import_hint: "Нажмите на своё приложение, чтобы импортировать подписку. Список серверов будет обновляться автоматически.",
+    copy_hint: "Или скопируйте ссылку на подписку:",
+    servers_heading: "Серверы",
+    servers_hint: "Отсканируйте код с другого устройства или скопируйте ссылку на отдельный сервер.",
+    steps_heading: "Как подключиться",
+    steps: [
+        "Установите одно из приложений выше из магазина приложений.",
+        "Откройте эту страницу на том же устройстве и нажмите кнопку приложения или отсканируйте QR-код подписки в самом приложении.",
+        "Выберите сервер в приложении и подключитесь. Если серверы изменились, обновите подписку в приложении.",
+    ],
+    awg_hint: "Для приложений Amnezia и AmneziaWG: отсканируйте код или скачайте файл .conf и импортируйте его как туннель.",
+    awg_download: "Скачать .conf",
+};
+
+fn strings_for(language_code: Option<&str>) -> &'static Strings {
+    match language_code.map(|l| l.to_ascii_lowercase()) {
+        Some(l) if l.starts_with("ru") => &RU,
+        _ => &EN,
+    }
+}
+
+struct AppLink {
+    name: &'static str,
+    platforms: &'static str,
+    url: String,
+}
+
+struct ServerLink {
+    name: String,
+    link: String,
+    qr_svg: String,
+}
+
+struct AwgConfigLink {
+    name: String,
+    download_url: String,
+    qr_svg: String,
+}
+
+#[derive(Template)]
+#[template(path = "subscription.html")]
+struct SubscriptionPageTemplate {
+    t: &'static Strings,
+    title: String,
+    plan_name: String,
+    expires_at: String,
+    traffic_left: String,
+    limited: bool,
+    sub_url: String,
+    sub_qr_svg: String,
+    apps: Vec<AppLink>,
+    servers: Vec<ServerLink>,
+    awg_configs: Vec<AwgConfigLink>,
+}
+
+/// Human page for people opening the subscription link in a browser
+pub async fn render(
+    state: &AppState,
+    sub: &Subscription,
+    nodes: &[NodeInfo],
+    user_keys: &UserKeys,
+    request_headers: &HeaderMap,
+) -> Response {
+    let info = crate::subscription::subscription_headers(state, sub).await;
+    let plan_name: String = sqlx::query_scalar("SELECT name FROM plans WHERE id = ?")
+        .bind(sub.plan_id)
+        .fetch_optional(&state.pool)
+        .await
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    let language_code: Option<String> = sqlx::query_scalar("SELECT language_code FROM users WHERE id = ?")
+        .bind(sub.user_id)
+        .fetch_optional(&state.pool)
+        .await
+        .ok()
+        .flatten()
+        .flatten();
+    let t = strings_for(language_code.as_deref());
+
+    let traffic_left = if info.total > 0 {
+        let left = (info.total - info.download).max(0);
+        format!("{} / {}", format_gb(left), format_gb(info.total))
+    } else {
+        t.unlimited.to_string()
+    };
+    let title = info.profile_title.clone().unwrap_or_else(|| t.default_title.to_string());
+    let sub_url = public_sub_url(request_headers, &sub.subscription_uuid);
+
+    let servers = generate_links(nodes, user_keys)
+        .into_iter()
+        .map(|(name, link)| ServerLink { qr_svg: qr_svg(&link), name, link })
+        .collect();
+
+    let awg_configs = match state.store_service.awg().client_configs(sub).await {
+        Ok(configs) => configs
+            .into_iter()
+            .map(|c| AwgConfigLink {
+                name: c.node_name,
+                download_url: format!("{}/awg/{}", sub_url, c.inbound_id),
+                qr_svg: qr_svg(&c.conf),
+            })
+            .collect(),
+        Err(e) => {
+            error!("Failed to build AmneziaWG configs for subscription {}: {}", sub.id, e);
+            Vec::new()
+        }
+    };
+
+    let template = SubscriptionPageTemplate {
+        t,
+        apps: app_links(&sub_url, &title),
+        sub_qr_svg: qr_svg(&sub_url),
+        title,
+        plan_name,
+        expires_at: sub.expires_at.format("%Y-%m-%d %H:%M UTC").to_string(),
+        traffic_left,
+        limited: sub.status == "limited",
+        sub_url,
+        servers,
+        awg_configs,
+    };
+
+    match template.render() {
+        Ok(html) => {
+            let mut response = Html(html).into_response();
+            response.headers_mut().insert(header::VARY, HeaderValue::from_static("User-Agent"));
+            response
+        }
+        Err(e) => {
+            error!("Failed to render subscription page: {}", e);
+            (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "Page rendering failed").into_response()
+        }
+    }
+}
+
+/// One-tap import deep links. Each points at the format the app negotiates anyway,
+/// pinned with `?client=` so older app versions with unknown User-Agents still work.
+fn app_links(sub_url: &str, title: &str) -> Vec<AppLink> {
+    let enc = |s: &str| urlencoding::encode(s).into_owned();
+    let with_client = |client: &str| format!("{}?client={}", sub_url, client);
+    let b64_url = base64::engine::general_purpose::STANDARD.encode(sub_url);
+
+    vec![
+        AppLink {
+            name: "Hiddify",
+            platforms: "Android · iOS · Windows · macOS · Linux",
+            url: format!("hiddify://import/{}#{}", sub_url, enc(title)),
+        },
+        AppLink {
+            name: "sing-box",
+            platforms: "Android · iOS · macOS",
+            url: format!("sing-box://import-remote-profile?url={}#{}", enc(&with_client("singbox")), enc(title)),
+        },
+        AppLink {
+            name: "Clash Meta / Mihomo",
+            platforms: "Android · Windows · macOS · Linux",
+            url: format!("clash://install-config?url={}&name={}", enc(&with_client("clash")), enc(title)),
+        },
+        AppLink {
+            name: "v2rayNG",
+            platforms: "Android",
+            url: format!("v2rayng://install-config?url={}#{}", enc(&with_client("v2ray")), enc(title)),
+        },
+        AppLink {
+            name: "Streisand",
+            platforms: "iOS · macOS",
+            url: format!("streisand://import/{}#{}", with_client("v2ray"), enc(title)),
+        },
+        AppLink {
+            name: "Happ",
+            platforms: "Android · iOS · Windows",
+            url: format!("happ://add/{}", sub_url),
+        },
+        AppLink {
+            name: "Shadowrocket",
+            platforms: "iOS",
+            url: format!("sub://{}#{}", b64_url, enc(title)),
+        },
+    ]
+}
+
+fn qr_svg(data: &str) -> String {
+    match QrCode::new(data.as_bytes()) {
+        Ok(code) => code
+            .render::<svg::Color>()
+            .min_dimensions(180, 180)
+            .quiet_zone(true)
+            .build(),
+        Err(e) => {
+            error!("Failed to build QR code: {}", e);
+            String::new()
+        }
+    }
+}
+
+/// Grayscale PNG of a QR code, for places that cannot show SVG (Telegram photos)
+pub fn qr_png(data: &str) -> Option<Vec<u8>> {
+    let image = QrCode::new(data.as_bytes())
+        .ok()?
+        .render::<image::Luma<u8>>()
+        .module_dimensions(8, 8)
+        .quiet_zone(true)
+        .build();
+
+    let mut png = std::io::Cursor::new(Vec::new());
+    image.write_to(&mut png, image::ImageFormat::Png).ok()?;
+    Some(png.into_inner())
+}
+
+fn format_gb(bytes: i64) -> String {
+    format!("{:.2} GB", bytes as f64 / 1024.0 / 1024.0 / 1024.0)
+}
+
+/// Absolute URL of this subscription as the visitor reached it (panel or frontend host)
+fn public_sub_url(request_headers: &HeaderMap, uuid: &str) -> String {
+    let header_str = |name: &str| request_headers.get(name).and_then(|v| v.to_str().ok());
+    let host = header_str("x-forwarded-host").or_else(|| header_str("host"));
+    let scheme = header_str("x-forwarded-proto").unwrap_or("https");
+    match host {
+        Some(host) => format!("{}://{}/sub/{}", scheme, host, uuid),
+        None => format!("/sub/{}", uuid),
+    }
+}
@@ -0,0 +1,177 @@
+use serde_json::{json, Value};
+use tracing::{error, warn};
+
+use crate::models::network::{Inbound, InboundType, StreamSettings as DbStreamSettings};
+use crate::models::node::{Node, NodeEngine};
+
+/// Local gRPC endpoint of Xray's StatsService; the agent queries it for traffic and online IPs
+pub const API_LISTEN: &str = "127.0.0.1:10085";
+
+/// Certificates the agent provisions; shared with sing-box nodes
+const DEFAULT_CERT_PATH: &str = "/etc/sing-box/certs/cert.pem";
+const DEFAULT_KEY_PATH: &str = "/etc/sing-box/certs/key.pem";
+
+pub struct XrayConfigGenerator;
+
+impl XrayConfigGenerator {
+    /// Generates a complete Xray configuration from the same DB inbounds sing-box nodes use.
+    /// User emails are the Telegram IDs, so per-user stats line up with sing-box user names.
+    pub fn generate_config(node: &Node, inbounds: Vec<Inbound>) -> Value {
+        let mut generated_inbounds = Vec::new();
+
+        for inbound in inbounds {
+            if !inbound.enable {
+                continue;
+            }
+            if !NodeEngine::Xray.supports(&inbound.protocol) {
+                warn!("Xray does not serve {} inbounds, skipping {}", inbound.protocol, inbound.tag);
+                continue;
+            }
+
+            let protocol_settings: InboundType = match serde_json::from_str(&inbound.settings) {
+                Ok(s) => s,
+                Err(e) => {
+                    error!("Failed to parse settings for inbound {}: {}", inbound.tag, e);
+                    continue;
+                }
+            };
+            let stream_settings: DbStreamSettings = match serde_json::from_str(&inbound.stream_settings) {
+                Ok(s) => s,
+                Err(e) => {
+                    error!("StreamSettings parse failed for inbound {}: {}", inbound.tag, e);
+                    continue;
+                }
+            };
+
+            let network = stream_settings.network.clone().unwrap_or_else(|| "tcp".to_string());
+            let (protocol, settings) = match protocol_settings {
+                InboundType::Vless(vless) => {
+                    // Vision only works over raw TCP; XHTTP and friends need an empty flow
+                    let vision_ok = network == "tcp";
+                    let clients: Vec<Value> = vless.clients.iter().map(|c| json!({
+                        "id": c.id,
+                        "email": c.email,
+                        "flow": if vision_ok { c.flow.as_str() } else { "" },
+                        "level": 0,
+                    })).collect();
+                    ("vless", json!({ "clients": clients, "decryption": "none" }))
+                },
+                InboundType::Trojan(trojan) => {
+                    let clients: Vec<Value> = trojan.clients.iter().map(|c| json!({
+                        "password": c.password,
+                        "email": c.email,
+                        "level": 0,
+                    })).collect();
+                    ("trojan", json!({ "clients": clients }))
+                },
+                InboundType::Shadowsocks(ss) => {
+                    let clients: Vec<Value> = ss.users.iter().map(|u| json!({
+                        "password": u.password,
+                        "email": u.name,
+                        "level": 0,
+                    })).collect();
+                    ("shadowsocks", json!({
+                        "method": ss.method,
+                        "password": ss.password,
+                        "clients": clients,
+                        "network": "tcp,udp",
+                    }))
+                },
+                _ => {
+                    warn!("Unsupported protocol for Xray inbound {}", inbound.tag);
+                    continue;
+                }
+            };
+
+            let mut entry = json!({
+                "tag": inbound.tag,
+                "listen": inbound.listen_ip,
+                "port": inbound.listen_port,
+                "protocol": protocol,
+                "settings": settings,
+                "sniffing": { "enabled": true, "destOverride": ["http", "tls", "quic"] },
+            });
+            if protocol != "shadowsocks" {
+                entry["streamSettings"] = Self::stream_settings(&network, stream_settings);
+            }
+            generated_inbounds.push(entry);
+        }
+
+        let mut rules = vec![json!({ "type": "field", "inboundTag": ["api"], "outboundTag": "api" })];
+        if node.config_block_torrent {
+            rules.push(json!({ "type": "field", "protocol": ["bittorrent"], "outboundTag": "block" }));
+        }
+        if node.config_block_ads {
+            rules.push(json!({ "type": "field", "domain": ["geosite:category-ads-all"], "outboundTag": "block" }));
+        }
+        if node.config_block_porn {
+            rules.push(json!({ "type": "field", "domain": ["geosite:category-porn"], "outboundTag": "block" }));
+        }
+
+        json!({
+            "log": { "loglevel": "warning" },
+            "api": {
+                "tag": "api",
+                "listen": API_LISTEN,
+                "services": ["StatsService", "HandlerService"],
+            },
+            "stats": {},
+            "policy": {
+                "levels": {
+                    "0": { "statsUserUplink": true, "statsUserDownlink": true, "statsUserOnline": true }
+                },
+                "system": { "statsInboundUplink": true, "statsInboundDownlink": true },
+            },
+            "inbounds": generated_inbounds,
+            "outbounds": [
+                { "tag": "direct", "protocol": "freedom" },
+                { "tag": "block", "protocol": "blackhole" },
+            ],
+            "routing": { "domainStrategy": "AsIs", "rules": rules },
+        })
+    }
+
+    fn stream_settings(network: &str, stream: DbStreamSettings) -> Value {
+        let security = stream.security.as_deref().unwrap_or("none");
+        let mut out = json!({ "network": network, "security": security });
+
+        match security {
+            "reality" => if let Some(reality) = stream.reality_settings {
+                out["realitySettings"] = json!({
+                    "show": false,
+                    "dest": reality.dest,
+                    "xver": reality.xver,
+                    "serverNames": reality.server_names,
+                    "privateKey": reality.private_key,
+                    "shortIds": reality.short_ids,
+                });
+            },
+            "tls" => {
+                let tls = stream.tls_settings;
+                let cert = tls.as_ref().and_then(|t| t.certificates.as_ref()).and_then(|c| c.first());
+                let pick = |path: Option<&String>, default: &str| {
+                    path.filter(|p| !p.is_empty()).cloned().unwrap_or_else(|| default.to_string())
+                };
+                out["tlsSettings"] = json!({
+                    "serverName": tls.as_ref().map(|t| t.server_name.clone()).unwrap_or_default(),
+                    "certificates": [{
+                        "certificateFile": pick(cert.map(|c| &c.certificate_path), DEFAULT_CERT_PATH),
+                        "keyFile": pick(cert.map(|c| &c.key_path), DEFAULT_KEY_PATH),
+                    }],
+                });
+            },
+            _ => {}
+        }
+
+        if network == "xhttp" {
+            let xhttp = stream.xhttp_settings;
+            out["xhttpSettings"] = json!({
+                "path": xhttp.as_ref().map(|x| x.path.as_str()).unwrap_or("/"),
+                "host": xhttp.as_ref().and_then(|x| x.host.clone()).unwrap_or_default(),
+                "mode": xhttp.as_ref().map(|x| x.mode.as_str()).unwrap_or("auto"),
+            });
+        }
+
+        out
+    }
+}
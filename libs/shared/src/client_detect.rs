@@ -0,0 +1,142 @@
+/// Subscription body formats served by `/sub/:uuid`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubFormat {
+    /// Clash Meta (Mihomo) YAML
+    Clash,
+    /// sing-box JSON profile
+    SingBox,
+    /// Base64 list of share links (v2rayN style)
+    Links,
+    /// HTML page for people opening the link in a browser
+    Landing,
+}
+
+impl SubFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            SubFormat::Clash => "application/yaml",
+            SubFormat::SingBox => "application/json",
+            SubFormat::Links => "text/plain",
+            SubFormat::Landing => "text/html; charset=utf-8",
+        }
+    }
+
+    pub fn filename(&self) -> &'static str {
+        match self {
+            SubFormat::Clash => "config.yaml",
+            SubFormat::SingBox => "config.json",
+            SubFormat::Links => "config.txt",
+            SubFormat::Landing => "index.html",
+        }
+    }
+}
+
+/// What a subscription client understands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientProfile {
+    /// Short name used in logs and cache keys
+    pub name: &'static str,
+    pub format: SubFormat,
+    pub hysteria2: bool,
+}
+
+impl ClientProfile {
+    const fn new(name: &'static str, format: SubFormat, hysteria2: bool) -> Self {
+        Self { name, format, hysteria2 }
+    }
+}
+
+/// User-Agent substrings (lowercase) in match order. More specific tokens come first:
+/// Hiddify and NekoBox embed "sing-box", v2rayN embeds "xray", Stash mentions "clash".
+const KNOWN_CLIENTS: &[(&str, ClientProfile)] = &[
+    ("hiddify", ClientProfile::new("hiddify", SubFormat::SingBox, true)),
+    ("nekobox", ClientProfile::new("nekobox", SubFormat::Links, true)),
+    ("nekoray", ClientProfile::new("nekobox", SubFormat::Links, true)),
+    ("sfa/", ClientProfile::new("sing-box", SubFormat::SingBox, true)),
+    ("sfi/", ClientProfile::new("sing-box", SubFormat::SingBox, true)),
+    ("sfm/", ClientProfile::new("sing-box", SubFormat::SingBox, true)),
+    ("sfw/", ClientProfile::new("sing-box", SubFormat::SingBox, true)),
+    ("sing-box", ClientProfile::new("sing-box", SubFormat::SingBox, true)),
+    ("singbox", ClientProfile::new("sing-box", SubFormat::SingBox, true)),
+    ("mihomo", ClientProfile::new("mihomo", SubFormat::Clash, true)),
+    ("clash.meta", ClientProfile::new("mihomo", SubFormat::Clash, true)),
+    ("clash-meta", ClientProfile::new("mihomo", SubFormat::Clash, true)),
+    ("clash-verge", ClientProfile::new("mihomo", SubFormat::Clash, true)),
+    ("flclash", ClientProfile::new("mihomo", SubFormat::Clash, true)),
+    ("stash", ClientProfile::new("mihomo", SubFormat::Clash, true)),
+    ("clash", ClientProfile::new("mihomo", SubFormat::Clash, true)),
+    ("v2rayng", ClientProfile::new("v2rayng", SubFormat::Links, true)),
+    ("v2rayn", ClientProfile::new("v2rayn", SubFormat::Links, true)),
+    ("streisand", ClientProfile::new("streisand", SubFormat::Links, true)),
+    ("shadowrocket", ClientProfile::new("shadowrocket", SubFormat::Links, true)),
+    // Happ is built on Xray core, which has no Hysteria2 outbound
+    ("happ", ClientProfile::new("happ", SubFormat::Links, false)),
+    ("v2box", ClientProfile::new("v2box", SubFormat::Links, false)),
+    ("xray", ClientProfile::new("xray", SubFormat::Links, false)),
+];
+
+const BROWSER: ClientProfile = ClientProfile::new("browser", SubFormat::Landing, false);
+/// Unknown non-browser clients keep the historical sing-box default
+const UNKNOWN: ClientProfile = ClientProfile::new("singbox", SubFormat::SingBox, true);
+
+/// Pick the profile for a request. An explicit `?client=` wins over the User-Agent.
+pub fn negotiate(client: Option<&str>, user_agent: Option<&str>) -> ClientProfile {
+    if let Some(profile) = client.and_then(from_query) {
+        return profile;
+    }
+    user_agent.map(detect).unwrap_or(UNKNOWN)
+}
+
+/// `?client=clash|v2ray|singbox|html`
+pub fn from_query(client: &str) -> Option<ClientProfile> {
+    match client.to_ascii_lowercase().as_str() {
+        "clash" | "mihomo" => Some(ClientProfile::new("clash", SubFormat::Clash, true)),
+        "v2ray" | "links" => Some(ClientProfile::new("v2ray", SubFormat::Links, true)),
+        "singbox" | "sing-box" => Some(ClientProfile::new("singbox", SubFormat::SingBox, true)),
+        "html" | "browser" => Some(BROWSER),
+        _ => None,
+    }
+}
+
+pub fn detect(user_agent: &str) -> ClientProfile {
+    let ua = user_agent.to_ascii_lowercase();
+    if let Some((_, profile)) = KNOWN_CLIENTS.iter().find(|(token, _)| ua.contains(token)) {
+        return *profile;
+    }
+    if ua.starts_with("mozilla/") {
+        return BROWSER;
+    }
+    UNKNOWN
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detects_known_clients() {
+        assert_eq!(detect("ClashMetaForAndroid/2.10.1.Meta").format, SubFormat::Clash);
+        assert_eq!(detect("mihomo/1.18.5").format, SubFormat::Clash);
+        assert_eq!(detect("SFA/1.9.3 (Android 14)").format, SubFormat::SingBox);
+        assert_eq!(detect("sing-box 1.10.1").format, SubFormat::SingBox);
+        assert_eq!(detect("HiddifyNext/2.5.7 (android) like ClashMeta v2ray sing-box").name, "hiddify");
+        assert_eq!(detect("v2rayNG/1.8.19").name, "v2rayng");
+        assert_eq!(detect("v2rayN/6.45").name, "v2rayn");
+        assert_eq!(detect("Streisand/1.6.7").format, SubFormat::Links);
+        assert_eq!(detect("Shadowrocket/2070 CFNetwork/1494.0.7 Darwin/23.4.0").name, "shadowrocket");
+        assert_eq!(detect("NekoBox/Android/1.3.1 (Prefer ClashMeta Format)").name, "nekobox");
+        let happ = detect("Happ/1.5.2");
+        assert_eq!(happ.format, SubFormat::Links);
+        assert!(!happ.hysteria2);
+    }
+
+    #[test]
+    fn test_browser_and_fallbacks() {
+        let chrome = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/126.0 Safari/537.36";
+        assert_eq!(detect(chrome).format, SubFormat::Landing);
+        assert_eq!(detect("curl/8.5.0").format, SubFormat::SingBox);
+        assert_eq!(negotiate(Some("clash"), Some(chrome)).format, SubFormat::Clash);
+        assert_eq!(negotiate(Some("unknown"), Some("v2rayNG/1.8.19")).format, SubFormat::Links);
+        assert_eq!(negotiate(None, None).format, SubFormat::SingBox);
+    }
+}
@@ -0,0 +1,31 @@
+/// Proxy core a node runs. The panel renders both from the same DB inbounds; the agent
+/// starts whichever one the panel asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeEngine {
+    Singbox,
+    Xray,
+}
+
+impl NodeEngine {
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "xray" => Self::Xray,
+            _ => Self::Singbox,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Singbox => "singbox",
+            Self::Xray => "xray",
+        }
+    }
+
+    /// Protocols the engine can serve; other inbounds are left out of its config
+    pub fn supports(&self, protocol: &str) -> bool {
+        match self {
+            Self::Singbox => true,
+            Self::Xray => matches!(protocol, "vless" | "trojan" | "shadowsocks"),
+        }
+    }
+}
@@ -1,5 +1,8 @@
 use serde::{Deserialize, Serialize};
 
+pub mod client_detect;
+pub mod engine;
+
 pub mod api {
     use super::*;
 
@@ -16,6 +19,32 @@ pub mod api {
         pub latency: Option<f64>,
         pub cpu_usage: Option<f64>,
         pub memory_usage: Option<f64>,
+        // sing-box binary (absent on older agents)
+        #[serde(default)]
+        pub singbox: Option<SingboxInfo>,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct SingboxInfo {
+        pub version: String,
+        /// Build tags reported by `sing-box version` (with_quic, with_reality_server, ...)
+        pub capabilities: Vec<String>,
+        /// CPU architecture in sing-box release naming (amd64, arm64, ...)
+        pub arch: String,
+        /// Error of the last failed upgrade attempt, if any
+        pub update_error: Option<String>,
+        /// Version and stage of the upgrade in progress ("1.11.0: downloading"), if any
+        #[serde(default)]
+        pub upgrade_status: Option<String>,
+    }
+
+    /// Binary the panel wants the node to run
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct SingboxTarget {
+        pub version: String,
+        pub url: String,
+        /// Required; agents refuse to install an archive they cannot verify
+        pub sha256: Option<String>,
     }
 
     #[derive(Debug, Serialize, Deserialize)]
@@ -31,6 +60,90 @@ pub mod api {
         pub success: bool,
         pub action: AgentAction,
         pub latest_version: Option<String>,
+        #[serde(default)]
+        pub singbox_target: Option<SingboxTarget>,
+    }
+
+    /// A single log line shipped from agent to panel
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct NodeLogLine {
+        pub seq: u64,
+        pub ts: i64,
+        /// "sing-box" or "agent"
+        pub source: String,
+        /// error | warn | info | debug | trace
+        pub level: String,
+        pub message: String,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct NodeLogBatch {
+        pub lines: Vec<NodeLogLine>,
+    }
+
+    /// Desired host firewall state for a node (GET /api/v2/node/firewall)
+    #[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+    pub struct FirewallState {
+        /// When false the agent removes its nftables table and leaves the host alone
+        pub enabled: bool,
+        pub tcp_ports: Vec<u16>,
+        pub udp_ports: Vec<u16>,
+        #[serde(default)]
+        pub port_hopping: Vec<PortHopRange>,
+        /// sing-box Clash API port, only reachable from `controller_allow`
+        pub controller_port: u16,
+        /// Panel addresses. Empty means the agent resolves the panel host itself.
+        #[serde(default)]
+        pub controller_allow: Vec<String>,
+        #[serde(default)]
+        pub bans: Vec<IpBan>,
+        /// Ports of other services on the host (monitoring, a second web server), open to everyone
+        #[serde(default)]
+        pub extra_tcp_ports: Vec<u16>,
+        #[serde(default)]
+        pub extra_udp_ports: Vec<u16>,
+    }
+
+    /// Live connections per sing-box user, reported by the agent (POST /api/v2/node/connections)
+    #[derive(Debug, Clone, Default, Serialize, Deserialize)]
+    pub struct ConnectionSnapshot {
+        pub users: Vec<UserConnections>,
+        /// Bytes each user moved since the last report the panel accepted
+        #[serde(default)]
+        pub traffic: Vec<UserTraffic>,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+    pub struct UserTraffic {
+        pub user: String,
+        pub upload: u64,
+        pub download: u64,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct UserConnections {
+        /// sing-box user name (the subscriber's Telegram ID)
+        pub user: String,
+        pub ips: Vec<String>,
+        pub connections: u32,
+        /// Bytes of the currently open connections
+        pub upload: u64,
+        pub download: u64,
+    }
+
+    /// UDP range redirected to a single inbound port (Hysteria2 port hopping)
+    #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+    pub struct PortHopRange {
+        pub start: u16,
+        pub end: u16,
+        pub target: u16,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+    pub struct IpBan {
+        pub ip: String,
+        /// Unix timestamp
+        pub expires_at: i64,
     }
 
     #[derive(Debug, Serialize, Deserialize, PartialEq)]
@@ -49,5 +162,74 @@ pub mod config {
     pub struct ConfigResponse {
         pub hash: String,
         pub content: serde_json::Value,
+        /// Proxy core the content is for ("singbox" | "xray"); older panels omit it
+        #[serde(default = "default_engine")]
+        pub engine: String,
+    }
+
+    fn default_engine() -> String {
+        "singbox".to_string()
+    }
+}
+
+pub mod subscription {
+    use super::*;
+    use base64::Engine;
+
+    /// Values behind the response headers that subscription clients
+    /// (Hiddify, Streisand, v2rayNG, Clash) show to customers
+    #[derive(Debug, Clone, Default, Serialize, Deserialize)]
+    pub struct SubscriptionHeaders {
+        pub upload: i64,
+        pub download: i64,
+        /// Bytes, 0 = unlimited
+        pub total: i64,
+        /// Unix timestamp
+        pub expire: Option<i64>,
+        pub update_interval_hours: u32,
+        pub profile_title: Option<String>,
+        pub profile_web_page_url: Option<String>,
+        pub support_url: Option<String>,
+        pub announce: Option<String>,
+    }
+
+    impl SubscriptionHeaders {
+        pub fn header_pairs(&self) -> Vec<(&'static str, String)> {
+            let mut userinfo = format!("upload={}; download={}; total={}", self.upload, self.download, self.total);
+            if let Some(expire) = self.expire {
+                userinfo.push_str(&format!("; expire={}", expire));
+            }
+
+            let mut headers = vec![
+                ("subscription-userinfo", userinfo),
+                ("profile-update-interval", self.update_interval_hours.max(1).to_string()),
+            ];
+            if let Some(title) = non_empty(&self.profile_title) {
+                headers.push(("profile-title", encode_text(title)));
+            }
+            if let Some(url) = non_empty(&self.profile_web_page_url) {
+                headers.push(("profile-web-page-url", url.to_string()));
+            }
+            if let Some(url) = non_empty(&self.support_url) {
+                headers.push(("support-url", url.to_string()));
+            }
+            if let Some(announce) = non_empty(&self.announce) {
+                headers.push(("announce", encode_text(announce)));
+            }
+            headers
+        }
+    }
+
+    fn non_empty(value: &Option<String>) -> Option<&str> {
+        value.as_deref().map(str::trim).filter(|v| !v.is_empty())
+    }
+
+    /// Header values must be visible ASCII; clients accept `base64:` for anything else
+    fn encode_text(text: &str) -> String {
+        if text.chars().all(|c| c.is_ascii_graphic() || c == ' ') {
+            text.to_string()
+        } else {
+            format!("base64:{}", base64::engine::general_purpose::STANDARD.encode(text))
+        }
     }
 }